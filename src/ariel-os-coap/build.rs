@@ -8,7 +8,17 @@ use std::fmt::Write as _;
 struct Peer {
     kccs: Option<String>,
     from: Option<KnownSource>,
-    scope: Scope,
+    scope: Option<Scope>,
+    aesccm256: Option<String>,
+    es256: Option<Es256>,
+}
+
+/// Signing key of an Authorization Server, along with the audience value of this device.
+#[derive(Deserialize)]
+struct Es256 {
+    x: String,
+    y: String,
+    audience: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -66,9 +76,47 @@ enum KnownScope {
 #[serde(rename_all = "kebab-case")]
 enum KnownSource {
     Unauthenticated,
+    AuthorizationServer,
+}
+
+/// Parses a 32 byte key expressed in CBOR Diagnostic Notation (EDN), e.g. as `h'0102...'`.
+fn parse_key(edn: &str) -> [u8; 32] {
+    let cbor = cbor_edn::StandaloneItem::parse(edn)
+        .expect("key is not valid CBOR Diagnostic Notation (EDN)")
+        .to_cbor()
+        .expect("CBOR Diagnostic Notation (EDN) is not expressible in CBOR");
+    let bytes: &minicbor::bytes::ByteSlice =
+        minicbor::decode(&cbor).expect("key is not a CBOR byte string");
+    (**bytes).try_into().expect("key is not 32 bytes long")
+}
+
+impl Scope {
+    /// Renders the scope as a Rust expression of type `coapcore::scope::UnionScope`.
+    fn to_rust(&self) -> String {
+        match self {
+            Scope::KnownScope(KnownScope::AllowAll) => {
+                "coapcore::scope::UnionScope::AllowAll".to_string()
+            }
+            Scope::Aif(aif) => {
+                let data: Vec<_> = aif
+                    .iter()
+                    .map(|(toid, tperm)| (toid, tperm.mask()))
+                    .collect();
+                let mut bytes = vec![];
+                minicbor::encode(data, &mut bytes).unwrap();
+                format!(
+                    "coapcore::scope::UnionScope::AifValue(coapcore::scope::AifValue::parse(&{bytes:?}).unwrap())"
+                )
+            }
+        }
+    }
 }
 
 fn main() {
+    if build_rs::input::cargo_feature("coap-transport-udp") {
+        check_bind_address();
+    }
+
     if !build_rs::input::cargo_feature("coap-server-config-storage") {
         return;
     }
@@ -91,30 +139,16 @@ fn main() {
     let peers: Vec<Peer> = serde_yaml::from_reader(peers_file).expect("failed to parse peers.yml");
 
     let mut unauthenticated_scope = None;
+    let mut as_key_aesccm256 = None;
+    let mut as_key_es256 = None;
     let mut chain_once_per_kccs = String::new();
     for peer in peers {
         // FIXME: Should we pre-parse the KCCS and have the parsed credentials as const in flash? Or
         // just parsed enough that there is no CBOR parsing but credential and material point to
         // overlapping slices?
-        let scope = match peer.scope {
-            Scope::KnownScope(KnownScope::AllowAll) => {
-                "coapcore::scope::UnionScope::AllowAll".to_string()
-            }
-            Scope::Aif(aif) => {
-                let data: Vec<_> = aif
-                    .into_iter()
-                    .map(|(toid, tperm)| (toid, tperm.mask()))
-                    .collect();
-                let mut bytes = vec![];
-                minicbor::encode(data, &mut bytes).unwrap();
-                format!(
-                    "coapcore::scope::UnionScope::AifValue(coapcore::scope::AifValue::parse(&{bytes:?}).unwrap())"
-                )
-            }
-        };
-
-        match (peer.kccs, peer.from) {
-            (Some(kccs), None) => {
+        match (peer.kccs, peer.from, peer.scope) {
+            (Some(kccs), None, Some(scope)) => {
+                let scope = scope.to_rust();
                 let kccs = cbor_edn::StandaloneItem::parse(&kccs)
                     .expect("data in kccs is not valid CBOR Diagnostic Notation (EDN)")
                     .to_cbor()
@@ -129,23 +163,62 @@ fn main() {
                 )
                 .expect("writing to String is infallible");
             }
-            (None, Some(KnownSource::Unauthenticated)) => {
+            (None, Some(KnownSource::Unauthenticated), Some(scope)) => {
                 assert!(
                     unauthenticated_scope.is_none(),
                     "Only a single `from: unauthenticated` record is usable.",
                 );
 
-                unauthenticated_scope = Some(format!("Some({scope})"));
+                unauthenticated_scope = Some(format!("Some({})", scope.to_rust()));
+            }
+            (None, Some(KnownSource::AuthorizationServer), None) => {
+                match (peer.aesccm256, peer.es256) {
+                    (Some(key), None) => {
+                        assert!(
+                            as_key_aesccm256.is_none(),
+                            "Only a single `aesccm256` authorization server is usable.",
+                        );
+
+                        as_key_aesccm256 = Some(format!("Some({:?})", parse_key(&key)));
+                    }
+                    (None, Some(es256)) => {
+                        assert!(
+                            es256.audience.len() <= 8,
+                            "The audience of an `es256` authorization server can be at most 8 bytes long.",
+                        );
+                        assert!(
+                            as_key_es256.is_none(),
+                            "Only a single `es256` authorization server is usable.",
+                        );
+
+                        as_key_es256 = Some(format!(
+                            "Some(({:?}, {:?}, {:?}))",
+                            parse_key(&es256.x),
+                            parse_key(&es256.y),
+                            es256.audience,
+                        ));
+                    }
+                    _ => panic!(
+                        "Every `from: authorization-server` record needs to have either an `aesccm256: ...` or an `es256: ...` key."
+                    ),
+                }
+            }
+            (None, Some(KnownSource::AuthorizationServer), Some(_)) => {
+                panic!(
+                    "`from: authorization-server` records can not have a scope; the scope is taken from the tokens."
+                )
             }
             _ => {
                 panic!(
-                    "Every peer record needs to have either a `kccs: ...` or a `from: unauthenticated` key."
+                    "Every peer record needs to have either a `kccs: ...` or a `from: ...` key, and a scope."
                 )
             }
         }
     }
 
     let unauthenticated_scope = unauthenticated_scope.unwrap_or("None".to_string());
+    let as_key_aesccm256 = as_key_aesccm256.unwrap_or("None".to_string());
+    let as_key_es256 = as_key_es256.unwrap_or("None".to_string());
 
     let peers_data = format!(
         "
@@ -157,8 +230,33 @@ fn main() {
         pub(super) fn unauthenticated_scope() -> Option<coapcore::scope::UnionScope> {{
             {unauthenticated_scope}
         }}
+
+        pub(super) fn as_key_aesccm256() -> Option<[u8; 32]> {{
+            {as_key_aesccm256}
+        }}
+
+        pub(super) fn as_key_es256() -> Option<([u8; 32], [u8; 32], &'static str)> {{
+            {as_key_es256}
+        }}
     ");
 
     let peers_file = build_rs::input::out_dir().join("peers.rs");
     std::fs::write(peers_file, peers_data).unwrap();
 }
+
+/// Checks `CONFIG_COAP_BIND_ADDRESS`, so that a bad address fails the build rather than the
+/// startup of the server.
+fn check_bind_address() {
+    build_rs::output::rerun_if_env_changed("CONFIG_COAP_BIND_ADDRESS");
+    let Ok(bind_address) = std::env::var("CONFIG_COAP_BIND_ADDRESS") else {
+        return;
+    };
+    let bind_address: std::net::IpAddr = bind_address
+        .parse()
+        .expect("CONFIG_COAP_BIND_ADDRESS is not a valid IP address");
+    // A socket bound to a unicast address does not receive requests sent to a multicast group.
+    assert!(
+        bind_address.is_unspecified() || !build_rs::input::cargo_feature("multicast"),
+        "CONFIG_COAP_BIND_ADDRESS needs to be unspecified (`::` or `0.0.0.0`) with the `multicast` feature"
+    );
+}
@@ -2,9 +2,9 @@
 //!
 //! This crate mainly provides easy-to-use wrappers around the [`coapcore`] crate, with presets
 //! tailored towards Ariel OS: It utilizes [`embassy_net`] to open a network accessible CoAP socket
-//! and selects [`embedded_nal_coap`] for CoAP over UDP, it selects [`ariel_os_random`] as a source
-//! of randomness, and [`lakers_crypto_rustcrypto`] for the cryptographic algorithm
-//! implementations.
+//! and selects [`embedded_nal_coap`] for CoAP over UDP (alternatively, it serves CoAP over TCP or
+//! over BLE GATT), it selects [`ariel_os_random`] as a source of randomness, and
+//! [`lakers_crypto_rustcrypto`] for the cryptographic algorithm implementations.
 #![no_std]
 #![deny(missing_docs)]
 
@@ -14,17 +14,65 @@ mod udp_nal;
 
 #[cfg(feature = "coap-server-config-storage")]
 mod stored;
+#[cfg(feature = "coap-server-config-storage")]
+pub use stored::reload_authorization_servers;
+
+#[cfg(feature = "coap-server-persist-contexts")]
+mod persist;
+
+#[cfg(feature = "coap-system-resources")]
+mod system;
 
 #[cfg(feature = "coap-transport-udp")]
 mod transport_udp;
 
+#[cfg(feature = "coap-transport-udp")]
+pub mod shared_client;
+#[cfg(feature = "coap-transport-udp")]
+pub use shared_client::shared_coap_client;
+
+#[cfg(feature = "coap-transport-tcp")]
+mod transport_tcp;
+
+#[cfg(feature = "coap-transport-ble")]
+mod transport_ble;
+
+// The transports each run the single server handler, so they can not be combined.
+#[cfg(any(
+    all(feature = "coap-transport-udp", feature = "coap-transport-tcp"),
+    all(feature = "coap-transport-udp", feature = "coap-transport-ble"),
+    all(feature = "coap-transport-tcp", feature = "coap-transport-ble"),
+))]
+compile_error!("Only one of the coap-transport-* features can be enabled at a time.");
+
+#[cfg(any(feature = "coap-transport-tcp", feature = "coap-transport-ble"))]
+mod respond;
+
+pub mod observe;
+pub mod time;
+
+#[cfg(feature = "coap-transport-udp")]
 use ariel_os_embassy::cell::SameExecutorCell;
-#[cfg(feature = "coap-server")]
+#[cfg(any(feature = "coap-server", feature = "coap-server-config-storage"))]
 use coap_handler_implementations::ReportingHandlerBuilder as _;
+#[cfg(feature = "coap-transport-udp")]
 use embassy_sync::watch::Watch;
 
-const CONCURRENT_REQUESTS: usize = 3;
+/// Number of client requests that can be in flight at the same time, and on CoAP-over-TCP, of
+/// connections that are served at the same time.
+#[cfg(any(feature = "coap-transport-udp", feature = "coap-transport-tcp"))]
+const CONCURRENT_REQUESTS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_CONCURRENT_REQUESTS",
+    3,
+    "maximum number of concurrent outgoing CoAP requests"
+);
+
+/// Port on which the server is reachable on network transports.
+#[cfg(any(feature = "coap-transport-udp", feature = "coap-transport-tcp"))]
+const PORT: u16 =
+    ariel_os_utils::u16_from_env_or!("CONFIG_COAP_PORT", 5683, "port of the CoAP server");
 
+#[cfg(feature = "coap-transport-udp")]
 static CLIENT_READY: Watch<
     embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
     SameExecutorCell<&'static embedded_nal_coap::CoAPRuntimeClient<'static, CONCURRENT_REQUESTS>>,
@@ -97,7 +145,7 @@ mod demo_setup {
 /// loop to run) get stalled.
 ///
 /// As the CoAP stack gets ready (which may take some time if the network is not ready yet), it also
-/// unblocks [`coap_client()`].
+/// unblocks `coap_client()` on CoAP-over-UDP.
 ///
 /// # Panics
 ///
@@ -112,8 +160,8 @@ pub async fn coap_run(handler: impl coap_handler::Handler + coap_handler::Report
 /// This is a separate function because if that function is not exposed publicly (i.e. when the
 /// laze feature `coap-server` is not active), it is called automatically in a separate task.
 ///
-/// It sets up the security configuration, and ultimately runs the CoAP transport (currently only
-/// CoAP-over-UDP) forever.
+/// It sets up the security configuration, and ultimately runs the CoAP transport (CoAP-over-UDP,
+/// CoAP-over-TCP or CoAP-over-GATT, depending on the selected `coap-transport-*` feature) forever.
 ///
 /// # Panics
 ///
@@ -137,6 +185,12 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
         }
     }
 
+    #[cfg(feature = "coap-server-config-storage")]
+    let handler = handler.at_with_attributes(&["ariel", "peers"], &[], stored::PeersResource);
+
+    #[cfg(feature = "coap-system-resources")]
+    let handler = system::with_system_resources(handler);
+
     // FIXME: Should we allow users to override that? After all, this is just convenience and may
     // be limiting in special applications.
     #[cfg(feature = "coap-server")]
@@ -150,12 +204,42 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
         security_config,
         || lakers_crypto_rustcrypto::Crypto::new(ariel_os_random::crypto_rng()),
         ariel_os_random::crypto_rng(),
-        coapcore::time::TimeUnknown,
+        time::SystemTime,
     );
+    #[cfg(all(
+        feature = "coap-server-persist-contexts",
+        any(
+            feature = "coap-server-config-storage",
+            feature = "coap-server-config-demokeys"
+        )
+    ))]
+    let handler = {
+        let handler =
+            handler.with_context_persistence(persist::ContextStore, persist::load().await);
+        // Restoring advanced the contexts' sequence number bounds, which need to be durable
+        // before any response is sent.
+        persist::flush().await;
+        handler
+    };
+
+    // Without security configuration, notifications are rendered by processing the original
+    // request again.
+    #[cfg(all(
+        feature = "coap-transport-udp",
+        not(any(
+            feature = "coap-server-config-storage",
+            feature = "coap-server-config-demokeys"
+        ))
+    ))]
+    let handler = observe::Unprotected(handler);
 
     cfg_if::cfg_if! {
         if #[cfg(feature = "coap-transport-udp")] {
             transport_udp::coap_run_udp(handler).await
+        } else if #[cfg(feature = "coap-transport-tcp")] {
+            transport_tcp::coap_run_tcp(handler).await
+        } else if #[cfg(feature = "coap-transport-ble")] {
+            transport_ble::coap_run_ble(handler).await
         } else if #[cfg(feature = "doc")] {
             loop {}
         } else {
@@ -168,12 +252,14 @@ async fn coap_run_impl(handler: impl coap_handler::Handler + coap_handler::Repor
 ///
 /// This asynchronously blocks until [`coap_run()`] has been called (which happens at startup
 /// when the corresponding feature `coap-server` is not active), and the CoAP stack is operational.
+/// Client requests are only supported on the CoAP-over-UDP transport, so this is only available
+/// with it.
 ///
 /// # Panics
 ///
-/// This is currently only available from the thread that hosts the network stack, and panics
-/// otherwise. This restriction will be lifted in the future (by generalization in
-/// [`embedded_nal_coap`] to allow different mutexes).
+/// This is only available from the thread that hosts the network stack, and panics otherwise.
+/// From other threads or executors, use [`shared_coap_client()`] instead.
+#[cfg(feature = "coap-transport-udp")]
 pub async fn coap_client()
 -> &'static embedded_nal_coap::CoAPRuntimeClient<'static, CONCURRENT_REQUESTS> {
     let mut receiver = CLIENT_READY
@@ -187,17 +273,51 @@ pub async fn coap_client()
         .expect("CoAP client can currently only be used from the thread the network is bound to")
 }
 
+/// Cryptographic backend used by [`OscoreEdhocClient`].
+#[cfg(feature = "coap-client-edhoc")]
+pub type ClientCrypto = lakers_crypto_rustcrypto::Crypto<ariel_os_random::CryptoRng>;
+
+/// Client side security context with the cryptographic presets of Ariel OS, as created by
+/// [`oscore_edhoc_client()`].
+///
+/// Being nameable, it can be stored in a `static` (e.g. a `StaticCell`) or a struct field.
+#[cfg(feature = "coap-client-edhoc")]
+pub type OscoreEdhocClient =
+    coapcore::client::OscoreEdhocClient<ClientCrypto, fn() -> ClientCrypto>;
+
+/// Creates a client side security context with the cryptographic presets of Ariel OS.
+///
+/// Requests sent through a stack wrapped by its
+/// [`protecting()`](coapcore::client::OscoreEdhocClient::protecting) method (typically a
+/// `coap_client().await.to(address)`) are protected with OSCORE. The security context is
+/// established on the first request by running EDHOC with the server, authenticating with
+/// `own_credential` and `own_key` and expecting the server to present `peer_credential`.
+#[cfg(feature = "coap-client-edhoc")]
+pub fn oscore_edhoc_client(
+    own_credential: lakers::Credential,
+    own_key: lakers::BytesP256ElemLen,
+    peer_credential: lakers::Credential,
+) -> OscoreEdhocClient {
+    fn crypto() -> ClientCrypto {
+        lakers_crypto_rustcrypto::Crypto::new(ariel_os_random::crypto_rng())
+    }
+
+    OscoreEdhocClient::new(own_credential, own_key, peer_credential, crypto)
+}
+
 /// Auto-started CoAP server that serves two purposes:
 ///
 /// * It provides the backend for the CoAP client operation (which leaves message sending to that
 ///   task).
-/// * It runs any CoAP server components provided by the OS (none yet).
+/// * It runs any CoAP server components provided by the OS (see the `coap-system-resources`
+///   feature).
 #[cfg(not(feature = "coap-server"))]
 #[ariel_os_macros::task(autostart)]
 async fn coap_run() {
     use coap_handler_implementations::new_dispatcher;
 
-    // FIXME: Provide an "all system components" constructor in this crate.
+    // System components are added by `coap_run_impl()`, as they are also added to the handlers of
+    // applications that run the server themselves.
     let handler = new_dispatcher();
     coap_run_impl(handler).await;
 }
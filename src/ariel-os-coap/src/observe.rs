@@ -0,0 +1,300 @@
+//! Observation of resources ([RFC7641](https://www.rfc-editor.org/rfc/rfc7641)).
+//!
+//! A resource is made observable by wrapping its handler with [`observable()`], and mounting it
+//! with the [`Observable`](coap_handler::Attribute::Observable) attribute:
+//!
+//! ```ignore
+//! static TEMPERATURE: Observable = Observable::new();
+//!
+//! let handler = new_dispatcher().at_with_attributes(
+//!     &["temp"],
+//!     &[coap_handler::Attribute::Observable],
+//!     observable(&TEMPERATURE, TemperatureResource),
+//! );
+//! ```
+//!
+//! Whenever the resource's state changes, the application calls [`Observable::notify()`]; the
+//! CoAP server then renders the resource again for every registered observer, and sends the
+//! result as a notification.
+//!
+//! Observation is only supported on the CoAP-over-UDP transport. Registrations are kept for a
+//! small number of observers; when more clients register, the oldest registration is dropped.
+
+use core::cell::Cell;
+
+use coap_message::{MessageOption as _, MutableWritableMessage, ReadableMessage};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+
+/// Signaled whenever any [`Observable`] changes.
+pub(crate) static NOTIFIED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Registration decision of the most recently built response, picked up by the transport when
+/// sending the response.
+static OUTCOME: critical_section::Mutex<Cell<Option<Outcome>>> =
+    critical_section::Mutex::new(Cell::new(None));
+
+/// Change notification point of an observable resource.
+pub struct Observable {
+    sequence: critical_section::Mutex<Cell<u32>>,
+}
+
+impl Observable {
+    /// Creates a new change notification point.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            sequence: critical_section::Mutex::new(Cell::new(0)),
+        }
+    }
+
+    /// Indicates that the resource's state changed, and notifications should be sent to its
+    /// observers.
+    ///
+    /// This can be called from any thread or interrupt; notifications are rendered and sent
+    /// asynchronously by the CoAP server. Changes that happen in quick succession may be reported
+    /// in a single notification.
+    pub fn notify(&self) {
+        critical_section::with(|cs| {
+            let sequence = self.sequence.borrow(cs);
+            sequence.set(sequence.get().wrapping_add(1));
+        });
+        NOTIFIED.signal(());
+    }
+
+    /// Returns the number of changes so far (wrapping).
+    pub(crate) fn sequence(&self) -> u32 {
+        critical_section::with(|cs| self.sequence.borrow(cs).get())
+    }
+}
+
+impl Default for Observable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone)]
+#[cfg_attr(
+    not(feature = "coap-transport-udp"),
+    allow(dead_code, reason = "only the UDP transport keeps registrations")
+)]
+pub(crate) enum Outcome {
+    /// A GET with Observe 0 was processed successfully.
+    Register(&'static Observable),
+    /// A GET with Observe 1 was processed.
+    Deregister,
+}
+
+/// Takes the registration decision of the most recently built response.
+#[cfg(feature = "coap-transport-udp")]
+pub(crate) fn take_outcome() -> Option<Outcome> {
+    critical_section::with(|cs| OUTCOME.borrow(cs).take())
+}
+
+/// Makes a resource observable, announcing its changes through `observable`.
+///
+/// See the [module level documentation](self) for usage.
+pub fn observable<H: coap_handler::Handler>(
+    observable: &'static Observable,
+    handler: H,
+) -> ObservableHandler<H> {
+    ObservableHandler {
+        observable,
+        inner: handler,
+    }
+}
+
+/// A handler that processes Observe options in GET requests before passing them on.
+///
+/// This is created through [`observable()`].
+pub struct ObservableHandler<H> {
+    observable: &'static Observable,
+    inner: H,
+}
+
+/// Observe option's effect on a request, as found by the [`ObservableHandler`].
+#[derive(Copy, Clone)]
+pub enum ObserveRequest {
+    /// The request asks for registration (Observe 0).
+    Register,
+    /// The request asks for deregistration (Observe 1).
+    Deregister,
+}
+
+impl<H: coap_handler::Handler> coap_handler::Handler for ObservableHandler<H> {
+    type RequestData = (Option<ObserveRequest>, H::RequestData);
+    type ExtractRequestError = H::ExtractRequestError;
+    type BuildResponseError<M: coap_message::MinimalWritableMessage> = H::BuildResponseError<M>;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        let code: u8 = request.code().into();
+        let observe = if code == coap_numbers::code::GET {
+            request
+                .options()
+                .find(|o| o.number() == coap_numbers::option::OBSERVE)
+                .and_then(|o| match o.value() {
+                    v if v.iter().all(|b| *b == 0) => Some(ObserveRequest::Register),
+                    [1] => Some(ObserveRequest::Deregister),
+                    _ => None,
+                })
+        } else {
+            None
+        };
+
+        Ok((observe, self.inner.extract_request_data(request)?))
+    }
+
+    fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+        // Observe option with up to 3 bytes of value
+        self.inner.estimate_length(&request.1) + 4
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        let (observe, request) = request;
+
+        // The Observe option precedes most options, so it is added before the inner handler gets
+        // to add any. If it can not be added, the response is sent without registering.
+        let registered = matches!(observe, Some(ObserveRequest::Register)) && {
+            let sequence = (self.observable.sequence() & 0x00ff_ffff).to_be_bytes();
+            let leading_zeros = sequence.iter().take_while(|b| **b == 0).count();
+            let value = sequence.get(leading_zeros..).unwrap_or_default();
+            <M::OptionNumber as coap_message::OptionNumber>::new(coap_numbers::option::OBSERVE)
+                .ok()
+                .is_some_and(|number| response.add_option(number, value).is_ok())
+        };
+
+        self.inner.build_response(response, request)?;
+
+        let outcome = match observe {
+            Some(ObserveRequest::Register) if registered => {
+                Some(Outcome::Register(self.observable))
+            }
+            Some(ObserveRequest::Deregister) => Some(Outcome::Deregister),
+            _ => None,
+        };
+        critical_section::with(|cs| OUTCOME.borrow(cs).set(outcome));
+        Ok(())
+    }
+}
+
+#[cfg(feature = "coap-transport-udp")]
+pub(crate) use renotify::{Renotify, Shared, Unprotected};
+
+#[cfg(feature = "coap-transport-udp")]
+mod renotify {
+    use core::cell::RefCell;
+
+    use coap_handler::Handler;
+    use coap_message::{MinimalWritableMessage, MutableWritableMessage, ReadableMessage};
+    use coap_message_implementations::{inmemory, inmemory_write};
+
+    /// A handler that can render notifications for a request it processed earlier.
+    pub(crate) trait Renotify: Handler {
+        /// Renders a notification for the original request `request` into `response`.
+        ///
+        /// Returns false if no notification can be rendered any more.
+        fn build_notification(
+            &mut self,
+            request: &inmemory::Message<'_>,
+            response: &mut inmemory_write::Message<'_>,
+        ) -> bool;
+    }
+
+    impl<H, Crypto, CryptoFactory, SSC, RNG, TP, P> Renotify
+        for coapcore::OscoreEdhocHandler<H, Crypto, CryptoFactory, SSC, RNG, TP, P>
+    where
+        H: Handler,
+        Crypto: lakers::Crypto,
+        CryptoFactory: Fn() -> Crypto,
+        SSC: coapcore::seccfg::ServerSecurityConfig,
+        RNG: rand_core::RngCore + rand_core::CryptoRng,
+        TP: coapcore::time::TimeProvider,
+        P: coapcore::persistence::ContextPersistence,
+    {
+        fn build_notification(
+            &mut self,
+            request: &inmemory::Message<'_>,
+            response: &mut inmemory_write::Message<'_>,
+        ) -> bool {
+            coapcore::OscoreEdhocHandler::build_notification(self, request, response).is_ok()
+        }
+    }
+
+    /// A handler serving without any security configuration, which renders notifications by
+    /// processing the original request again.
+    pub(crate) struct Unprotected<H>(pub(crate) H);
+
+    impl<H: Handler> Handler for Unprotected<H> {
+        type RequestData = H::RequestData;
+        type ExtractRequestError = H::ExtractRequestError;
+        type BuildResponseError<M: MinimalWritableMessage> = H::BuildResponseError<M>;
+
+        fn extract_request_data<M: ReadableMessage>(
+            &mut self,
+            request: &M,
+        ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+            self.0.extract_request_data(request)
+        }
+
+        fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+            self.0.estimate_length(request)
+        }
+
+        fn build_response<M: MutableWritableMessage>(
+            &mut self,
+            response: &mut M,
+            request: Self::RequestData,
+        ) -> Result<(), Self::BuildResponseError<M>> {
+            self.0.build_response(response, request)
+        }
+    }
+
+    impl<H: Handler> Renotify for Unprotected<H> {
+        fn build_notification(
+            &mut self,
+            request: &inmemory::Message<'_>,
+            response: &mut inmemory_write::Message<'_>,
+        ) -> bool {
+            self.0
+                .extract_request_data(request)
+                .is_ok_and(|extracted| self.0.build_response(response, extracted).is_ok())
+        }
+    }
+
+    /// A handler that is shared between the CoAP server and the notification sender.
+    ///
+    /// Both use the handler only synchronously, so the borrow never fails.
+    pub(crate) struct Shared<'a, H>(pub(crate) &'a RefCell<H>);
+
+    impl<H: Handler> Handler for Shared<'_, H> {
+        type RequestData = H::RequestData;
+        type ExtractRequestError = H::ExtractRequestError;
+        type BuildResponseError<M: MinimalWritableMessage> = H::BuildResponseError<M>;
+
+        fn extract_request_data<M: ReadableMessage>(
+            &mut self,
+            request: &M,
+        ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+            self.0.borrow_mut().extract_request_data(request)
+        }
+
+        fn estimate_length(&mut self, request: &Self::RequestData) -> usize {
+            self.0.borrow_mut().estimate_length(request)
+        }
+
+        fn build_response<M: MutableWritableMessage>(
+            &mut self,
+            response: &mut M,
+            request: Self::RequestData,
+        ) -> Result<(), Self::BuildResponseError<M>> {
+            self.0.borrow_mut().build_response(response, request)
+        }
+    }
+}
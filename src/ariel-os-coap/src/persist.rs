@@ -0,0 +1,228 @@
+//! Persistence of OSCORE security contexts in storage.
+//!
+//! The contexts are kept in RAM, where the [`coapcore::OscoreEdhocHandler`] updates them through
+//! [`ContextStore`], and are written to storage in a separate task (as CoAP handlers can not wait
+//! for storage).
+//!
+//! Each context occupies a slot, stored under `ariel-os-coap.oscore.{slot}`. Free slots have no
+//! item.
+//!
+//! Along with each slot, the sequence number bound that was last written successfully is tracked,
+//! as the handler must not use sequence numbers beyond it: Only that bound would be restored after
+//! a reboot.
+#![allow(
+    clippy::indexing_slicing,
+    reason = "slot indices are below MAX_PERSISTED_CONTEXTS by construction"
+)]
+
+use core::cell::RefCell;
+
+use ariel_os_debug::log::{debug, info};
+use coapcore::persistence::{ContextPersistence, MAX_PERSISTED_LEN, PersistedContext};
+use embassy_sync::{
+    blocking_mutex::{CriticalSectionMutex, raw::CriticalSectionRawMutex},
+    mutex::Mutex,
+    signal::Signal,
+};
+use embassy_time::{Duration, Timer};
+
+/// Number of persisted contexts; this matches the number of contexts the handler keeps.
+const MAX_PERSISTED_CONTEXTS: usize = 4;
+
+/// Storage keys of the contexts, by slot.
+const KEYS: [&str; MAX_PERSISTED_CONTEXTS] = [
+    "ariel-os-coap.oscore.0",
+    "ariel-os-coap.oscore.1",
+    "ariel-os-coap.oscore.2",
+    "ariel-os-coap.oscore.3",
+];
+
+type Encoded = heapless::Vec<u8, MAX_PERSISTED_LEN>;
+
+struct PersistedContexts {
+    slots: [Option<PersistedContext>; MAX_PERSISTED_CONTEXTS],
+    /// Sequence number bounds of the slots' contexts as last written to storage; `None` if the
+    /// slot's context has not been written yet.
+    durable: [Option<u64>; MAX_PERSISTED_CONTEXTS],
+    /// Bit mask of the slots that changed since they were last written to storage.
+    dirty: u8,
+}
+
+static CONTEXTS: CriticalSectionMutex<RefCell<PersistedContexts>> =
+    CriticalSectionMutex::new(RefCell::new(PersistedContexts {
+        slots: [const { None }; MAX_PERSISTED_CONTEXTS],
+        durable: [None; MAX_PERSISTED_CONTEXTS],
+        dirty: 0,
+    }));
+
+/// Time after which a failed write is retried.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Signaled whenever slots are marked dirty.
+static PERSIST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Serializes [`flush()`] runs, so that an older state is never written after a newer one.
+static FLUSHING: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// Loads the persisted contexts from storage.
+///
+/// The returned contexts are to be passed to
+/// [`with_context_persistence()`](coapcore::OscoreEdhocHandler::with_context_persistence).
+pub(crate) async fn load() -> heapless::Vec<PersistedContext, MAX_PERSISTED_CONTEXTS> {
+    let mut restored = heapless::Vec::new();
+    for (slot, key) in KEYS.iter().enumerate() {
+        let encoded: Option<Encoded> = ariel_os_storage::get(key)
+            .await
+            .expect("flash error prevents startup");
+        let Some(encoded) = encoded else {
+            continue;
+        };
+        let Ok(context) = PersistedContext::decode(&encoded) else {
+            info!("Discarding unusable OSCORE context in slot {}.", slot);
+            CONTEXTS.lock(|contexts| contexts.borrow_mut().dirty |= 1 << slot);
+            continue;
+        };
+
+        CONTEXTS.lock(|contexts| {
+            let mut contexts = contexts.borrow_mut();
+            contexts.durable[slot] = Some(context.sequence_number_bound());
+            contexts.slots[slot] = Some(context.clone());
+        });
+        restored
+            .push(context)
+            .unwrap_or_else(|_| unreachable!("one item per slot"));
+    }
+    debug!("Loaded {} persisted OSCORE contexts.", restored.len());
+    restored
+}
+
+/// Writes changed slots to storage.
+///
+/// This is run once after the restored contexts were handed to the handler (which stores them
+/// again with advanced sequence numbers), and whenever contexts change.
+///
+/// Returns whether all changes were written; failed slots stay dirty, and [`PERSIST`] is signaled
+/// again so that they are retried.
+pub(crate) async fn flush() -> bool {
+    let _flushing = FLUSHING.lock().await;
+
+    let (slots, dirty) = CONTEXTS.lock(|contexts| {
+        let mut contexts = contexts.borrow_mut();
+        (contexts.slots.clone(), core::mem::take(&mut contexts.dirty))
+    });
+
+    let mut success = true;
+    for (slot, context) in slots.iter().enumerate() {
+        if dirty & (1 << slot) == 0 {
+            continue;
+        }
+        let result = match context {
+            Some(context) => {
+                let mut buffer = [0; MAX_PERSISTED_LEN];
+                let encoded = Encoded::from_slice(context.encode(&mut buffer))
+                    .unwrap_or_else(|()| unreachable!("buffer has the same size"));
+                ariel_os_storage::insert(KEYS[slot], encoded).await
+            }
+            None => ariel_os_storage::remove(KEYS[slot]).await,
+        };
+
+        CONTEXTS.lock(|contexts| {
+            let mut contexts = contexts.borrow_mut();
+            if result.is_err() {
+                contexts.dirty |= 1 << slot;
+                return;
+            }
+            // The slot may have been given to a different context while writing, whose state
+            // is not durable yet.
+            let current = contexts.slots[slot]
+                .as_ref()
+                .map(PersistedContext::recipient_id);
+            if let Some(context) = context
+                && current == Some(context.recipient_id())
+            {
+                contexts.durable[slot] = Some(context.sequence_number_bound());
+            }
+        });
+        if result.is_err() {
+            // A stale state limits the sequence numbers the handler may use.
+            info!("Failed to store OSCORE context in slot {}.", slot);
+            success = false;
+        }
+    }
+    if !success {
+        PERSIST.signal(());
+    }
+    success
+}
+
+#[ariel_os_macros::task(autostart)]
+async fn persist() {
+    loop {
+        PERSIST.wait().await;
+        if !flush().await {
+            Timer::after(RETRY_DELAY).await;
+        }
+    }
+}
+
+/// A [`ContextPersistence`] that writes to the slots in storage.
+pub(crate) struct ContextStore;
+
+impl ContextPersistence for ContextStore {
+    fn store(&mut self, context: &PersistedContext) {
+        let stored = CONTEXTS.lock(|contexts| {
+            let mut contexts = contexts.borrow_mut();
+            let Some(slot) = contexts
+                .slots
+                .iter()
+                .position(|s| {
+                    s.as_ref()
+                        .is_some_and(|s| s.recipient_id() == context.recipient_id())
+                })
+                .or_else(|| contexts.slots.iter().position(Option::is_none))
+            else {
+                return false;
+            };
+            contexts.slots[slot] = Some(context.clone());
+            contexts.dirty |= 1 << slot;
+            true
+        });
+        if stored {
+            PERSIST.signal(());
+        } else {
+            // The context stays usable until reboot.
+            info!("No free slot for persisting OSCORE context.");
+        }
+    }
+
+    fn remove(&mut self, recipient_id: &[u8]) {
+        let removed = CONTEXTS.lock(|contexts| {
+            let mut contexts = contexts.borrow_mut();
+            let Some(slot) = contexts
+                .slots
+                .iter()
+                .position(|s| s.as_ref().is_some_and(|s| s.recipient_id() == recipient_id))
+            else {
+                return false;
+            };
+            contexts.slots[slot] = None;
+            contexts.durable[slot] = None;
+            contexts.dirty |= 1 << slot;
+            true
+        });
+        if removed {
+            PERSIST.signal(());
+        }
+    }
+
+    fn durable_sequence_number_bound(&self, recipient_id: &[u8]) -> Option<u64> {
+        CONTEXTS.lock(|contexts| {
+            let contexts = contexts.borrow();
+            let slot = contexts
+                .slots
+                .iter()
+                .position(|s| s.as_ref().is_some_and(|s| s.recipient_id() == recipient_id))?;
+            contexts.durable[slot]
+        })
+    }
+}
@@ -0,0 +1,43 @@
+//! Request processing for transports that are not implemented through [`embedded_nal_coap`].
+
+use ariel_os_debug::log::{Debug2Format, error};
+use coap_message::{MinimalWritableMessage as _, error::RenderableOnMinimal as _};
+use coap_message_implementations::{inmemory, inmemory_write};
+
+/// Processes a request given as its code and its serialized options and payload.
+///
+/// The response is written into `response_code` and `response_tail` (its serialized options and
+/// payload); the used length of the latter is returned.
+pub(crate) fn respond(
+    handler: &mut impl coap_handler::Handler,
+    code: u8,
+    options_and_payload: &[u8],
+    response_code: &mut u8,
+    response_tail: &mut [u8],
+) -> usize {
+    let request = inmemory::Message::new(code, options_and_payload);
+    let mut response = inmemory_write::Message::new(response_code, response_tail);
+
+    match handler.extract_request_data(&request) {
+        Ok(extracted) => {
+            if let Err(e) = handler.build_response(&mut response, extracted) {
+                error!(
+                    "Rendering successful extraction failed with {:?}",
+                    Debug2Format(&e)
+                );
+                // FIXME rewind message
+                if e.render(&mut response).is_err() {
+                    response.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
+                }
+            }
+        }
+        Err(e) => {
+            if e.render(&mut response).is_err() {
+                // FIXME rewind message
+                response.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+
+    response.finish()
+}
@@ -0,0 +1,202 @@
+//! A CoAP client that can be used from any thread or executor.
+//!
+//! Requests are built and their responses processed on the caller's side, in a buffer that is
+//! handed over to the executor running the CoAP stack, where the request is sent through the
+//! [`embedded_nal_coap`] client. One request is in flight at a time; further requests wait for
+//! their turn.
+
+use core::net::SocketAddr;
+
+use coap_message::MinimalWritableMessage as _;
+use coap_message_implementations::{inmemory, inmemory_write};
+use coap_request::{Request, Stack};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
+
+use crate::CONCURRENT_REQUESTS;
+
+/// Largest length of a request's or response's options and payload.
+const MAX_MESSAGE_LEN: usize = 1024;
+
+/// A request or response that is handed between the caller and the CoAP stack.
+struct Exchange {
+    code: u8,
+    buffer: [u8; MAX_MESSAGE_LEN],
+    /// Length of the options and payload in `buffer`.
+    len: usize,
+    /// Destination of the request in `buffer`, until it is taken up by [`forward()`].
+    destination: Option<SocketAddr>,
+    /// Outcome of the request in `buffer`, once it completed.
+    outcome: Option<Result<(), TransportError>>,
+}
+
+impl Exchange {
+    fn message(&self) -> inmemory::Message<'_> {
+        #[allow(clippy::indexing_slicing, reason = "length was produced by the writer")]
+        inmemory::Message::new(self.code, &self.buffer[..self.len])
+    }
+}
+
+static EXCHANGE: Mutex<CriticalSectionRawMutex, Exchange> = Mutex::new(Exchange {
+    code: 0,
+    buffer: [0; MAX_MESSAGE_LEN],
+    len: 0,
+    destination: None,
+    outcome: None,
+});
+
+/// Held by the caller throughout a request, so that only one is in flight.
+static IN_FLIGHT: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// Signaled when a request was placed in [`EXCHANGE`].
+static SUBMITTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signaled when an outcome was placed in [`EXCHANGE`].
+static COMPLETED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Errors of requests sent through a [`SharedClient`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TransportError {
+    /// The request did not fit into the buffer that is handed to the CoAP stack.
+    RequestTooLarge,
+    /// The response did not fit into the buffer that is handed back from the CoAP stack.
+    ResponseTooLarge,
+    /// The CoAP stack failed to send the request or to receive a response.
+    Transport,
+}
+
+/// Returns a CoAP client that can be used from any thread or executor.
+///
+/// Unlike [`coap_client()`](crate::coap_client), this can be called at any time; requests wait
+/// until the CoAP stack is operational. Requests need to fit into a buffer of 1024 bytes (for
+/// their options and payload), as does their response.
+#[must_use]
+pub fn shared_coap_client() -> SharedClient {
+    SharedClient { _private: () }
+}
+
+/// A CoAP client usable from any thread or executor.
+///
+/// This is created through [`shared_coap_client()`].
+#[derive(Copy, Clone)]
+pub struct SharedClient {
+    _private: (),
+}
+
+impl SharedClient {
+    /// Returns a [`Stack`] that sends requests to `address`.
+    #[must_use]
+    pub fn to(self, address: SocketAddr) -> SharedRequester {
+        SharedRequester { address }
+    }
+}
+
+/// A [`Stack`] sending requests to a fixed address through a [`SharedClient`].
+pub struct SharedRequester {
+    address: SocketAddr,
+}
+
+impl Stack for SharedRequester {
+    type RequestUnionError =
+        <inmemory_write::Message<'static> as coap_message::MinimalWritableMessage>::UnionError;
+    type RequestMessage<'a>
+        = inmemory_write::Message<'a>
+    where
+        Self: 'a;
+    type ResponseMessage<'a>
+        = inmemory::Message<'a>
+    where
+        Self: 'a;
+    type TransportError = TransportError;
+
+    async fn request<Req: Request<Self>>(
+        &mut self,
+        mut request: Req,
+    ) -> Result<Req::Output, TransportError> {
+        let _in_flight = IN_FLIGHT.lock().await;
+
+        // The exchange is only accessible once any cancelled earlier request that was taken up
+        // by `forward()` completed. The destination is placed together with the request, so that
+        // no request is sent to another one's destination.
+        let carry = {
+            let mut exchange = EXCHANGE.lock().await;
+            // A cancelled earlier request may not have been taken up yet.
+            exchange.destination = None;
+            exchange.outcome = None;
+            let Exchange {
+                code, buffer, len, ..
+            } = &mut *exchange;
+            let mut message = inmemory_write::Message::new(code, buffer);
+            let carry = request
+                .build_request(&mut message)
+                .await
+                .map_err(|_| TransportError::RequestTooLarge)?;
+            *len = message.finish();
+            exchange.destination = Some(self.address);
+            carry
+        };
+        SUBMITTED.signal(());
+
+        loop {
+            COMPLETED.wait().await;
+            let exchange = EXCHANGE.lock().await;
+            // Completions of cancelled earlier requests left no outcome.
+            if let Some(outcome) = exchange.outcome {
+                outcome?;
+                return Ok(request.process_response(&exchange.message(), carry).await);
+            }
+        }
+    }
+}
+
+/// Sends the requests of [`SharedClient`]s through `client`.
+///
+/// This needs to run on the executor that runs the CoAP stack.
+pub(crate) async fn forward(
+    client: &embedded_nal_coap::CoAPRuntimeClient<'_, CONCURRENT_REQUESTS>,
+) -> ! {
+    loop {
+        SUBMITTED.wait().await;
+        let mut exchange = EXCHANGE.lock().await;
+        // A request replacing a cancelled one may have been submitted after it was taken up.
+        let Some(address) = exchange.destination.take() else {
+            continue;
+        };
+        let outcome = match client.to(address).request(Forward(&mut exchange)).await {
+            Ok(outcome) => outcome,
+            Err(_) => Err(TransportError::Transport),
+        };
+        exchange.outcome = Some(outcome);
+        drop(exchange);
+        COMPLETED.signal(());
+    }
+}
+
+/// Request that forwards the request in an [`Exchange`], and places the response there.
+struct Forward<'a>(&'a mut Exchange);
+
+impl<S: Stack> Request<S> for Forward<'_> {
+    type Carry = ();
+    type Output = Result<(), TransportError>;
+
+    async fn build_request(
+        &mut self,
+        request: &mut S::RequestMessage<'_>,
+    ) -> Result<Self::Carry, S::RequestUnionError> {
+        request.set_from_message(&self.0.message())
+    }
+
+    async fn process_response(
+        &mut self,
+        response: &S::ResponseMessage<'_>,
+        (): Self::Carry,
+    ) -> Self::Output {
+        let Exchange {
+            code, buffer, len, ..
+        } = &mut *self.0;
+        let mut copy = inmemory_write::Message::new(code, buffer);
+        copy.set_from_message(response)
+            .map_err(|_| TransportError::ResponseTooLarge)?;
+        *len = copy.finish();
+        Ok(())
+    }
+}
@@ -1,8 +1,19 @@
 //! Credential and key configuration backed by ariel-os storage
 
-use ariel_os_debug::log::{Cbor, debug, info};
+use core::cell::RefCell;
+
+use ariel_os_debug::log::{Cbor, debug, error, info};
 use cbor_macro::cbo;
-use coapcore::seccfg::ServerSecurityConfig;
+use coapcore::{
+    CredentialError,
+    ace::{CwtClaimsSet, HeaderMap},
+    seccfg::{ConfigBuilder, ConfigBuilderClaims, ServerSecurityConfig},
+};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+mod runtime_peers;
+
+pub(crate) use runtime_peers::PeersResource;
 
 mod flash_peers {
     include!(concat!(env!("OUT_DIR"), "/peers.rs"));
@@ -16,14 +27,68 @@ pub async fn server_security_config() -> impl ServerSecurityConfig {
 // don't have the async context to access any storage at CoAP time.
 struct StoredPolicy {
     own_edhoc_credential: (lakers::Credential, lakers::BytesP256ElemLen),
+    /// Configuration that only holds the keys of the Authorization Servers, and is used to
+    /// process their tokens.
+    authorization_servers: RefCell<ConfigBuilder>,
+}
+
+/// Authorization Server configuration loaded by [`reload_authorization_servers()`], which the
+/// policy picks up when it next processes a token.
+static RELOADED_AUTHORIZATION_SERVERS: CriticalSectionMutex<RefCell<Option<ConfigBuilder>>> =
+    CriticalSectionMutex::new(RefCell::new(None));
+
+/// Reloads the Authorization Server keys from storage.
+///
+/// This needs to be called after changing the keys stored under `ariel-os-coap.as-key-aesccm256`
+/// or `ariel-os-coap.as-key-es256` for the change to take effect without a restart. Security
+/// contexts established from earlier tokens are kept. If storage can not be read, the previous
+/// keys stay in use.
+pub async fn reload_authorization_servers() {
+    match load_authorization_servers().await {
+        Ok(config) => {
+            RELOADED_AUTHORIZATION_SERVERS.lock(|reloaded| *reloaded.borrow_mut() = Some(config));
+        }
+        Err(()) => error!("Failed to read Authorization Server keys, keeping the previous ones."),
+    }
 }
 
 impl ServerSecurityConfig for StoredPolicy {
-    // FIXME: Decide based on peers.rs input (but so far tokens are not implemented)
-    const PARSES_TOKENS: bool = false;
+    // Authorization Server keys may be provisioned into storage at runtime, so tokens are always
+    // parsed; without any key, they are rejected.
+    const PARSES_TOKENS: bool = true;
     const HAS_EDHOC: bool = true;
     type GeneralClaims = StoredClaims;
 
+    fn decrypt_symmetric_token<'buf>(
+        &self,
+        headers: &HeaderMap<'_>,
+        aad: &[u8],
+        ciphertext_buffer: &'buf mut [u8],
+    ) -> Result<(StoredClaims, CwtClaimsSet<'buf>), CredentialError> {
+        let (claims, claims_set) = self.authorization_servers().decrypt_symmetric_token(
+            headers,
+            aad,
+            ciphertext_buffer,
+        )?;
+        Ok((claims.into(), claims_set))
+    }
+
+    fn verify_asymmetric_token<'b>(
+        &self,
+        headers: &HeaderMap<'_>,
+        signed_data: &[u8],
+        signature: &[u8],
+        signed_payload: &'b [u8],
+    ) -> Result<(StoredClaims, CwtClaimsSet<'b>), CredentialError> {
+        let (claims, claims_set) = self.authorization_servers().verify_asymmetric_token(
+            headers,
+            signed_data,
+            signature,
+            signed_payload,
+        )?;
+        Ok((claims.into(), claims_set))
+    }
+
     fn own_edhoc_credential(&self) -> Option<(lakers::Credential, lakers::BytesP256ElemLen)> {
         Some(self.own_edhoc_credential)
     }
@@ -38,16 +103,23 @@ impl ServerSecurityConfig for StoredPolicy {
         );
 
         for (credential, scope) in flash_peers::kccs() {
-            if credential.by_kid().is_ok_and(|by_kid| by_kid == id_cred_x)
-                || credential
-                    .by_value()
-                    .is_ok_and(|by_value| by_value == id_cred_x)
-            {
+            if credential_matches(&credential, &id_cred_x) {
                 debug!("Credential recognized.");
-                return Some((credential, StoredClaims { scope }));
+                return Some((credential, StoredClaims::unbounded(scope)));
             }
         }
 
+        if let Some((credential, scope, revocation)) = runtime_peers::find(&id_cred_x) {
+            debug!("Credential recognized from runtime provisioning.");
+            return Some((
+                credential,
+                StoredClaims {
+                    revocation: Some(revocation),
+                    ..StoredClaims::unbounded(scope)
+                },
+            ));
+        }
+
         // FIXME: This should be a default behavior -- but should it be part of a utility function
         // for expand_id_cred_x, or should it be where that is called?
         if let Some(credential_by_value) = id_cred_x.get_ccs() {
@@ -62,10 +134,18 @@ impl ServerSecurityConfig for StoredPolicy {
     }
 
     fn nosec_authorization(&self) -> Option<Self::GeneralClaims> {
-        flash_peers::unauthenticated_scope().map(|scope| StoredClaims { scope })
+        flash_peers::unauthenticated_scope().map(StoredClaims::unbounded)
     }
 }
 
+/// Returns whether `id_cred_x` identifies `credential` by key ID or by value.
+fn credential_matches(credential: &lakers::Credential, id_cred_x: &lakers::IdCred) -> bool {
+    credential.by_kid().is_ok_and(|by_kid| by_kid == *id_cred_x)
+        || credential
+            .by_value()
+            .is_ok_and(|by_value| by_value == *id_cred_x)
+}
+
 /// Generates a private key and some credential matching it.
 ///
 /// The 60 byte is kind of arbitrary; it's long enough for this, but needs to also accommodate
@@ -93,6 +173,17 @@ fn generate_credpair() -> (heapless::Vec<u8, 60>, lakers::BytesP256ElemLen) {
 }
 
 impl StoredPolicy {
+    /// Returns the Authorization Server configuration, after applying any pending reload.
+    fn authorization_servers(&self) -> core::cell::Ref<'_, ConfigBuilder> {
+        if let Some(reloaded) =
+            RELOADED_AUTHORIZATION_SERVERS.lock(|reloaded| reloaded.borrow_mut().take())
+        {
+            debug!("Applying reloaded Authorization Server keys.");
+            *self.authorization_servers.borrow_mut() = reloaded;
+        }
+        self.authorization_servers.borrow()
+    }
+
     async fn load() -> Self {
         // Storage format: ([u8], [u8; 32]), where the former is a CCS, and the latter the
         // corresponding key. We may need to extend the latter to be a COSE_Key when crypto agility
@@ -119,15 +210,91 @@ impl StoredPolicy {
             lakers::Credential::parse_ccs(&credential).expect("Processable by construction");
         let own_edhoc_credential = (credential, key);
 
+        runtime_peers::load().await;
+
         Self {
             own_edhoc_credential,
+            authorization_servers: RefCell::new(
+                load_authorization_servers()
+                    .await
+                    .expect("flash error prevents startup"),
+            ),
         }
     }
 }
 
+/// Storage key of the symmetric AES-CCM-16-128-256 key shared with an Authorization Server.
+///
+/// Storage format: `[u8; 32]`.
+const AS_KEY_AESCCM256: &str = "ariel-os-coap.as-key-aesccm256";
+
+/// Storage key of the ES256 signing key of an Authorization Server.
+///
+/// Storage format: `([u8; 32], [u8; 32], heapless::String<8>)`, which are the x and y coordinates
+/// of the public key, and the audience value with which tokens for this device are issued.
+const AS_KEY_ES256: &str = "ariel-os-coap.as-key-es256";
+
+/// Loads the Authorization Server keys, where keys in storage take precedence over the ones
+/// configured at build time.
+async fn load_authorization_servers() -> Result<ConfigBuilder, ()> {
+    let mut config = ConfigBuilder::new();
+
+    let aesccm256: Option<[u8; 32]> = ariel_os_storage::get(AS_KEY_AESCCM256)
+        .await
+        .map_err(drop)?;
+    if let Some(key) = aesccm256.or_else(flash_peers::as_key_aesccm256) {
+        debug!("Accepting tokens encrypted with AES-CCM-16-128-256.");
+        config = config.with_aif_symmetric_as_aesccm256(key);
+    }
+
+    let es256: Option<([u8; 32], [u8; 32], heapless::String<8>)> =
+        ariel_os_storage::get(AS_KEY_ES256).await.map_err(drop)?;
+    let es256 = es256.or_else(|| {
+        flash_peers::as_key_es256().map(|(x, y, audience)| {
+            let audience = audience.try_into().expect("length checked at build time");
+            (x, y, audience)
+        })
+    });
+    if let Some((x, y, audience)) = es256 {
+        match audience.as_str().try_into() {
+            Ok(audience) => {
+                debug!("Accepting tokens signed with ES256.");
+                config = config.with_aif_asymmetric_es256(x, y, audience);
+            }
+            Err(()) => error!("Audience of the ES256 Authorization Server is too long, ignoring."),
+        }
+    }
+
+    Ok(config)
+}
+
 #[derive(Debug)]
 struct StoredClaims {
     scope: coapcore::scope::UnionScope,
+    time_constraint: coapcore::time::TimeConstraint,
+    /// Set for peers provisioned at runtime, whose claims end when the peer is revoked.
+    revocation: Option<runtime_peers::Revocation>,
+}
+
+impl StoredClaims {
+    /// Claims for peers configured in the policy, which are not limited in time.
+    fn unbounded(scope: coapcore::scope::UnionScope) -> Self {
+        Self {
+            scope,
+            time_constraint: coapcore::time::TimeConstraint::unbounded(),
+            revocation: None,
+        }
+    }
+}
+
+impl From<ConfigBuilderClaims> for StoredClaims {
+    fn from(claims: ConfigBuilderClaims) -> Self {
+        Self {
+            scope: claims.scope,
+            time_constraint: claims.time_constraint,
+            revocation: None,
+        }
+    }
 }
 
 impl coapcore::GeneralClaims for StoredClaims {
@@ -138,7 +305,12 @@ impl coapcore::GeneralClaims for StoredClaims {
     }
 
     fn time_constraint(&self) -> coapcore::time::TimeConstraint {
-        coapcore::time::TimeConstraint::unbounded()
+        self.time_constraint
+    }
+
+    fn is_revoked(&self) -> bool {
+        self.revocation
+            .is_some_and(runtime_peers::Revocation::is_revoked)
     }
 
     fn is_important(&self) -> bool {
@@ -0,0 +1,395 @@
+//! Peers provisioned at runtime through the `/ariel/peers` resource.
+//!
+//! The peers are kept in RAM, where [`StoredPolicy`](super::StoredPolicy) looks them up, and are
+//! written to storage in a separate task (as CoAP handlers can not wait for storage).
+//!
+//! Each peer occupies a slot, stored as a single item under `ariel-os-coap.peer.{slot}` that holds
+//! both its credential (a CCS) and its scope (an AIF value), so that a credential is never stored
+//! next to the scope of a different one. Free slots have no item.
+//!
+//! Every change of a slot bumps its generation, which revokes the claims handed out for the
+//! slot's previous peer; security contexts established with them are discarded on their next use.
+
+use core::cell::RefCell;
+
+use ariel_os_debug::log::{debug, info};
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, MutableWritableMessage,
+    OptionNumber as _, ReadableMessage,
+};
+use coap_message_utils::Error as CoAPError;
+use embassy_sync::{
+    blocking_mutex::{CriticalSectionMutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Timer};
+
+const MAX_RUNTIME_PEERS: usize = 4;
+
+/// Storage keys of the peers, by slot.
+///
+/// Storage format: `(heapless::Vec<u8, _>, heapless::Vec<u8, _>)`, which are the credential and
+/// the scope.
+const PEER_KEYS: [&str; MAX_RUNTIME_PEERS] = [
+    "ariel-os-coap.peer.0",
+    "ariel-os-coap.peer.1",
+    "ariel-os-coap.peer.2",
+    "ariel-os-coap.peer.3",
+];
+
+/// Upper bound of a credential's length; the actual limit is imposed by [`fits_storage()`].
+const MAX_CREDENTIAL_LEN: usize = ariel_os_storage::DATA_BUFFER_SIZE;
+/// Maximum length of an AIF value accepted by [`coapcore::scope::AifValue`].
+const MAX_SCOPE_LEN: usize = 64;
+
+type Credential = heapless::Vec<u8, MAX_CREDENTIAL_LEN>;
+type Scope = heapless::Vec<u8, MAX_SCOPE_LEN>;
+
+#[derive(Clone)]
+struct RuntimePeer {
+    credential: Credential,
+    scope: Scope,
+}
+
+#[derive(Clone)]
+struct RuntimePeers {
+    slots: [Option<RuntimePeer>; MAX_RUNTIME_PEERS],
+    /// Number of changes of each slot, see [`Revocation`].
+    generations: [u32; MAX_RUNTIME_PEERS],
+    /// Bit mask of the slots that changed since they were last written to storage.
+    dirty: u8,
+}
+
+static PEERS: CriticalSectionMutex<RefCell<RuntimePeers>> =
+    CriticalSectionMutex::new(RefCell::new(RuntimePeers {
+        slots: [const { None }; MAX_RUNTIME_PEERS],
+        generations: [0; MAX_RUNTIME_PEERS],
+        dirty: 0,
+    }));
+
+/// Identifies the peer of a slot at the time claims were created for it.
+///
+/// The claims are revoked once the slot is changed, i.e. when the peer is revoked or its scope is
+/// replaced.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Revocation {
+    slot: usize,
+    generation: u32,
+}
+
+impl Revocation {
+    /// Returns whether the peer's slot changed since the claims were created.
+    pub(super) fn is_revoked(self) -> bool {
+        PEERS.lock(|peers| peers.borrow().generations.get(self.slot) != Some(&self.generation))
+    }
+}
+
+/// Time after which a failed write is retried.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Signaled whenever slots are marked dirty.
+static PERSIST: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Returns whether a peer with `credential` and `scope` can be stored.
+///
+/// Key and value of an item need to fit in the storage's data buffer together; this allows a few
+/// bytes for their length prefixes, and for those of the credential and the scope in the value.
+fn fits_storage(credential: &[u8], scope: &[u8]) -> bool {
+    PEER_KEYS[0].len() + credential.len() + scope.len() + 8 <= ariel_os_storage::DATA_BUFFER_SIZE
+}
+
+/// Loads the peers from storage.
+///
+/// This needs to run before the policy is used.
+pub(super) async fn load() {
+    let mut slots = [const { None }; MAX_RUNTIME_PEERS];
+    for (slot, key) in slots.iter_mut().zip(PEER_KEYS) {
+        let peer: Option<(Credential, Scope)> = ariel_os_storage::get(key)
+            .await
+            .expect("flash error prevents startup");
+        *slot = peer.map(|(credential, scope)| RuntimePeer { credential, scope });
+    }
+
+    PEERS.lock(|peers| peers.borrow_mut().slots = slots);
+}
+
+/// Writes changed slots to storage.
+///
+/// Slots that fail to be written stay dirty, and are retried after [`RETRY_DELAY`].
+#[ariel_os_macros::task(autostart)]
+async fn persist() {
+    loop {
+        PERSIST.wait().await;
+
+        let (slots, dirty) = PEERS.lock(|peers| {
+            let mut peers = peers.borrow_mut();
+            (peers.slots.clone(), core::mem::take(&mut peers.dirty))
+        });
+
+        let mut failed = 0;
+        for (slot, (peer, key)) in slots.into_iter().zip(PEER_KEYS).enumerate() {
+            if dirty & (1 << slot) == 0 {
+                continue;
+            }
+            let result = match peer {
+                Some(RuntimePeer { credential, scope }) => {
+                    ariel_os_storage::insert(key, (credential, scope)).await
+                }
+                None => ariel_os_storage::remove(key).await,
+            };
+            if result.is_err() {
+                // The change is effective in RAM regardless.
+                info!("Failed to store runtime peer in slot {}.", slot);
+                failed |= 1 << slot;
+            }
+        }
+
+        if failed != 0 {
+            PEERS.lock(|peers| peers.borrow_mut().dirty |= failed);
+            PERSIST.signal(());
+            Timer::after(RETRY_DELAY).await;
+        }
+    }
+}
+
+/// Returns the credential that matches `id_cred_x` along with its scope, if it was provisioned
+/// at runtime.
+pub(super) fn find(
+    id_cred_x: &lakers::IdCred,
+) -> Option<(lakers::Credential, coapcore::scope::UnionScope, Revocation)> {
+    let (credential, scope, revocation) = PEERS.lock(|peers| {
+        let peers = peers.borrow();
+        let mut slots = peers.slots.iter().zip(peers.generations).enumerate();
+        slots.find_map(|(slot, (peer, generation))| {
+            let peer = peer.as_ref()?;
+            let credential = lakers::Credential::parse_ccs(&peer.credential).ok()?;
+            let revocation = Revocation { slot, generation };
+            super::credential_matches(&credential, id_cred_x)
+                .then(|| (credential, peer.scope.clone(), revocation))
+        })
+    })?;
+    let scope = coapcore::scope::AifValue::parse(&scope).ok()?;
+    Some((credential, scope.into(), revocation))
+}
+
+/// Change requested for a single credential in an iPATCH request.
+struct Change<'a> {
+    credential: &'a [u8],
+    /// New scope, or `None` if the credential is revoked.
+    scope: Option<&'a [u8]>,
+}
+
+/// Parses an iPATCH payload, which is a CBOR map from credentials to scopes (or `null`).
+fn parse_changes(
+    payload: &[u8],
+) -> Result<heapless::Vec<Change<'_>, { 2 * MAX_RUNTIME_PEERS }>, CoAPError> {
+    use minicbor::data::Type;
+
+    let mut changes = heapless::Vec::new();
+    let mut decoder = minicbor::Decoder::new(payload);
+    let Ok(Some(len)) = decoder.map() else {
+        return Err(CoAPError::bad_request());
+    };
+    for _ in 0..len {
+        let credential = decoder.bytes().map_err(|_| CoAPError::bad_request())?;
+        if credential.len() > MAX_CREDENTIAL_LEN
+            || lakers::Credential::parse_ccs(credential).is_err()
+        {
+            debug!("Credential is unusable or too long.");
+            return Err(CoAPError::bad_request());
+        }
+
+        let scope = if matches!(decoder.datatype(), Ok(Type::Null)) {
+            decoder.null().map_err(|_| CoAPError::bad_request())?;
+            None
+        } else {
+            let start = decoder.position();
+            decoder.skip().map_err(|_| CoAPError::bad_request())?;
+            let scope = payload
+                .get(start..decoder.position())
+                .ok_or_else(CoAPError::bad_request)?;
+            if scope.len() > MAX_SCOPE_LEN || coapcore::scope::AifValue::parse(scope).is_err() {
+                debug!("Scope is not a usable AIF value.");
+                return Err(CoAPError::bad_request());
+            }
+            if !fits_storage(credential, scope) {
+                debug!("Credential is too long to be stored.");
+                return Err(CoAPError::bad_request());
+            }
+            Some(scope)
+        };
+
+        changes
+            .push(Change { credential, scope })
+            .map_err(|_| CoAPError::bad_request())?;
+    }
+    if decoder.position() != payload.len() {
+        return Err(CoAPError::bad_request());
+    }
+
+    Ok(changes)
+}
+
+impl RuntimePeers {
+    /// Applies the changes, marking the affected slots as dirty and revoking the claims of their
+    /// previous peers.
+    ///
+    /// Returns `Err(())` if there are no free slots left for a new credential, in which case some
+    /// changes may have been applied.
+    fn apply(&mut self, changes: &[Change<'_>]) -> Result<(), ()> {
+        for change in changes {
+            let existing = self.slots.iter().position(|slot| {
+                slot.as_ref()
+                    .is_some_and(|peer| peer.credential == change.credential)
+            });
+            let slot = match (existing, change.scope) {
+                (Some(slot), _) => slot,
+                (None, Some(_)) => self.slots.iter().position(Option::is_none).ok_or(())?,
+                // Revoking an unknown credential is a no-op.
+                (None, None) => continue,
+            };
+            #[allow(clippy::indexing_slicing, reason = "slot was found among the slots")]
+            {
+                self.slots[slot] = change.scope.map(|scope| RuntimePeer {
+                    credential: change
+                        .credential
+                        .try_into()
+                        .expect("length checked on parsing"),
+                    scope: scope.try_into().expect("length checked on parsing"),
+                });
+                self.generations[slot] = self.generations[slot].wrapping_add(1);
+            }
+            self.dirty |= 1 << slot;
+        }
+        Ok(())
+    }
+
+    /// Encodes the peers as a CBOR map from credentials to scopes into `buffer`.
+    fn encode(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<usize, minicbor::encode::Error<minicbor::encode::write::EndOfSlice>> {
+        use minicbor::encode::Write as _;
+
+        let mut encoder = minicbor::Encoder::new(minicbor::encode::write::Cursor::new(buffer));
+        encoder.map(self.slots.iter().flatten().count() as u64)?;
+        for peer in self.slots.iter().flatten() {
+            encoder.bytes(&peer.credential)?;
+            // The scope is CBOR already.
+            encoder
+                .writer_mut()
+                .write_all(&peer.scope)
+                .map_err(minicbor::encode::Error::write)?;
+        }
+        Ok(encoder.into_writer().position())
+    }
+
+    /// Upper bound of the length of [`.encode()`](Self::encode)'s output.
+    fn encoded_len(&self) -> usize {
+        // Map header, and for each entry, a byte string header and the scope.
+        1 + self
+            .slots
+            .iter()
+            .flatten()
+            .map(|peer| 3 + peer.credential.len() + peer.scope.len())
+            .sum::<usize>()
+    }
+}
+
+/// CoAP resource through which peers are provisioned at runtime.
+///
+/// A GET returns the provisioned peers as a CBOR map from credentials (CCSs in byte strings) to
+/// scopes (AIF values). An iPATCH with a map of the same shape adds peers or replaces their
+/// scopes; credentials mapped to `null` are revoked. Peers configured at build time are not
+/// affected.
+///
+/// Access to the resource is governed by the scopes of the security configuration like for any
+/// other resource.
+pub(crate) struct PeersResource;
+
+pub(crate) enum PeersRequest {
+    Get,
+    Changed,
+}
+
+impl coap_handler::Handler for PeersResource {
+    type RequestData = PeersRequest;
+    type ExtractRequestError = CoAPError;
+    type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+    fn extract_request_data<M: ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<PeersRequest, CoAPError> {
+        use coap_numbers::{code, option};
+
+        for o in request.options() {
+            match o.number() {
+                option::URI_PATH | option::ACCEPT | option::CONTENT_FORMAT => (),
+                // Odd option numbers are critical.
+                n if n % 2 == 1 => return Err(CoAPError::bad_option(n)),
+                _ => (),
+            }
+        }
+
+        let code: u8 = request.code().into();
+        match code {
+            code::GET => Ok(PeersRequest::Get),
+            code::IPATCH => {
+                let changes = parse_changes(request.payload())?;
+                // Applied to a copy, so that the request takes effect either fully or not at all.
+                PEERS
+                    .lock(|peers| {
+                        let mut updated = peers.borrow().clone();
+                        updated.apply(&changes)?;
+                        *peers.borrow_mut() = updated;
+                        Ok(())
+                    })
+                    .map_err(|()| {
+                        debug!("No free slot for runtime peer.");
+                        CoAPError::bad_request()
+                    })?;
+                PERSIST.signal(());
+                info!("Runtime peers changed.");
+                Ok(PeersRequest::Changed)
+            }
+            _ => Err(CoAPError::method_not_allowed()),
+        }
+    }
+
+    fn estimate_length(&mut self, request: &PeersRequest) -> usize {
+        match request {
+            PeersRequest::Get => PEERS.lock(|peers| peers.borrow().encoded_len()),
+            PeersRequest::Changed => 1,
+        }
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: PeersRequest,
+    ) -> Result<(), M::UnionError> {
+        use coap_numbers::{code, option};
+
+        match request {
+            PeersRequest::Get => {
+                let peers = PEERS.lock(|peers| peers.borrow().clone());
+                response.set_code(M::Code::new(code::CONTENT).map_err(Into::into)?);
+                // application/cbor
+                response.add_option(
+                    M::OptionNumber::new(option::CONTENT_FORMAT).map_err(Into::into)?,
+                    &[60],
+                )?;
+                let buffer = response.payload_mut_with_len(peers.encoded_len())?;
+                let len = peers
+                    .encode(buffer)
+                    .expect("buffer is sized to fit the encoded peers");
+                response.truncate(len)?;
+            }
+            PeersRequest::Changed => {
+                response.set_code(M::Code::new(code::CHANGED).map_err(Into::into)?);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+//! Resources provided by the operating system under `/ariel/`.
+//!
+//! Like any other resource, these are subject to the server's access policy: a client can only
+//! use them if its scope lists their paths.
+//!
+//! Resources that need to wait for hardware (storage keys and sensor readings) are served from a
+//! snapshot that is collected in a separate task (as CoAP handlers can not wait); the snapshot is
+//! taken at startup, and again after every GET request. A snapshot is only served for
+//! [`MAX_SNAPSHOT_AGE`] seconds after it was taken, with a Max-Age option limiting how long it can
+//! be cached; later requests get a 5.03 (Service Unavailable) response, with a Max-Age after which
+//! a new snapshot is expected to be available.
+
+#[cfg(any(feature = "sensors", feature = "storage"))]
+use core::cell::RefCell;
+
+use coap_handler_implementations::ReportingHandlerBuilder as _;
+use coap_message::{
+    Code as _, MessageOption as _, MinimalWritableMessage, MutableWritableMessage,
+    OptionNumber as _, ReadableMessage,
+};
+use coap_message_utils::Error as CoAPError;
+#[cfg(any(feature = "sensors", feature = "storage"))]
+use embassy_sync::{
+    blocking_mutex::{CriticalSectionMutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+#[cfg(any(feature = "sensors", feature = "storage"))]
+use embassy_time::Instant;
+
+// Rebooting is only offered where the access policy protects it.
+#[cfg(any(
+    feature = "coap-server-config-storage",
+    feature = "coap-server-config-demokeys"
+))]
+mod reboot;
+#[cfg(feature = "sensors")]
+mod sensors;
+#[cfg(feature = "storage")]
+mod storage_keys;
+
+/// Content-Format of application/cbor.
+const CBOR: u8 = 60;
+
+/// Largest length of the representations that are collected by tasks.
+///
+/// On BLE, a message needs to fit into an attribute value, together with its header, options and
+/// the overhead of OSCORE.
+#[cfg(feature = "coap-transport-ble")]
+const MAX_COLLECTED_LEN: usize = crate::transport_ble::MAX_MESSAGE_LEN - 128;
+#[cfg(not(feature = "coap-transport-ble"))]
+const MAX_COLLECTED_LEN: usize = 512;
+
+/// A representation collected by a task, encoded.
+#[cfg(any(feature = "sensors", feature = "storage"))]
+type Encoded = heapless::Vec<u8, MAX_COLLECTED_LEN>;
+
+/// Seconds for which a collected snapshot is served.
+#[cfg(any(feature = "sensors", feature = "storage"))]
+const MAX_SNAPSHOT_AGE: u32 = 5;
+
+/// Seconds after which a client should retry when no fresh snapshot is available.
+#[cfg(any(feature = "sensors", feature = "storage"))]
+const RETRY_AFTER: u32 = 1;
+
+/// A representation that is collected by a task, see the [module level documentation](self).
+#[cfg(any(feature = "sensors", feature = "storage"))]
+struct Collected {
+    /// The most recently collected representation, and when it was collected.
+    snapshot: CriticalSectionMutex<RefCell<Option<(Instant, Encoded)>>>,
+    /// Signaled when the representation should be collected again.
+    refresh: Signal<CriticalSectionRawMutex, ()>,
+}
+
+#[cfg(any(feature = "sensors", feature = "storage"))]
+impl Collected {
+    const fn new() -> Self {
+        Self {
+            snapshot: CriticalSectionMutex::new(RefCell::new(None)),
+            refresh: Signal::new(),
+        }
+    }
+
+    /// Returns for how long the most recently collected representation is fresh, and requests a
+    /// new collection.
+    fn freshness(&self) -> Freshness {
+        self.refresh.signal(());
+        self.snapshot.lock(|snapshot| {
+            let Some((collected, _)) = *snapshot.borrow() else {
+                return Freshness::Unavailable(RETRY_AFTER);
+            };
+            let age = u32::try_from(collected.elapsed().as_secs()).unwrap_or(u32::MAX);
+            if age >= MAX_SNAPSHOT_AGE {
+                return Freshness::Unavailable(RETRY_AFTER);
+            }
+            Freshness::For(MAX_SNAPSHOT_AGE - age)
+        })
+    }
+
+    /// Copies the most recently collected representation into `buffer`.
+    fn render(&self, buffer: &mut [u8]) -> Option<usize> {
+        self.snapshot.lock(|snapshot| {
+            let snapshot = snapshot.borrow();
+            let (_, encoded) = snapshot.as_ref()?;
+            buffer.get_mut(..encoded.len())?.copy_from_slice(encoded);
+            Some(encoded.len())
+        })
+    }
+
+    /// Stores a newly collected representation.
+    fn store(&self, encoded: Encoded) {
+        self.snapshot
+            .lock(|snapshot| *snapshot.borrow_mut() = Some((Instant::now(), encoded)));
+    }
+
+    /// Waits until the representation should be collected again.
+    async fn wait_refresh(&self) {
+        self.refresh.wait().await;
+    }
+}
+
+/// Adds the system resources to a handler.
+pub(crate) fn with_system_resources(
+    handler: impl coap_handler::Handler + coap_handler::Reporting,
+) -> impl coap_handler::Handler + coap_handler::Reporting {
+    let handler = handler
+        .at_with_attributes(
+            &["ariel", "buildinfo"],
+            &[],
+            ReadOnly {
+                content_format: CBOR,
+                max_len: 16 + ariel_os_buildinfo::OS_NAME.len() + ariel_os_buildinfo::BOARD.len(),
+                freshness: || Freshness::Default,
+                render: render_buildinfo,
+            },
+        )
+        .at_with_attributes(
+            &["ariel", "identity"],
+            &[],
+            ReadOnly {
+                content_format: CBOR,
+                max_len: IDENTITY_MAX_LEN,
+                freshness: || Freshness::Default,
+                render: render_identity,
+            },
+        );
+
+    #[cfg(any(
+        feature = "coap-server-config-storage",
+        feature = "coap-server-config-demokeys"
+    ))]
+    let handler = handler.at_with_attributes(&["ariel", "reboot"], &[], reboot::RebootResource);
+
+    #[cfg(feature = "storage")]
+    let handler = handler.at_with_attributes(
+        &["ariel", "storage"],
+        &[],
+        ReadOnly {
+            content_format: CBOR,
+            max_len: storage_keys::MAX_ENCODED_LEN,
+            freshness: storage_keys::freshness,
+            render: storage_keys::render,
+        },
+    );
+
+    #[cfg(feature = "sensors")]
+    let handler = handler.at_with_attributes(
+        &["ariel", "sensors"],
+        &[],
+        ReadOnly {
+            content_format: sensors::SENML_CBOR,
+            max_len: sensors::MAX_ENCODED_LEN,
+            freshness: sensors::freshness,
+            render: sensors::render,
+        },
+    );
+
+    handler
+}
+
+/// Encodes the build information as a CBOR map with the keys "os" and "board".
+fn render_buildinfo(buffer: &mut [u8]) -> Option<usize> {
+    let mut encoder = minicbor::Encoder::new(minicbor::encode::write::Cursor::new(buffer));
+    encoder
+        .map(2)
+        .and_then(|e| e.str("os"))
+        .and_then(|e| e.str(ariel_os_buildinfo::OS_NAME))
+        .and_then(|e| e.str("board"))
+        .and_then(|e| e.str(ariel_os_buildinfo::BOARD))
+        .ok()?;
+    Some(encoder.into_writer().position())
+}
+
+/// Upper bound of the encoded device identity.
+const IDENTITY_MAX_LEN: usize = 2 + 32;
+
+/// Encodes the device identity as a CBOR byte string.
+///
+/// This is unavailable on devices without a device identity.
+fn render_identity(buffer: &mut [u8]) -> Option<usize> {
+    let id = ariel_os_identity::device_id_bytes().ok()?;
+    let mut encoder = minicbor::Encoder::new(minicbor::encode::write::Cursor::new(buffer));
+    encoder.bytes(id.as_ref()).ok()?;
+    Some(encoder.into_writer().position())
+}
+
+/// A resource that only supports GET, and whose representation is produced by a function.
+struct ReadOnly {
+    content_format: u8,
+    /// Upper bound of the length of `render`'s output.
+    max_len: usize,
+    /// Returns for how long the representation is fresh; called before `render`.
+    freshness: fn() -> Freshness,
+    /// Writes the representation into the buffer, returning its length, or `None` if the
+    /// representation is unavailable.
+    render: fn(&mut [u8]) -> Option<usize>,
+}
+
+/// For how long the representation of a [`ReadOnly`] resource is fresh (the Max-Age option).
+enum Freshness {
+    /// For CoAP's default of 60 seconds.
+    Default,
+    /// For the given number of seconds.
+    For(u32),
+    /// No fresh representation is available; one is expected after the given number of seconds.
+    Unavailable(u32),
+}
+
+/// Checks that a request has no unprocessed critical options, and returns its code.
+fn check_options(request: &impl ReadableMessage) -> Result<u8, CoAPError> {
+    use coap_numbers::option;
+
+    for o in request.options() {
+        match o.number() {
+            option::URI_PATH | option::ACCEPT => (),
+            // Odd option numbers are critical.
+            n if n % 2 == 1 => return Err(CoAPError::bad_option(n)),
+            _ => (),
+        }
+    }
+    Ok(request.code().into())
+}
+
+impl coap_handler::Handler for ReadOnly {
+    type RequestData = ();
+    type ExtractRequestError = CoAPError;
+    type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+    fn extract_request_data<M: ReadableMessage>(&mut self, request: &M) -> Result<(), CoAPError> {
+        if check_options(request)? != coap_numbers::code::GET {
+            return Err(CoAPError::method_not_allowed());
+        }
+        Ok(())
+    }
+
+    fn estimate_length(&mut self, _request: &()) -> usize {
+        self.max_len
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        _request: (),
+    ) -> Result<(), M::UnionError> {
+        use coap_numbers::{code, option};
+
+        let max_age = match (self.freshness)() {
+            Freshness::Default => None,
+            Freshness::For(seconds) => Some(seconds),
+            Freshness::Unavailable(seconds) => {
+                response.set_code(M::Code::new(code::SERVICE_UNAVAILABLE).map_err(Into::into)?);
+                add_max_age(response, seconds)?;
+                return Ok(());
+            }
+        };
+        response.set_code(M::Code::new(code::CONTENT).map_err(Into::into)?);
+        response.add_option(
+            M::OptionNumber::new(option::CONTENT_FORMAT).map_err(Into::into)?,
+            &[self.content_format],
+        )?;
+        if let Some(seconds) = max_age {
+            add_max_age(response, seconds)?;
+        }
+        let buffer = response.payload_mut_with_len(self.max_len)?;
+        if let Some(len) = (self.render)(buffer) {
+            response.truncate(len)?;
+        } else {
+            response.truncate(0)?;
+            response.set_code(M::Code::new(code::SERVICE_UNAVAILABLE).map_err(Into::into)?);
+        }
+        Ok(())
+    }
+}
+
+/// Adds a Max-Age option of `seconds` to `response`.
+fn add_max_age<M: MinimalWritableMessage>(
+    response: &mut M,
+    seconds: u32,
+) -> Result<(), M::UnionError> {
+    // A uint option, without leading zero bytes.
+    let bytes = seconds.to_be_bytes();
+    let leading_zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    response.add_option(
+        M::OptionNumber::new(coap_numbers::option::MAX_AGE).map_err(Into::into)?,
+        bytes.get(leading_zeros..).unwrap_or_default(),
+    )
+}
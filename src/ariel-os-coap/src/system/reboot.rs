@@ -0,0 +1,49 @@
+//! The `/ariel/reboot` resource, rebooting the device on a POST request.
+
+use coap_message::{Code as _, MinimalWritableMessage, MutableWritableMessage, ReadableMessage};
+use coap_message_utils::Error as CoAPError;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+
+use super::check_options;
+
+/// Signaled when a reboot was requested.
+static REBOOT: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Resource that reboots the device on a POST request.
+pub(super) struct RebootResource;
+
+impl coap_handler::Handler for RebootResource {
+    type RequestData = ();
+    type ExtractRequestError = CoAPError;
+    type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+    fn extract_request_data<M: ReadableMessage>(&mut self, request: &M) -> Result<(), CoAPError> {
+        if check_options(request)? != coap_numbers::code::POST {
+            return Err(CoAPError::method_not_allowed());
+        }
+        REBOOT.signal(());
+        Ok(())
+    }
+
+    fn estimate_length(&mut self, _request: &()) -> usize {
+        1
+    }
+
+    fn build_response<M: MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        _request: (),
+    ) -> Result<(), M::UnionError> {
+        response.set_code(M::Code::new(coap_numbers::code::CHANGED).map_err(Into::into)?);
+        Ok(())
+    }
+}
+
+#[ariel_os_macros::task(autostart)]
+async fn reboot() {
+    REBOOT.wait().await;
+    ariel_os_debug::log::info!("Rebooting as requested through CoAP.");
+    // Gives the CoAP server time to send the response.
+    embassy_time::Timer::after_millis(500).await;
+    ariel_os_power::reboot();
+}
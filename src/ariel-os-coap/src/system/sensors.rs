@@ -0,0 +1,219 @@
+//! The `/ariel/sensors` resource, reporting the readings of all registered sensors.
+//!
+//! The representation is a SenML pack in CBOR ([RFC8428]) with one record per reading channel.
+//! Records are named `{sensor}/{channel}`, where `sensor` is the sensor's position in the registry
+//! and `channel` is derived from the channel's label; channels that are opaque or currently
+//! unavailable are not reported.
+//!
+//! [RFC8428]: https://www.rfc-editor.org/rfc/rfc8428
+
+use core::fmt::Write as _;
+
+use ariel_os_debug::log::debug;
+use ariel_os_sensors::{Label, MeasurementUnit, Reading as _};
+use ariel_os_sensors_registry::REGISTRY;
+use embassy_time::{Duration, with_timeout};
+
+use super::{Collected, Encoded, Freshness};
+
+/// Content-Format of application/senml+cbor.
+pub(super) const SENML_CBOR: u8 = 112;
+
+/// Largest length of the encoded pack; records that do not fit are not reported.
+pub(super) const MAX_ENCODED_LEN: usize = super::MAX_COLLECTED_LEN;
+
+/// Largest length of an encoded record.
+const MAX_RECORD_LEN: usize = 64;
+
+/// Time after which a sensor that was triggered is skipped if it did not provide a reading.
+const READING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// SenML labels (RFC8428 Section 6).
+const SENML_NAME: i8 = 0;
+const SENML_UNIT: i8 = 1;
+const SENML_VALUE: i8 = 2;
+
+/// The most recently collected pack.
+static READINGS: Collected = Collected::new();
+
+/// Returns for how long the most recently collected pack is fresh, and requests a new collection.
+pub(super) fn freshness() -> Freshness {
+    READINGS.freshness()
+}
+
+/// Copies the most recently collected pack into `buffer`.
+pub(super) fn render(buffer: &mut [u8]) -> Option<usize> {
+    READINGS.render(buffer)
+}
+
+#[ariel_os_macros::task(autostart)]
+async fn collect() {
+    loop {
+        READINGS.store(read_all().await);
+        READINGS.wait_refresh().await;
+    }
+}
+
+/// Reads all registered sensors, and encodes their readings.
+async fn read_all() -> Encoded {
+    // An indefinite-length array, as the number of records is only known after reading.
+    let mut encoded = Encoded::new();
+    encoded
+        .push(0x9f)
+        .unwrap_or_else(|_| unreachable!("buffer is not empty"));
+    for (index, sensor) in REGISTRY.sensors().enumerate() {
+        // Sensors are read one at a time, as waiting for a sensor whose measurement was not
+        // triggered would not end.
+        if let Err(e) = sensor.trigger_measurement() {
+            debug!("Error when triggering a measurement: {}", e);
+            continue;
+        }
+        let samples = match with_timeout(READING_TIMEOUT, sensor.wait_for_reading()).await {
+            Ok(Ok(samples)) => samples,
+            Ok(Err(e)) => {
+                debug!("Error when reading: {}", e);
+                continue;
+            }
+            Err(_) => {
+                debug!("Sensor did not provide a reading in time");
+                continue;
+            }
+        };
+        for (channel, sample) in samples.samples() {
+            let (Some(label), Ok(value)) = (label_name(channel.label()), sample.value()) else {
+                continue;
+            };
+            let mut record = [0; MAX_RECORD_LEN];
+            let Some(record) = encode_record(
+                &mut record,
+                index,
+                label,
+                unit_symbol(channel.unit()),
+                scaled(value, channel.scaling()),
+            ) else {
+                continue;
+            };
+            // One byte is left for the final break.
+            if encoded.len() + record.len() < MAX_ENCODED_LEN {
+                encoded
+                    .extend_from_slice(record)
+                    .unwrap_or_else(|()| unreachable!("length was checked"));
+            }
+        }
+    }
+    encoded
+        .push(0xff)
+        .unwrap_or_else(|_| unreachable!("space was left for the break"));
+    encoded
+}
+
+/// Encodes a SenML record into `buffer`, returning the used part.
+fn encode_record<'b>(
+    buffer: &'b mut [u8; MAX_RECORD_LEN],
+    sensor: usize,
+    label: &str,
+    unit: Option<&str>,
+    value: f32,
+) -> Option<&'b [u8]> {
+    let mut name = heapless::String::<32>::new();
+    write!(name, "{sensor}/{label}").ok()?;
+
+    let mut encoder = minicbor::Encoder::new(minicbor::encode::write::Cursor::new(&mut buffer[..]));
+    encoder
+        .map(if unit.is_some() { 3 } else { 2 })
+        .and_then(|e| e.i8(SENML_NAME))
+        .and_then(|e| e.str(&name))
+        .ok()?;
+    if let Some(unit) = unit {
+        encoder.i8(SENML_UNIT).and_then(|e| e.str(unit)).ok()?;
+    }
+    encoder.i8(SENML_VALUE).and_then(|e| e.f32(value)).ok()?;
+    let len = encoder.into_writer().position();
+    buffer.get(..len)
+}
+
+/// Applies a channel's scaling (a power of ten) to a sample's value.
+#[allow(
+    clippy::cast_precision_loss,
+    reason = "SenML values are floating point numbers anyway"
+)]
+fn scaled(value: i32, scaling: i8) -> f32 {
+    let mut value = value as f32;
+    for _ in 0..scaling.unsigned_abs() {
+        if scaling > 0 {
+            value *= 10.0;
+        } else {
+            value /= 10.0;
+        }
+    }
+    value
+}
+
+/// Returns the part of a record name that describes the channel.
+fn label_name(label: Label) -> Option<&'static str> {
+    Some(match label {
+        Label::AccelerationX => "acceleration-x",
+        Label::AccelerationY => "acceleration-y",
+        Label::AccelerationZ => "acceleration-z",
+        Label::Altitude => "altitude",
+        Label::AngularVelocityX => "angular-velocity-x",
+        Label::AngularVelocityY => "angular-velocity-y",
+        Label::AngularVelocityZ => "angular-velocity-z",
+        Label::Co2 => "co2",
+        Label::GroundSpeed => "ground-speed",
+        Label::Latitude => "latitude",
+        Label::Longitude => "longitude",
+        Label::Pressure => "pressure",
+        Label::RelativeHumidity => "relative-humidity",
+        Label::Heading => "heading",
+        Label::Temperature => "temperature",
+        Label::VerticalSpeed => "vertical-speed",
+        Label::X => "x",
+        Label::Y => "y",
+        Label::Z => "z",
+        // Opaque channels, and any added later
+        _ => return None,
+    })
+}
+
+/// Returns the SenML unit symbol of a measurement unit, if one is registered.
+fn unit_symbol(unit: MeasurementUnit) -> Option<&'static str> {
+    Some(match unit {
+        MeasurementUnit::Ampere => "A",
+        MeasurementUnit::Becquerel => "Bq",
+        MeasurementUnit::Candela => "cd",
+        MeasurementUnit::Celsius => "Cel",
+        MeasurementUnit::Coulomb => "C",
+        MeasurementUnit::Decibel => "dB",
+        MeasurementUnit::Farad => "F",
+        MeasurementUnit::Gram => "g",
+        MeasurementUnit::Gray => "Gy",
+        MeasurementUnit::Henry => "H",
+        MeasurementUnit::Hertz => "Hz",
+        MeasurementUnit::Joule => "J",
+        MeasurementUnit::Katal => "kat",
+        MeasurementUnit::Kelvin => "K",
+        MeasurementUnit::Lumen => "lm",
+        MeasurementUnit::Lux => "lx",
+        MeasurementUnit::Meter => "m",
+        MeasurementUnit::MeterPerSecond => "m/s",
+        MeasurementUnit::Mole => "mol",
+        MeasurementUnit::Newton => "N",
+        MeasurementUnit::Ohm => "Ohm",
+        MeasurementUnit::PartsPerMillion => "ppm",
+        MeasurementUnit::Pascal => "Pa",
+        MeasurementUnit::Percent => "%",
+        MeasurementUnit::PercentageRelativeHumidity => "%RH",
+        MeasurementUnit::Radian => "rad",
+        MeasurementUnit::Second => "s",
+        MeasurementUnit::Siemens => "S",
+        MeasurementUnit::Sievert => "Sv",
+        MeasurementUnit::Steradian => "sr",
+        MeasurementUnit::Tesla => "T",
+        MeasurementUnit::Volt => "V",
+        MeasurementUnit::Watt => "W",
+        MeasurementUnit::Weber => "Wb",
+        // Units without a SenML symbol (e.g., acceleration in g), and any added later
+        _ => return None,
+    })
+}
@@ -0,0 +1,69 @@
+//! The `/ariel/storage` resource, listing the keys present in storage.
+//!
+//! The representation is a CBOR array of the keys as text strings. Keys that do not fit into
+//! [`MAX_ENCODED_LEN`] are not listed.
+
+use ariel_os_debug::log::info;
+
+use super::{Collected, Encoded, Freshness};
+
+/// Largest number of keys that are listed.
+const MAX_KEYS: usize = 16;
+
+/// Largest length of the encoded list.
+pub(super) const MAX_ENCODED_LEN: usize = super::MAX_COLLECTED_LEN;
+
+/// The most recently collected list.
+static KEYS: Collected = Collected::new();
+
+/// Returns for how long the most recently collected list is fresh, and requests a new collection.
+pub(super) fn freshness() -> Freshness {
+    KEYS.freshness()
+}
+
+/// Copies the most recently collected list into `buffer`.
+pub(super) fn render(buffer: &mut [u8]) -> Option<usize> {
+    KEYS.render(buffer)
+}
+
+/// Encodes as many of the keys as fit.
+fn encode<K: AsRef<str>>(keys: &[K]) -> Encoded {
+    // Array header, and for each key, a text string header of up to 2 bytes and the key.
+    let mut len = 1;
+    let count = keys
+        .iter()
+        .take_while(|key| {
+            len += 2 + key.as_ref().len();
+            len <= MAX_ENCODED_LEN
+        })
+        .count();
+
+    let mut buffer = [0; MAX_ENCODED_LEN];
+    let mut encoder = minicbor::Encoder::new(minicbor::encode::write::Cursor::new(&mut buffer[..]));
+    encoder
+        .array(count as u64)
+        .expect("buffer is sized for the keys");
+    for key in keys.iter().take(count) {
+        encoder
+            .str(key.as_ref())
+            .expect("buffer is sized for the keys");
+    }
+    let len = encoder.into_writer().position();
+    #[allow(
+        clippy::indexing_slicing,
+        reason = "length was produced by the encoder"
+    )]
+    Encoded::from_slice(&buffer[..len])
+        .unwrap_or_else(|()| unreachable!("buffer has the same size"))
+}
+
+#[ariel_os_macros::task(autostart)]
+async fn collect() {
+    loop {
+        match ariel_os_storage::keys::<MAX_KEYS>().await {
+            Ok(keys) => KEYS.store(encode(&keys)),
+            Err(_) => info!("Failed to list storage keys."),
+        }
+        KEYS.wait_refresh().await;
+    }
+}
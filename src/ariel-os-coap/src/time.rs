@@ -0,0 +1,90 @@
+//! Wall-clock time used to enforce the time constraints of ACE tokens.
+//!
+//! Tokens are only rejected as expired (or as not yet valid) as far as the system knows the
+//! current time. The system starts out knowing nothing about the time; it learns about it
+//!
+//! * from the application through [`set_unix_time()`], for example after an SNTP exchange or from
+//!   reading an RTC, and
+//! * from the issue time of tokens accepted from a trusted Authorization Server, which is a lower
+//!   bound of the current time.
+//!
+//! Drift of the system timer since the time was learned is not accounted for.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_time::Instant;
+
+/// A Unix time in seconds at a given [`Instant`].
+type Anchor = (Instant, u64);
+
+/// What is known about the current time.
+#[derive(Copy, Clone)]
+struct Knowledge {
+    /// Time set by the application.
+    set: Option<Anchor>,
+    /// Lower bound of the time, learned from trusted sources.
+    lower_bound: Option<Anchor>,
+}
+
+static KNOWLEDGE: CriticalSectionMutex<Cell<Knowledge>> =
+    CriticalSectionMutex::new(Cell::new(Knowledge {
+        set: None,
+        lower_bound: None,
+    }));
+
+/// Returns the Unix time at `anchor`, advanced by the time elapsed since.
+fn advance((at, seconds): Anchor) -> u64 {
+    seconds.saturating_add(at.elapsed().as_secs())
+}
+
+/// Sets the current wall-clock time, given as Unix time in seconds.
+///
+/// This is typically called after an SNTP exchange, or at startup with the time read from an RTC.
+/// It may be called again at any time, for example to correct drift.
+pub fn set_unix_time(seconds: u64) {
+    KNOWLEDGE.lock(|knowledge| {
+        knowledge.set(Knowledge {
+            set: Some((Instant::now(), seconds)),
+            ..knowledge.get()
+        });
+    });
+}
+
+/// Returns the current wall-clock time as an interval of Unix time in seconds.
+///
+/// The upper bound is `None` if the time was never set through [`set_unix_time()`]; the lower
+/// bound is 0 if nothing is known about the time.
+pub fn unix_time() -> (u64, Option<u64>) {
+    let knowledge = KNOWLEDGE.lock(Cell::get);
+    let lower_bound = knowledge.lower_bound.map_or(0, advance);
+    match knowledge.set.map(advance) {
+        // A set time that is earlier than a trusted lower bound is wrong.
+        Some(now) => (now.max(lower_bound), Some(now.max(lower_bound))),
+        None => (lower_bound, None),
+    }
+}
+
+/// A [`TimeProvider`](coapcore::time::TimeProvider) backed by the system's knowledge of the
+/// wall-clock time.
+///
+/// See the [module level documentation](self) for how the system learns the time.
+pub struct SystemTime;
+
+impl coapcore::time::TimeProvider for SystemTime {
+    fn now(&mut self) -> (u64, Option<u64>) {
+        unix_time()
+    }
+
+    fn past_trusted(&mut self, timestamp: u64) {
+        KNOWLEDGE.lock(|knowledge| {
+            let current = knowledge.get();
+            if current.lower_bound.map_or(0, advance) < timestamp {
+                knowledge.set(Knowledge {
+                    lower_bound: Some((Instant::now(), timestamp)),
+                    ..current
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,194 @@
+//! Transport implementation for CoAP-over-GATT.
+//!
+//! This follows [draft-amsuess-core-coap-over-gatt]: The server offers a single characteristic;
+//! a client writes a request into it, and reads the response from it (or receives it as a
+//! notification). Messages are serialized as their code followed by their options and payload;
+//! they carry neither length nor token, as only one request is processed at a time.
+//!
+//! Only one client is served at a time.
+//!
+//! [draft-amsuess-core-coap-over-gatt]: https://datatracker.ietf.org/doc/draft-amsuess-core-coap-over-gatt/
+
+use ariel_os_debug::log::{Debug2Format, debug, error, info};
+use embassy_futures::select::{Either, select};
+use embassy_time::Timer;
+use trouble_host::prelude::*;
+
+use crate::respond::respond;
+
+/// Largest message that is processed, which is the largest value of an attribute.
+pub(crate) const MAX_MESSAGE_LEN: usize = 512;
+
+/// The CoAP service's UUID, in the little-endian form used in advertisements.
+const SERVICE_UUID_LE: [u8; 16] = [
+    0xbc, 0x36, 0xa2, 0x40, 0xfb, 0xf8, 0xfa, 0x9d, 0x6d, 0x49, 0x00, 0x33, 0xb7, 0x04, 0xf8, 0x8d,
+];
+
+/// Name under which the device advertises.
+const NAME: &str = "Ariel OS";
+
+type Message = heapless::Vec<u8, MAX_MESSAGE_LEN>;
+
+#[gatt_server]
+struct Server {
+    coap: CoapService,
+}
+
+#[gatt_service(uuid = "8df804b7-3300-496d-9dfa-f8fb40a236bc")]
+struct CoapService {
+    #[characteristic(uuid = "2a58fc3f-3c62-4ecc-8167-d66d4d9410c2", read, write, notify)]
+    message: Message,
+}
+
+/// Runs the CoAP handler on CoAP-over-GATT indefinitely.
+///
+/// # Panics
+///
+/// This can only be run once, as it takes the system's BLE stack.
+pub(crate) async fn coap_run_ble(mut handler: impl coap_handler::Handler) -> ! {
+    let stack = ariel_os_embassy::ble::ble_stack().await;
+    let Host {
+        mut peripheral,
+        mut runner,
+        ..
+    } = stack.build();
+
+    let server = Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
+        name: NAME,
+        appearance: &appearance::UNKNOWN,
+    }))
+    .expect("attribute table is sized for the service");
+
+    let mut adv_data = [0; 31];
+    let adv_len = AdStructure::encode_slice(
+        &[
+            AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+            AdStructure::ServiceUuids128(&[SERVICE_UUID_LE]),
+        ],
+        &mut adv_data[..],
+    )
+    .expect("advertisement fits");
+    let mut scan_data = [0; 31];
+    let scan_len = AdStructure::encode_slice(
+        &[AdStructure::CompleteLocalName(NAME.as_bytes())],
+        &mut scan_data[..],
+    )
+    .expect("scan response fits");
+    #[allow(
+        clippy::indexing_slicing,
+        reason = "lengths were produced by the encoder"
+    )]
+    let (adv_data, scan_data) = (&adv_data[..adv_len], &scan_data[..scan_len]);
+
+    info!("Starting up CoAP-over-GATT server");
+
+    let serve = async {
+        loop {
+            let advertiser = match peripheral
+                .advertise(
+                    &AdvertisementParameters::default(),
+                    Advertisement::ConnectableScannableUndirected {
+                        adv_data,
+                        scan_data,
+                    },
+                )
+                .await
+            {
+                Ok(advertiser) => advertiser,
+                Err(e) => {
+                    error!("Failed to advertise: {:?}", Debug2Format(&e));
+                    Timer::after_secs(1).await;
+                    continue;
+                }
+            };
+            let connection = match advertiser.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    info!("Failed to accept connection: {:?}", Debug2Format(&e));
+                    continue;
+                }
+            };
+            match connection.with_attribute_server(&server) {
+                Ok(connection) => serve_connection(&server, &connection, &mut handler).await,
+                Err(e) => error!("Failed to serve connection: {:?}", Debug2Format(&e)),
+            }
+        }
+    };
+
+    let Either::First(result) = select(runner.run(), serve).await;
+    if let Err(e) = result {
+        error!("BLE host failed: {:?}", Debug2Format(&e));
+    }
+    panic!("BLE host stopped");
+}
+
+/// Serves requests on an established connection until it is closed.
+async fn serve_connection<P: PacketPool>(
+    server: &Server<'_>,
+    connection: &GattConnection<'_, '_, P>,
+    handler: &mut impl coap_handler::Handler,
+) {
+    let characteristic = server.coap.message;
+    loop {
+        match connection.next().await {
+            GattConnectionEvent::Disconnected { reason } => {
+                debug!(
+                    "CoAP-over-GATT client disconnected: {:?}",
+                    Debug2Format(&reason)
+                );
+                return;
+            }
+            GattConnectionEvent::Gatt { event } => {
+                let response = match &event {
+                    GattEvent::Write(write) if write.handle() == characteristic.handle => {
+                        process(handler, write.data())
+                    }
+                    _ => None,
+                };
+
+                match event.accept() {
+                    Ok(reply) => reply.send().await,
+                    Err(e) => error!("Failed to accept GATT event: {:?}", Debug2Format(&e)),
+                }
+
+                if let Some(response) = response {
+                    if let Err(e) = server.set(&characteristic, &response) {
+                        error!("Failed to set response: {:?}", Debug2Format(&e));
+                    }
+                    // Clients that did not subscribe read the response instead.
+                    if characteristic.notify(connection, &response).await.is_err() {
+                        debug!("Response was not sent as a notification.");
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Processes a message written by the client, returning the response if it was a request.
+fn process(handler: &mut impl coap_handler::Handler, message: &[u8]) -> Option<Message> {
+    let Some((&code, options_and_payload)) = message.split_first() else {
+        debug!("Ignoring empty message.");
+        return None;
+    };
+    if !(1..=31).contains(&code) {
+        debug!("Ignoring non-request message.");
+        return None;
+    }
+
+    let mut response = Message::new();
+    response
+        .resize_default(MAX_MESSAGE_LEN)
+        .unwrap_or_else(|()| unreachable!("resized to capacity"));
+    let (response_code, response_tail) = response.split_first_mut().expect("buffer is not empty");
+    let response_len = respond(
+        handler,
+        code,
+        options_and_payload,
+        response_code,
+        response_tail,
+    );
+    response.truncate(1 + response_len);
+    Some(response)
+}
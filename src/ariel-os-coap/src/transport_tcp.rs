@@ -0,0 +1,253 @@
+//! Transport implementation for CoAP-over-TCP ([RFC8323](https://www.rfc-editor.org/rfc/rfc8323)).
+//!
+//! Up to [`CONCURRENT_REQUESTS`] connections are served at the same time, each on a socket of its
+//! own, which the network stack needs to have room for. A connection that stays idle for
+//! [`IDLE_TIMEOUT`] is released so that other clients get a chance to connect.
+
+use core::cell::RefCell;
+
+use ariel_os_debug::log::{Debug2Format, debug, info};
+use ariel_os_embassy::net::NetworkStack;
+use embassy_futures::join::join_array;
+use embassy_net::tcp::TcpSocket;
+use embassy_time::{Duration, with_timeout};
+use embedded_io_async::{Read as _, ReadExactError, Write as _};
+
+use crate::respond::respond;
+use crate::{CONCURRENT_REQUESTS, PORT};
+
+/// Time after which an idle connection is released.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest accepted length of a message's options and payload.
+///
+/// This is announced to the peer as Max-Message-Size, and is the default of RFC8323.
+const MAX_MESSAGE_SIZE: usize = 1152;
+
+/// Largest token length defined in RFC8323.
+const MAX_TKL: usize = 8;
+
+/// Length of the longest header: Len and TKL, extended length, code and token.
+const MAX_HEADER_LEN: usize = 1 + 4 + 1 + MAX_TKL;
+
+/// Signaling codes (RFC8323 Section 5).
+const CSM: u8 = 0xe1;
+const PING: u8 = 0xe2;
+const PONG: u8 = 0xe3;
+const RELEASE: u8 = 0xe4;
+const ABORT: u8 = 0xe5;
+
+/// Max-Message-Size option of CSM messages.
+const MAX_MESSAGE_SIZE_OPTION: u8 = 2;
+
+type Token = heapless::Vec<u8, MAX_TKL>;
+
+enum ReadError {
+    /// The peer closed the connection.
+    Closed,
+    /// The message can not be processed; the connection needs to be aborted.
+    Unprocessable,
+    Tcp(embassy_net::tcp::Error),
+}
+
+impl From<ReadExactError<embassy_net::tcp::Error>> for ReadError {
+    fn from(e: ReadExactError<embassy_net::tcp::Error>) -> Self {
+        match e {
+            ReadExactError::UnexpectedEof => ReadError::Closed,
+            ReadExactError::Other(e) => ReadError::Tcp(e),
+        }
+    }
+}
+
+/// Runs the CoAP handler on CoAP-over-TCP indefinitely.
+///
+/// # Panics
+///
+/// This can only be run once, as it sets up a system wide CoAP handler.
+pub(crate) async fn coap_run_tcp(handler: impl coap_handler::Handler) -> ! {
+    let stack = ariel_os_embassy::net::network_stack().await.unwrap();
+
+    stack.wait_config_up().await;
+
+    info!("Starting up CoAP-over-TCP server");
+
+    // Requests are processed without awaiting, so connections never hold the handler at the same
+    // time.
+    let handler = RefCell::new(handler);
+    join_array(core::array::from_fn::<_, CONCURRENT_REQUESTS, _>(|_| {
+        accept_connections(stack, &handler)
+    }))
+    .await;
+    unreachable!("connections are accepted indefinitely")
+}
+
+/// Accepts connections on a socket of its own, and serves them one after another.
+async fn accept_connections(stack: NetworkStack, handler: &RefCell<impl coap_handler::Handler>) {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut request = [0; MAX_MESSAGE_SIZE];
+    let mut response = [0; MAX_MESSAGE_SIZE];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        if let Err(e) = socket.accept(PORT).await {
+            info!("Failed to accept connection: {:?}", Debug2Format(&e));
+            continue;
+        }
+        debug!(
+            "Accepted CoAP-over-TCP connection from {:?}",
+            Debug2Format(&socket.remote_endpoint())
+        );
+
+        if let Err(e) = serve_connection(&mut socket, handler, &mut request, &mut response).await {
+            info!("CoAP-over-TCP connection failed: {:?}", Debug2Format(&e));
+        }
+
+        socket.close();
+        // Errors here only mean that the peer is gone already.
+        let _ = socket.flush().await;
+    }
+}
+
+/// Serves requests on an established connection until it is closed or released.
+async fn serve_connection(
+    socket: &mut TcpSocket<'_>,
+    handler: &RefCell<impl coap_handler::Handler>,
+    request: &mut [u8; MAX_MESSAGE_SIZE],
+    response: &mut [u8; MAX_MESSAGE_SIZE],
+) -> Result<(), embassy_net::tcp::Error> {
+    // Every connection starts with a CSM from either side.
+    let max_message_size = u16::try_from(MAX_MESSAGE_SIZE)
+        .expect("size fits the option")
+        .to_be_bytes();
+    let csm_options = [
+        (MAX_MESSAGE_SIZE_OPTION << 4) | 2,
+        max_message_size[0],
+        max_message_size[1],
+    ];
+    write_message(socket, CSM, &[], &csm_options).await?;
+
+    loop {
+        let read = with_timeout(IDLE_TIMEOUT, read_message(socket, request)).await;
+        let (code, token, len) = match read {
+            Ok(Ok(message)) => message,
+            Ok(Err(ReadError::Closed)) => return Ok(()),
+            Ok(Err(ReadError::Tcp(e))) => return Err(e),
+            Ok(Err(ReadError::Unprocessable)) => {
+                info!("Aborting CoAP-over-TCP connection on unprocessable message.");
+                return write_message(socket, ABORT, &[], &[]).await;
+            }
+            Err(_) => {
+                debug!("Releasing idle CoAP-over-TCP connection.");
+                return write_message(socket, RELEASE, &[], &[]).await;
+            }
+        };
+        #[allow(clippy::indexing_slicing, reason = "length was checked while reading")]
+        let options_and_payload = &request[..len];
+
+        match code {
+            // Requests
+            1..=31 => {
+                let (response_code, response_tail) =
+                    response.split_first_mut().expect("buffer is not empty");
+                let response_len = respond(
+                    &mut *handler.borrow_mut(),
+                    code,
+                    options_and_payload,
+                    response_code,
+                    response_tail,
+                );
+                let response_code = *response_code;
+                #[allow(clippy::indexing_slicing, reason = "length was produced by the writer")]
+                let response_tail = &response_tail[..response_len];
+                write_message(socket, response_code, &token, response_tail).await?;
+            }
+            PING => write_message(socket, PONG, &token, &[]).await?,
+            RELEASE | ABORT => return Ok(()),
+            // The peer's CSM does not affect our responses: Its Max-Message-Size can not be below
+            // the default, and we are not using any other capabilities. Empty messages, responses
+            // and Pongs are not expected, and ignored.
+            _ => (),
+        }
+    }
+}
+
+/// Reads a message into `buffer`, returning its code, token and the length of its options and
+/// payload.
+async fn read_message(
+    socket: &mut TcpSocket<'_>,
+    buffer: &mut [u8; MAX_MESSAGE_SIZE],
+) -> Result<(u8, Token, usize), ReadError> {
+    let mut first = [0];
+    socket.read_exact(&mut first).await?;
+    let [first] = first;
+
+    let len = match first >> 4 {
+        13 => {
+            let mut extended = [0; 1];
+            socket.read_exact(&mut extended).await?;
+            usize::from(extended[0]) + 13
+        }
+        14 => {
+            let mut extended = [0; 2];
+            socket.read_exact(&mut extended).await?;
+            usize::from(u16::from_be_bytes(extended)) + 269
+        }
+        15 => return Err(ReadError::Unprocessable),
+        len => usize::from(len),
+    };
+    let tkl = usize::from(first & 0x0f);
+
+    let mut code = [0];
+    socket.read_exact(&mut code).await?;
+    let [code] = code;
+
+    let mut token = Token::new();
+    token
+        .resize_default(tkl)
+        .map_err(|()| ReadError::Unprocessable)?;
+    socket.read_exact(&mut token).await?;
+
+    let buffer = buffer.get_mut(..len).ok_or(ReadError::Unprocessable)?;
+    socket.read_exact(buffer).await?;
+
+    Ok((code, token, len))
+}
+
+/// Writes a message with the given serialized options and payload.
+async fn write_message(
+    socket: &mut TcpSocket<'_>,
+    code: u8,
+    token: &[u8],
+    options_and_payload: &[u8],
+) -> Result<(), embassy_net::tcp::Error> {
+    let len = options_and_payload.len();
+    debug_assert!(len < 269 + 65536, "messages are bounded by the buffer size");
+    #[allow(
+        clippy::cast_possible_truncation,
+        reason = "tokens are at most 8 bytes long, and lengths are range checked in the match"
+    )]
+    let header = {
+        let tkl = token.len() as u8;
+        let mut header = heapless::Vec::<u8, MAX_HEADER_LEN>::new();
+        match len {
+            0..13 => header.push(((len as u8) << 4) | tkl),
+            13..269 => header
+                .push((13 << 4) | tkl)
+                .and_then(|()| header.push((len - 13) as u8)),
+            _ => header.push((14 << 4) | tkl).and_then(|()| {
+                header
+                    .extend_from_slice(&((len - 269) as u16).to_be_bytes())
+                    .map_err(|()| 0)
+            }),
+        }
+        .and_then(|()| header.push(code))
+        .and_then(|()| header.extend_from_slice(token).map_err(|()| 0))
+        .expect("header is sized for the longest header");
+        header
+    };
+
+    socket.write_all(&header).await?;
+    socket.write_all(options_and_payload).await?;
+    socket.flush().await
+}
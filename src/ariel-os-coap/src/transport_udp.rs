@@ -1,22 +1,78 @@
 //! Transport implementation for CoAP-over-UDP.
 
-use core::net::{Ipv6Addr, SocketAddr};
+use core::cell::RefCell;
+use core::net::{IpAddr, SocketAddr};
 
-use ariel_os_debug::log::info;
+use ariel_os_debug::log::{Debug2Format, debug, info};
 use ariel_os_embassy::cell::SameExecutorCell;
 
+use coap_message_implementations::{inmemory, inmemory_write};
+use embassy_futures::select::{Either, select};
 use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embedded_nal_async::UnconnectedUdp as _;
+use rand_core::RngCore as _;
 use static_cell::StaticCell;
 
+use super::observe::{self, NOTIFIED, Observable, Outcome, Renotify};
+use super::shared_client;
 use super::udp_nal;
-use super::{CLIENT_READY, CONCURRENT_REQUESTS};
+use super::{CLIENT_READY, CONCURRENT_REQUESTS, PORT};
+
+/// Size of the socket's receive buffer.
+const RX_BUFFER_SIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_RX_BUFFER_SIZE",
+    1500,
+    "size of the CoAP-over-UDP receive buffer"
+);
+
+/// Size of the socket's transmit buffer.
+const TX_BUFFER_SIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_TX_BUFFER_SIZE",
+    1500,
+    "size of the CoAP-over-UDP transmit buffer"
+);
+
+/// Number of datagrams that can be queued in each direction.
+const PACKET_METADATA: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_COAP_PACKET_METADATA",
+    2,
+    "number of datagrams queued in each direction of the CoAP-over-UDP socket"
+);
+
+/// Local address the server binds to; the unspecified address accepts requests on any address.
+///
+/// It is checked by the build script, which also requires the unspecified address with the
+/// `multicast` feature.
+const BIND_ADDRESS: &str = ariel_os_utils::str_from_env_or!(
+    "CONFIG_COAP_BIND_ADDRESS",
+    "::",
+    "IPv4 or IPv6 address the CoAP-over-UDP server binds to"
+);
+
+/// Number of observations that are served at the same time.
+const MAX_REGISTRATIONS: usize = 4;
+
+/// Largest request (in options and payload) that can register an observation.
+const MAX_REQUEST_LEN: usize = 128;
+
+/// Largest token length defined in RFC7252.
+const MAX_TKL: usize = 8;
+
+/// Length of the CoAP-over-UDP header without token.
+const HEADER_LEN: usize = 4;
+
+/// Message types (RFC7252 Section 3).
+const NON: u8 = 1;
+const RST: u8 = 3;
+
+type Token = heapless::Vec<u8, MAX_TKL>;
 
 /// Runs the CoAP handler on CoAP-over-UDP indefinitely.
 ///
 /// # Panics
 ///
 /// This can only be run once, as it sets up a system wide CoAP handler.
-pub(crate) async fn coap_run_udp(mut handler: impl coap_handler::Handler) -> ! {
+pub(crate) async fn coap_run_udp(handler: impl coap_handler::Handler + Renotify) -> ! {
     static COAP: StaticCell<embedded_nal_coap::CoAPShared<CONCURRENT_REQUESTS>> = StaticCell::new();
 
     let stack = ariel_os_embassy::net::network_stack().await.unwrap();
@@ -28,12 +84,13 @@ pub(crate) async fn coap_run_udp(mut handler: impl coap_handler::Handler) -> ! {
     // request, because we shouldn't hand out a client early).
     stack.wait_config_up().await;
 
-    // FIXME trim to CoAP requirements (those values are just a likely good starting point for "we
-    // process any message immediately anyway")
-    let mut rx_meta = [PacketMetadata::EMPTY; 2];
-    let mut rx_buffer = [0; 1500];
-    let mut tx_meta = [PacketMetadata::EMPTY; 2];
-    let mut tx_buffer = [0; 1500];
+    #[cfg(feature = "multicast")]
+    join_all_coap_nodes(stack);
+
+    let mut rx_meta = [PacketMetadata::EMPTY; PACKET_METADATA];
+    let mut rx_buffer = [0; RX_BUFFER_SIZE];
+    let mut tx_meta = [PacketMetadata::EMPTY; PACKET_METADATA];
+    let mut tx_buffer = [0; TX_BUFFER_SIZE];
 
     let socket = UdpSocket::new(
         stack,
@@ -45,11 +102,17 @@ pub(crate) async fn coap_run_udp(mut handler: impl coap_handler::Handler) -> ! {
 
     info!("Starting up CoAP server");
 
-    let local_any = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 5683);
-    let mut unconnected = udp_nal::UnconnectedUdp::bind_multiple(socket, local_any)
+    let bind_address: IpAddr = BIND_ADDRESS
+        .parse()
+        .expect("CONFIG_COAP_BIND_ADDRESS is not a valid IP address");
+    let local = SocketAddr::new(bind_address, PORT);
+    let unconnected = udp_nal::UnconnectedUdp::bind_multiple(socket, local)
         .await
         .unwrap();
 
+    let handler = RefCell::new(handler);
+    let mut observing = ObservingSocket::new(unconnected, &handler);
+
     info!("Server is ready.");
 
     let coap = COAP.init_with(embedded_nal_coap::CoAPShared::new);
@@ -61,17 +124,310 @@ pub(crate) async fn coap_run_udp(mut handler: impl coap_handler::Handler) -> ! {
     static CLIENT: StaticCell<embedded_nal_coap::CoAPRuntimeClient<'static, CONCURRENT_REQUESTS>> =
         StaticCell::new();
 
+    let client: &'static _ = CLIENT.init(client);
     CLIENT_READY
         .sender()
-        .send(SameExecutorCell::new_async(&*CLIENT.init(client)).await);
-
-    server
-        .run(
-            &mut unconnected,
-            &mut handler,
-            &mut ariel_os_random::fast_rng(),
-        )
-        .await
-        .expect("UDP error");
+        .send(SameExecutorCell::new_async(client).await);
+
+    let run = server.run(
+        &mut observing,
+        &mut observe::Shared(&handler),
+        &mut ariel_os_random::fast_rng(),
+    );
+    match select(run, shared_client::forward(client)).await {
+        Either::First(result) => result.expect("UDP error"),
+        Either::Second(never) => never,
+    }
     unreachable!("embassy-net's sockets do not get closed (but embedded-nal-coap can't know that)");
 }
+
+/// Joins the All-CoAP-Nodes multicast groups (RFC7252 Section 12.8).
+///
+/// Clients send requests to these groups to discover servers.
+///
+/// Failure is not fatal: the server is still reachable through its unicast addresses.
+#[cfg(feature = "multicast")]
+fn join_all_coap_nodes(stack: embassy_net::Stack<'_>) {
+    use core::net::{Ipv4Addr, Ipv6Addr};
+
+    const ALL_COAP_NODES_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 1, 187);
+    const ALL_COAP_NODES_V6_LINK_LOCAL: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfd);
+
+    if let Err(e) = stack.join_multicast_group(ALL_COAP_NODES_V4) {
+        info!(
+            "Could not join All-CoAP-Nodes group (IPv4): {:?}",
+            Debug2Format(&e)
+        );
+    }
+    if let Err(e) = stack.join_multicast_group(ALL_COAP_NODES_V6_LINK_LOCAL) {
+        info!(
+            "Could not join All-CoAP-Nodes group (IPv6): {:?}",
+            Debug2Format(&e)
+        );
+    }
+}
+
+/// A request that was received, and may register an observation once its response is sent.
+struct Request {
+    local: SocketAddr,
+    remote: SocketAddr,
+    token: Token,
+    code: u8,
+    options_and_payload: heapless::Vec<u8, MAX_REQUEST_LEN>,
+}
+
+/// An observation of a client on an [`Observable`].
+struct Registration {
+    observable: &'static Observable,
+    request: Request,
+    /// The observable's sequence number when a notification was last built.
+    sent: u32,
+    /// Message ID of the last notification, by which a Reset is recognized.
+    message_id: u16,
+}
+
+/// A socket that keeps observation registrations, and sends notifications on behalf of the CoAP
+/// server.
+///
+/// Registrations are established and ended by inspecting the messages the server receives and
+/// sends; notifications are sent while the server waits for the next request.
+struct ObservingSocket<'h, S, H> {
+    socket: S,
+    handler: &'h RefCell<H>,
+    registrations: [Option<Registration>; MAX_REGISTRATIONS],
+    /// Index of the registration that is replaced next if all are occupied.
+    next_replaced: usize,
+    /// The request that is being processed by the server.
+    last_request: Option<Request>,
+    message_id: u16,
+}
+
+impl<'h, S: embedded_nal_async::UnconnectedUdp, H: Renotify> ObservingSocket<'h, S, H> {
+    fn new(socket: S, handler: &'h RefCell<H>) -> Self {
+        #[allow(
+            clippy::cast_possible_truncation,
+            reason = "any part of the random number is suitable"
+        )]
+        let message_id = ariel_os_random::fast_rng().next_u32() as u16;
+        Self {
+            socket,
+            handler,
+            registrations: [const { None }; MAX_REGISTRATIONS],
+            next_replaced: 0,
+            last_request: None,
+            message_id,
+        }
+    }
+
+    /// Sends a notification for every registration whose observable changed.
+    ///
+    /// `buf` is used as scratch space.
+    async fn send_notifications(&mut self, buf: &mut [u8]) -> Result<(), S::Error> {
+        for entry in &mut self.registrations {
+            let Some(mut registration) = entry.take_if(|r| r.observable.sequence() != r.sent)
+            else {
+                continue;
+            };
+            registration.sent = registration.observable.sequence();
+
+            let Some((len, keep)) = render_notification(
+                &mut *self.handler.borrow_mut(),
+                &registration.request,
+                self.message_id,
+                buf,
+            ) else {
+                debug!("Observation ended.");
+                continue;
+            };
+            registration.message_id = self.message_id;
+            self.message_id = self.message_id.wrapping_add(1);
+            let (local, remote) = (registration.request.local, registration.request.remote);
+            if keep {
+                *entry = Some(registration);
+            } else {
+                debug!("Observation ended with final notification.");
+            }
+
+            #[allow(clippy::indexing_slicing, reason = "length was produced by the writer")]
+            self.socket.send(local, remote, &buf[..len]).await?;
+        }
+        Ok(())
+    }
+
+    /// Updates the registrations when the response to the last request is sent.
+    fn process_response(&mut self, remote: SocketAddr, message: &[u8]) {
+        let outcome = observe::take_outcome();
+        let Some((code, token)) = parse_header(message) else {
+            return;
+        };
+        let Some(request) = self
+            .last_request
+            .take_if(|request| request.remote == remote && request.token.as_slice() == token)
+        else {
+            // Not a response (the CoAP client sends through this socket too), or not one to a
+            // request that could be observing.
+            return;
+        };
+
+        // Any request with the same token replaces the registration (RFC7641 Section 3.3.1).
+        for entry in &mut self.registrations {
+            if entry
+                .as_ref()
+                .is_some_and(|r| r.request.remote == remote && r.request.token == request.token)
+            {
+                *entry = None;
+            }
+        }
+
+        if let Some(Outcome::Register(observable)) = outcome
+            && (0x40..0x60).contains(&code)
+        {
+            debug!("Registering observation from {:?}", Debug2Format(&remote));
+            let registration = Registration {
+                observable,
+                request,
+                sent: observable.sequence(),
+                message_id: 0,
+            };
+            let index = self
+                .registrations
+                .iter()
+                .position(Option::is_none)
+                .unwrap_or_else(|| {
+                    let index = self.next_replaced;
+                    self.next_replaced = (self.next_replaced + 1) % MAX_REGISTRATIONS;
+                    index
+                });
+            if let Some(entry) = self.registrations.get_mut(index) {
+                *entry = Some(registration);
+            }
+        }
+    }
+
+    /// Records a received request, and ends observations on a Reset.
+    fn process_received(&mut self, local: SocketAddr, remote: SocketAddr, message: &[u8]) {
+        let Some((&[first, code, mid_high, mid_low], rest)) =
+            message.split_first_chunk::<HEADER_LEN>()
+        else {
+            return;
+        };
+        let message_type = (first >> 4) & 0x03;
+
+        if message_type == RST {
+            let message_id = u16::from_be_bytes([mid_high, mid_low]);
+            for entry in &mut self.registrations {
+                if entry
+                    .as_ref()
+                    .is_some_and(|r| r.request.remote == remote && r.message_id == message_id)
+                {
+                    debug!("Observation ended by Reset.");
+                    *entry = None;
+                }
+            }
+            return;
+        }
+
+        if !(1..=31).contains(&code) {
+            return;
+        }
+        let Some((token, options_and_payload)) = rest.split_at_checked(usize::from(first & 0x0f))
+        else {
+            return;
+        };
+        // Requests that do not fit can not register; they are still processed.
+        self.last_request = Token::from_slice(token)
+            .ok()
+            .zip(heapless::Vec::from_slice(options_and_payload).ok())
+            .map(|(token, options_and_payload)| Request {
+                local,
+                remote,
+                token,
+                code,
+                options_and_payload,
+            });
+    }
+}
+
+impl<S: embedded_nal_async::UnconnectedUdp, H: Renotify> embedded_nal_async::UnconnectedUdp
+    for ObservingSocket<'_, S, H>
+{
+    type Error = S::Error;
+
+    async fn send(
+        &mut self,
+        local: SocketAddr,
+        remote: SocketAddr,
+        data: &[u8],
+    ) -> Result<(), Self::Error> {
+        self.process_response(remote, data);
+        self.socket.send(local, remote, data).await
+    }
+
+    async fn receive_into(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, SocketAddr, SocketAddr), Self::Error> {
+        loop {
+            self.send_notifications(buffer).await?;
+            // Receiving is cancellation safe; a notification interrupting it is only processed
+            // before the next message is received.
+            if let Either::First(received) =
+                select(self.socket.receive_into(buffer), NOTIFIED.wait()).await
+            {
+                let (len, local, remote) = received?;
+                if let Some(message) = buffer.get(..len) {
+                    self.process_received(local, remote, message);
+                }
+                return Ok((len, local, remote));
+            }
+        }
+    }
+}
+
+/// Returns the code and token of a message.
+fn parse_header(message: &[u8]) -> Option<(u8, &[u8])> {
+    let (&[first, code, _, _], rest) = message.split_first_chunk::<HEADER_LEN>()?;
+    let token = rest.get(..usize::from(first & 0x0f))?;
+    Some((code, token))
+}
+
+/// Renders a non-confirmable notification for `request` into `buf`.
+///
+/// Returns the message's length and whether the observation continues, or `None` if the
+/// observation ended without a notification.
+fn render_notification(
+    handler: &mut impl Renotify,
+    request: &Request,
+    message_id: u16,
+    buf: &mut [u8],
+) -> Option<(usize, bool)> {
+    let (header, rest) = buf.split_first_chunk_mut::<HEADER_LEN>()?;
+    let token_len = request.token.len();
+    let (token, tail) = rest.split_at_mut_checked(token_len)?;
+    token.copy_from_slice(&request.token);
+
+    let mut code = 0;
+    let mut response = inmemory_write::Message::new(&mut code, tail);
+    let built = handler.build_notification(
+        &inmemory::Message::new(request.code, &request.options_and_payload),
+        &mut response,
+    );
+    let len = response.finish();
+    // Only a successful rendering through an observable resource continues the observation.
+    let continues = matches!(observe::take_outcome(), Some(Outcome::Register(_)));
+    if !built {
+        return None;
+    }
+
+    #[allow(
+        clippy::cast_possible_truncation,
+        reason = "token length is limited by the Token type"
+    )]
+    let tkl = token_len as u8;
+    let [mid_high, mid_low] = message_id.to_be_bytes();
+    *header = [0x40 | (NON << 4) | tkl, code, mid_high, mid_low];
+    Some((
+        HEADER_LEN + token_len + len,
+        continues && (0x40..0x60).contains(&code),
+    ))
+}
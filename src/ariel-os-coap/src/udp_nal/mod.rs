@@ -146,7 +146,9 @@ impl nal::UnconnectedUdp for UnconnectedUdp<'_> {
         );
 
         let remote_endpoint = udp::UdpMetadata {
-            local_address: if is_unspec_ip(local) {
+            // Responses to requests received on a multicast address are sent from a unicast
+            // address the stack picks.
+            local_address: if is_unspec_ip(local) || local.ip().is_multicast() {
                 None
             } else {
                 // A conversion of the addr part only might be cheaper, but would also mean we need
@@ -399,6 +399,9 @@ async fn init_task(mut peripherals: hal::OptionalPeripherals) {
 
     debug!("ariel-os-embassy::init_task() done");
 
+    #[cfg(feature = "thread-time-slicing")]
+    ariel_os_threads::start_time_slicing();
+
     #[cfg(feature = "threading")]
     ariel_os_threads::events::THREAD_START_EVENT.set();
 }
@@ -67,49 +67,75 @@ fn with_global<R>(action: impl FnOnce(&mut SelectedRng) -> R) -> R {
     rng.lock(|rng| action(&mut rng.borrow_mut()))
 }
 
+#[cfg(feature = "threading")]
+ariel_os_threads::thread_local! {
+    /// The fast RNG of each thread, seeded from the global RNG on first use.
+    static THREAD_RNG: rand_pcg::Pcg32 = with_global(rand_pcg::Pcg32::from_rng);
+}
+
 /// The OS provided fast random number generator.
 ///
 /// This will generally be faster to produce random numbers than [`CryptoRng`].
 ///
 /// Such an RNG can be requested by any component, and will always be seeded appropriately.
+///
+/// With threading enabled, all [`FastRng`]s of a thread share the thread's RNG, which is kept in
+/// thread-local storage.
 pub struct FastRng {
-    inner: rand_pcg::Pcg32,
-    // Make the type not Send to later allow using thread-locals
+    /// RNG owned by this instance, seeded on first use outside of a thread.
+    inner: Option<rand_pcg::Pcg32>,
+    // Make the type not Send, so that it keeps using the RNG of the thread it was created on.
     _private: core::marker::PhantomData<*const ()>,
 }
 
+impl FastRng {
+    /// Runs `f` with the RNG of the current thread, or with the RNG owned by this instance if
+    /// there is no current thread.
+    fn with_rng<R>(&mut self, mut f: impl FnMut(&mut rand_pcg::Pcg32) -> R) -> R {
+        #[cfg(feature = "threading")]
+        if self.inner.is_none()
+            && let Some(res) = THREAD_RNG.with(&mut f)
+        {
+            return res;
+        }
+        f(self
+            .inner
+            .get_or_insert_with(|| with_global(rand_pcg::Pcg32::from_rng)))
+    }
+}
+
 // Re-implementing the trait rather than Deref'ing into inner: This avoids leaking implementation
 // details to users who might otherwise come to depend on platform specifics of the FastRng.
 impl RngCore for FastRng {
     fn next_u32(&mut self) -> u32 {
-        self.inner.next_u32()
+        self.with_rng(RngCore::next_u32)
     }
     fn next_u64(&mut self) -> u64 {
-        self.inner.next_u64()
+        self.with_rng(RngCore::next_u64)
     }
     fn fill_bytes(&mut self, dest: &mut [u8]) {
-        self.inner.fill_bytes(dest);
+        self.with_rng(|rng| rng.fill_bytes(dest));
     }
 }
 
 impl rand_core_06::RngCore for FastRng {
     fn next_u32(&mut self) -> u32 {
-        self.inner.next_u32()
+        self.with_rng(RngCore::next_u32)
     }
     fn next_u64(&mut self) -> u64 {
-        self.inner.next_u64()
+        self.with_rng(RngCore::next_u64)
     }
     fn fill_bytes(&mut self, dest: &mut [u8]) {
-        self.inner.fill_bytes(dest);
+        self.with_rng(|rng| rng.fill_bytes(dest));
     }
     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core_06::Error> {
-        self.inner.fill_bytes(dest);
+        self.with_rng(|rng| rng.fill_bytes(dest));
         Ok(())
     }
 }
 
 /// Same as [`FastRng`], but can be shared across threads and tasks.
-/// This is to differentiate from [`FastRng`] that could get optimized in a future version to use thread-local storage.
+/// This is to differentiate from [`FastRng`] that uses thread-local storage when threading is enabled.
 /// Should be used only when sharing between threads is necessary (e.g. integrating with C code).
 #[doc(hidden)]
 pub struct FastRngSend {
@@ -274,17 +300,19 @@ pub fn construct_rng(hwrng: &mut impl RngCore) {
 }
 
 /// Returns a suitably initialized fast random number generator.
+///
+/// Within a thread, this is cheap, as the thread's RNG is only seeded once.
 #[must_use]
 #[inline]
 pub fn fast_rng() -> FastRng {
     FastRng {
-        inner: with_global(rand_pcg::Pcg32::from_rng),
+        inner: None,
         _private: PhantomData,
     }
 }
 
 /// Same as [`fast_rng()`]. Can be shared across threads and tasks.
-/// This is to differentiate from [`fast_rng()`] that could get optimized in a future version to use thread-local storage.
+/// This is to differentiate from [`fast_rng()`] that uses thread-local storage when threading is enabled.
 /// Should be used only when sharing between threads is necessary (e.g. integrating with C code).
 #[doc(hidden)]
 #[must_use]
@@ -127,6 +127,24 @@ mod tests {
         assert_eq!(runqueue.get_next(), None);
     }
 
+    #[test]
+    fn test_rq_has_multiple() {
+        let mut runqueue: RunQueue<8, 32> = RunQueue::new();
+
+        assert!(!runqueue.has_multiple(RunqueueId::new(0)));
+
+        runqueue.add(ThreadId::new(0), RunqueueId::new(0));
+        runqueue.add(ThreadId::new(1), RunqueueId::new(1));
+        assert!(!runqueue.has_multiple(RunqueueId::new(0)));
+        assert!(!runqueue.has_multiple(RunqueueId::new(1)));
+
+        runqueue.add(ThreadId::new(2), RunqueueId::new(0));
+        assert!(runqueue.has_multiple(RunqueueId::new(0)));
+
+        runqueue.del(ThreadId::new(0));
+        assert!(!runqueue.has_multiple(RunqueueId::new(0)));
+    }
+
     #[test]
     fn iter() {
         let mut runqueue: RunQueue<8, 32> = RunQueue::new();
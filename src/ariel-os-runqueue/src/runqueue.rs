@@ -18,6 +18,12 @@ impl RunqueueId {
     pub const fn new(value: u8) -> Self {
         Self(value)
     }
+
+    /// Returns the wrapped ID.
+    #[must_use]
+    pub const fn get(self) -> u8 {
+        self.0
+    }
 }
 
 impl From<RunqueueId> for usize {
@@ -70,8 +76,12 @@ impl<const N_QUEUES: usize, const N_THREADS: usize> RunQueue<{ N_QUEUES }, { N_T
     /// Returns a new [`RunQueue`].
     #[must_use]
     pub const fn new() -> RunQueue<{ N_QUEUES }, { N_THREADS }> {
-        // unfortunately we cannot assert!() on N_QUEUES and N_THREADS,
-        // as panics in const fn's are not (yet) implemented.
+        const {
+            assert!(
+                N_QUEUES <= USIZE_BITS,
+                "the number of runqueues must fit into the bitcache"
+            );
+        }
         RunQueue {
             bitcache: 0,
             queues: CList::new(),
@@ -178,11 +188,19 @@ impl<const N_QUEUES: usize, const N_THREADS: usize> RunQueue<{ N_QUEUES }, { N_T
     }
 
     /// Checks if a runqueue is empty.
-    pub fn is_empty(&mut self, rq: RunqueueId) -> bool {
+    pub fn is_empty(&self, rq: RunqueueId) -> bool {
         debug_assert!((rq.0 as usize) < N_QUEUES);
         self.queues.is_empty(rq.0)
     }
 
+    /// Checks if a runqueue contains more than one thread.
+    pub fn has_multiple(&self, rq: RunqueueId) -> bool {
+        debug_assert!((rq.0 as usize) < N_QUEUES);
+        self.queues
+            .peek_head(rq.0)
+            .is_some_and(|head| self.queues.peek_next(head) != head)
+    }
+
     /// Returns an iterator over the [`RunQueue`], starting after thread `start` in runqueue `rq`.
     ///
     /// The `start` is not included in the iterator.
@@ -266,8 +284,12 @@ mod clist {
 
     impl<const N_QUEUES: usize, const N_THREADS: usize> CList<N_QUEUES, N_THREADS> {
         pub const fn new() -> Self {
-            // TODO: ensure N fits in u8
-            // assert!(N<255); is not allowed in const because it could panic
+            const {
+                assert!(
+                    N_THREADS <= Self::sentinel() as usize,
+                    "thread indexes must fit into u8, with 0xFF being reserved as sentinel"
+                );
+            }
             CList {
                 tail: [Self::sentinel(); N_QUEUES],
                 next_idxs: [Self::sentinel(); N_THREADS],
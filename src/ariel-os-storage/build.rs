@@ -31,7 +31,21 @@ fn main() {
     };
 
     // `sequential-storage` needs at least two flash pages.
-    assert!(storage_size_total / flash_page_size >= 2);
+    let page_count = storage_size_total / flash_page_size;
+    assert!(page_count >= 2);
+
+    // A key and its value are stored as a single item, which needs to fit into a page.
+    let data_buffer_size = match env::var("CONFIG_STORAGE_DATA_BUFFER_SIZE") {
+        Ok(size) => size
+            .parse::<u32>()
+            .expect("could not parse `CONFIG_STORAGE_DATA_BUFFER_SIZE`"),
+        // Default of `DATA_BUFFER_SIZE`.
+        Err(_) => 128,
+    };
+    assert!(
+        data_buffer_size <= flash_page_size,
+        "`CONFIG_STORAGE_DATA_BUFFER_SIZE` exceeds the flash page size of {flash_page_size} bytes"
+    );
 
     // Put the linker script somewhere the linker can find it
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
@@ -42,7 +56,11 @@ fn main() {
 
     std::fs::write(out.join("storage.x"), &storage_template).unwrap();
 
+    // Sizes the caches and erase counters of the global storage.
+    println!("cargo:rustc-env=ARIEL_OS_STORAGE_PAGE_COUNT={page_count}");
+
     println!("cargo:rerun-if-env-changed=CARGO_CFG_CONTEXT");
+    println!("cargo:rerun-if-env-changed=CONFIG_STORAGE_DATA_BUFFER_SIZE");
     println!("cargo:rerun-if-changed=storage.ld.in");
     println!("cargo:rustc-link-search={}", out.display());
 }
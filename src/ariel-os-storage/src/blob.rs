@@ -0,0 +1,540 @@
+//! Storage of values larger than [`DATA_BUFFER_SIZE`], written and read as streams.
+//!
+//! A blob is stored in chunks under keys derived from its name, and a header that is stored once
+//! all chunks are written. Chunks alternate between two generations, so that an interrupted write
+//! leaves the previous content of the blob intact; its leftover chunks are removed when the blob
+//! is written next.
+//!
+//! All keys of a blob start with [`KEY_PREFIX`], and are not reported as keys to users.
+use core::{fmt::Write as _, ops::DerefMut};
+
+use ariel_os_hal::hal::storage::FlashError;
+use arrayvec::{ArrayString, ArrayVec};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::MutexGuard};
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use embedded_storage_async::nor_flash::NorFlash;
+use sequential_storage::cache::KeyCacheImpl;
+
+use crate::{
+    Cache, DATA_BUFFER_SIZE, GlobalFlash, MAX_KEY_LEN, RawValue, RemovableFlash, Storage, lock,
+    persist_erase_counts, remove_from,
+};
+
+/// Prefix of the keys of the headers and chunks of blobs.
+pub(crate) const KEY_PREFIX: &str = "ARIEL_BLOB/";
+
+/// Length of the suffix appended to a blob's name to form the key of a chunk.
+const CHUNK_SUFFIX_LEN: usize = "#0fff".len();
+
+/// Maximum length of a blob's name.
+pub const MAX_BLOB_NAME_LEN: usize = MAX_KEY_LEN - KEY_PREFIX.len() - CHUNK_SUFFIX_LEN;
+
+/// Length of the chunks of the blob with the shortest name.
+const MAX_CHUNK_LEN: usize = DATA_BUFFER_SIZE - KEY_PREFIX.len() - CHUNK_SUFFIX_LEN - 4;
+
+/// Maximum number of chunks of a blob, as limited by the chunk key format.
+const MAX_CHUNKS: u32 = 0x1000;
+
+const _: () = assert!(
+    DATA_BUFFER_SIZE > MAX_KEY_LEN + 4,
+    "the data buffer is too small for blob chunks"
+);
+
+type Guard = MutexGuard<'static, CriticalSectionRawMutex, Storage<GlobalFlash, Cache>>;
+
+/// Errors of accessing blobs.
+#[derive(Debug)]
+pub enum BlobError<E> {
+    /// Accessing the storage failed.
+    Storage(sequential_storage::Error<E>),
+    /// The blob's name is longer than [`MAX_BLOB_NAME_LEN`].
+    NameTooLong,
+    /// The blob exceeds the number of chunks that can be stored.
+    TooLarge,
+    /// A chunk of the blob is missing or has an unexpected length.
+    Corrupted,
+}
+
+impl<E> From<sequential_storage::Error<E>> for BlobError<E> {
+    fn from(error: sequential_storage::Error<E>) -> Self {
+        Self::Storage(error)
+    }
+}
+
+impl<E: core::fmt::Debug> embedded_io_async::Error for BlobError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            BlobError::Storage(_) | BlobError::Corrupted => ErrorKind::Other,
+            BlobError::NameTooLong => ErrorKind::InvalidInput,
+            BlobError::TooLarge => ErrorKind::OutOfMemory,
+        }
+    }
+}
+
+/// Header of a blob, stored under its name.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy)]
+struct Header {
+    /// Generation of the chunks holding the content (0 or 1).
+    generation: u8,
+    /// Length of the content.
+    len: u32,
+}
+
+impl Header {
+    fn chunks(self, chunk_len: u32) -> u32 {
+        self.len.div_ceil(chunk_len)
+    }
+}
+
+fn check_name<E>(name: &str) -> Result<(), BlobError<E>> {
+    if name.len() > MAX_BLOB_NAME_LEN {
+        return Err(BlobError::NameTooLong);
+    }
+    Ok(())
+}
+
+/// Returns the length of the chunks of the blob `name`, leaving room for the serialized chunk key.
+#[expect(
+    clippy::cast_possible_truncation,
+    reason = "chunks are smaller than a page"
+)]
+fn chunk_len(name: &str) -> u32 {
+    (MAX_CHUNK_LEN - name.len()) as u32
+}
+
+/// Returns the key of the header of the blob `name`.
+fn header_key(name: &str) -> ArrayString<MAX_KEY_LEN> {
+    let mut key = ArrayString::new();
+    // Fits, as the name was checked against its limit.
+    write!(key, "{KEY_PREFIX}{name}").unwrap();
+    key
+}
+
+fn chunk_key(name: &str, generation: u8, index: u32) -> ArrayString<MAX_KEY_LEN> {
+    let mut key = ArrayString::new();
+    // Fits, as the name and index were checked against their limits.
+    write!(key, "{KEY_PREFIX}{name}#{generation:x}{index:03x}").unwrap();
+    key
+}
+
+/// Removes the chunks of the blob content described by `header`.
+///
+/// They are removed from the last one, so that an interrupted removal leaves chunks behind only
+/// from the start, where [`remove_stale_chunks()`] finds them.
+async fn remove_chunks<F, C>(
+    storage: &mut Storage<F, C>,
+    name: &str,
+    header: Header,
+) -> Result<(), BlobError<F::Error>>
+where
+    F: RemovableFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    for index in (0..header.chunks(chunk_len(name))).rev() {
+        remove_from(storage, &chunk_key(name, header.generation, index)).await?;
+    }
+    Ok(())
+}
+
+/// Removes the chunks of `generation` left behind by an interrupted write or removal.
+///
+/// Chunks are written from the first one, and removed from the last one, so leftovers are found
+/// from the start.
+async fn remove_stale_chunks<F, C>(
+    storage: &mut Storage<F, C>,
+    name: &str,
+    generation: u8,
+) -> Result<(), BlobError<F::Error>>
+where
+    F: RemovableFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    for index in 0..MAX_CHUNKS {
+        let key = chunk_key(name, generation, index);
+        if storage.get_raw::<RawValue>(&key).await?.is_none() {
+            break;
+        }
+        remove_from(storage, &key).await?;
+    }
+    Ok(())
+}
+
+/// Starts writing the blob `name`, replacing any earlier content once
+/// [`finish()`](BlobWriter::finish()) is called.
+///
+/// **The global storage stays locked until the writer is finished or dropped: other storage
+/// functions (e.g., [`get()`](crate::get()) or [`insert()`](crate::insert())) must not be called
+/// meanwhile, as they would wait for the writer forever.**
+pub async fn write_blob(name: &str) -> Result<BlobWriter<'_>, BlobError<FlashError>> {
+    check_name(name)?;
+    write_blob_in(lock().await, name).await
+}
+
+/// Starts writing the blob `name` into `storage`, see [`write_blob()`].
+async fn write_blob_in<S, F, C>(
+    mut storage: S,
+    name: &str,
+) -> Result<BlobWriter<'_, S>, BlobError<F::Error>>
+where
+    S: DerefMut<Target = Storage<F, C>>,
+    F: RemovableFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    let previous = storage.get::<Header>(&header_key(name)).await?;
+    let generation = previous.map_or(0, |header| header.generation ^ 1);
+    remove_stale_chunks(&mut *storage, name, generation).await?;
+    if previous.is_none() {
+        // An interrupted removal of the blob may have left chunks of either generation.
+        remove_stale_chunks(&mut *storage, name, generation ^ 1).await?;
+    }
+    Ok(BlobWriter {
+        storage,
+        name,
+        previous,
+        generation,
+        chunk_len: chunk_len(name),
+        len: 0,
+        chunk: ArrayVec::new(),
+    })
+}
+
+/// Starts reading the blob `name`.
+///
+/// If no blob with the name is found, `None` is returned.
+///
+/// **The global storage stays locked until the reader is dropped: other storage functions (e.g.,
+/// [`get()`](crate::get()) or [`insert()`](crate::insert())) must not be called meanwhile, as they
+/// would wait for the reader forever.**
+pub async fn read_blob(name: &str) -> Result<Option<BlobReader<'_>>, BlobError<FlashError>> {
+    check_name(name)?;
+    read_blob_in(lock().await, name).await
+}
+
+/// Starts reading the blob `name` from `storage`, see [`read_blob()`].
+async fn read_blob_in<S, F, C>(
+    mut storage: S,
+    name: &str,
+) -> Result<Option<BlobReader<'_, S>>, BlobError<F::Error>>
+where
+    S: DerefMut<Target = Storage<F, C>>,
+    F: NorFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    let Some(header) = storage.get::<Header>(&header_key(name)).await? else {
+        return Ok(None);
+    };
+    Ok(Some(BlobReader {
+        storage,
+        name,
+        header,
+        chunk_len: chunk_len(name),
+        position: 0,
+        chunk: RawValue::default(),
+    }))
+}
+
+/// Deletes the blob `name`.
+pub async fn remove_blob(name: &str) -> Result<(), BlobError<FlashError>> {
+    check_name(name)?;
+    let mut storage = lock().await;
+    remove_blob_in(&mut storage, name).await?;
+    Ok(persist_erase_counts(&mut storage).await?)
+}
+
+/// Deletes the blob `name` from `storage`.
+async fn remove_blob_in<F, C>(
+    storage: &mut Storage<F, C>,
+    name: &str,
+) -> Result<(), BlobError<F::Error>>
+where
+    F: RemovableFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    let key = header_key(name);
+    if let Some(header) = storage.get::<Header>(&key).await? {
+        // The header goes first, so that the blob is never seen with missing chunks.
+        remove_from(storage, &key).await?;
+        remove_chunks(storage, name, header).await?;
+    }
+    Ok(())
+}
+
+/// Writes the content of a blob, created through [`write_blob()`].
+pub struct BlobWriter<'a, S = Guard> {
+    storage: S,
+    name: &'a str,
+    previous: Option<Header>,
+    generation: u8,
+    chunk_len: u32,
+    /// Length of the content written so far.
+    len: u32,
+    chunk: ArrayVec<u8, MAX_CHUNK_LEN>,
+}
+
+impl<S, F, C> BlobWriter<'_, S>
+where
+    S: DerefMut<Target = Storage<F, C>>,
+    F: NorFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    /// Stores the current chunk.
+    async fn store_chunk(&mut self) -> Result<(), BlobError<F::Error>> {
+        let index = self.len.div_ceil(self.chunk_len).saturating_sub(1);
+        let key = chunk_key(self.name, self.generation, index);
+        self.storage.insert_raw(&key, &*self.chunk).await?;
+        self.chunk.clear();
+        Ok(())
+    }
+}
+
+impl BlobWriter<'_> {
+    /// Stores the rest of the content, and makes it the content of the blob.
+    ///
+    /// Until this is called, reading the blob returns its earlier content.
+    pub async fn finish(mut self) -> Result<(), BlobError<FlashError>> {
+        complete(&mut self).await?;
+        Ok(persist_erase_counts(&mut self.storage).await?)
+    }
+}
+
+/// Stores the rest of the content of `writer`, and makes it the content of the blob.
+async fn complete<S, F, C>(writer: &mut BlobWriter<'_, S>) -> Result<(), BlobError<F::Error>>
+where
+    S: DerefMut<Target = Storage<F, C>>,
+    F: RemovableFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    if !writer.chunk.is_empty() {
+        writer.store_chunk().await?;
+    }
+    let header = Header {
+        generation: writer.generation,
+        len: writer.len,
+    };
+    writer
+        .storage
+        .insert(&header_key(writer.name), header)
+        .await?;
+    if let Some(previous) = writer.previous {
+        remove_chunks(&mut *writer.storage, writer.name, previous).await?;
+    }
+    Ok(())
+}
+
+impl<S, F, C> ErrorType for BlobWriter<'_, S>
+where
+    S: DerefMut<Target = Storage<F, C>>,
+    F: NorFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    type Error = BlobError<F::Error>;
+}
+
+impl<S, F, C> Write for BlobWriter<'_, S>
+where
+    S: DerefMut<Target = Storage<F, C>>,
+    F: NorFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk_len = self.chunk_len as usize;
+        if self.chunk.len() == chunk_len {
+            self.store_chunk().await?;
+        }
+        if self.len / self.chunk_len >= MAX_CHUNKS {
+            return Err(BlobError::TooLarge);
+        }
+
+        let taken = buf.len().min(chunk_len - self.chunk.len());
+        #[expect(clippy::indexing_slicing, reason = "at most the length of `buf`")]
+        self.chunk
+            .try_extend_from_slice(&buf[..taken])
+            .map_err(|_| BlobError::TooLarge)?;
+        #[expect(clippy::cast_possible_truncation, reason = "at most a chunk's length")]
+        let taken_u32 = taken as u32;
+        self.len += taken_u32;
+        Ok(taken)
+    }
+}
+
+/// Reads the content of a blob, created through [`read_blob()`].
+pub struct BlobReader<'a, S = Guard> {
+    storage: S,
+    name: &'a str,
+    header: Header,
+    chunk_len: u32,
+    /// Position of the next byte to read.
+    position: u32,
+    /// Chunk containing `position`, or empty if not loaded yet.
+    chunk: RawValue,
+}
+
+impl<S> BlobReader<'_, S> {
+    /// Returns the length of the blob's content.
+    #[must_use]
+    pub fn len(&self) -> u32 {
+        self.header.len
+    }
+
+    /// Returns whether the blob is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.header.len == 0
+    }
+}
+
+impl<S, F, C> ErrorType for BlobReader<'_, S>
+where
+    S: DerefMut<Target = Storage<F, C>>,
+    F: NorFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    type Error = BlobError<F::Error>;
+}
+
+impl<S, F, C> Read for BlobReader<'_, S>
+where
+    S: DerefMut<Target = Storage<F, C>>,
+    F: NorFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() || self.position >= self.header.len {
+            return Ok(0);
+        }
+
+        let chunk_len = self.chunk_len;
+        let offset = (self.position % chunk_len) as usize;
+        if offset == 0 || self.chunk.is_empty() {
+            let index = self.position / chunk_len;
+            let key = chunk_key(self.name, self.header.generation, index);
+            let expected_len = (self.header.len - index * chunk_len).min(chunk_len) as usize;
+            self.chunk = self
+                .storage
+                .get_raw::<RawValue>(&key)
+                .await?
+                .filter(|chunk| chunk.len() == expected_len)
+                .ok_or(BlobError::Corrupted)?;
+        }
+
+        let available = self.chunk.get(offset..).ok_or(BlobError::Corrupted)?;
+        let taken = buf.len().min(available.len());
+        #[expect(clippy::indexing_slicing, reason = "at most the length of both")]
+        buf[..taken].copy_from_slice(&available[..taken]);
+        #[expect(clippy::cast_possible_truncation, reason = "at most a chunk's length")]
+        let taken_u32 = taken as u32;
+        self.position += taken_u32;
+        Ok(taken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::{is_reserved, testing::storage};
+
+    fn content(len: usize, seed: u8) -> Vec<u8> {
+        (0..=u8::MAX)
+            .cycle()
+            .map(|i| seed.wrapping_add(i))
+            .take(len)
+            .collect()
+    }
+
+    async fn write<F, C>(s: &mut Storage<F, C>, name: &str, content: &[u8])
+    where
+        F: RemovableFlash,
+        C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+    {
+        let mut writer = write_blob_in(&mut *s, name).await.unwrap();
+        writer.write_all(content).await.unwrap();
+        complete(&mut writer).await.unwrap();
+    }
+
+    async fn read<F, C>(s: &mut Storage<F, C>, name: &str) -> Option<Vec<u8>>
+    where
+        F: NorFlash,
+        C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+    {
+        let mut reader = read_blob_in(&mut *s, name).await.unwrap()?;
+        let mut content = vec![0; usize::try_from(reader.len()).unwrap()];
+        reader.read_exact(&mut content).await.unwrap();
+        Some(content)
+    }
+
+    async fn has_chunk<F, C>(s: &mut Storage<F, C>, generation: u8, index: u32) -> bool
+    where
+        F: NorFlash,
+        C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+    {
+        s.get_raw::<RawValue>(&chunk_key("cert", generation, index))
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[test]
+    fn replaces_content_and_its_chunks() {
+        block_on(async {
+            let mut s = storage();
+            // Spans three chunks.
+            let first = content(300, 0);
+            write(&mut s, "cert", &first).await;
+            assert_eq!(read(&mut s, "cert").await, Some(first));
+            assert!(has_chunk(&mut s, 0, 2).await);
+
+            let second = content(150, 7);
+            write(&mut s, "cert", &second).await;
+            assert_eq!(read(&mut s, "cert").await, Some(second));
+            assert!(has_chunk(&mut s, 1, 1).await);
+            for index in 0..3 {
+                assert!(!has_chunk(&mut s, 0, index).await);
+            }
+        });
+    }
+
+    #[test]
+    fn interrupted_writes_keep_earlier_content() {
+        block_on(async {
+            let mut s = storage();
+            let first = content(100, 0);
+            write(&mut s, "cert", &first).await;
+
+            // Stores the first two chunks of the other generation before being dropped.
+            let mut writer = write_blob_in(&mut s, "cert").await.unwrap();
+            writer.write_all(&content(250, 3)).await.unwrap();
+            drop(writer);
+            assert!(has_chunk(&mut s, 1, 1).await);
+            assert_eq!(read(&mut s, "cert").await, Some(first));
+
+            // Writing again removes the stale chunks before reusing their generation.
+            let second = content(50, 5);
+            write(&mut s, "cert", &second).await;
+            assert!(!has_chunk(&mut s, 1, 1).await);
+            assert_eq!(read(&mut s, "cert").await, Some(second));
+        });
+    }
+
+    #[test]
+    fn blob_keys_are_reserved() {
+        block_on(async {
+            let mut s = storage();
+            s.insert("plain", 1u32).await.unwrap();
+            write(&mut s, "cert", &content(200, 0)).await;
+
+            let keys = s.keys_matching::<8>(|key| !is_reserved(key)).await.unwrap();
+            assert_eq!(keys.len(), 1);
+            assert_eq!(keys.first().map(ArrayString::as_str), Some("plain"));
+            assert_eq!(s.get::<u32>("cert").await.unwrap(), None);
+
+            remove_blob_in(&mut s, "cert").await.unwrap();
+            assert_eq!(read(&mut s, "cert").await, None);
+            assert_eq!(s.keys::<8>().await.unwrap().len(), 1);
+        });
+    }
+}
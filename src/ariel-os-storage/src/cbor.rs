@@ -0,0 +1,216 @@
+//! Export and import of the global storage as CBOR, e.g., for backups and factory provisioning.
+//!
+//! The exported document is a CBOR map from each key (as a text string) to its value in the
+//! serialized form it is stored in (as a byte string).
+use ariel_os_hal::hal::storage::FlashError;
+use arrayvec::ArrayString;
+use embedded_storage_async::nor_flash::NorFlash;
+use minicbor::{Decoder, Encoder, encode::write::Cursor};
+use sequential_storage::cache::KeyCacheImpl;
+
+use crate::{
+    DATA_BUFFER_SIZE, MAX_KEY_LEN, RawValue, Storage, is_reserved, lock, persist_erase_counts,
+};
+
+/// Errors of [`export()`] and [`import()`].
+#[derive(Debug)]
+pub enum TransferError<E> {
+    /// Accessing the storage failed.
+    Storage(sequential_storage::Error<E>),
+    /// The exported document does not fit into the buffer.
+    BufferTooSmall,
+    /// The document to import is not a map of keys to values.
+    Malformed,
+    /// A key or value in the document to import is too large to be stored.
+    TooLarge,
+}
+
+impl<E> From<sequential_storage::Error<E>> for TransferError<E> {
+    fn from(error: sequential_storage::Error<E>) -> Self {
+        Self::Storage(error)
+    }
+}
+
+/// Exports the values of up to `N` keys into `buffer`, and returns the length of the document.
+///
+/// Keys used by the storage itself are not exported.
+///
+/// <div class="warning">
+/// This is slow, as all items in flash have to be read.
+/// </div>
+pub async fn export<const N: usize>(buffer: &mut [u8]) -> Result<usize, TransferError<FlashError>> {
+    export_from::<N, _, _>(&mut *lock().await, buffer).await
+}
+
+/// Imports the values of a document produced by [`export()`], and returns how many were imported.
+///
+/// Values of keys that are already stored are overwritten; other keys are left untouched. Keys
+/// used by the storage itself are skipped.
+///
+/// The whole document is checked before any value is stored, so a malformed document does not
+/// lead to a partial import. Running out of space during the import can, though.
+pub async fn import(document: &[u8]) -> Result<usize, TransferError<FlashError>> {
+    let mut s = lock().await;
+    let count = import_into(&mut s, document).await?;
+    persist_erase_counts(&mut s).await?;
+    Ok(count)
+}
+
+/// Exports the values of up to `N` keys of `s` into `buffer`, see [`export()`].
+async fn export_from<const N: usize, F, C>(
+    s: &mut Storage<F, C>,
+    buffer: &mut [u8],
+) -> Result<usize, TransferError<F::Error>>
+where
+    F: NorFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    let keys = s.keys_matching::<N>(|key| !is_reserved(key)).await?;
+    let mut encoder = Encoder::new(Cursor::new(buffer));
+
+    encoder
+        .begin_map()
+        .map_err(|_| TransferError::BufferTooSmall)?;
+    for key in keys {
+        // Keys are listed before reading the values, so any value is present.
+        let Some(value) = s.get_raw::<RawValue>(&key).await? else {
+            continue;
+        };
+        encoder
+            .str(&key)
+            .and_then(|encoder| encoder.bytes(&value))
+            .map_err(|_| TransferError::BufferTooSmall)?;
+    }
+    encoder.end().map_err(|_| TransferError::BufferTooSmall)?;
+
+    Ok(encoder.into_writer().position())
+}
+
+/// Imports the values of `document` into `s`, see [`import()`].
+async fn import_into<F, C>(
+    s: &mut Storage<F, C>,
+    document: &[u8],
+) -> Result<usize, TransferError<F::Error>>
+where
+    F: NorFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    let count = validate(document)?;
+
+    let mut decoder = Decoder::new(document);
+    for entry in decoder
+        .map_iter::<&str, &[u8]>()
+        .map_err(|_| TransferError::Malformed)?
+    {
+        let (key, value) = entry.map_err(|_| TransferError::Malformed)?;
+        if is_reserved(key) {
+            continue;
+        }
+        let value = RawValue::new(value).ok_or(TransferError::TooLarge)?;
+        s.insert_raw(key, value).await?;
+    }
+
+    Ok(count)
+}
+
+/// Checks that every entry of `document` can be stored.
+///
+/// Returns the number of entries that are not skipped for being reserved.
+fn validate<E>(document: &[u8]) -> Result<usize, TransferError<E>> {
+    let mut decoder = Decoder::new(document);
+    let mut count = 0;
+    for entry in decoder
+        .map_iter::<&str, &[u8]>()
+        .map_err(|_| TransferError::Malformed)?
+    {
+        let (key, value) = entry.map_err(|_| TransferError::Malformed)?;
+        if is_reserved(key) {
+            continue;
+        }
+        // Leaves room for the serialized key's length within the data buffer.
+        if key.len() > MAX_KEY_LEN || key.len() + value.len() + 4 > DATA_BUFFER_SIZE {
+            return Err(TransferError::TooLarge);
+        }
+        // Empty values can not be stored, and are never exported.
+        if value.is_empty() {
+            return Err(TransferError::Malformed);
+        }
+        count += 1;
+    }
+    if decoder.position() != document.len() {
+        return Err(TransferError::Malformed);
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::{MARKER_KEY, MARKER_VALUE, testing::storage};
+
+    #[test]
+    fn roundtrip() {
+        block_on(async {
+            let mut source = storage();
+            source.insert(MARKER_KEY, MARKER_VALUE).await.unwrap();
+            source.insert("a", 1u32).await.unwrap();
+            source.insert("b", [1u8, 2, 3]).await.unwrap();
+            source.insert("removed", 4u32).await.unwrap();
+            source.remove("removed").await.unwrap();
+
+            let mut buffer = [0; 64];
+            let len = export_from::<8, _, _>(&mut source, &mut buffer)
+                .await
+                .unwrap();
+
+            let mut target = storage();
+            target.insert("b", 9u32).await.unwrap();
+            target.insert("c", 5u32).await.unwrap();
+            let document = buffer.get(..len).unwrap();
+            assert_eq!(import_into(&mut target, document).await.unwrap(), 2);
+
+            assert_eq!(target.get::<u32>("a").await.unwrap(), Some(1));
+            assert_eq!(target.get::<[u8; 3]>("b").await.unwrap(), Some([1, 2, 3]));
+            assert_eq!(target.get::<u32>("c").await.unwrap(), Some(5));
+            assert_eq!(target.get::<u32>("removed").await.unwrap(), None);
+            assert_eq!(target.get::<u8>(MARKER_KEY).await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn rejects_malformed_documents_entirely() {
+        block_on(async {
+            let mut source = storage();
+            source.insert("a", 1u32).await.unwrap();
+            source.insert("b", 2u32).await.unwrap();
+            let mut buffer = [0; 64];
+            let len = export_from::<8, _, _>(&mut source, &mut buffer)
+                .await
+                .unwrap();
+
+            let mut target = storage();
+            // Cut off within the last entry.
+            let document = buffer.get(..len - 2).unwrap();
+            assert!(matches!(
+                import_into(&mut target, document).await,
+                Err(TransferError::Malformed)
+            ));
+            assert!(target.keys::<8>().await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn reports_small_buffers() {
+        block_on(async {
+            let mut source = storage();
+            source.insert("a", 1u32).await.unwrap();
+            let mut buffer = [0; 4];
+            assert!(matches!(
+                export_from::<8, _, _>(&mut source, &mut buffer).await,
+                Err(TransferError::BufferTooSmall)
+            ));
+        });
+    }
+}
@@ -1,7 +1,10 @@
 //! Provides key-value pair persistent storage on flash.
 //!
-//! Currently the same type used for serializing must be used for deserializing.
+//! With the untyped functions ([`get()`], [`insert()`]), the same type used for serializing must
+//! be used for deserializing.
 //! While not doing so won't cause unsafety, it might return garbage data, or panic.
+//! Values accessed through a [`StorageKey`] carry a version instead, and are checked (and
+//! possibly migrated) when they are read.
 
 #![cfg_attr(not(test), no_std)]
 #![deny(missing_docs)]
@@ -9,8 +12,16 @@
 // TODO: overhaul errors
 #![expect(clippy::missing_errors_doc)]
 
+mod blob;
+mod cbor;
+mod namespace;
 mod postcard_value;
 mod storage;
+#[cfg(test)]
+mod testing;
+mod transaction;
+mod typed;
+mod wear;
 
 use core::ops::Range;
 
@@ -23,14 +34,73 @@ use embassy_sync::{
     mutex::{Mutex, MutexGuard},
     once_lock::OnceLock,
 };
+#[cfg(not(context = "stm32"))]
+use embedded_storage_async::nor_flash::MultiwriteNorFlash;
+#[cfg(context = "stm32")]
+use embedded_storage_async::nor_flash::NorFlash;
+use sequential_storage::cache::KeyCacheImpl;
 
+pub use blob::{
+    BlobError, BlobReader, BlobWriter, MAX_BLOB_NAME_LEN, read_blob, remove_blob, write_blob,
+};
+pub use cbor::{TransferError, export, import};
+pub use namespace::Namespace;
 pub use storage::*;
+pub use transaction::{Transaction, TransactionFull};
+pub use typed::{Migration, SchemaError, StorageKey};
+pub use wear::EraseCounting;
+
+/// Number of flash pages of the global storage.
+pub const PAGE_COUNT: usize = ariel_os_utils::usize_from_env_or!(
+    "ARIEL_OS_STORAGE_PAGE_COUNT",
+    2,
+    "number of flash pages in the storage range (set by the build script)"
+);
 
-static STORAGE: OnceLock<Mutex<CriticalSectionRawMutex, Storage<Flash>>> = OnceLock::new();
+cfg_if::cfg_if! {
+    if #[cfg(feature = "cache-key-pointer")] {
+        /// Number of keys whose location is cached.
+        const CACHED_KEYS: usize = ariel_os_utils::usize_from_env_or!(
+            "CONFIG_STORAGE_CACHED_KEYS",
+            8,
+            "number of storage keys whose location is cached"
+        );
+
+        /// Cache of the global storage.
+        pub type Cache = sequential_storage::cache::KeyPointerCache<
+            PAGE_COUNT,
+            arrayvec::ArrayString<MAX_KEY_LEN>,
+            CACHED_KEYS,
+        >;
+    } else if #[cfg(feature = "cache-page-pointer")] {
+        /// Cache of the global storage.
+        pub type Cache = sequential_storage::cache::PagePointerCache<PAGE_COUNT>;
+    } else {
+        /// Cache of the global storage.
+        pub type Cache = sequential_storage::cache::NoCache;
+    }
+}
+
+/// Flash of the global storage.
+pub type GlobalFlash = EraseCounting<Flash, PAGE_COUNT>;
+
+static STORAGE: OnceLock<Mutex<CriticalSectionRawMutex, Storage<GlobalFlash, Cache>>> =
+    OnceLock::new();
 
 const MARKER_KEY: &str = "ARIEL_INIT_MARK";
 const MARKER_VALUE: u8 = 0;
 
+/// Key under which the erase counts of the pages are kept across reboots.
+const ERASE_COUNTS_KEY: &str = "ARIEL_ERASE_COUNTS";
+
+/// Keys used by the storage itself, which are not reported to users.
+const RESERVED_KEYS: &[&str] = &[MARKER_KEY, ERASE_COUNTS_KEY, transaction::JOURNAL_KEY];
+
+/// Returns whether `key` is used by the storage itself, including for blobs.
+fn is_reserved(key: &str) -> bool {
+    RESERVED_KEYS.contains(&key) || key.starts_with(blob::KEY_PREFIX)
+}
+
 /// Gets a [`Range`] from the linker that can be used for a global [`Storage`].
 ///
 /// This expects two symbols `__storage_start` and `__storage_end`.
@@ -69,8 +139,12 @@ fn init_(p: &mut OptionalPeripherals) {
     let flash_range = flash_range_from_linker();
     info!("storage: using flash range {:?}", &flash_range);
 
-    let flash = flash_init(p);
-    let _ = STORAGE.init(Mutex::new(Storage::new(flash, flash_range)));
+    let flash = EraseCounting::new(flash_init(p), flash_range.start);
+    let _ = STORAGE.init(Mutex::new(Storage::with_cache(
+        flash,
+        flash_range,
+        Cache::new(),
+    )));
 }
 
 /// Initializes the global storage.
@@ -91,11 +165,19 @@ pub async fn init(p: &mut OptionalPeripherals) {
     #[cfg(context = "rp")]
     embassy_time::block_for(embassy_time::Duration::from_millis(10));
 
+    if let Ok(Some(counts)) = get(ERASE_COUNTS_KEY).await {
+        lock().await.flash_mut().restore(counts);
+    }
+
     // Use a marker to ensure that this storage is initialized.
     if Ok(Some(MARKER_VALUE)) != get::<u8>(MARKER_KEY).await {
         ariel_os_debug::log::info!("storage: initializing");
         erase_all().await.unwrap();
     }
+
+    if transaction::recover().await.is_err() {
+        ariel_os_debug::log::error!("storage: failed to complete interrupted transaction");
+    }
 }
 
 /// Stores a key-value pair into flash memory.
@@ -105,7 +187,9 @@ pub async fn insert<'d, V>(key: &str, value: V) -> Result<(), sequential_storage
 where
     V: Serialize + Deserialize<'d> + Into<PostcardValue<V>>,
 {
-    lock().await.insert::<V>(key, value).await
+    let mut s = lock().await;
+    s.insert::<V>(key, value).await?;
+    persist_erase_counts(&mut s).await
 }
 
 /// Gets the last stored value from the flash that is associated with the given key.
@@ -125,23 +209,177 @@ where
 /// Additional calls to [`get()`] with the same key will return `None` until
 /// a new one is stored again.
 ///
+/// Where the flash allows it, the item is removed with [`Storage::remove_item()`], so nothing is
+/// kept for the key; otherwise, see [`Storage::remove()`] for how removals are stored.
+pub async fn remove(key: &str) -> Result<(), sequential_storage::Error<FlashError>> {
+    let mut s = lock().await;
+    remove_from(&mut s, key).await?;
+    persist_erase_counts(&mut s).await
+}
+
+/// Flash that [`remove_from()`] can remove items from.
+///
+/// STM32 flash drivers do not implement `MultiwriteNorFlash`, so removals are stored there.
+#[cfg(not(context = "stm32"))]
+pub(crate) trait RemovableFlash: MultiwriteNorFlash {}
+#[cfg(not(context = "stm32"))]
+impl<F: MultiwriteNorFlash> RemovableFlash for F {}
+/// Flash that [`remove_from()`] can remove items from.
+///
+/// STM32 flash drivers do not implement `MultiwriteNorFlash`, so removals are stored there.
+#[cfg(context = "stm32")]
+pub(crate) trait RemovableFlash: NorFlash {}
+#[cfg(context = "stm32")]
+impl<F: NorFlash> RemovableFlash for F {}
+
+/// Removes `key` from a storage, the way [`remove()`] does.
+pub(crate) async fn remove_from<F, C>(
+    s: &mut Storage<F, C>,
+    key: &str,
+) -> Result<(), sequential_storage::Error<F::Error>>
+where
+    F: RemovableFlash,
+    C: KeyCacheImpl<arrayvec::ArrayString<MAX_KEY_LEN>>,
+{
+    #[cfg(not(context = "stm32"))]
+    {
+        s.remove_item(key).await
+    }
+    #[cfg(context = "stm32")]
+    {
+        s.remove(key).await
+    }
+}
+
+/// Lists the keys that have a value stored.
+///
+/// At most `N` keys are returned; the order is not significant.
+///
 /// <div class="warning">
-/// This is really slow!
+/// This is slow, as all items in flash have to be read.
+/// </div>
+pub async fn keys<const N: usize>() -> Result<
+    arrayvec::ArrayVec<arrayvec::ArrayString<MAX_KEY_LEN>, N>,
+    sequential_storage::Error<FlashError>,
+> {
+    lock()
+        .await
+        .keys_matching::<N>(|key| !is_reserved(key))
+        .await
+}
+
+/// Returns an iterator over the keys that have a value stored, and their values in serialized
+/// form.
+///
+/// At most `N` keys are iterated over; the order is not significant. The global storage stays
+/// locked while the iterator exists.
 ///
-/// All items in flash have to be read and deserialized to find the items with the key.
-/// This is unlikely to be cached well.
+/// <div class="warning">
+/// This is slow, as all items in flash have to be read.
 /// </div>
-// STM32 flash drivers do not implement `MultiwriteNorFlash`.
-#[cfg(not(context = "stm32"))]
-pub async fn remove(key: &str) -> Result<(), sequential_storage::Error<FlashError>> {
-    lock().await.remove(key).await
+pub async fn iter<const N: usize>() -> Result<Iter<N>, sequential_storage::Error<FlashError>> {
+    let mut storage = lock().await;
+    let keys = storage.keys_matching::<N>(|key| !is_reserved(key)).await?;
+    Ok(Iter {
+        storage,
+        keys: keys.into_iter(),
+    })
+}
+
+/// Iterator over the stored keys and values, created through [`iter()`].
+pub struct Iter<const N: usize> {
+    storage: MutexGuard<'static, CriticalSectionRawMutex, Storage<GlobalFlash, Cache>>,
+    keys: arrayvec::IntoIter<arrayvec::ArrayString<MAX_KEY_LEN>, N>,
+}
+
+impl<const N: usize> Iter<N> {
+    /// Returns the next key and its value, or `None` when all keys have been iterated over.
+    pub async fn next(
+        &mut self,
+    ) -> Result<
+        Option<(arrayvec::ArrayString<MAX_KEY_LEN>, RawValue)>,
+        sequential_storage::Error<FlashError>,
+    > {
+        for key in self.keys.by_ref() {
+            // Keys are listed before iterating, so any value is present unless removed through
+            // other means.
+            if let Some(value) = self.storage.get_raw::<RawValue>(&key).await? {
+                return Ok(Some((key, value)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Reports how the storage's flash is used.
+///
+/// The current values of up to `N` keys are told apart from garbage.
+///
+/// See [`Storage::usage()`] for details.
+///
+/// <div class="warning">
+/// This is slow, as all of the storage's flash has to be read.
+/// </div>
+pub async fn usage<const N: usize>() -> Result<Usage, sequential_storage::Error<FlashError>> {
+    lock().await.usage::<N>().await
+}
+
+/// Returns how often each page of the storage's flash has been erased.
+///
+/// The counts are kept in storage across reboots; erases that happen through [`lock()`] are
+/// persisted with the next change through the global functions. Comparing the counts with the
+/// endurance given in the MCU's datasheet shows how far the storage is worn.
+pub async fn erase_counts() -> [u32; PAGE_COUNT] {
+    lock().await.flash_mut().counts()
+}
+
+impl<T> StorageKey<T> {
+    /// Gets the value of this key from the global storage.
+    ///
+    /// A value stored with another version of this key is upgraded through its migration, and
+    /// stored again in its upgraded form.
+    ///
+    /// If no value with the key is found, `None` is returned.
+    pub async fn get(&self) -> Result<Option<T>, SchemaError<FlashError>>
+    where
+        T: Serialize + for<'d> Deserialize<'d>,
+    {
+        let mut s = lock().await;
+        let value = s.get_typed(self).await?;
+        persist_erase_counts(&mut s).await?;
+        Ok(value)
+    }
+
+    /// Stores a value of this key into the global storage.
+    ///
+    /// It will overwrite the last value that has the same key.
+    pub async fn insert(&self, value: &T) -> Result<(), SchemaError<FlashError>>
+    where
+        T: Serialize,
+    {
+        let mut s = lock().await;
+        s.insert_typed(self, value).await?;
+        Ok(persist_erase_counts(&mut s).await?)
+    }
+}
+
+/// Stores the erase counts if they changed.
+async fn persist_erase_counts(
+    s: &mut Storage<GlobalFlash, Cache>,
+) -> Result<(), sequential_storage::Error<FlashError>> {
+    // Storing the counts may erase another page, which is then stored with the next change.
+    match s.flash_mut().take_changed() {
+        Some(counts) => s.insert(ERASE_COUNTS_KEY, counts).await,
+        None => Ok(()),
+    }
 }
 
 /// Resets the flash in the entire flash range.
 pub async fn erase_all() -> Result<(), sequential_storage::Error<FlashError>> {
     let mut s = lock().await;
     s.erase_all().await?;
-    s.insert(MARKER_KEY, MARKER_VALUE).await
+    s.insert(MARKER_KEY, MARKER_VALUE).await?;
+    persist_erase_counts(&mut s).await
 }
 
 /// Gets a [`MutexGuard`] of the global [`Storage`] object.
@@ -163,6 +401,7 @@ pub async fn erase_all() -> Result<(), sequential_storage::Error<FlashError>> {
 ///     s.insert("counter", value + 1).await.unwrap();
 /// }
 /// ```
-pub async fn lock() -> MutexGuard<'static, CriticalSectionRawMutex, storage::Storage<Flash>> {
+pub async fn lock()
+-> MutexGuard<'static, CriticalSectionRawMutex, storage::Storage<GlobalFlash, Cache>> {
     STORAGE.get().await.lock().await
 }
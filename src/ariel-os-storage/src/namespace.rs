@@ -0,0 +1,126 @@
+//! Isolated views of the global storage for individual subsystems.
+use arrayvec::{ArrayString, ArrayVec};
+
+use ariel_os_hal::hal::storage::FlashError;
+
+use crate::{
+    Deserialize, MAX_KEY_LEN, PostcardValue, Serialize, is_reserved, lock, persist_erase_counts,
+    remove_from,
+};
+
+/// A view of the global storage that only contains the keys starting with a prefix.
+///
+/// Keys passed to and returned from a [`Namespace`] do not include the prefix, so that several
+/// subsystems can use the same key names without clashing:
+///
+/// ```ignore
+/// static NETWORK: Namespace = Namespace::new("net/");
+///
+/// // Stored as "net/ssid".
+/// NETWORK.insert("ssid", ssid).await?;
+/// ```
+pub struct Namespace {
+    prefix: &'static str,
+}
+
+impl Namespace {
+    /// Creates the namespace of keys starting with `prefix`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix` is longer than [`MAX_KEY_LEN`].
+    #[must_use]
+    pub const fn new(prefix: &'static str) -> Self {
+        assert!(prefix.len() <= MAX_KEY_LEN, "prefix is too long");
+        Self { prefix }
+    }
+
+    /// Returns the prefix of the keys in this namespace.
+    #[must_use]
+    pub const fn prefix(&self) -> &'static str {
+        self.prefix
+    }
+
+    /// Returns the key under which `key` is stored in the global storage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefix and `key` together are longer than [`MAX_KEY_LEN`].
+    fn full_key(&self, key: &str) -> ArrayString<MAX_KEY_LEN> {
+        let mut full_key = ArrayString::new();
+        full_key.push_str(self.prefix);
+        full_key.push_str(key);
+        full_key
+    }
+
+    /// Stores a key-value pair into this namespace.
+    ///
+    /// It will overwrite the last value that has the same key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefix and `key` together are longer than [`MAX_KEY_LEN`].
+    pub async fn insert<'d, V>(
+        &self,
+        key: &str,
+        value: V,
+    ) -> Result<(), sequential_storage::Error<FlashError>>
+    where
+        V: Serialize + Deserialize<'d> + Into<PostcardValue<V>>,
+    {
+        let mut s = lock().await;
+        s.insert::<V>(&self.full_key(key), value).await?;
+        persist_erase_counts(&mut s).await
+    }
+
+    /// Gets the last stored value of the key from this namespace.
+    ///
+    /// If no value with the key is found, `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefix and `key` together are longer than [`MAX_KEY_LEN`].
+    pub async fn get<V>(
+        &self,
+        key: &str,
+    ) -> Result<Option<V>, sequential_storage::Error<FlashError>>
+    where
+        V: Serialize + for<'d> Deserialize<'d> + Into<PostcardValue<V>>,
+    {
+        lock().await.get(&self.full_key(key)).await
+    }
+
+    /// Deletes the key from this namespace.
+    ///
+    /// See [`remove()`](crate::remove()) for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefix and `key` together are longer than [`MAX_KEY_LEN`].
+    pub async fn remove(&self, key: &str) -> Result<(), sequential_storage::Error<FlashError>> {
+        let mut s = lock().await;
+        remove_from(&mut s, &self.full_key(key)).await?;
+        persist_erase_counts(&mut s).await
+    }
+
+    /// Lists the keys that have a value stored in this namespace, without the prefix.
+    ///
+    /// At most `N` keys are returned; the order is not significant.
+    ///
+    /// <div class="warning">
+    /// This is slow, as all items in flash have to be read.
+    /// </div>
+    pub async fn keys<const N: usize>(
+        &self,
+    ) -> Result<ArrayVec<ArrayString<MAX_KEY_LEN>, N>, sequential_storage::Error<FlashError>> {
+        let full_keys = lock()
+            .await
+            .keys_matching::<N>(|key| key.starts_with(self.prefix) && !is_reserved(key))
+            .await?;
+        Ok(full_keys
+            .iter()
+            .filter_map(|key| key.strip_prefix(self.prefix))
+            .filter_map(|key| ArrayString::from(key).ok())
+            .collect())
+    }
+}
@@ -1,13 +1,13 @@
 //! Storage module wrapping [`sequential_storage`] in an object together with
 //! a flash range and backend.
-use core::ops::Range;
+use core::ops::{Deref, Range};
 
-use arrayvec::ArrayString;
+use arrayvec::{ArrayString, ArrayVec};
 use embedded_storage_async::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash};
 use sequential_storage::{
-    cache::NoCache,
+    cache::{KeyCacheImpl, NoCache},
     erase_all,
-    map::{Value, fetch_item, remove_item, store_item},
+    map::{SerializationError, Value, fetch_all_items, fetch_item, remove_item, store_item},
 };
 
 pub use crate::postcard_value::PostcardValue;
@@ -15,29 +15,60 @@ pub use serde::{Deserialize, Serialize};
 
 /// Maximum key length.
 pub const MAX_KEY_LEN: usize = 64usize;
-/// Data buffer length.
-pub const DATA_BUFFER_SIZE: usize = 128usize;
+/// Data buffer length, which limits the length of a key and its value together.
+///
+/// This can be configured through `CONFIG_STORAGE_DATA_BUFFER_SIZE`. Larger values need to fit
+/// into a flash page, and increase the stack usage of storage operations. For values that are
+/// larger still, see [`write_blob()`](crate::write_blob()).
+pub const DATA_BUFFER_SIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_STORAGE_DATA_BUFFER_SIZE",
+    128,
+    "size of the buffer holding a storage key and value"
+);
+
+/// Length of an item's header in flash.
+const ITEM_HEADER_LEN: usize = 8;
 
 /// Object holding an instance of a key-value pair storage.
 ///
 /// You should probably look into using the global instance accessible via
 /// `ariel_os_storage::storage::{get,insert,remove}`.
-pub struct Storage<F> {
+///
+/// The cache `C` (see [`sequential_storage::cache`]) speeds up operations by remembering the
+/// state of pages and the location of items. It is only valid as long as the flash range is
+/// exclusively accessed through this instance.
+pub struct Storage<F, C = NoCache> {
     flash: F,
     storage_range: Range<u32>,
+    cache: C,
 }
 
 impl<F: NorFlash> Storage<F> {
-    /// Creates a new [`Storage`] instance.
+    /// Creates a new [`Storage`] instance without a cache.
     pub const fn new(flash: F, storage_range: Range<u32>) -> Storage<F> {
+        Self::with_cache(flash, storage_range, NoCache::new())
+    }
+}
+
+impl<F: NorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> Storage<F, C> {
+    /// Creates a new [`Storage`] instance that uses the given (fresh) cache.
+    pub const fn with_cache(flash: F, storage_range: Range<u32>, cache: C) -> Storage<F, C> {
         Self {
             flash,
             storage_range,
+            cache,
         }
     }
 
+    /// Returns the flash this instance operates on.
+    pub(crate) fn flash_mut(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
     /// Gets a [`Value`] from this [`Storage`] instance.
     ///
+    /// If no value with the key is found, or it was removed, `None` is returned.
+    ///
     /// # Panics
     ///
     /// Currently panics if `key.len() > MAX_KEY_LEN`.
@@ -48,18 +79,22 @@ impl<F: NorFlash> Storage<F> {
         let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
         let mut data_buffer = [0; DATA_BUFFER_SIZE];
 
-        fetch_item::<_, V, _>(
+        let entry = fetch_item::<_, Entry<V>, _>(
             &mut self.flash,
             self.storage_range.clone(),
-            &mut NoCache::new(),
+            &mut self.cache,
             &mut data_buffer,
             &key,
         )
-        .await
+        .await?;
+        Ok(entry.and_then(Entry::into_value))
     }
 
     /// Inserts a [`Value`] into this [`Storage`] instance.
     ///
+    /// Values that serialize to zero bytes are rejected with
+    /// [`SerializationError::InvalidData`], as they could not be told apart from removed ones.
+    ///
     /// # Panics
     ///
     /// Currently panics if `key.len() > MAX_KEY_LEN`.
@@ -67,16 +102,25 @@ impl<F: NorFlash> Storage<F> {
         &mut self,
         key: &str,
         value: V,
+    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
+        self.store_entry(key, Entry::Present(value)).await
+    }
+
+    /// Stores an [`Entry`] for the key.
+    async fn store_entry<'d, V: Value<'d>>(
+        &mut self,
+        key: &str,
+        entry: Entry<V>,
     ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
         let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
         let mut data_buffer = [0; DATA_BUFFER_SIZE];
         store_item(
             &mut self.flash,
             self.storage_range.clone(),
-            &mut NoCache::new(),
+            &mut self.cache,
             &mut data_buffer,
             &key,
-            &value,
+            &entry,
         )
         .await
     }
@@ -84,6 +128,9 @@ impl<F: NorFlash> Storage<F> {
     /// Stores a key-value pair into flash memory.
     ///
     /// It will overwrite the last value that has the same key.
+    ///
+    /// Values that serialize to zero bytes (e.g., `()`) are rejected, as they could not be told
+    /// apart from removed ones.
     pub async fn insert<'d, V>(
         &mut self,
         key: &str,
@@ -109,45 +156,219 @@ impl<F: NorFlash> Storage<F> {
     where
         V: Serialize + for<'d> Deserialize<'d> + Into<PostcardValue<V>>,
     {
-        let key = ArrayString::<MAX_KEY_LEN>::from(key).unwrap();
+        let postcard_value = self.get_raw::<PostcardValue<V>>(key).await?;
+        Ok(postcard_value.map(PostcardValue::into_inner))
+    }
+
+    /// Deletes an item from flash.
+    ///
+    /// Additional calls to [`Storage::get()`] with the same key will return `None` until
+    /// a new one is stored again.
+    ///
+    /// The removal is recorded by storing an empty value for the key, which works on any flash;
+    /// the empty value is kept until a later value for the key replaces it. On
+    /// [`MultiwriteNorFlash`], [`Storage::remove_item()`] does not leave it behind.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`.
+    pub async fn remove(
+        &mut self,
+        key: &str,
+    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
+        self.store_entry(key, Entry::<&[u8]>::Removed).await
+    }
+
+    /// Lists the keys that have a value stored in this [`Storage`] instance.
+    ///
+    /// At most `N` keys are returned; the order is not significant.
+    ///
+    /// <div class="warning">
+    /// This is slow, as all items in flash have to be read.
+    /// </div>
+    pub async fn keys<const N: usize>(
+        &mut self,
+    ) -> Result<
+        ArrayVec<ArrayString<MAX_KEY_LEN>, N>,
+        sequential_storage::Error<<F as ErrorType>::Error>,
+    > {
+        self.keys_with_prefix("").await
+    }
+
+    /// Lists the keys starting with `prefix` that have a value stored in this [`Storage`]
+    /// instance.
+    ///
+    /// At most `N` keys are returned; the order is not significant.
+    ///
+    /// <div class="warning">
+    /// This is slow, as all items in flash have to be read.
+    /// </div>
+    pub async fn keys_with_prefix<const N: usize>(
+        &mut self,
+        prefix: &str,
+    ) -> Result<
+        ArrayVec<ArrayString<MAX_KEY_LEN>, N>,
+        sequential_storage::Error<<F as ErrorType>::Error>,
+    > {
+        self.keys_matching(|key| key.starts_with(prefix)).await
+    }
+
+    /// Lists the keys accepted by `filter` that have a value stored in this [`Storage`]
+    /// instance.
+    ///
+    /// At most `N` keys are returned; keys that are not accepted do not count towards them.
+    pub(crate) async fn keys_matching<const N: usize>(
+        &mut self,
+        filter: impl Fn(&str) -> bool,
+    ) -> Result<
+        ArrayVec<ArrayString<MAX_KEY_LEN>, N>,
+        sequential_storage::Error<<F as ErrorType>::Error>,
+    > {
         let mut data_buffer = [0; DATA_BUFFER_SIZE];
+        let mut items = fetch_all_items::<ArrayString<MAX_KEY_LEN>, _, _>(
+            &mut self.flash,
+            self.storage_range.clone(),
+            &mut self.cache,
+            &mut data_buffer,
+        )
+        .await?;
 
-        let postcard_value = fetch_item::<_, PostcardValue<V>, _>(
+        let mut keys = ArrayVec::<_, N>::new();
+        // Items are iterated oldest first; values are not decoded, except for telling removals
+        // apart. Keys beyond the first `N` are skipped, but removals are still tracked.
+        while let Some((key, value)) = items.next::<&[u8]>(&mut data_buffer).await? {
+            if !filter(&key) {
+                continue;
+            }
+            let position = keys.iter().position(|k| *k == key);
+            match (position, value.is_empty()) {
+                (Some(position), true) => {
+                    keys.swap_remove(position);
+                }
+                (None, false) => {
+                    // Full lists are handled by skipping further keys.
+                    let _ = keys.try_push(key);
+                }
+                _ => {}
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Reports how the flash range of this [`Storage`] instance is used.
+    ///
+    /// Values of up to `N` keys are told apart from garbage; values of further keys are counted
+    /// as garbage. The figures are estimates, as they do not account for all the bookkeeping
+    /// overhead of [`sequential_storage`].
+    ///
+    /// <div class="warning">
+    /// This is slow, as the entire flash range has to be read.
+    /// </div>
+    pub async fn usage<const N: usize>(
+        &mut self,
+    ) -> Result<Usage, sequential_storage::Error<<F as ErrorType>::Error>> {
+        #[expect(clippy::cast_possible_truncation, reason = "flash offsets are u32")]
+        let page_size = F::ERASE_SIZE as u32;
+        let page_count = (self.storage_range.end - self.storage_range.start) / page_size;
+
+        // Items are appended to pages, so whatever is erased at the end of a page is free. One
+        // page is always kept erased to move live items into during garbage collection.
+        let mut erased = 0;
+        for page in 0..page_count {
+            let start = self.storage_range.start + page * page_size;
+            erased += self.erased_tail(start..start + page_size).await?;
+        }
+
+        let mut data_buffer = [0; DATA_BUFFER_SIZE];
+        let mut items = fetch_all_items::<ArrayString<MAX_KEY_LEN>, _, _>(
             &mut self.flash,
             self.storage_range.clone(),
-            &mut NoCache::new(),
+            &mut self.cache,
             &mut data_buffer,
-            &key,
         )
         .await?;
-        Ok(postcard_value.map(PostcardValue::into_inner))
+        // Items are iterated oldest first, so the last item of a key holds its current value.
+        let mut latest = ArrayVec::<(ArrayString<MAX_KEY_LEN>, u32), N>::new();
+        while let Some((key, value)) = items.next::<&[u8]>(&mut data_buffer).await? {
+            // Removed keys have no current value.
+            let size = if value.is_empty() {
+                0
+            } else {
+                item_size::<F>(key.len() + 1 + value.len())
+            };
+            if let Some((_, latest_size)) = latest.iter_mut().find(|(k, _)| *k == key) {
+                *latest_size = size;
+            } else if latest.try_push((key, size)).is_err() {
+                break;
+            }
+        }
+
+        let capacity = page_count.saturating_sub(1) * page_size;
+        let free = erased.saturating_sub(page_size).min(capacity);
+        let live = latest.iter().map(|(_, size)| size).sum::<u32>();
+        Ok(Usage {
+            capacity,
+            free,
+            live: live.min(capacity - free),
+        })
+    }
+
+    /// Returns how many bytes at the end of `range` are erased.
+    async fn erased_tail(
+        &mut self,
+        range: Range<u32>,
+    ) -> Result<u32, sequential_storage::Error<<F as ErrorType>::Error>> {
+        // Needs to be a multiple of the flash's read size, which it is on all supported MCUs.
+        const CHUNK_LEN: u32 = 32;
+
+        let mut erased = 0;
+        let mut chunk = [0; CHUNK_LEN as usize];
+        let mut end = range.end;
+        while end > range.start {
+            let start = end.saturating_sub(CHUNK_LEN).max(range.start);
+            #[expect(clippy::indexing_slicing, reason = "at most CHUNK_LEN long")]
+            let chunk = &mut chunk[..(end - start) as usize];
+            self.flash
+                .read(start, chunk)
+                .await
+                .map_err(|value| sequential_storage::Error::Storage { value })?;
+
+            let erased_in_chunk = chunk.iter().rev().take_while(|b| **b == 0xff).count();
+            #[expect(clippy::cast_possible_truncation, reason = "at most CHUNK_LEN")]
+            let erased_in_chunk = erased_in_chunk as u32;
+            erased += erased_in_chunk;
+            if erased_in_chunk < end - start {
+                break;
+            }
+            end = start;
+        }
+        Ok(erased)
     }
 
     /// Resets the flash in the entire flash range of this [`Storage`] instance.
     pub async fn erase_all(
         &mut self,
-    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
-        erase_all(&mut self.flash, self.storage_range.clone()).await
+    ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>>
+    where
+        C: Default,
+    {
+        erase_all(&mut self.flash, self.storage_range.clone()).await?;
+        // The cache describes the previous state of the flash.
+        self.cache = C::default();
+        Ok(())
     }
 }
 
-impl<F: MultiwriteNorFlash> Storage<F> {
-    /// Deletes an item from flash.
-    ///
-    /// Additional calls to [`Storage::get()`] with the same key will return `None` until
-    /// a new one is stored again.
+impl<F: MultiwriteNorFlash, C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>> Storage<F, C> {
+    /// Deletes an item from flash, without storing anything for its key.
     ///
-    /// <div class="warning">
-    /// This is really slow!
-    ///
-    /// All items in flash have to be read and deserialized to find the items with the key.
-    /// This is unlikely to be cached well.
-    /// </div>
+    /// Unlike with [`Storage::remove()`], the items of the key are marked as erased in place, so
+    /// that garbage collection drops them entirely.
     ///
     /// # Panics
     ///
     /// Currently panics if `key.len() > MAX_KEY_LEN`.
-    pub async fn remove(
+    pub async fn remove_item(
         &mut self,
         key: &str,
     ) -> Result<(), sequential_storage::Error<<F as ErrorType>::Error>> {
@@ -156,10 +377,258 @@ impl<F: MultiwriteNorFlash> Storage<F> {
         remove_item(
             &mut self.flash,
             self.storage_range.clone(),
-            &mut NoCache::new(),
+            &mut self.cache,
             &mut data_buffer,
             &key,
         )
         .await
     }
 }
+
+/// A stored value, or the marker that the key was removed.
+///
+/// On plain [`NorFlash`], removals are stored as empty values, as an item can not be removed
+/// without erasing its page; present values are therefore never empty.
+enum Entry<V> {
+    Removed,
+    Present(V),
+}
+
+impl<V> Entry<V> {
+    fn into_value(self) -> Option<V> {
+        match self {
+            Entry::Removed => None,
+            Entry::Present(value) => Some(value),
+        }
+    }
+}
+
+impl<'d, V: Value<'d>> Value<'d> for Entry<V> {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        match self {
+            Entry::Removed => Ok(0),
+            Entry::Present(value) => match value.serialize_into(buffer)? {
+                0 => Err(SerializationError::InvalidData),
+                len => Ok(len),
+            },
+        }
+    }
+
+    fn deserialize_from(buffer: &'d [u8]) -> Result<(Self, usize), SerializationError> {
+        if buffer.is_empty() {
+            return Ok((Entry::Removed, 0));
+        }
+        let (value, used) = V::deserialize_from(buffer)?;
+        Ok((Entry::Present(value), used))
+    }
+}
+
+/// A value in its serialized form, as it is stored in flash.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RawValue(ArrayVec<u8, DATA_BUFFER_SIZE>);
+
+impl RawValue {
+    /// Creates a [`RawValue`] from its serialized form.
+    ///
+    /// Returns `None` if `bytes` is longer than [`DATA_BUFFER_SIZE`].
+    #[must_use]
+    pub fn new(bytes: &[u8]) -> Option<Self> {
+        ArrayVec::try_from(bytes).ok().map(Self)
+    }
+}
+
+impl Deref for RawValue {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Value<'_> for RawValue {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        buffer
+            .get_mut(..self.0.len())
+            .ok_or(SerializationError::BufferTooSmall)?
+            .copy_from_slice(&self.0);
+        Ok(self.0.len())
+    }
+
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+        let value = Self::new(buffer).ok_or(SerializationError::InvalidData)?;
+        Ok((value, buffer.len()))
+    }
+}
+
+/// Returns the space an item with `data_len` bytes of serialized key and value takes in flash.
+#[expect(
+    clippy::cast_possible_truncation,
+    reason = "items are smaller than a page"
+)]
+fn item_size<F: NorFlash>(data_len: usize) -> u32 {
+    (ITEM_HEADER_LEN.next_multiple_of(F::WRITE_SIZE) + data_len.next_multiple_of(F::WRITE_SIZE))
+        as u32
+}
+
+/// Usage of the flash range of a [`Storage`], as reported by [`Storage::usage()`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Usage {
+    /// Bytes that can hold items.
+    pub capacity: u32,
+    /// Bytes not used yet.
+    pub free: u32,
+    /// Bytes taken by the current values of keys.
+    pub live: u32,
+}
+
+impl Usage {
+    /// Returns the bytes taken by outdated or removed values.
+    ///
+    /// These are reclaimed when their page is erased during garbage collection.
+    #[must_use]
+    pub fn garbage(&self) -> u32 {
+        self.capacity.saturating_sub(self.free + self.live)
+    }
+
+    /// Returns the share of used bytes that are garbage, in percent.
+    #[must_use]
+    #[expect(clippy::cast_possible_truncation, reason = "at most 100")]
+    pub fn garbage_percent(&self) -> u8 {
+        let used = self.capacity.saturating_sub(self.free);
+        if used == 0 {
+            return 0;
+        }
+        (u64::from(self.garbage()) * 100 / u64::from(used)) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::testing::{MockFlash, storage};
+
+    #[test]
+    fn usage_tells_current_values_from_garbage() {
+        block_on(async {
+            let mut s = storage();
+            let usage = s.usage::<8>().await.unwrap();
+            // One of the four pages is kept erased.
+            assert_eq!(usage.capacity, 3 * 1024);
+            assert_eq!(usage.free, usage.capacity);
+            assert_eq!(usage.live, 0);
+
+            // The item header, and the key and value with their lengths.
+            let item = item_size::<MockFlash>(3);
+            s.insert("a", 1u32).await.unwrap();
+            let usage = s.usage::<8>().await.unwrap();
+            assert_eq!(usage.live, item);
+            assert!(usage.free <= usage.capacity - item);
+
+            s.insert("a", 2u32).await.unwrap();
+            let usage = s.usage::<8>().await.unwrap();
+            assert_eq!(usage.live, item);
+            assert!(usage.garbage() >= item);
+
+            s.remove("a").await.unwrap();
+            let usage = s.usage::<8>().await.unwrap();
+            assert_eq!(usage.live, 0);
+            assert!(usage.garbage() >= 2 * item);
+            assert!(usage.garbage_percent() > 0);
+        });
+    }
+
+    #[test]
+    fn lists_each_stored_key_once() {
+        block_on(async {
+            let mut s = storage();
+            s.insert("ns/a", 1u32).await.unwrap();
+            s.insert("ns/b", 2u32).await.unwrap();
+            s.insert("other", 3u32).await.unwrap();
+            s.insert("ns/a", 4u32).await.unwrap();
+
+            let keys = s.keys_with_prefix::<8>("ns/").await.unwrap();
+            let mut keys: Vec<_> = keys.iter().map(ArrayString::as_str).collect();
+            keys.sort_unstable();
+            assert_eq!(keys, ["ns/a", "ns/b"]);
+            assert_eq!(s.keys::<8>().await.unwrap().len(), 3);
+
+            s.remove("ns/b").await.unwrap();
+            let keys = s.keys_with_prefix::<8>("ns/").await.unwrap();
+            assert_eq!(keys.len(), 1);
+            assert_eq!(keys.first().map(ArrayString::as_str), Some("ns/a"));
+
+            // Stored again after being removed.
+            s.insert("ns/b", 5u32).await.unwrap();
+            assert_eq!(s.keys_with_prefix::<8>("ns/").await.unwrap().len(), 2);
+        });
+    }
+
+    #[test]
+    fn removals_are_stored_as_empty_values() {
+        let mut buffer = [0; 8];
+        assert_eq!(
+            Entry::<&[u8]>::Removed.serialize_into(&mut buffer).unwrap(),
+            0
+        );
+        assert!(matches!(
+            Entry::<&[u8]>::deserialize_from(&[]),
+            Ok((Entry::Removed, 0))
+        ));
+        assert!(matches!(
+            Entry::<&[u8]>::deserialize_from(&[1, 2]),
+            Ok((Entry::Present([1, 2]), 2))
+        ));
+        // A present empty value would read back as a removal.
+        assert!(matches!(
+            Entry::Present(&[][..]).serialize_into(&mut buffer),
+            Err(SerializationError::InvalidData)
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_values() {
+        block_on(async {
+            let mut s = storage();
+            assert!(matches!(
+                s.insert("unit", ()).await,
+                Err(sequential_storage::Error::SerializationError(
+                    SerializationError::InvalidData
+                ))
+            ));
+            assert!(s.insert_raw("raw", &[][..]).await.is_err());
+            assert!(s.keys::<8>().await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn removes_values() {
+        block_on(async {
+            let mut s = storage();
+            s.insert("a", 1u32).await.unwrap();
+            s.insert("b", 2u32).await.unwrap();
+
+            s.remove("a").await.unwrap();
+            assert_eq!(s.get::<u32>("a").await.unwrap(), None);
+            s.remove_item("b").await.unwrap();
+            assert_eq!(s.get::<u32>("b").await.unwrap(), None);
+            assert!(s.keys::<8>().await.unwrap().is_empty());
+            assert_eq!(s.usage::<8>().await.unwrap().live, 0);
+
+            s.insert("a", 3u32).await.unwrap();
+            assert_eq!(s.get::<u32>("a").await.unwrap(), Some(3));
+        });
+    }
+
+    #[test]
+    fn lists_at_most_n_keys() {
+        block_on(async {
+            let mut s = storage();
+            for key in ["a", "b", "c"] {
+                s.insert(key, 1u32).await.unwrap();
+            }
+            assert_eq!(s.keys::<2>().await.unwrap().len(), 2);
+        });
+    }
+}
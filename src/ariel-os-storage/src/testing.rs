@@ -0,0 +1,20 @@
+//! Helpers for testing on a mock flash.
+use sequential_storage::mock_flash::{MockFlashBase, WriteCountCheck};
+
+use crate::Storage;
+
+/// Four pages of 1 KiB, written in words of 4 bytes.
+pub(crate) type MockFlash = MockFlashBase<4, 4, 256>;
+
+/// Size of a [`MockFlash`].
+pub(crate) const FLASH_SIZE: u32 = 4 * 1024;
+
+/// Returns an erased [`MockFlash`].
+pub(crate) fn flash() -> MockFlash {
+    MockFlash::new(WriteCountCheck::Twice, None, true)
+}
+
+/// Returns a [`Storage`] on an erased [`MockFlash`].
+pub(crate) fn storage() -> Storage<MockFlash> {
+    Storage::new(flash(), 0..FLASH_SIZE)
+}
@@ -0,0 +1,229 @@
+//! Atomic updates of several keys.
+use ariel_os_hal::hal::storage::FlashError;
+use arrayvec::{ArrayString, ArrayVec};
+use sequential_storage::cache::KeyCacheImpl;
+use serde::Serialize;
+
+use crate::{
+    DATA_BUFFER_SIZE, MAX_KEY_LEN, RawValue, RemovableFlash, Storage, lock, persist_erase_counts,
+    remove_from,
+};
+
+/// Key under which a transaction is kept while it is applied.
+pub(crate) const JOURNAL_KEY: &str = "ARIEL_JOURNAL";
+
+/// Length available for a transaction's changes, leaving room for the serialized journal key.
+const JOURNAL_LEN: usize = DATA_BUFFER_SIZE - JOURNAL_KEY.len() - 4;
+
+/// A batch of insertions and removals that takes effect atomically.
+///
+/// On [`commit()`](Transaction::commit()), the batch is stored as a single item before it is
+/// applied key by key. If power is lost or writing fails while applying it, it is applied again
+/// by the next commit or during the next initialization, so that eventually either all or none of
+/// the changes are in place. A transaction that could not be stored is never applied.
+///
+/// All changes, including their keys, need to fit into a single item of [`DATA_BUFFER_SIZE`].
+///
+/// Example:
+///
+/// ```ignore
+/// let mut transaction = Transaction::new();
+/// transaction.insert("wifi/ssid", &ssid)?;
+/// transaction.insert("wifi/password", &password)?;
+/// transaction.remove("wifi/last-error")?;
+/// transaction.commit().await?;
+/// ```
+#[derive(Default)]
+pub struct Transaction {
+    /// Changes as a sequence of postcard-encoded key and serialized value pairs; an empty value
+    /// removes the key.
+    journal: ArrayVec<u8, JOURNAL_LEN>,
+}
+
+/// The change does not fit into the [`Transaction`] any more.
+#[derive(Debug)]
+pub struct TransactionFull;
+
+impl Transaction {
+    /// Creates an empty transaction.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds storing a key-value pair to the transaction.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`, or if the value serializes to zero bytes
+    /// (e.g., `()`), as such values can not be stored.
+    pub fn insert<V: Serialize>(&mut self, key: &str, value: &V) -> Result<(), TransactionFull> {
+        let mut buffer = [0; DATA_BUFFER_SIZE];
+        let value = postcard::to_slice(value, &mut buffer).map_err(|_| TransactionFull)?;
+        assert!(!value.is_empty(), "empty values can not be stored");
+        self.push(key, value)
+    }
+
+    /// Adds deleting a key to the transaction.
+    ///
+    /// # Panics
+    ///
+    /// Currently panics if `key.len() > MAX_KEY_LEN`.
+    pub fn remove(&mut self, key: &str) -> Result<(), TransactionFull> {
+        self.push(key, &[])
+    }
+
+    fn push(&mut self, key: &str, value: &[u8]) -> Result<(), TransactionFull> {
+        assert!(key.len() <= MAX_KEY_LEN, "key is too long");
+        let mut buffer = [0; JOURNAL_LEN];
+        let change = postcard::to_slice(&(key, value), &mut buffer).map_err(|_| TransactionFull)?;
+        self.journal
+            .try_extend_from_slice(change)
+            .map_err(|_| TransactionFull)
+    }
+
+    /// Applies all changes of the transaction to the global storage.
+    ///
+    /// If this returns an error, the transaction either was not stored, in which case none of
+    /// its changes are applied, or it was stored but not completely applied, in which case the
+    /// remaining changes are applied by the next [`commit()`](Transaction::commit()) or during the
+    /// next initialization.
+    pub async fn commit(self) -> Result<(), sequential_storage::Error<FlashError>> {
+        if self.journal.is_empty() {
+            return Ok(());
+        }
+
+        let mut s = lock().await;
+        commit_to(&mut s, &self.journal).await?;
+        persist_erase_counts(&mut s).await
+    }
+}
+
+/// Applies a transaction that was interrupted by a power loss.
+pub(crate) async fn recover() -> Result<(), sequential_storage::Error<FlashError>> {
+    recover_in(&mut *lock().await).await
+}
+
+/// Completes the transaction whose journal is stored in `s`, if there is any.
+async fn recover_in<F, C>(s: &mut Storage<F, C>) -> Result<(), sequential_storage::Error<F::Error>>
+where
+    F: RemovableFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    if let Some(journal) = s.get_raw::<RawValue>(JOURNAL_KEY).await? {
+        ariel_os_debug::log::info!("storage: completing interrupted transaction");
+        apply(s, &journal).await?;
+    }
+    Ok(())
+}
+
+/// Stores `journal` and applies its changes to `s`.
+///
+/// A transaction that was not completely applied before is completed first, as its journal
+/// would be replaced otherwise.
+async fn commit_to<F, C>(
+    s: &mut Storage<F, C>,
+    journal: &[u8],
+) -> Result<(), sequential_storage::Error<F::Error>>
+where
+    F: RemovableFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    recover_in(s).await?;
+    if let Err(e) = s.insert_raw(JOURNAL_KEY, journal).await {
+        // The journal may have been written nonetheless; as the commit fails, it must not be
+        // applied later.
+        if remove_from(s, JOURNAL_KEY).await.is_err() {
+            ariel_os_debug::log::error!("storage: failed to discard transaction");
+        }
+        return Err(e);
+    }
+    // If this fails, the journal is kept, so that the changes are completed later.
+    apply(s, journal).await
+}
+
+/// Applies the changes of `journal`, and removes it once they are in place.
+async fn apply<F, C>(
+    s: &mut Storage<F, C>,
+    journal: &[u8],
+) -> Result<(), sequential_storage::Error<F::Error>>
+where
+    F: RemovableFlash,
+    C: KeyCacheImpl<ArrayString<MAX_KEY_LEN>>,
+{
+    let mut changes = journal;
+    while !changes.is_empty() {
+        let ((key, value), rest) = postcard::take_from_bytes::<(&str, &[u8])>(changes)
+            .map_err(|_| sequential_storage::Error::Corrupted {})?;
+        // An empty value removes the key.
+        if value.is_empty() {
+            remove_from(s, key).await?;
+        } else {
+            s.insert_raw(key, value).await?;
+        }
+        changes = rest;
+    }
+    remove_from(s, JOURNAL_KEY).await
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::testing::storage;
+
+    fn transaction() -> Transaction {
+        let mut transaction = Transaction::new();
+        transaction.insert("a", &1u32).unwrap();
+        transaction.remove("b").unwrap();
+        transaction
+    }
+
+    #[test]
+    fn recovers_stored_journal() {
+        block_on(async {
+            let mut s = storage();
+            s.insert("b", 2u32).await.unwrap();
+            // As if power was lost right after storing the journal.
+            s.insert_raw(JOURNAL_KEY, &*transaction().journal)
+                .await
+                .unwrap();
+
+            recover_in(&mut s).await.unwrap();
+
+            assert_eq!(s.get::<u32>("a").await.unwrap(), Some(1));
+            assert_eq!(s.get::<u32>("b").await.unwrap(), None);
+            assert!(s.get_raw::<RawValue>(JOURNAL_KEY).await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn failed_commits_are_completed_or_not_applied() {
+        block_on(async {
+            let transaction = transaction();
+            let mut completed_later = false;
+            // Makes the flash fail once at every point of the commit in turn.
+            for bytes_until_shutdown in 0.. {
+                let mut s = storage();
+                s.insert("b", 2u32).await.unwrap();
+                s.flash_mut().bytes_until_shutdown = Some(bytes_until_shutdown);
+                if commit_to(&mut s, &transaction.journal).await.is_ok() {
+                    break;
+                }
+
+                recover_in(&mut s).await.unwrap();
+
+                let a = s.get::<u32>("a").await.unwrap();
+                let b = s.get::<u32>("b").await.unwrap();
+                match (a, b) {
+                    (Some(1), None) => completed_later = true,
+                    (None, Some(2)) => {}
+                    other => panic!("transaction partially applied: {other:?}"),
+                }
+                assert!(s.get_raw::<RawValue>(JOURNAL_KEY).await.unwrap().is_none());
+            }
+            assert!(completed_later);
+        });
+    }
+}
@@ -0,0 +1,271 @@
+//! Typed and versioned keys.
+//!
+//! Values stored through a [`StorageKey`] are preceded by the key's version, so that reading them
+//! with a different version fails cleanly instead of producing garbage, or upgrades them through
+//! the key's [`Migration`].
+use core::marker::PhantomData;
+
+use arrayvec::ArrayVec;
+use embedded_storage_async::nor_flash::{ErrorType, NorFlash};
+use sequential_storage::{
+    cache::KeyCacheImpl,
+    map::{SerializationError, Value},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{DATA_BUFFER_SIZE, MAX_KEY_LEN, Storage};
+
+/// Marks values that carry a version, as opposed to values stored through the untyped API.
+const ENVELOPE_MARKER: [u8; 2] = [0xa7, 0x1e];
+
+/// Upgrades a value stored with an earlier version of a [`StorageKey`].
+///
+/// It is called with the stored version and the value's postcard encoding, and returns the value
+/// in the key's current type, or `None` if it can not be upgraded. Values stored through the
+/// untyped API (e.g., [`insert()`](crate::insert())) are passed as version 0.
+pub type Migration<T> = fn(version: u16, bytes: &[u8]) -> Option<T>;
+
+/// A key whose values are of type `T`.
+///
+/// Example:
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct Config { interval_s: u32, threshold: i16 }
+///
+/// static CONFIG: StorageKey<Config> = StorageKey::new("config", 2).with_migration(|version, bytes| {
+///     match version {
+///         // Version 1 only had the interval.
+///         1 => postcard::from_bytes(bytes).ok().map(|interval_s| Config { interval_s, threshold: 0 }),
+///         _ => None,
+///     }
+/// });
+///
+/// let config = CONFIG.get().await?;
+/// ```
+pub struct StorageKey<T> {
+    name: &'static str,
+    version: u16,
+    migration: Option<Migration<T>>,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> StorageKey<T> {
+    /// Creates a key stored under `name`, whose values are stored with `version`.
+    ///
+    /// The version needs to be changed whenever the serialized form of `T` changes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is longer than [`MAX_KEY_LEN`].
+    #[must_use]
+    pub const fn new(name: &'static str, version: u16) -> Self {
+        assert!(name.len() <= MAX_KEY_LEN, "key name is too long");
+        Self {
+            name,
+            version,
+            migration: None,
+            _type: PhantomData,
+        }
+    }
+
+    /// Sets the migration through which values of other versions are upgraded.
+    #[must_use]
+    pub const fn with_migration(mut self, migration: Migration<T>) -> Self {
+        self.migration = Some(migration);
+        self
+    }
+
+    /// Returns the name the key is stored under.
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the version values are stored with.
+    #[must_use]
+    pub const fn version(&self) -> u16 {
+        self.version
+    }
+}
+
+/// Errors of accessing values through a [`StorageKey`].
+#[derive(Debug)]
+pub enum SchemaError<E> {
+    /// Accessing the storage failed.
+    Storage(sequential_storage::Error<E>),
+    /// The value was stored with a different version, and could not be migrated.
+    VersionMismatch {
+        /// Version of the stored value.
+        stored: u16,
+        /// Version of the key.
+        expected: u16,
+    },
+    /// The stored value could not be decoded into the key's type.
+    Malformed,
+    /// The value does not fit into [`DATA_BUFFER_SIZE`].
+    TooLarge,
+}
+
+impl<E> From<sequential_storage::Error<E>> for SchemaError<E> {
+    fn from(error: sequential_storage::Error<E>) -> Self {
+        Self::Storage(error)
+    }
+}
+
+/// Stored form of a value of a [`StorageKey`]: its version and postcard encoding.
+struct Versioned {
+    version: u16,
+    bytes: ArrayVec<u8, DATA_BUFFER_SIZE>,
+}
+
+impl Versioned {
+    fn encode<T: Serialize>(version: u16, value: &T) -> Option<Self> {
+        let mut buffer = [0; DATA_BUFFER_SIZE];
+        let used = postcard::to_slice(value, &mut buffer).ok()?;
+        Some(Self {
+            version,
+            bytes: ArrayVec::try_from(&*used).ok()?,
+        })
+    }
+}
+
+impl Value<'_> for Versioned {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        let (marker, rest) = buffer
+            .split_at_mut_checked(ENVELOPE_MARKER.len())
+            .ok_or(SerializationError::BufferTooSmall)?;
+        marker.copy_from_slice(&ENVELOPE_MARKER);
+        let version_len = postcard::to_slice(&self.version, rest)
+            .map_err(|_| SerializationError::BufferTooSmall)?
+            .len();
+        let bytes = rest
+            .get_mut(version_len..version_len + self.bytes.len())
+            .ok_or(SerializationError::BufferTooSmall)?;
+        bytes.copy_from_slice(&self.bytes);
+        Ok(ENVELOPE_MARKER.len() + version_len + self.bytes.len())
+    }
+
+    fn deserialize_from(buffer: &[u8]) -> Result<(Self, usize), SerializationError> {
+        let (version, bytes) = match buffer.strip_prefix(&ENVELOPE_MARKER) {
+            Some(versioned) => postcard::take_from_bytes::<u16>(versioned)
+                .map_err(|_| SerializationError::InvalidData)?,
+            None => (0, buffer),
+        };
+        let bytes = ArrayVec::try_from(bytes).map_err(|_| SerializationError::InvalidData)?;
+        Ok((Self { version, bytes }, buffer.len()))
+    }
+}
+
+impl<F: NorFlash, C: KeyCacheImpl<arrayvec::ArrayString<MAX_KEY_LEN>>> Storage<F, C> {
+    /// Gets the value of a typed key from this [`Storage`] instance.
+    ///
+    /// A value stored with another version is upgraded through the key's migration, and stored
+    /// again in its upgraded form.
+    ///
+    /// If no value with the key is found, `None` is returned.
+    pub async fn get_typed<T>(
+        &mut self,
+        key: &StorageKey<T>,
+    ) -> Result<Option<T>, SchemaError<<F as ErrorType>::Error>>
+    where
+        T: Serialize + for<'d> Deserialize<'d>,
+    {
+        let Some(stored) = self.get_raw::<Versioned>(key.name).await? else {
+            return Ok(None);
+        };
+
+        if stored.version == key.version {
+            return postcard::from_bytes(&stored.bytes)
+                .map(Some)
+                .map_err(|_| SchemaError::Malformed);
+        }
+
+        let value = key
+            .migration
+            .and_then(|migrate| migrate(stored.version, &stored.bytes))
+            .ok_or(SchemaError::VersionMismatch {
+                stored: stored.version,
+                expected: key.version,
+            })?;
+        self.insert_typed(key, &value).await?;
+        Ok(Some(value))
+    }
+
+    /// Stores the value of a typed key into this [`Storage`] instance.
+    ///
+    /// It will overwrite the last value that has the same key.
+    pub async fn insert_typed<T: Serialize>(
+        &mut self,
+        key: &StorageKey<T>,
+        value: &T,
+    ) -> Result<(), SchemaError<<F as ErrorType>::Error>> {
+        let versioned = Versioned::encode(key.version, value).ok_or(SchemaError::TooLarge)?;
+        Ok(self.insert_raw(key.name, versioned).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::{RawValue, testing::storage};
+
+    /// Upgrades the interval stored by earlier firmware through the untyped API.
+    fn from_untyped(version: u16, bytes: &[u8]) -> Option<(u32, i16)> {
+        match version {
+            0 => postcard::from_bytes(bytes)
+                .ok()
+                .map(|interval| (interval, 0)),
+            _ => None,
+        }
+    }
+
+    static CONFIG: StorageKey<(u32, i16)> =
+        StorageKey::new("config", 1).with_migration(from_untyped);
+
+    #[test]
+    fn untyped_values_are_unchanged() {
+        block_on(async {
+            let mut s = storage();
+            s.insert("config", 300u32).await.unwrap();
+            // The stored form is the plain postcard encoding, as written by earlier firmware.
+            let raw = s.get_raw::<RawValue>("config").await.unwrap().unwrap();
+            assert_eq!(&*raw, &[0xac, 0x02]);
+            assert_eq!(s.get::<u32>("config").await.unwrap(), Some(300));
+        });
+    }
+
+    #[test]
+    fn migrates_untyped_values() {
+        block_on(async {
+            let mut s = storage();
+            s.insert("config", 300u32).await.unwrap();
+
+            assert_eq!(s.get_typed(&CONFIG).await.unwrap(), Some((300, 0)));
+            // The upgraded value was stored with the key's version.
+            let raw = s.get_raw::<RawValue>("config").await.unwrap().unwrap();
+            assert!(raw.starts_with(&ENVELOPE_MARKER));
+            assert_eq!(s.get_typed(&CONFIG).await.unwrap(), Some((300, 0)));
+        });
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        static NEWER: StorageKey<(u32, i16)> = StorageKey::new("config", 2);
+
+        block_on(async {
+            let mut s = storage();
+            s.insert_typed(&CONFIG, &(60, -5)).await.unwrap();
+            assert!(matches!(
+                s.get_typed(&NEWER).await,
+                Err(SchemaError::VersionMismatch {
+                    stored: 1,
+                    expected: 2
+                })
+            ));
+            assert_eq!(s.get_typed(&CONFIG).await.unwrap(), Some((60, -5)));
+        });
+    }
+}
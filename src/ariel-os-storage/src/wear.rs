@@ -0,0 +1,118 @@
+//! Flash wrapper counting page erases, used to report wear of the storage range.
+use embedded_storage_async::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, ReadNorFlash};
+
+/// A flash counting how often each of the `PAGES` pages after `start` was erased.
+pub struct EraseCounting<F, const PAGES: usize> {
+    flash: F,
+    start: u32,
+    counts: [u32; PAGES],
+    /// Whether `counts` changed since they were last taken through [`Self::take_changed()`].
+    changed: bool,
+}
+
+impl<F: NorFlash, const PAGES: usize> EraseCounting<F, PAGES> {
+    /// Wraps `flash`, counting erases of the pages starting at offset `start`.
+    pub const fn new(flash: F, start: u32) -> Self {
+        Self {
+            flash,
+            start,
+            counts: [0; PAGES],
+            changed: false,
+        }
+    }
+
+    /// Returns the number of erases of each page.
+    #[must_use]
+    pub fn counts(&self) -> [u32; PAGES] {
+        self.counts
+    }
+
+    /// Adds erase counts from an earlier run (e.g., loaded from storage) to the current counts.
+    pub(crate) fn restore(&mut self, counts: [u32; PAGES]) {
+        for (count, earlier) in self.counts.iter_mut().zip(counts) {
+            *count = count.saturating_add(earlier);
+        }
+    }
+
+    /// Returns the counts if they changed since the last call.
+    pub(crate) fn take_changed(&mut self) -> Option<[u32; PAGES]> {
+        core::mem::take(&mut self.changed).then_some(self.counts)
+    }
+}
+
+impl<F: ErrorType, const PAGES: usize> ErrorType for EraseCounting<F, PAGES> {
+    type Error = F::Error;
+}
+
+impl<F: ReadNorFlash, const PAGES: usize> ReadNorFlash for EraseCounting<F, PAGES> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes).await
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash, const PAGES: usize> NorFlash for EraseCounting<F, PAGES> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.flash.erase(from, to).await?;
+
+        #[expect(clippy::cast_possible_truncation, reason = "flash offsets are u32")]
+        let page_size = F::ERASE_SIZE as u32;
+        let first = from.saturating_sub(self.start) / page_size;
+        let last = to.saturating_sub(self.start).div_ceil(page_size);
+        for page in first..last {
+            if let Some(count) = self.counts.get_mut(page as usize) {
+                *count = count.saturating_add(1);
+                self.changed = true;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.flash.write(offset, bytes).await
+    }
+}
+
+impl<F: MultiwriteNorFlash, const PAGES: usize> MultiwriteNorFlash for EraseCounting<F, PAGES> {}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::{
+        Storage,
+        testing::{FLASH_SIZE, flash},
+    };
+
+    #[test]
+    fn counts_erases_per_page() {
+        block_on(async {
+            let mut flash = EraseCounting::<_, 4>::new(flash(), 0);
+            flash.erase(1024, 3 * 1024).await.unwrap();
+            assert_eq!(flash.counts(), [0, 1, 1, 0]);
+            assert_eq!(flash.take_changed(), Some([0, 1, 1, 0]));
+            assert_eq!(flash.take_changed(), None);
+
+            flash.restore([5, 0, 0, 1]);
+            assert_eq!(flash.counts(), [5, 1, 1, 1]);
+        });
+    }
+
+    #[test]
+    fn counts_erases_of_storage() {
+        block_on(async {
+            let mut s = Storage::new(EraseCounting::<_, 4>::new(flash(), 0), 0..FLASH_SIZE);
+            s.erase_all().await.unwrap();
+            assert_eq!(s.flash_mut().counts(), [1; 4]);
+        });
+    }
+}
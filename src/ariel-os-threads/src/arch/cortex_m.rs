@@ -296,10 +296,15 @@ global_asm!(
 ///
 /// - must not be called manually (only by PendSV)
 unsafe extern "C" fn sched() -> u64 {
+    #[cfg(feature = "time-slicing")]
+    crate::time_slice::arm();
+
     let (current_high_regs, next_high_regs) = loop {
         if let Some(res) = critical_section::with(|cs| {
             let scheduler = unsafe { &mut *SCHEDULER.as_ptr(cs) };
 
+            #[cfg(feature = "multi-core")]
+            scheduler.release_killed();
             #[cfg(feature = "multi-core")]
             scheduler.add_current_thread_to_rq();
 
@@ -311,6 +316,9 @@ unsafe extern "C" fn sched() -> u64 {
 
                     #[cfg(not(feature = "multi-core"))]
                     {
+                        #[cfg(feature = "cpu-time")]
+                        scheduler.account_idle_time();
+
                         Cpu::wfi();
                         // this fence seems necessary, see #310.
                         core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
@@ -319,6 +327,9 @@ unsafe extern "C" fn sched() -> u64 {
                 }
             };
 
+            #[cfg(feature = "cpu-time")]
+            scheduler.account_cpu_time(next_tid);
+
             // `current_high_regs` will be null if there is no current thread.
             // This is only the case once, when the very first thread starts running.
             // The returned `r1` therefore will be null, and saving/ restoring
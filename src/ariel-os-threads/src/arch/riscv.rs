@@ -326,11 +326,16 @@ extern "C" fn sched() {
     // SAFETY: `steal().reset()` is safe on an initialized software interrupt
     unsafe { SoftwareInterrupt::<0>::steal().reset() }
 
+    #[cfg(feature = "time-slicing")]
+    crate::time_slice::arm();
+
     let mut mstatus = register::mstatus::read();
 
     // Get the next thread to execute, if None is returned this means we don't have to do any
     // switching and just go back to the previous thread.
     if let Some((current_high_regs, next_high_regs)) = SCHEDULER.with_mut(|mut scheduler| {
+        #[cfg(feature = "multi-core")]
+        scheduler.release_killed();
         #[cfg(feature = "multi-core")]
         scheduler.add_current_thread_to_rq();
 
@@ -338,6 +343,9 @@ extern "C" fn sched() {
             "idle threads should be enabled, the scheduler should always have a thread ready",
         );
 
+        #[cfg(feature = "cpu-time")]
+        scheduler.account_cpu_time(next_tid);
+
         let mut current_high_regs = core::ptr::null_mut();
 
         if let Some(current_tid_ref) = scheduler.current_tid_mut() {
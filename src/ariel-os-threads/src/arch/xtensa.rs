@@ -114,15 +114,25 @@ extern "C" fn FROM_CPU_INTR1(trap_frame: &mut TrapFrame) {
 /// It should only be called from inside the trap handler that is responsible for
 /// context switching.
 unsafe fn sched(trap_frame: &mut TrapFrame) {
+    #[cfg(feature = "time-slicing")]
+    crate::time_slice::arm();
+
     loop {
         if SCHEDULER.with_mut(|mut scheduler| {
+            #[cfg(feature = "multi-core")]
+            scheduler.release_killed();
             #[cfg(feature = "multi-core")]
             scheduler.add_current_thread_to_rq();
 
             let Some(next_tid) = scheduler.get_next_tid() else {
+                #[cfg(feature = "cpu-time")]
+                scheduler.account_idle_time();
                 return false;
             };
 
+            #[cfg(feature = "cpu-time")]
+            scheduler.account_cpu_time(next_tid);
+
             if let Some(current_tid) = scheduler.current_tid() {
                 if next_tid == current_tid {
                     return true;
@@ -1,6 +1,7 @@
 /// Starts the `fn_name` function in a dedicated thread at startup.
 ///
 /// The thread is given a `stacksize`-byte stack, and has priority `priority`.
+/// It is named after `fn_name`.
 #[doc(hidden)]
 #[macro_export]
 macro_rules! autostart_thread {
@@ -12,7 +13,8 @@ macro_rules! autostart_thread {
             fn [<__start_thread_ $fn_name>] () {
                 use $crate::macro_reexports::static_cell::ConstStaticCell;
                 static STACK: ConstStaticCell<[u8; $stacksize]> = ConstStaticCell::new([0u8; $stacksize]);
-                $crate::create_noarg($fn_name, STACK.take(), $priority, $affinity);
+                let thread_id = $crate::create_noarg($fn_name, STACK.take(), $priority, $affinity);
+                $crate::set_name(thread_id, stringify!($fn_name));
             }
         }
     };
@@ -0,0 +1,153 @@
+//! Accounting of the time that threads have been running and that cores have been idle.
+//!
+//! The scheduler reads the timer whenever it picks a thread to run and whenever a core goes to
+//! sleep because no thread is ready, and charges the time since the previous reading to the thread
+//! that was running, or to the core's idle time.
+//! With idle threads, the time spent running an idle thread counts as idle time of the core.
+
+use embassy_time::Duration;
+
+use crate::{CORE_COUNT, CoreId, SCHEDULER, Scheduler, THREAD_COUNT, ThreadId, core_id};
+
+/// CPU time bookkeeping of the scheduler.
+pub(crate) struct CpuTime {
+    /// Timer ticks each thread has been running.
+    threads: [u64; THREAD_COUNT],
+    /// Timer ticks each core has been idle.
+    idle: [u64; CORE_COUNT],
+    /// What each core has been doing since the last reading, and when that reading was taken.
+    cores: [(CoreActivity, u64); CORE_COUNT],
+    /// Threads that only run when their core has nothing else to do.
+    idle_threads: [bool; THREAD_COUNT],
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CoreActivity {
+    /// The core has not scheduled a thread yet.
+    Startup,
+    Running(ThreadId),
+    Idle,
+}
+
+impl CpuTime {
+    pub const fn new() -> Self {
+        Self {
+            threads: [0; THREAD_COUNT],
+            idle: [0; CORE_COUNT],
+            cores: [(CoreActivity::Startup, 0); CORE_COUNT],
+            idle_threads: [false; THREAD_COUNT],
+        }
+    }
+
+    /// Charges the ticks since the last reading on core `core` and switches to `activity`.
+    fn switch(&mut self, core: usize, activity: CoreActivity, now: u64) {
+        let (previous, since) = self.cores[core];
+        let elapsed = now.saturating_sub(since);
+        match previous {
+            CoreActivity::Startup => {}
+            CoreActivity::Running(tid) => {
+                self.threads[usize::from(tid)] += elapsed;
+                if self.idle_threads[usize::from(tid)] {
+                    self.idle[core] += elapsed;
+                }
+            }
+            CoreActivity::Idle => self.idle[core] += elapsed,
+        }
+        self.cores[core] = (activity, now);
+    }
+
+    /// Returns the ticks of the ongoing activity on every core for which `f` returns `true`.
+    fn ongoing(&self, now: u64, f: impl Fn(usize, CoreActivity) -> bool) -> u64 {
+        self.cores
+            .iter()
+            .enumerate()
+            .filter(|&(core, &(activity, _))| f(core, activity))
+            .map(|(_, &(_, since))| now.saturating_sub(since))
+            .sum()
+    }
+}
+
+impl Scheduler {
+    /// Accounts for the scheduler picking `thread_id` to run on the current core.
+    ///
+    /// Must be called every time the scheduler has selected the next thread, even if it is the
+    /// thread that was running already.
+    #[allow(dead_code, reason = "used in scheduler implementation")]
+    pub(crate) fn account_cpu_time(&mut self, thread_id: ThreadId) {
+        let core = usize::from(core_id());
+        let activity = CoreActivity::Running(thread_id);
+        if self.cpu_time.cores[core].0 != activity {
+            self.cpu_time
+                .switch(core, activity, embassy_time_driver::now());
+        }
+    }
+
+    /// Accounts for the current core going to sleep because no thread is ready.
+    #[allow(dead_code, reason = "used in scheduler implementation")]
+    pub(crate) fn account_idle_time(&mut self) {
+        let core = usize::from(core_id());
+        if self.cpu_time.cores[core].0 != CoreActivity::Idle {
+            self.cpu_time
+                .switch(core, CoreActivity::Idle, embassy_time_driver::now());
+        }
+    }
+
+    /// Resets the CPU time of a newly created thread.
+    pub(crate) fn reset_cpu_time(&mut self, thread_id: ThreadId) {
+        self.cpu_time.threads[usize::from(thread_id)] = 0;
+        self.cpu_time.idle_threads[usize::from(thread_id)] = false;
+    }
+
+    /// Returns the CPU time of a thread in timer ticks, including the ongoing time slot if it is
+    /// currently running.
+    pub(crate) fn thread_cpu_ticks(&self, thread_id: ThreadId) -> u64 {
+        let running = CoreActivity::Running(thread_id);
+        self.cpu_time.threads[usize::from(thread_id)]
+            + self
+                .cpu_time
+                .ongoing(embassy_time_driver::now(), |_, activity| {
+                    activity == running
+                })
+    }
+}
+
+/// Marks `thread_id` as an idle thread, whose CPU time also counts as idle time of its core.
+#[cfg(feature = "idle-threads")]
+pub(crate) fn set_idle_thread(thread_id: ThreadId) {
+    SCHEDULER.with_mut(|mut scheduler| {
+        scheduler.cpu_time.idle_threads[usize::from(thread_id)] = true;
+    });
+}
+
+/// Returns the total time a thread has been running.
+///
+/// Time is measured with the resolution of the timer driver, whenever the scheduler switches
+/// threads.
+/// Time spent in interrupt handlers is charged to the thread that was interrupted.
+///
+/// Returns `None` if this is not a valid thread.
+pub fn cpu_time(thread_id: ThreadId) -> Option<Duration> {
+    SCHEDULER.with(|scheduler| {
+        scheduler
+            .is_valid_tid(thread_id)
+            .then(|| Duration::from_ticks(scheduler.thread_cpu_ticks(thread_id)))
+    })
+}
+
+/// Returns the total time a core has been idle, i.e., sleeping or running its idle thread,
+/// because no other thread was ready.
+pub fn idle_time(core: CoreId) -> Duration {
+    let core = usize::from(core);
+    SCHEDULER.with(|scheduler| {
+        let cpu_time = &scheduler.cpu_time;
+        let ongoing = cpu_time.ongoing(embassy_time_driver::now(), |c, activity| {
+            c == core
+                && match activity {
+                    CoreActivity::Idle => true,
+                    CoreActivity::Running(tid) => cpu_time.idle_threads[usize::from(tid)],
+                    CoreActivity::Startup => false,
+                }
+        });
+        Duration::from_ticks(cpu_time.idle[core] + ongoing)
+    })
+}
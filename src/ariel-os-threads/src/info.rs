@@ -0,0 +1,108 @@
+//! Introspection of the threads currently known to the scheduler.
+
+use crate::{
+    RunqueueId, SCHEDULER, THREAD_COUNT, ThreadId, ThreadState, thread::STACK_PAINT_COLOR,
+};
+
+/// Snapshot of a thread's scheduling and stack information.
+///
+/// Returned by [`thread_info()`] and [`threads()`].
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ThreadInfo {
+    /// Id of the thread.
+    pub tid: ThreadId,
+    /// Name of the thread, if any.
+    ///
+    /// Threads started with the `thread` macro are named after their function.
+    pub name: Option<&'static str>,
+    /// State of the thread at the time of the snapshot.
+    pub state: ThreadState,
+    /// Current priority of the thread, including inherited priority.
+    pub prio: RunqueueId,
+    /// Core affinity of the thread.
+    #[cfg(feature = "core-affinity")]
+    pub core_affinity: crate::CoreAffinity,
+    /// Size of the thread's stack in bytes.
+    ///
+    /// This is zero on architectures that do not track thread stacks.
+    pub stack_size: usize,
+    /// Peak stack usage of the thread in bytes, measured through stack painting.
+    ///
+    /// This is a lower bound of the actual stack usage, as stack data may "collide" with the
+    /// paint.
+    pub stack_peak_usage: usize,
+    /// Total time the thread has been running.
+    #[cfg(feature = "cpu-time")]
+    pub cpu_time: embassy_time::Duration,
+}
+
+impl ThreadInfo {
+    /// Returns the amount of stack space that has never been used so far.
+    #[must_use]
+    pub fn stack_free_min(&self) -> usize {
+        self.stack_size - self.stack_peak_usage
+    }
+}
+
+/// Returns a snapshot of the thread with the given [`ThreadId`].
+///
+/// Returns `None` if this is not a valid thread.
+///
+/// Measuring the stack usage runs in `O(n)` of the stack size, but happens outside of the
+/// critical section.
+pub fn thread_info(thread_id: ThreadId) -> Option<ThreadInfo> {
+    let (mut info, stack_lowest) = SCHEDULER.with(|scheduler| {
+        if !scheduler.is_valid_tid(thread_id) {
+            return None;
+        }
+        let thread = scheduler.get_unchecked(thread_id);
+        let info = ThreadInfo {
+            tid: thread.tid,
+            name: thread.name,
+            state: thread.state,
+            prio: thread.prio,
+            #[cfg(feature = "core-affinity")]
+            core_affinity: thread.core_affinity,
+            stack_size: thread.stack_highest - thread.stack_lowest,
+            stack_peak_usage: 0,
+            #[cfg(feature = "cpu-time")]
+            cpu_time: embassy_time::Duration::from_ticks(scheduler.thread_cpu_ticks(thread_id)),
+        };
+        Some((info, thread.stack_lowest))
+    })?;
+
+    // The painted part of a stack is always at its lowest addresses.
+    let stack_free = (stack_lowest..stack_lowest + info.stack_size)
+        .take_while(|&pos| {
+            // SAFETY: stacks are `'static` and never move, and reading unused stack space is fine
+            // on all our platforms, even while the thread is running.
+            unsafe { core::ptr::read_volatile(pos as *const u8) == STACK_PAINT_COLOR }
+        })
+        .count();
+    info.stack_peak_usage = info.stack_size - stack_free;
+
+    Some(info)
+}
+
+/// Returns an iterator over snapshots of all currently existing threads, in the order of their
+/// [`ThreadId`]s.
+///
+/// Every thread is looked up only when the iterator reaches it, so threads that are created or
+/// that end while iterating may or may not be included.
+pub fn threads() -> impl Iterator<Item = ThreadInfo> {
+    (0..THREAD_COUNT).filter_map(|tid| thread_info(ThreadId::new(tid as u8)))
+}
+
+/// Sets the name of a thread, which is reported by [`thread_info()`] and [`threads()`].
+///
+/// Returns `false` if this is not a valid thread.
+pub fn set_name(thread_id: ThreadId, name: &'static str) -> bool {
+    SCHEDULER.with_mut(|mut scheduler| {
+        if !scheduler.is_valid_tid(thread_id) {
+            return false;
+        }
+        scheduler.get_unchecked_mut(thread_id).name = Some(name);
+        true
+    })
+}
@@ -4,6 +4,8 @@
 //! Within one priority level, threads are scheduled cooperatively.
 //! This means that there is no time slicing that would equally distribute CPU time among same-priority threads.
 //! **Instead, you need to use [`yield_same()`] to explicitly yield to another thread with the same priority.**
+//! With the `time-slicing` feature enabled, a timer additionally rotates threads of the same
+//! priority once the running one has used up its time slice, see `set_time_slice()`.
 //! If no thread is ready, the core is prompted to enter deep sleep until a next thread is ready.
 //!
 //! Threads should be implemented using the `ariel_os_macros::thread` proc macro, which takes care
@@ -14,12 +16,35 @@
 //! Optionally, the stacksize and a priority between 1 and [`SCHED_PRIO_LEVELS`] can be configured.
 //! By default, the stack size is 2048 bytes and priority is 1.
 //!
+//! Values that each thread has its own copy of can be declared using [`thread_local!`], see
+//! [`ThreadLocal`].
+//!
+//! With the `spawn` feature enabled, threads can also be created at runtime using `spawn()`,
+//! which runs a closure on a stack taken from a static pool and returns a `JoinHandle` to wait for
+//! its return value.
+//!
+//! [`threads()`] returns snapshots of all existing threads, including their state, priority and
+//! peak stack usage, e.g., for monitoring stack budgets.
+//! With the `cpu-time` feature enabled, the scheduler additionally accounts how long each thread
+//! has been running and each core has been idle, see `cpu_time()` and `idle_time()`.
+//!
 //! # Synchronization
 //!
-//! The `threading` module supports three basic synchronization primitives:
+//! The `threading` module supports these basic synchronization primitives:
 //! - [`Channel`](sync::Channel): synchronous (blocking) channel for sending data between threads
+//! - [`Queue`](sync::Queue): bounded, buffered queue for sending data between threads and from
+//!   interrupt handlers
 //! - [`Lock`](sync::Lock): basic locking object
+//! - [`Mutex`](sync::Mutex): mutex with priority inheritance, with the variants
+//!   [`CeilingMutex`](sync::CeilingMutex) implementing the priority ceiling protocol and
+//!   [`RecursiveMutex`](sync::RecursiveMutex) that can be locked repeatedly by its owner
+//! - [`RwLock`](sync::RwLock): reader-writer lock with priority inheritance for writers
+//! - [`Semaphore`](sync::Semaphore): counting semaphore
 //! - [`thread_flags`]: thread-flag implementation for signaling between threads
+//!
+//! Their blocking operations also come with `_timeout()` variants, e.g.,
+//! [`Mutex::lock_timeout()`](sync::Mutex::lock_timeout), which return a [`TimeoutError`] if the
+//! operation did not complete in time.
 
 #![cfg_attr(not(any(test, context = "native")), no_std)]
 #![cfg_attr(target_arch = "xtensa", feature(asm_experimental_arch))]
@@ -38,13 +63,26 @@ mod arch;
 mod autostart_thread;
 mod blocker;
 mod core_affinity;
+#[cfg(feature = "cpu-time")]
+mod cpu_time;
 mod ensure_once;
+mod info;
 mod thread;
 mod threadlist;
 mod timeout;
+mod tls;
 
 #[cfg(feature = "multi-core")]
 mod smp;
+#[cfg(feature = "spawn")]
+mod spawn;
+#[cfg(feature = "time-slicing")]
+mod time_slice;
+
+#[cfg(all(feature = "time-slicing", feature = "infini-core"))]
+compile_error!(r#""time-slicing" is not supported with "infini-core""#);
+#[cfg(all(feature = "cpu-time", feature = "infini-core"))]
+compile_error!(r#""cpu-time" is not supported with "infini-core""#);
 
 pub mod sync;
 pub mod thread_flags;
@@ -67,11 +105,20 @@ pub mod events {
 pub use ariel_os_runqueue::{RunqueueId, ThreadId};
 pub use blocker::block_on;
 pub use core_affinity::CoreAffinity;
+#[cfg(feature = "cpu-time")]
+pub use cpu_time::{cpu_time, idle_time};
+pub use info::{ThreadInfo, set_name, thread_info, threads};
+pub use thread::ThreadState;
 pub use thread_flags as flags;
-pub use timeout::{sleep, sleep_until};
+pub use timeout::{TimeoutError, sleep, sleep_until};
+pub use tls::ThreadLocal;
 
 #[cfg(feature = "multi-core")]
 pub use smp::isr_stack_core1_get_limits;
+#[cfg(feature = "spawn")]
+pub use spawn::{JoinError, JoinHandle, SPAWN_STACK_COUNT, SPAWN_STACKSIZE, SpawnError, spawn};
+#[cfg(feature = "time-slicing")]
+pub use time_slice::{TIME_SLICE_US, set_time_slice, time_slice};
 
 use arch::{Arch, Cpu, ThreadData, schedule};
 
@@ -79,7 +126,7 @@ use arch::{Arch, Cpu, ThreadData, schedule};
 use ariel_os_runqueue::RunQueue;
 
 use ensure_once::EnsureOnce;
-use thread::{Thread, ThreadState};
+use thread::Thread;
 
 #[cfg(feature = "multi-core")]
 use smp::{Multicore, schedule_on_core};
@@ -87,10 +134,36 @@ use smp::{Multicore, schedule_on_core};
 use static_cell::ConstStaticCell;
 
 /// The number of possible priority levels.
-pub const SCHED_PRIO_LEVELS: usize = THREAD_COUNT;
+///
+/// Can be configured at build time through the `CONFIG_SCHED_PRIO_LEVELS` environment variable,
+/// up to the number of bits of a `usize` (i.e., 32 on 32-bit architectures).
+pub const SCHED_PRIO_LEVELS: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_SCHED_PRIO_LEVELS",
+    16,
+    "number of scheduler priority levels"
+);
 
 /// The maximum number of concurrent threads that can be created.
-pub const THREAD_COUNT: usize = 16;
+///
+/// Can be configured at build time through the `CONFIG_THREAD_COUNT` environment variable, up to
+/// 254.
+pub const THREAD_COUNT: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_THREAD_COUNT",
+    16,
+    "maximum number of concurrent threads"
+);
+
+const _: () = {
+    assert!(
+        SCHED_PRIO_LEVELS > 0 && SCHED_PRIO_LEVELS <= usize::BITS as usize,
+        "`CONFIG_SCHED_PRIO_LEVELS` must be between 1 and the number of bits of a `usize`"
+    );
+    // `ThreadId`s are stored as `u8`, with `0xFF` being reserved by the runqueue.
+    assert!(
+        THREAD_COUNT > 0 && THREAD_COUNT < 0xFF,
+        "`CONFIG_THREAD_COUNT` must be between 1 and 254"
+    );
+};
 
 /// Number of processor cores.
 pub const CORE_COUNT: usize = {
@@ -122,9 +195,25 @@ struct Scheduler {
     /// resource access.
     thread_blocklist: [Option<ThreadId>; THREAD_COUNT],
 
+    /// Bookkeeping for the stacks of spawned threads.
+    #[cfg(feature = "spawn")]
+    spawn_slots: [spawn::SpawnSlot; SPAWN_STACK_COUNT],
+
+    /// Time slices and their bookkeeping.
+    #[cfg(feature = "time-slicing")]
+    time_slicing: time_slice::TimeSlicing,
+
+    /// Running and idle time of threads and cores.
+    #[cfg(feature = "cpu-time")]
+    cpu_time: cpu_time::CpuTime,
+
     /// The currently running thread(s).
     #[cfg(feature = "multi-core")]
     current_threads: [Option<ThreadId>; CORE_COUNT],
+    /// Killed thread that each core last switched away from, whose TCB is released on the core's
+    /// next scheduler invocation, when its context has been saved.
+    #[cfg(feature = "multi-core")]
+    killed_threads: [Option<ThreadId>; CORE_COUNT],
     #[cfg(feature = "single-core")]
     current_thread: Option<ThreadId>,
 }
@@ -136,8 +225,16 @@ impl Scheduler {
             runqueue: RunQueue::new(),
             threads: [const { Thread::default() }; THREAD_COUNT],
             thread_blocklist: [const { None }; THREAD_COUNT],
+            #[cfg(feature = "spawn")]
+            spawn_slots: [spawn::SpawnSlot::Vacant; SPAWN_STACK_COUNT],
+            #[cfg(feature = "time-slicing")]
+            time_slicing: time_slice::TimeSlicing::new(),
+            #[cfg(feature = "cpu-time")]
+            cpu_time: cpu_time::CpuTime::new(),
             #[cfg(feature = "multi-core")]
             current_threads: [None; CORE_COUNT],
+            #[cfg(feature = "multi-core")]
+            killed_threads: [None; CORE_COUNT],
             #[cfg(feature = "single-core")]
             current_thread: None,
         }
@@ -217,6 +314,8 @@ impl Scheduler {
         thread.prio = prio;
         thread.tid = tid;
         thread.state = ThreadState::Parked;
+        thread.name = None;
+        thread.generation = thread.generation.wrapping_add(1);
 
         // At least native needs the `tid` field populated, so we call this
         // after populating `thread` with the already known info.
@@ -227,6 +326,9 @@ impl Scheduler {
             thread.core_affinity = _core_affinity.unwrap_or_default();
         }
 
+        #[cfg(feature = "cpu-time")]
+        self.reset_cpu_time(tid);
+
         Some(tid)
     }
 
@@ -253,11 +355,20 @@ impl Scheduler {
     }
 
     /// Returns an unused [`ThreadId`] / Thread slot.
+    ///
+    /// The slot of a thread that ended is skipped while a core still executes it, as its context
+    /// is saved into the slot when the core switches away from it.
     fn get_unused(&mut self) -> Option<(&mut Thread, ThreadId)> {
         for i in 0..THREAD_COUNT {
-            if self.threads[i].state == ThreadState::Invalid {
-                return Some((&mut self.threads[i], ThreadId::new(i as u8)));
+            let tid = ThreadId::new(i as u8);
+            if self.threads[i].state != ThreadState::Invalid {
+                continue;
             }
+            #[cfg(any(feature = "single-core", feature = "multi-core"))]
+            if self.is_running(tid).is_some() {
+                continue;
+            }
+            return Some((&mut self.threads[i], tid));
         }
         None
     }
@@ -267,7 +378,10 @@ impl Scheduler {
         if usize::from(thread_id) >= THREAD_COUNT {
             false
         } else {
-            self.threads[usize::from(thread_id)].state != ThreadState::Invalid
+            !matches!(
+                self.threads[usize::from(thread_id)].state,
+                ThreadState::Invalid | ThreadState::Zombie
+            )
         }
     }
 
@@ -283,6 +397,10 @@ impl Scheduler {
         let thread = self.get_unchecked_mut(tid);
         let old_state = core::mem::replace(&mut thread.state, state);
         let prio = thread.prio;
+        #[cfg(feature = "spawn")]
+        if state == ThreadState::Invalid {
+            self.spawn_slot_exit(tid);
+        }
         if state == ThreadState::Running {
             #[cfg(not(feature = "infini-core"))]
             self.runqueue.add(tid, prio);
@@ -306,6 +424,10 @@ impl Scheduler {
 
             schedule();
         }
+
+        #[cfg(feature = "time-slicing")]
+        self.time_slice_restart();
+
         old_state
     }
 
@@ -342,7 +464,7 @@ impl Scheduler {
         }
 
         #[cfg(not(feature = "infini-core"))]
-        {
+        'reschedule: {
             if thread.state != ThreadState::Running {
                 // No runqueue changes or scheduler invocation needed.
                 return;
@@ -355,8 +477,11 @@ impl Scheduler {
             // should not be applied.
             #[cfg(feature = "multi-core")]
             match self.is_running(thread_id) {
-                Some(core) if prio < old_prio => return schedule_on_core(CoreId(core as u8)),
-                Some(_) => return,
+                Some(core) if prio < old_prio => {
+                    schedule_on_core(CoreId(core as u8));
+                    break 'reschedule;
+                }
+                Some(_) => break 'reschedule,
                 _ => {}
             }
 
@@ -372,8 +497,11 @@ impl Scheduler {
             // analogous to the above multi-core implementation.
             #[cfg(feature = "single-core")]
             match self.is_running(thread_id) {
-                Some(_) if prio < old_prio => return schedule(),
-                Some(_) => return,
+                Some(_) if prio < old_prio => {
+                    schedule();
+                    break 'reschedule;
+                }
+                Some(_) => break 'reschedule,
                 _ => {}
             }
 
@@ -383,6 +511,55 @@ impl Scheduler {
                 self.schedule_if_higher_prio(thread_id, prio);
             }
         }
+
+        #[cfg(feature = "time-slicing")]
+        self.time_slice_restart();
+    }
+
+    /// Ends a thread that is not the current thread.
+    ///
+    /// The thread is removed from the runqueue and its [`ThreadId`] becomes available for new
+    /// threads.
+    ///
+    /// # Errors
+    ///
+    /// See [`kill()`].
+    #[cfg(not(feature = "infini-core"))]
+    fn kill(&mut self, thread_id: ThreadId) -> Result<(), KillError> {
+        let state = self.get_state(thread_id).ok_or(KillError::InvalidThread)?;
+        if self.current_tid() == Some(thread_id) {
+            return Err(KillError::CurrentThread);
+        }
+        let mut end_state = ThreadState::Invalid;
+        match state {
+            ThreadState::Running => {
+                // On multi-core, a thread that is executing on another core is not in the
+                // runqueue, instead that core has to switch away from it. Its TCB is only
+                // released once that happened (see `release_killed()`).
+                #[cfg(feature = "multi-core")]
+                if let Some(core) = self.is_running(thread_id) {
+                    end_state = ThreadState::Zombie;
+                    schedule_on_core(CoreId(core as u8));
+                } else {
+                    self.runqueue.del(thread_id);
+                }
+                #[cfg(not(feature = "multi-core"))]
+                self.runqueue.del(thread_id);
+            }
+            ThreadState::Parked | ThreadState::FlagBlocked(_) => {}
+            // The thread is linked into the waiting list of a synchronization primitive, which
+            // cannot be fixed up from here.
+            _ => return Err(KillError::Blocked),
+        }
+        let thread = self.get_unchecked_mut(thread_id);
+        thread.state = end_state;
+        // Prevent a pending deadline timer from waking up the next thread using this TCB.
+        thread.deadline = None;
+
+        #[cfg(feature = "spawn")]
+        self.spawn_slot_exit(thread_id);
+
+        Ok(())
     }
 
     /// Triggers the scheduler if the thread has a higher priority than (one of)
@@ -421,6 +598,25 @@ impl Scheduler {
         }
     }
 
+    /// Releases the TCB of a killed thread that the current core switched away from during its
+    /// previous scheduler invocation, and remembers the current thread if it was killed.
+    ///
+    /// This must be called at the start of every scheduler invocation, before the current thread
+    /// changes.
+    #[cfg(feature = "multi-core")]
+    #[allow(dead_code, reason = "used in scheduler implementation")]
+    fn release_killed(&mut self) {
+        let core = usize::from(core_id());
+        if let Some(tid) = self.killed_threads[core].take() {
+            self.get_unchecked_mut(tid).state = ThreadState::Invalid;
+        }
+        if let Some(tid) = self.current_tid()
+            && self.get_unchecked(tid).state == ThreadState::Zombie
+        {
+            self.killed_threads[core] = Some(tid);
+        }
+    }
+
     /// Adds the thread that is running on the current core to the
     /// runqueue if it has state [`ThreadState::Running`].
     #[cfg(feature = "multi-core")]
@@ -575,6 +771,15 @@ pub unsafe fn start_threading() {
     Cpu::start_threading();
 }
 
+/// Starts the time-slicing timer.
+///
+/// This is called by `ariel-os-embassy` once the time driver is initialized.
+#[doc(hidden)]
+#[cfg(feature = "time-slicing")]
+pub fn start_time_slicing() {
+    time_slice::start();
+}
+
 #[cfg(feature = "idle-threads")]
 fn setup_idle_threads() {
     ariel_os_debug::log::debug!(
@@ -597,7 +802,10 @@ fn setup_idle_threads() {
 
     // Create one idle thread for each core with lowest priority.
     for stack in &IDLE_THREAD_STACKS {
-        create_noarg(idle_thread, stack.take(), 0, None);
+        let thread_id = create_noarg(idle_thread, stack.take(), 0, None);
+        set_name(thread_id, "idle");
+        #[cfg(feature = "cpu-time")]
+        cpu_time::set_idle_thread(thread_id);
     }
 }
 
@@ -652,19 +860,23 @@ where
     T: Arguable + Send,
 {
     let arg = Some(arg.into_arg());
-
-    // Convert `fn(T)` into `fn()`, must go through *const().
-    let func = {
-        let func = func as *const ();
-        // SAFETY:
-        // https://doc.rust-lang.org/stable/std/primitive.fn.html#casting-to-and-from-integers
-        // "Transmuting between raw pointers and function pointers (i.e., two pointer types) is fine."
-        unsafe { core::mem::transmute::<*const (), fn()>(func) }
-    };
+    let func = erase_thread_fn(func);
 
     unsafe { create_raw(func, arg, stack, prio, core_affinity) }
 }
 
+/// Converts `fn(T)` into `fn()`, so that it can be stored as thread entry function.
+///
+/// The argument has to be passed separately when the thread is set up.
+fn erase_thread_fn<T: Arguable>(func: fn(T)) -> fn() {
+    // Must go through *const().
+    let func = func as *const ();
+    // SAFETY:
+    // https://doc.rust-lang.org/stable/std/primitive.fn.html#casting-to-and-from-integers
+    // "Transmuting between raw pointers and function pointers (i.e., two pointer types) is fine."
+    unsafe { core::mem::transmute::<*const (), fn()>(func) }
+}
+
 /// Low-level function to create a thread without argument.
 ///
 /// # Panics
@@ -814,6 +1026,63 @@ pub fn unpark(thread_id: ThreadId) -> bool {
     })
 }
 
+/// Possible errors when killing a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KillError {
+    /// No valid thread exists for this [`ThreadId`].
+    InvalidThread,
+    /// The current thread cannot kill itself, it should return from its thread function instead.
+    CurrentThread,
+    /// The thread is blocked on a synchronization primitive (e.g., a [`Mutex`](sync::Mutex) or
+    /// [`Channel`](sync::Channel)) and cannot be removed from it.
+    Blocked,
+}
+
+impl core::fmt::Display for KillError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidThread => write!(f, "invalid thread"),
+            Self::CurrentThread => write!(f, "the current thread cannot be killed"),
+            Self::Blocked => write!(f, "thread is blocked on a synchronization primitive"),
+        }
+    }
+}
+
+impl core::error::Error for KillError {}
+
+/// Ends another thread.
+///
+/// The thread stops executing immediately and its [`ThreadId`] becomes available to new threads.
+/// Threads that are ready, [parked](park), sleeping, or waiting for [`thread_flags`] can be
+/// killed.
+/// If the thread was created with `spawn()`, joining it returns `JoinError::Killed` and its stack
+/// is returned to the pool.
+///
+/// Resources held by the killed thread, e.g., a locked [`Mutex`](sync::Mutex), are **not**
+/// released. In particular, a [`Mutex`](sync::Mutex), [`RwLock`](sync::RwLock) or
+/// [`CeilingMutex`](sync::CeilingMutex) it holds stays owned by its [`ThreadId`], which a new
+/// thread may get; such a lock must not be used after the kill. Only kill threads that hold no
+/// locks.
+///
+/// On multi-core, a thread that is executing on another core stops executing as soon as that
+/// core switches away from it; its [`ThreadId`] becomes available once that core schedules
+/// again.
+///
+/// # Errors
+///
+/// Returns an error if `thread_id` is not a valid thread, is the current thread, or is blocked on
+/// a synchronization primitive.
+#[cfg(not(feature = "infini-core"))]
+pub fn kill(thread_id: ThreadId) -> Result<(), KillError> {
+    SCHEDULER.with_mut(|mut scheduler| scheduler.kill(thread_id))?;
+
+    #[cfg(feature = "spawn")]
+    spawn::notify_joiners();
+
+    Ok(())
+}
+
 /// Returns the priority of a thread.
 ///
 /// Returns `None` if this is not a valid thread.
@@ -0,0 +1,446 @@
+//! Dynamic creation of threads, backed by a static pool of stacks.
+//!
+//! Threads created with [`spawn()`] run a closure on one of [`SPAWN_STACK_COUNT`] statically
+//! allocated stacks.
+//! The closure and its return value are stored at the top of that stack, the remaining space is
+//! used as the thread's stack.
+//! Once a spawned thread has ended and its [`JoinHandle`] has been joined or dropped, its stack
+//! is returned to the pool and its [`ThreadId`] can be reused.
+
+#![expect(unsafe_code)]
+
+use core::{cell::UnsafeCell, marker::PhantomData, mem::ManuallyDrop};
+
+use critical_section::CriticalSection;
+
+use crate::{
+    CoreAffinity, RunqueueId, SCHEDULER, Scheduler, ThreadId, ThreadState, erase_thread_fn,
+    sync::WaitQueue,
+};
+
+/// The number of stacks available to threads created with [`spawn()`].
+///
+/// This limits the number of spawned threads that can exist at the same time.
+pub const SPAWN_STACK_COUNT: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_THREAD_SPAWN_STACK_COUNT",
+    4,
+    "number of stacks for spawned threads"
+);
+
+/// The size (in bytes) of each stack used by threads created with [`spawn()`].
+///
+/// This includes the space needed for the closure and its return value.
+pub const SPAWN_STACKSIZE: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_THREAD_SPAWN_STACKSIZE",
+    2048,
+    "stack size of spawned threads (in bytes)"
+);
+
+/// Alignment of the pool stacks, sufficient for all supported architectures.
+const STACK_ALIGN: usize = 16;
+
+const _: () = assert!(
+    SPAWN_STACKSIZE % STACK_ALIGN == 0,
+    "CONFIG_THREAD_SPAWN_STACKSIZE must be a multiple of 16"
+);
+
+#[repr(C, align(16))]
+struct PoolStack(UnsafeCell<[u8; SPAWN_STACKSIZE]>);
+
+// SAFETY: each stack is used by at most one thread at a time, which is tracked in the scheduler's
+// `spawn_slots`.
+unsafe impl Sync for PoolStack {}
+
+static STACKS: [PoolStack; SPAWN_STACK_COUNT] =
+    [const { PoolStack(UnsafeCell::new([0u8; SPAWN_STACKSIZE])) }; SPAWN_STACK_COUNT];
+
+/// Threads blocked in [`JoinHandle::join()`].
+static JOINERS: WaitQueue = WaitQueue::new();
+
+/// Bookkeeping for one of the pool stacks.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum SpawnSlot {
+    /// The stack is unused.
+    Vacant,
+    /// The stack belongs to a spawned thread.
+    Occupied {
+        /// Thread that was spawned on this stack.
+        tid: ThreadId,
+        /// The closure has returned and the return value has been stored.
+        finished: bool,
+        /// The thread has ended, either by returning or by being killed.
+        exited: bool,
+        /// The [`JoinHandle`] has been joined or dropped.
+        detached: bool,
+    },
+}
+
+const fn round_up(value: usize) -> usize {
+    value.div_ceil(STACK_ALIGN) * STACK_ALIGN
+}
+
+/// Offset of the return value within a pool stack.
+const fn result_offset<T>() -> usize {
+    SPAWN_STACKSIZE - round_up(size_of::<T>())
+}
+
+/// Offset of the closure within a pool stack, which is also the size of the actual thread stack.
+const fn closure_offset<F, T>() -> usize {
+    result_offset::<T>() - round_up(size_of::<F>())
+}
+
+fn stack_base(slot: usize) -> *mut u8 {
+    STACKS[slot].0.get().cast::<u8>()
+}
+
+fn result_ptr<T>(slot: usize) -> *mut T {
+    // SAFETY: the offset is within the bounds of the stack.
+    unsafe { stack_base(slot).add(result_offset::<T>()).cast::<T>() }
+}
+
+/// Possible errors when spawning a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpawnError {
+    /// All [`SPAWN_STACK_COUNT`] stacks are in use.
+    NoFreeStack,
+    /// The maximum number of concurrent threads ([`THREAD_COUNT`](crate::THREAD_COUNT)) has been
+    /// reached.
+    NoFreeThread,
+}
+
+impl core::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NoFreeStack => write!(f, "no free stack for spawning a thread"),
+            Self::NoFreeThread => write!(f, "maximum number of threads reached"),
+        }
+    }
+}
+
+impl core::error::Error for SpawnError {}
+
+/// Possible errors when joining a thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JoinError {
+    /// The thread was killed before it could return a value.
+    Killed,
+}
+
+impl core::fmt::Display for JoinError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Killed => write!(f, "thread was killed"),
+        }
+    }
+}
+
+impl core::error::Error for JoinError {}
+
+/// Spawns a new thread running `func`, with priority `prio`.
+///
+/// The thread runs on a stack taken from a static pool of [`SPAWN_STACK_COUNT`] stacks of
+/// [`SPAWN_STACKSIZE`] bytes each.
+/// The stack is returned to the pool once the thread has ended and the returned [`JoinHandle`]
+/// has been joined or dropped.
+///
+/// The closure and its return value are stored on the thread's stack, and must together fit into
+/// half of it, which is checked at compile time.
+///
+/// # Errors
+///
+/// Returns an error if no stack is available from the pool, or if the maximum number of concurrent
+/// threads has been reached.
+pub fn spawn<F, T>(
+    func: F,
+    prio: u8,
+    core_affinity: Option<CoreAffinity>,
+) -> Result<JoinHandle<T>, SpawnError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    const {
+        assert!(
+            align_of::<F>() <= STACK_ALIGN && align_of::<T>() <= STACK_ALIGN,
+            "alignment of the closure and its return value must not exceed 16 bytes"
+        );
+        assert!(
+            closure_offset::<F, T>() >= SPAWN_STACKSIZE / 2,
+            "closure and return value must fit into half of `SPAWN_STACKSIZE`"
+        );
+    }
+
+    SCHEDULER.with_mut(|mut scheduler| {
+        let slot = scheduler
+            .spawn_slot_unused()
+            .ok_or(SpawnError::NoFreeStack)?;
+        let base = stack_base(slot);
+
+        // SAFETY: the slot is unused, so nothing else accesses its stack.
+        let stack = unsafe { core::slice::from_raw_parts_mut(base, closure_offset::<F, T>()) };
+        // SAFETY: the offset is within the bounds of the stack.
+        let closure = unsafe { base.add(closure_offset::<F, T>()).cast::<F>() };
+
+        let tid = scheduler
+            .create(
+                erase_thread_fn(trampoline::<F, T>),
+                Some(closure as usize),
+                stack,
+                RunqueueId::new(prio),
+                core_affinity,
+            )
+            .ok_or(SpawnError::NoFreeThread)?;
+
+        // SAFETY: the closure region does not overlap with the thread stack, and the thread only
+        // reads it after it has been started below.
+        unsafe { closure.write(func) };
+
+        scheduler.spawn_slots[slot] = SpawnSlot::Occupied {
+            tid,
+            finished: false,
+            exited: false,
+            detached: false,
+        };
+        scheduler.set_state(tid, ThreadState::Running);
+
+        Ok(JoinHandle {
+            slot: slot as u8,
+            thread_id: tid,
+            _result: PhantomData,
+        })
+    })
+}
+
+/// Entry point of spawned threads.
+///
+/// # Panics
+///
+/// Panics if the current thread was not spawned.
+fn trampoline<F, T>(closure: usize)
+where
+    F: FnOnce() -> T,
+{
+    // SAFETY: `spawn()` has written the closure there before starting the thread, and it is only
+    // read once.
+    let func = unsafe { (closure as *mut F).read() };
+    let result = func();
+
+    let unclaimed = critical_section::with(|cs| {
+        SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            let tid = scheduler.current_tid().unwrap();
+            let (slot, detached) = scheduler.spawn_slot_finish(tid);
+            if detached {
+                // Nobody is going to join, drop the result outside of the critical section.
+                Some(result)
+            } else {
+                // SAFETY: the result region does not overlap with the thread stack, and the
+                // `JoinHandle` only reads it after `finished` has been set.
+                unsafe { result_ptr::<T>(slot).write(result) };
+                None
+            }
+        })
+    });
+    drop(unclaimed);
+
+    notify_joiners();
+}
+
+/// An owned permission to join on a spawned thread (block on its termination).
+///
+/// Dropping the handle detaches the thread: it keeps running, and its stack is returned to the
+/// pool once it has ended.
+#[must_use = "dropping a `JoinHandle` detaches the thread"]
+pub struct JoinHandle<T> {
+    slot: u8,
+    thread_id: ThreadId,
+    _result: PhantomData<T>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Returns the [`ThreadId`] of the spawned thread.
+    ///
+    /// The [`ThreadId`] may be reused by another thread once this thread has ended.
+    #[must_use]
+    pub fn thread_id(&self) -> ThreadId {
+        self.thread_id
+    }
+
+    /// Returns whether the thread's closure has returned or the thread was killed.
+    ///
+    /// This may return `true` shortly before the thread has actually exited, as the return value
+    /// is stored before the thread ends. [`join()`](JoinHandle::join()) does not block for long
+    /// once this returns `true`.
+    pub fn is_finished(&self) -> bool {
+        SCHEDULER.with(|scheduler| {
+            matches!(
+                scheduler.spawn_slots[usize::from(self.slot)],
+                SpawnSlot::Occupied { finished: true, .. }
+                    | SpawnSlot::Occupied { exited: true, .. }
+            )
+        })
+    }
+
+    /// Waits for the thread to end (blocking), and returns its return value.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`JoinError::Killed`] if the thread was killed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn join(self) -> Result<T, JoinError> {
+        let this = ManuallyDrop::new(self);
+        loop {
+            let res = critical_section::with(|cs| {
+                let res = this.poll(cs);
+                if res.is_none() {
+                    JOINERS.wait_cs(cs);
+                }
+                res
+            });
+            if let Some(res) = res {
+                return res;
+            }
+        }
+    }
+
+    /// Returns the thread's return value if it has ended (non-blocking).
+    ///
+    /// # Errors
+    ///
+    /// Returns the [`JoinHandle`] back if the thread is still running.
+    pub fn try_join(self) -> Result<Result<T, JoinError>, Self> {
+        let this = ManuallyDrop::new(self);
+        match critical_section::with(|cs| this.poll(cs)) {
+            Some(res) => Ok(res),
+            None => Err(ManuallyDrop::into_inner(this)),
+        }
+    }
+
+    /// Takes the return value if the thread has ended, which detaches it.
+    fn poll(&self, cs: CriticalSection<'_>) -> Option<Result<T, JoinError>> {
+        SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            let SpawnSlot::Occupied {
+                finished,
+                exited,
+                detached,
+                ..
+            } = &mut scheduler.spawn_slots[usize::from(self.slot)]
+            else {
+                unreachable!("slot of a `JoinHandle` is always occupied");
+            };
+            let res = if *finished {
+                // SAFETY: the result has been written by the thread before `finished` was set,
+                // and is only read once as `detached` is set below.
+                Ok(unsafe { result_ptr::<T>(usize::from(self.slot)).read() })
+            } else if *exited {
+                Err(JoinError::Killed)
+            } else {
+                return None;
+            };
+            *detached = true;
+            Some(res)
+        })
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        // If the thread has already finished, this takes and drops its return value.
+        let _ = critical_section::with(|cs| {
+            let res = self.poll(cs);
+            if res.is_none() {
+                SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                    if let SpawnSlot::Occupied { detached, .. } =
+                        &mut scheduler.spawn_slots[usize::from(self.slot)]
+                    {
+                        *detached = true;
+                    }
+                });
+            }
+            res
+        });
+    }
+}
+
+impl Scheduler {
+    /// Returns the index of a pool stack that can be used for a new thread.
+    fn spawn_slot_unused(&self) -> Option<usize> {
+        (0..SPAWN_STACK_COUNT).find(|&slot| match self.spawn_slots[slot] {
+            SpawnSlot::Vacant => true,
+            // The stack of an ended thread may still be in use until the scheduler has switched
+            // away from it.
+            SpawnSlot::Occupied {
+                tid,
+                exited: true,
+                detached: true,
+                ..
+            } => !self.is_on_core(tid),
+            SpawnSlot::Occupied { .. } => false,
+        })
+    }
+
+    /// Marks the slot of the running spawned thread `tid` as finished.
+    ///
+    /// Returns the slot index, and whether the [`JoinHandle`] has been dropped already.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `tid` was not spawned.
+    fn spawn_slot_finish(&mut self, tid: ThreadId) -> (usize, bool) {
+        self.spawn_slots
+            .iter_mut()
+            .enumerate()
+            .find_map(|(slot, state)| match state {
+                SpawnSlot::Occupied {
+                    tid: slot_tid,
+                    finished,
+                    exited: false,
+                    detached,
+                } if *slot_tid == tid => {
+                    *finished = true;
+                    Some((slot, *detached))
+                }
+                _ => None,
+            })
+            .expect("spawned thread should have a slot")
+    }
+
+    /// Marks the slot of the spawned thread `tid` (if any) as exited.
+    ///
+    /// Must be called whenever a thread ends.
+    pub(crate) fn spawn_slot_exit(&mut self, tid: ThreadId) {
+        for state in &mut self.spawn_slots {
+            if let SpawnSlot::Occupied {
+                tid: slot_tid,
+                exited,
+                ..
+            } = state
+                && *slot_tid == tid
+            {
+                *exited = true;
+            }
+        }
+    }
+
+    /// Returns whether the thread is currently executing on a core.
+    fn is_on_core(&self, tid: ThreadId) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(any(feature = "single-core", feature = "multi-core"))] {
+                self.is_running(tid).is_some()
+            } else {
+                // On infini-core, the pool stacks are not used by the threads.
+                let _ = (self, tid);
+                false
+            }
+        }
+    }
+}
+
+/// Wakes up all threads blocked in [`JoinHandle::join()`].
+pub(crate) fn notify_joiners() {
+    JOINERS.notify_all();
+}
@@ -0,0 +1,291 @@
+//! This module provides a mutex implementing the (immediate) priority ceiling protocol.
+
+#![deny(missing_docs)]
+#![expect(unsafe_code)]
+#![expect(
+    clippy::undocumented_unsafe_blocks,
+    reason = "should be addressed eventually"
+)]
+
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use ariel_os_runqueue::{RunqueueId, ThreadId};
+use critical_section::CriticalSection;
+use embassy_time::Duration;
+
+use crate::{
+    SCHED_PRIO_LEVELS, SCHEDULER,
+    thread::ThreadState,
+    threadlist::ThreadList,
+    timeout::{TimeoutError, block_until, deadline_after},
+};
+
+/// A mutex implementing the immediate priority ceiling protocol.
+///
+/// Each [`CeilingMutex`] has a priority ceiling, which should be at least the priority of every
+/// thread that locks it.
+/// While a thread holds the lock, it runs with the ceiling priority. Thus, no other thread that
+/// might lock the mutex can preempt it, which bounds the time any thread is blocked by the mutex
+/// to a single critical section of a lower priority thread.
+/// A thread with a higher priority than the ceiling keeps its priority while holding the lock,
+/// which voids this guarantee.
+///
+/// Unlike with [`Mutex`](super::Mutex), the priority of the owner does not depend on which
+/// threads are waiting.
+pub struct CeilingMutex<T> {
+    ceiling: RunqueueId,
+    state: UnsafeCell<LockState>,
+    inner: UnsafeCell<T>,
+}
+
+/// State of a [`CeilingMutex`].
+enum LockState {
+    Unlocked,
+    Locked {
+        /// The current owner of the lock.
+        owner_id: ThreadId,
+        /// The original priority of the current owner (without the ceiling).
+        owner_prio: RunqueueId,
+        /// Waiters for the mutex.
+        waiters: ThreadList,
+    },
+}
+
+impl LockState {
+    /// Makes `owner_id` the owner of the mutex and raises its priority to `ceiling`.
+    fn lock(
+        &mut self,
+        cs: CriticalSection<'_>,
+        owner_id: ThreadId,
+        waiters: ThreadList,
+        ceiling: RunqueueId,
+    ) {
+        let owner_prio = SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            let owner_prio = scheduler.get_unchecked(owner_id).prio;
+            scheduler.set_priority(owner_id, owner_prio.max(ceiling));
+            owner_prio
+        });
+        *self = LockState::Locked {
+            owner_id,
+            owner_prio,
+            waiters,
+        };
+    }
+
+    /// Locks the mutex for the current thread if it is unlocked.
+    ///
+    /// Returns `false` if the mutex is locked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    fn try_lock(&mut self, cs: CriticalSection<'_>, ceiling: RunqueueId) -> bool {
+        let current = SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            scheduler
+                .current()
+                .expect("Function should be called inside a thread context.")
+                .tid
+        });
+        match self {
+            LockState::Unlocked => {
+                self.lock(cs, current, ThreadList::new(), ceiling);
+                true
+            }
+            LockState::Locked { .. } => false,
+        }
+    }
+
+    /// Inserts the current thread into the waitlist of a locked mutex, which also triggers the
+    /// scheduler.
+    fn wait(&mut self, cs: CriticalSection<'_>) {
+        if let LockState::Locked { waiters, .. } = self {
+            // The owner already runs with the ceiling priority, so no priority inheritance is
+            // needed.
+            waiters.put_current(cs, ThreadState::LockBlocked);
+            // Context switch happens here as soon as we leave the critical section.
+        }
+    }
+}
+
+impl<T> CeilingMutex<T> {
+    /// Creates a new **unlocked** [`CeilingMutex`] with priority ceiling `ceiling`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ceiling` is >= [`SCHED_PRIO_LEVELS`].
+    pub const fn new(ceiling: RunqueueId, value: T) -> Self {
+        assert!(
+            (ceiling.get() as usize) < SCHED_PRIO_LEVELS,
+            "ceiling must be a valid priority"
+        );
+        Self {
+            ceiling,
+            state: UnsafeCell::new(LockState::Unlocked),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns the priority ceiling of the mutex.
+    pub fn ceiling(&self) -> RunqueueId {
+        self.ceiling
+    }
+
+    /// Returns whether the mutex is locked.
+    pub fn is_locked(&self) -> bool {
+        critical_section::with(|_| {
+            let state = unsafe { &*self.state.get() };
+            !matches!(state, LockState::Unlocked)
+        })
+    }
+
+    /// Acquires a mutex, blocking the current thread until it is able to do so.
+    ///
+    /// Once the mutex is acquired, the current thread's priority is raised to the ceiling of the
+    /// mutex, until the returned [`CeilingMutexGuard`] is dropped.
+    /// The priority is reset once the mutex is released. This means that a **user can not change
+    /// a thread's priority while it holds the lock**, because it will be changed back after
+    /// release!
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn lock(&self) -> CeilingMutexGuard<'_, T> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            if !state.try_lock(cs, self.ceiling) {
+                state.wait(cs);
+            }
+        });
+        // Mutex was either directly acquired because it was unlocked, or the current thread was
+        // popped from the waitlist and acquired the mutex.
+
+        CeilingMutexGuard::new(self)
+    }
+
+    /// Acquires a mutex, blocking the current thread until it is able to do so or `timeout` has
+    /// passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the mutex could not be acquired in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn lock_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<CeilingMutexGuard<'_, T>, TimeoutError> {
+        // SAFETY (for all closures): access to the state only happens in critical sections, so
+        // it's always unique.
+        block_until(
+            deadline_after(timeout),
+            |cs| unsafe { &mut *self.state.get() }.try_lock(cs, self.ceiling),
+            |cs| unsafe { &mut *self.state.get() }.wait(cs),
+            |cs| {
+                if let LockState::Locked { waiters, .. } = unsafe { &mut *self.state.get() } {
+                    waiters.remove_current(cs);
+                }
+            },
+        )?;
+
+        Ok(CeilingMutexGuard::new(self))
+    }
+
+    /// Attempts to acquire this lock, in a non-blocking fashion.
+    ///
+    /// If the mutex was unlocked, it will be locked and a [`CeilingMutexGuard`] is returned.
+    /// If the mutex was locked `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn try_lock(&self) -> Option<CeilingMutexGuard<'_, T>> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            state
+                .try_lock(cs, self.ceiling)
+                .then(|| CeilingMutexGuard::new(self))
+        })
+    }
+
+    /// Releases the mutex.
+    ///
+    /// If there are waiters, the first waiter acquires the mutex.
+    fn release(&self) {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            if let LockState::Locked {
+                waiters,
+                owner_id,
+                owner_prio,
+            } = state
+            {
+                // Reset original priority of owner.
+                SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                    scheduler.set_priority(*owner_id, *owner_prio);
+                });
+                // Pop next thread from waitlist so that it can acquire the mutex.
+                let mut waiters = core::mem::replace(waiters, ThreadList::new());
+                if let Some((tid, _)) = waiters.pop(cs) {
+                    state.lock(cs, tid, waiters, self.ceiling);
+                } else {
+                    // Unlock if waitlist was empty.
+                    *state = LockState::Unlocked;
+                }
+            }
+        });
+    }
+}
+
+unsafe impl<T: Send> Sync for CeilingMutex<T> {}
+
+/// Grants access to the [`CeilingMutex`] inner data.
+///
+/// Dropping the [`CeilingMutexGuard`] will unlock the [`CeilingMutex`].
+#[must_use = "if unused the CeilingMutex will immediately unlock"]
+pub struct CeilingMutexGuard<'a, T> {
+    mutex: &'a CeilingMutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> CeilingMutexGuard<'a, T> {
+    fn new(mutex: &'a CeilingMutex<T>) -> Self {
+        Self {
+            mutex,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for CeilingMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: CeilingMutexGuard always has unique access.
+        unsafe { &*self.mutex.inner.get() }
+    }
+}
+
+impl<T> DerefMut for CeilingMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: CeilingMutexGuard always has unique access.
+        unsafe { &mut *self.mutex.inner.get() }
+    }
+}
+
+impl<T> Drop for CeilingMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unlock the mutex when the guard is dropped.
+        self.mutex.release();
+    }
+}
+
+unsafe impl<T: Sync> Sync for CeilingMutexGuard<'_, T> {}
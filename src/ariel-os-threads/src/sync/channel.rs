@@ -12,7 +12,9 @@ use core::mem::MaybeUninit;
 
 use crate::ThreadState;
 use crate::threadlist::ThreadList;
-use critical_section::with;
+use crate::timeout::{TimeoutError, block_until, deadline_after};
+use critical_section::{CriticalSection, with};
+use embassy_time::Duration;
 
 enum ChannelState {
     Idle,
@@ -48,70 +50,47 @@ impl<T: Copy + Send> Channel<T> {
     /// Panics if this is called outside of a thread context.
     pub fn send(&self, something: &T) {
         with(|cs| {
-            let state = unsafe { &mut *self.state.get() };
-            match state {
-                ChannelState::Idle => {
-                    let mut waiters = ThreadList::new();
-                    waiters.put_current(
-                        cs,
-                        crate::ThreadState::ChannelTxBlocked(
-                            core::ptr::from_ref::<T>(something) as usize
-                        ),
-                    );
-                    *state = ChannelState::SendersWaiting(waiters);
-                }
-                ChannelState::ReceiversWaiting(waiters) => {
-                    if let Some((_, head_state)) = waiters.pop(cs) {
-                        if waiters.is_empty(cs) {
-                            *state = ChannelState::Idle;
-                        }
-                        if let ThreadState::ChannelRxBlocked(ptr) = head_state {
-                            // copy over `something`
-                            unsafe { (ptr as *mut T).write(*something) };
-                        } else {
-                            unreachable!("unexpected thread state");
-                        }
-                    } else {
-                        unreachable!("unexpected empty thread list");
-                    }
-                }
-                ChannelState::SendersWaiting(waiters) => {
-                    waiters.put_current(
-                        cs,
-                        crate::ThreadState::ChannelTxBlocked(core::ptr::from_ref(self) as usize),
-                    );
-                }
+            if !self.pass_to_receiver(cs, something) {
+                self.wait_for_receiver(cs, something);
             }
         });
     }
 
+    /// Send on the channel (blocking), giving up after `timeout`.
+    ///
+    /// Like [`Self::send()`], but returns an error if no receiver took the data before `timeout`
+    /// has passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the data was not received in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn send_timeout(&self, something: &T, timeout: Duration) -> Result<(), TimeoutError> {
+        block_until(
+            deadline_after(timeout),
+            |cs| self.pass_to_receiver(cs, something),
+            |cs| self.wait_for_receiver(cs, something),
+            |cs| {
+                let state = unsafe { &mut *self.state.get() };
+                if let ChannelState::SendersWaiting(waiters) = state {
+                    waiters.remove_current(cs);
+                    if waiters.is_empty(cs) {
+                        *state = ChannelState::Idle;
+                    }
+                }
+            },
+        )
+    }
+
     /// Try to send on the channel (non-blocking).
     ///
     /// Returns `true` if a receiver was waiting and received
     /// the data, `false` otherwise.
     pub fn try_send(&self, something: &T) -> bool {
-        with(|cs| {
-            let state = unsafe { &mut *self.state.get() };
-            match state {
-                ChannelState::ReceiversWaiting(waiters) => {
-                    if let Some((_, head_state)) = waiters.pop(cs) {
-                        if waiters.is_empty(cs) {
-                            *state = ChannelState::Idle;
-                        }
-                        if let ThreadState::ChannelRxBlocked(ptr) = head_state {
-                            // copy over `something`
-                            unsafe { (ptr as *mut T).write(*something) };
-                        } else {
-                            unreachable!("unexpected thread state");
-                        }
-                    } else {
-                        unreachable!("unexpected empty thread list");
-                    }
-                    true
-                }
-                _ => false,
-            }
-        })
+        with(|cs| self.pass_to_receiver(cs, something))
     }
 
     /// Receive on the channel (blocking).
@@ -126,33 +105,10 @@ impl<T: Copy + Send> Channel<T> {
         let mut res: MaybeUninit<T> = MaybeUninit::uninit();
 
         with(|cs| {
-            let state = unsafe { &mut *self.state.get() };
             let ptr = res.as_mut_ptr();
-            match state {
-                ChannelState::Idle => {
-                    let mut waiters = ThreadList::new();
-                    waiters.put_current(cs, crate::ThreadState::ChannelRxBlocked(ptr as usize));
-                    *state = ChannelState::ReceiversWaiting(waiters);
-                }
-                ChannelState::ReceiversWaiting(waiters) => {
-                    waiters.put_current(cs, crate::ThreadState::ChannelRxBlocked(ptr as usize));
-                    // sender will copy message
-                }
-                ChannelState::SendersWaiting(waiters) => {
-                    if let Some((_, head_state)) = waiters.pop(cs) {
-                        if waiters.is_empty(cs) {
-                            *state = ChannelState::Idle;
-                        }
-                        if let ThreadState::ChannelTxBlocked(other_ptr) = head_state {
-                            // copy over `something`
-                            unsafe { ptr.write(*(other_ptr as *const T)) };
-                        } else {
-                            unreachable!("unexpected thread state");
-                        }
-                    } else {
-                        unreachable!("unexpected empty thread list");
-                    }
-                }
+            if !self.take_from_sender(cs, ptr) {
+                // sender will copy message
+                self.wait_for_sender(cs, ptr);
             }
         });
 
@@ -163,35 +119,51 @@ impl<T: Copy + Send> Channel<T> {
         unsafe { res.assume_init() }
     }
 
+    /// Receive on the channel (blocking), giving up after `timeout`.
+    ///
+    /// Like [`Self::recv()`], but returns an error if no sender provided data before `timeout` has
+    /// passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if no data was received in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, TimeoutError> {
+        let mut res: MaybeUninit<T> = MaybeUninit::uninit();
+        let ptr = res.as_mut_ptr();
+
+        block_until(
+            deadline_after(timeout),
+            |cs| self.take_from_sender(cs, ptr),
+            |cs| self.wait_for_sender(cs, ptr),
+            |cs| {
+                let state = unsafe { &mut *self.state.get() };
+                if let ChannelState::ReceiversWaiting(waiters) = state {
+                    waiters.remove_current(cs);
+                    if waiters.is_empty(cs) {
+                        *state = ChannelState::Idle;
+                    }
+                }
+            },
+        )?;
+
+        // ensure the compiler honors what happened to memory while the thread
+        // was scheduled away.
+        core::sync::atomic::fence(core::sync::atomic::Ordering::Acquire);
+
+        Ok(unsafe { res.assume_init() })
+    }
+
     /// Try to send on the channel (non-blocking).
     ///
     /// Returns `Some` data if a sender was waiting and the
     /// data could be received, `None` otherwise.
     pub fn try_recv(&self) -> Option<T> {
         let mut res: MaybeUninit<T> = MaybeUninit::uninit();
-        let have_received = with(|cs| {
-            let state = unsafe { &mut *self.state.get() };
-            match state {
-                ChannelState::SendersWaiting(waiters) => {
-                    let ptr = res.as_mut_ptr();
-                    if let Some((_, head_state)) = waiters.pop(cs) {
-                        if waiters.is_empty(cs) {
-                            *state = ChannelState::Idle;
-                        }
-                        if let ThreadState::ChannelTxBlocked(other_ptr) = head_state {
-                            // copy over `something`
-                            unsafe { ptr.write(*(other_ptr as *const T)) };
-                        } else {
-                            unreachable!("unexpected thread state");
-                        }
-                        true
-                    } else {
-                        unreachable!("unexpected empty thread list");
-                    }
-                }
-                _ => false,
-            }
-        });
+        let have_received = with(|cs| self.take_from_sender(cs, res.as_mut_ptr()));
 
         if have_received {
             core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Acquire);
@@ -200,6 +172,87 @@ impl<T: Copy + Send> Channel<T> {
             None
         }
     }
+
+    /// Copies `something` to the first waiting receiver, if any.
+    ///
+    /// Returns `true` if a receiver was waiting.
+    fn pass_to_receiver(&self, cs: CriticalSection<'_>, something: &T) -> bool {
+        let state = unsafe { &mut *self.state.get() };
+        let ChannelState::ReceiversWaiting(waiters) = state else {
+            return false;
+        };
+        let Some((_, head_state)) = waiters.pop(cs) else {
+            // Only receivers that have timed out were left.
+            *state = ChannelState::Idle;
+            return false;
+        };
+        if waiters.is_empty(cs) {
+            *state = ChannelState::Idle;
+        }
+        if let ThreadState::ChannelRxBlocked(ptr) = head_state {
+            // copy over `something`
+            unsafe { (ptr as *mut T).write(*something) };
+        } else {
+            unreachable!("unexpected thread state");
+        }
+        true
+    }
+
+    /// Copies the data of the first waiting sender to `ptr`, if any.
+    ///
+    /// Returns `true` if a sender was waiting.
+    fn take_from_sender(&self, cs: CriticalSection<'_>, ptr: *mut T) -> bool {
+        let state = unsafe { &mut *self.state.get() };
+        let ChannelState::SendersWaiting(waiters) = state else {
+            return false;
+        };
+        let Some((_, head_state)) = waiters.pop(cs) else {
+            // Only senders that have timed out were left.
+            *state = ChannelState::Idle;
+            return false;
+        };
+        if waiters.is_empty(cs) {
+            *state = ChannelState::Idle;
+        }
+        if let ThreadState::ChannelTxBlocked(other_ptr) = head_state {
+            // copy over `something`
+            unsafe { ptr.write(*(other_ptr as *const T)) };
+        } else {
+            unreachable!("unexpected thread state");
+        }
+        true
+    }
+
+    /// Suspends the current thread until a receiver takes `something`.
+    ///
+    /// Must only be called if no receiver is waiting.
+    fn wait_for_receiver(&self, cs: CriticalSection<'_>, something: &T) {
+        let state = unsafe { &mut *self.state.get() };
+        let thread_state =
+            ThreadState::ChannelTxBlocked(core::ptr::from_ref::<T>(something) as usize);
+        if let ChannelState::SendersWaiting(waiters) = state {
+            waiters.put_current(cs, thread_state);
+        } else {
+            let mut waiters = ThreadList::new();
+            waiters.put_current(cs, thread_state);
+            *state = ChannelState::SendersWaiting(waiters);
+        }
+    }
+
+    /// Suspends the current thread until a sender has copied its data to `ptr`.
+    ///
+    /// Must only be called if no sender is waiting.
+    fn wait_for_sender(&self, cs: CriticalSection<'_>, ptr: *mut T) {
+        let state = unsafe { &mut *self.state.get() };
+        let thread_state = ThreadState::ChannelRxBlocked(ptr as usize);
+        if let ChannelState::ReceiversWaiting(waiters) = state {
+            waiters.put_current(cs, thread_state);
+        } else {
+            let mut waiters = ThreadList::new();
+            waiters.put_current(cs, thread_state);
+            *state = ChannelState::ReceiversWaiting(waiters);
+        }
+    }
 }
 
 impl<T: Copy + Send> Default for Channel<T> {
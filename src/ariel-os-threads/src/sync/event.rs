@@ -9,7 +9,13 @@
 
 use core::cell::UnsafeCell;
 
-use crate::{ThreadState, threadlist::ThreadList};
+use embassy_time::Duration;
+
+use crate::{
+    ThreadState,
+    threadlist::ThreadList,
+    timeout::{TimeoutError, block_until, deadline_after},
+};
 
 /// An [`Event`], allowing to notify multiple threads that some event has happened.
 ///
@@ -80,6 +86,38 @@ impl Event {
         });
     }
 
+    /// Waits for this [`Event`] to be set (blocking), giving up after `timeout`.
+    ///
+    /// Like [`Self::wait()`], but returns an error if the event was not set before `timeout` has
+    /// passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the event was not set in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<(), TimeoutError> {
+        block_until(
+            deadline_after(timeout),
+            |_| {
+                let state = unsafe { &*self.state.get() };
+                matches!(state, LockState::Unlocked)
+            },
+            |cs| {
+                if let LockState::Locked(waiters) = unsafe { &mut *self.state.get() } {
+                    waiters.put_current(cs, ThreadState::LockBlocked);
+                }
+            },
+            |cs| {
+                if let LockState::Locked(waiters) = unsafe { &mut *self.state.get() } {
+                    waiters.remove_current(cs);
+                }
+            },
+        )
+    }
+
     /// Clears the event (non-blocking).
     ///
     /// If the event was set, it will be cleared and the function returns true.
@@ -8,7 +8,13 @@
 
 use core::cell::UnsafeCell;
 
-use crate::{ThreadState, threadlist::ThreadList};
+use embassy_time::Duration;
+
+use crate::{
+    ThreadState,
+    threadlist::ThreadList,
+    timeout::{TimeoutError, block_until, deadline_after},
+};
 
 /// A basic locking object.
 ///
@@ -73,6 +79,44 @@ impl Lock {
         });
     }
 
+    /// Get this lock (blocking), giving up after `timeout`.
+    ///
+    /// Like [`Self::acquire()`], but returns an error if the lock could not be acquired before
+    /// `timeout` has passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the lock was not acquired in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Result<(), TimeoutError> {
+        block_until(
+            deadline_after(timeout),
+            |_| {
+                let state = unsafe { &mut *self.state.get() };
+                match state {
+                    LockState::Unlocked => {
+                        *state = LockState::Locked(ThreadList::new());
+                        true
+                    }
+                    LockState::Locked(_) => false,
+                }
+            },
+            |cs| {
+                if let LockState::Locked(waiters) = unsafe { &mut *self.state.get() } {
+                    waiters.put_current(cs, ThreadState::LockBlocked);
+                }
+            },
+            |cs| {
+                if let LockState::Locked(waiters) = unsafe { &mut *self.state.get() } {
+                    waiters.remove_current(cs);
+                }
+            },
+        )
+    }
+
     /// Get the lock (non-blocking).
     ///
     /// If the lock was unlocked, it will be locked and the function returns true.
@@ -1,12 +1,22 @@
 //! Synchronization primitives.
+mod ceiling_mutex;
 mod channel;
 mod event;
 mod lock;
 mod mutex;
+mod queue;
+mod recursive_mutex;
+mod rwlock;
+mod semaphore;
 mod wait_queue;
 
+pub use ceiling_mutex::{CeilingMutex, CeilingMutexGuard};
 pub use channel::Channel;
 pub use event::Event;
 pub use lock::Lock;
 pub use mutex::{Mutex, MutexGuard};
+pub use queue::Queue;
+pub use recursive_mutex::{RecursiveMutex, RecursiveMutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
 pub use wait_queue::WaitQueue;
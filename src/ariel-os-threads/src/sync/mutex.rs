@@ -13,8 +13,14 @@ use core::{
 
 use ariel_os_runqueue::{RunqueueId, ThreadId};
 use critical_section::CriticalSection;
+use embassy_time::Duration;
 
-use crate::{SCHEDULER, thread::ThreadState, threadlist::ThreadList};
+use crate::{
+    SCHEDULER,
+    thread::ThreadState,
+    threadlist::ThreadList,
+    timeout::{TimeoutError, block_until, deadline_after},
+};
 
 /// A basic mutex with priority inheritance.
 pub struct Mutex<T> {
@@ -55,6 +61,37 @@ impl LockState {
             owner_prio,
         }
     }
+
+    /// Inserts the current thread into the waitlist of a locked mutex, which also triggers the
+    /// scheduler.
+    ///
+    /// If the current owner of the mutex has a lower priority than the current thread, it
+    /// inherits the waiting thread's priority.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    fn wait(&mut self, cs: CriticalSection<'_>) {
+        if let LockState::Locked {
+            waiters,
+            owner_id,
+            owner_prio,
+        } = self
+        {
+            match waiters.put_current(cs, ThreadState::LockBlocked) {
+                // `Some` when the inserted thread is the highest priority
+                // thread in the waitlist.
+                Some(waiter_prio) if waiter_prio > *owner_prio => {
+                    // Current mutex owner inherits the priority.
+                    SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                        scheduler.set_priority(*owner_id, waiter_prio);
+                    });
+                }
+                _ => {}
+            }
+            // Context switch happens here as soon as we leave the critical section.
+        }
+    }
 }
 
 impl<T> Mutex<T> {
@@ -98,25 +135,7 @@ impl<T> Mutex<T> {
                 LockState::Unlocked => {
                     *state = LockState::locked_with_current(cs);
                 }
-                LockState::Locked {
-                    waiters,
-                    owner_id,
-                    owner_prio,
-                } => {
-                    // Insert thread in waitlist, which also triggers the scheduler.
-                    match waiters.put_current(cs, ThreadState::LockBlocked) {
-                        // `Some` when the inserted thread is the highest priority
-                        // thread in the waitlist.
-                        Some(waiter_prio) if waiter_prio > *owner_prio => {
-                            // Current mutex owner inherits the priority.
-                            SCHEDULER.with_mut_cs(cs, |mut scheduler| {
-                                scheduler.set_priority(*owner_id, waiter_prio);
-                            });
-                        }
-                        _ => {}
-                    }
-                    // Context switch happens here as soon as we leave the critical section.
-                }
+                LockState::Locked { .. } => state.wait(cs),
             }
         });
         // Mutex was either directly acquired because it was unlocked, or the current thread was entered
@@ -126,6 +145,61 @@ impl<T> Mutex<T> {
         MutexGuard::new(self)
     }
 
+    /// Acquires a mutex, blocking the current thread until it is able to do so or `timeout` has
+    /// passed.
+    ///
+    /// Like [`Self::lock()`], including the priority inheritance while waiting.
+    /// If the timeout is reached, the priority of the current owner is reset to the priority of
+    /// the remaining waiters.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the mutex could not be acquired in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn lock_timeout(&self, timeout: Duration) -> Result<MutexGuard<'_, T>, TimeoutError> {
+        block_until(
+            deadline_after(timeout),
+            |cs| {
+                // SAFETY: access to the state only happens in critical sections, so it's always unique.
+                let state = unsafe { &mut *self.state.get() };
+                if let LockState::Unlocked = *state {
+                    *state = LockState::locked_with_current(cs);
+                    true
+                } else {
+                    false
+                }
+            },
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            |cs| unsafe { &mut *self.state.get() }.wait(cs),
+            |cs| {
+                // SAFETY: access to the state only happens in critical sections, so it's always unique.
+                let state = unsafe { &mut *self.state.get() };
+                if let LockState::Locked {
+                    waiters,
+                    owner_id,
+                    owner_prio,
+                } = state
+                {
+                    waiters.remove_current(cs);
+                    // The owner might have inherited the priority of the current thread.
+                    let prio = waiters
+                        .head_prio(cs)
+                        .map_or(*owner_prio, |prio| prio.max(*owner_prio));
+                    SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                        scheduler.set_priority(*owner_id, prio);
+                    });
+                }
+            },
+        )?;
+        // Mutex was either directly acquired because it was unlocked, or the current thread was
+        // popped from the waitlist and acquired the mutex.
+
+        Ok(MutexGuard::new(self))
+    }
+
     /// Attempts to acquire this lock, in a non-blocking fashion.
     ///
     /// If the mutex was unlocked, it will be locked and a [`MutexGuard`] is returned.
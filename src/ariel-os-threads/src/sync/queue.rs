@@ -0,0 +1,241 @@
+//! This module provides a bounded multi-producer, multi-consumer queue.
+
+#![expect(unsafe_code)]
+#![deny(missing_docs)]
+#![expect(
+    clippy::undocumented_unsafe_blocks,
+    reason = "should be addressed eventually"
+)]
+
+use core::{cell::UnsafeCell, mem::MaybeUninit};
+
+use critical_section::CriticalSection;
+use embassy_time::Duration;
+use rbi::RingBufferIndex;
+
+use crate::{
+    sync::WaitQueue,
+    timeout::{TimeoutError, block_until, deadline_after},
+};
+
+/// A bounded FIFO queue for sending data between threads.
+///
+/// Unlike [`Channel`](super::Channel), a [`Queue`] buffers up to `N` values, so that senders
+/// only block if the queue is full.
+/// Any number of threads can send and receive on the same queue.
+///
+/// [`Self::try_send()`] never blocks, and can also be used from interrupt handlers.
+///
+/// `N` must be a power of two between 2 and 128.
+pub struct Queue<T, const N: usize> {
+    index: UnsafeCell<RingBufferIndex>,
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    /// Receivers waiting for the queue to be non-empty.
+    receivers: WaitQueue,
+    /// Senders waiting for the queue to be non-full.
+    senders: WaitQueue,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, N> {}
+
+impl<T, const N: usize> Queue<T, N> {
+    /// Creates a new, empty [`Queue`].
+    #[must_use]
+    pub const fn new() -> Self {
+        const {
+            assert!(
+                N.is_power_of_two() && N >= 2 && N <= 128,
+                "queue size must be a power of two between 2 and 128"
+            );
+        }
+        Self {
+            index: UnsafeCell::new(RingBufferIndex::new(N as u8)),
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            receivers: WaitQueue::new(),
+            senders: WaitQueue::new(),
+        }
+    }
+
+    /// Returns the maximum number of values the queue can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the number of values currently in the queue.
+    pub fn len(&self) -> usize {
+        critical_section::with(|_| usize::from(unsafe { &*self.index.get() }.available()))
+    }
+
+    /// Returns whether the queue is empty.
+    pub fn is_empty(&self) -> bool {
+        critical_section::with(|_| unsafe { &*self.index.get() }.is_empty())
+    }
+
+    /// Returns whether the queue is full.
+    pub fn is_full(&self) -> bool {
+        critical_section::with(|_| unsafe { &*self.index.get() }.is_full())
+    }
+
+    /// Sends a value on the queue (blocking).
+    ///
+    /// If the queue is full, the current thread is suspended until a receiver has made room.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context while the queue is full.
+    pub fn send(&self, value: T) {
+        let mut value = Some(value);
+        while let Some(v) = value.take() {
+            critical_section::with(|cs| {
+                if let Err(v) = self.put(cs, v) {
+                    value = Some(v);
+                    self.senders.wait_cs(cs);
+                }
+            });
+        }
+    }
+
+    /// Sends a value on the queue (blocking), giving up after `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the value back if the queue stayed full until `timeout` elapsed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context while the queue is full.
+    pub fn send_timeout(&self, value: T, timeout: Duration) -> Result<(), T> {
+        let deadline = deadline_after(timeout);
+        let mut value = Some(value);
+        while let Some(v) = value.take() {
+            let sent = block_until(
+                deadline,
+                |cs| match self.put(cs, v) {
+                    Ok(()) => true,
+                    Err(v) => {
+                        value = Some(v);
+                        false
+                    }
+                },
+                |cs| self.senders.wait_cs(cs),
+                |cs| self.senders.remove_current_cs(cs),
+            );
+            if sent.is_err() {
+                break;
+            }
+            // Otherwise, either the value was sent, or another sender was faster and we wait
+            // again.
+        }
+        value.map_or(Ok(()), Err)
+    }
+
+    /// Tries to send a value on the queue (non-blocking).
+    ///
+    /// This can also be called from interrupt handlers.
+    ///
+    /// # Errors
+    ///
+    /// Returns the value back if the queue is full.
+    pub fn try_send(&self, value: T) -> Result<(), T> {
+        critical_section::with(|cs| self.put(cs, value))
+    }
+
+    /// Receives a value from the queue (blocking).
+    ///
+    /// If the queue is empty, the current thread is suspended until a value has been sent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context while the queue is empty.
+    pub fn recv(&self) -> T {
+        loop {
+            let value = critical_section::with(|cs| {
+                let value = self.take(cs);
+                if value.is_none() {
+                    self.receivers.wait_cs(cs);
+                }
+                value
+            });
+            if let Some(value) = value {
+                return value;
+            }
+        }
+    }
+
+    /// Receives a value from the queue (blocking), giving up after `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if no value was received in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context while the queue is empty.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, TimeoutError> {
+        let deadline = deadline_after(timeout);
+        loop {
+            let mut value = None;
+            block_until(
+                deadline,
+                |cs| {
+                    value = self.take(cs);
+                    value.is_some()
+                },
+                |cs| self.receivers.wait_cs(cs),
+                |cs| self.receivers.remove_current_cs(cs),
+            )?;
+            // Another receiver might have been faster, in which case we wait again.
+            if let Some(value) = value {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Tries to receive a value from the queue (non-blocking).
+    ///
+    /// Returns `None` if the queue is empty.
+    pub fn try_recv(&self) -> Option<T> {
+        critical_section::with(|cs| self.take(cs))
+    }
+
+    /// Stores `value` in the next free slot and wakes up a waiting receiver.
+    ///
+    /// # Errors
+    ///
+    /// Returns the value back if the queue is full.
+    fn put(&self, cs: CriticalSection<'_>, value: T) -> Result<(), T> {
+        let index = unsafe { &mut *self.index.get() };
+        let Some(pos) = index.put() else {
+            return Err(value);
+        };
+        // SAFETY: the slot was free, and is only read again after `index.get()` returned it.
+        unsafe { (*self.slots[usize::from(pos)].get()).write(value) };
+        self.receivers.notify_one_cs(cs);
+        Ok(())
+    }
+
+    /// Takes the oldest value out of the queue and wakes up a waiting sender.
+    fn take(&self, cs: CriticalSection<'_>) -> Option<T> {
+        let index = unsafe { &mut *self.index.get() };
+        let pos = index.get()?;
+        // SAFETY: the slot has been written by `put()`, and is marked free again by `index.get()`.
+        let value = unsafe { (*self.slots[usize::from(pos)].get()).assume_init_read() };
+        self.senders.notify_one_cs(cs);
+        Some(value)
+    }
+}
+
+impl<T, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        while let Some(pos) = self.index.get_mut().get() {
+            // SAFETY: the slot has been written by `put()` and not read since.
+            unsafe { self.slots[usize::from(pos)].get_mut().assume_init_drop() };
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! This module provides a recursive mutex with priority inheritance.
+
+#![deny(missing_docs)]
+#![expect(unsafe_code)]
+#![expect(
+    clippy::undocumented_unsafe_blocks,
+    reason = "should be addressed eventually"
+)]
+
+use core::{cell::UnsafeCell, marker::PhantomData, ops::Deref};
+
+use ariel_os_runqueue::{RunqueueId, ThreadId};
+use critical_section::CriticalSection;
+use embassy_time::Duration;
+
+use crate::{
+    SCHEDULER,
+    thread::ThreadState,
+    threadlist::ThreadList,
+    timeout::{TimeoutError, block_until, deadline_after},
+};
+
+/// A mutex with priority inheritance that can be locked multiple times by the thread that owns
+/// it.
+///
+/// The mutex is only released once every [`RecursiveMutexGuard`] of the owner has been dropped.
+/// As guards of the same thread may coexist, they only grant shared access to the inner data;
+/// use interior mutability, e.g., a [`RefCell`](core::cell::RefCell), to modify it.
+///
+/// Priority inheritance works like with [`Mutex`](super::Mutex).
+pub struct RecursiveMutex<T> {
+    state: UnsafeCell<LockState>,
+    inner: T,
+}
+
+/// State of a [`RecursiveMutex`].
+enum LockState {
+    Unlocked,
+    Locked {
+        /// The current owner of the lock.
+        owner_id: ThreadId,
+        /// The original priority of the current owner (without priority inheritance).
+        owner_prio: RunqueueId,
+        /// How often the owner has locked the mutex.
+        count: usize,
+        /// Waiters for the mutex.
+        waiters: ThreadList,
+    },
+}
+
+impl LockState {
+    /// Locks the mutex for the current thread if it is unlocked, or increments the lock count if
+    /// the current thread already owns it.
+    ///
+    /// Returns `false` if the mutex is locked by another thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context, or if the lock count overflows.
+    fn try_lock(&mut self, cs: CriticalSection<'_>) -> bool {
+        let (current_id, current_prio) = SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            let current = scheduler
+                .current()
+                .expect("Function should be called inside a thread context.");
+            (current.tid, current.prio)
+        });
+        match self {
+            LockState::Unlocked => {
+                *self = LockState::Locked {
+                    owner_id: current_id,
+                    owner_prio: current_prio,
+                    count: 1,
+                    waiters: ThreadList::new(),
+                };
+                true
+            }
+            LockState::Locked {
+                owner_id, count, ..
+            } if *owner_id == current_id => {
+                *count = count
+                    .checked_add(1)
+                    .expect("lock count should not overflow");
+                true
+            }
+            LockState::Locked { .. } => false,
+        }
+    }
+
+    /// Inserts the current thread into the waitlist of a locked mutex, which also triggers the
+    /// scheduler.
+    ///
+    /// If the current owner of the mutex has a lower priority than the current thread, it
+    /// inherits the waiting thread's priority.
+    fn wait(&mut self, cs: CriticalSection<'_>) {
+        if let LockState::Locked {
+            waiters,
+            owner_id,
+            owner_prio,
+            ..
+        } = self
+        {
+            match waiters.put_current(cs, ThreadState::LockBlocked) {
+                // `Some` when the inserted thread is the highest priority
+                // thread in the waitlist.
+                Some(waiter_prio) if waiter_prio > *owner_prio => {
+                    // Current mutex owner inherits the priority.
+                    SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                        scheduler.set_priority(*owner_id, waiter_prio);
+                    });
+                }
+                _ => {}
+            }
+            // Context switch happens here as soon as we leave the critical section.
+        }
+    }
+}
+
+impl<T> RecursiveMutex<T> {
+    /// Creates a new **unlocked** [`RecursiveMutex`].
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: UnsafeCell::new(LockState::Unlocked),
+            inner: value,
+        }
+    }
+
+    /// Returns whether the mutex is locked.
+    pub fn is_locked(&self) -> bool {
+        critical_section::with(|_| {
+            let state = unsafe { &*self.state.get() };
+            !matches!(state, LockState::Unlocked)
+        })
+    }
+
+    /// Acquires a mutex, blocking the current thread until it is able to do so.
+    ///
+    /// If the mutex is unlocked or already owned by the current thread, a
+    /// [`RecursiveMutexGuard`] is returned right away.
+    /// Otherwise, this function will block the current thread until the mutex gets unlocked
+    /// elsewhere.
+    ///
+    /// If the current owner of the mutex has a lower priority than the current thread, it will
+    /// inherit the waiting thread's priority.
+    /// The priority is reset once the mutex is released. This means that a **user can not change
+    /// a thread's priority while it holds the lock**, because it will be changed back after
+    /// release!
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context, or if the lock count overflows.
+    pub fn lock(&self) -> RecursiveMutexGuard<'_, T> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            if !state.try_lock(cs) {
+                state.wait(cs);
+            }
+        });
+        // Mutex was either directly acquired, or the current thread was popped from the waitlist
+        // and acquired the mutex.
+
+        RecursiveMutexGuard::new(self)
+    }
+
+    /// Acquires a mutex, blocking the current thread until it is able to do so or `timeout` has
+    /// passed.
+    ///
+    /// Like [`Self::lock()`], including the priority inheritance while waiting.
+    /// If the timeout is reached, the priority of the current owner is reset to the priority of
+    /// the remaining waiters.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if the mutex could not be acquired in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context, or if the lock count overflows.
+    pub fn lock_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<RecursiveMutexGuard<'_, T>, TimeoutError> {
+        // SAFETY (for all closures): access to the state only happens in critical sections, so
+        // it's always unique.
+        block_until(
+            deadline_after(timeout),
+            |cs| unsafe { &mut *self.state.get() }.try_lock(cs),
+            |cs| unsafe { &mut *self.state.get() }.wait(cs),
+            |cs| {
+                if let LockState::Locked {
+                    waiters,
+                    owner_id,
+                    owner_prio,
+                    ..
+                } = unsafe { &mut *self.state.get() }
+                {
+                    waiters.remove_current(cs);
+                    // The owner might have inherited the priority of the current thread.
+                    let prio = waiters
+                        .head_prio(cs)
+                        .map_or(*owner_prio, |prio| prio.max(*owner_prio));
+                    SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                        scheduler.set_priority(*owner_id, prio);
+                    });
+                }
+            },
+        )?;
+
+        Ok(RecursiveMutexGuard::new(self))
+    }
+
+    /// Attempts to acquire this lock, in a non-blocking fashion.
+    ///
+    /// If the mutex was unlocked or is owned by the current thread, a [`RecursiveMutexGuard`] is
+    /// returned.
+    /// If the mutex was locked by another thread `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context, or if the lock count overflows.
+    pub fn try_lock(&self) -> Option<RecursiveMutexGuard<'_, T>> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            state.try_lock(cs).then(|| RecursiveMutexGuard::new(self))
+        })
+    }
+
+    /// Releases the mutex once.
+    ///
+    /// If this was the last guard of the owner and there are waiters, the first waiter will be
+    /// woken up.
+    fn release(&self) {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            if let LockState::Locked {
+                waiters,
+                owner_id,
+                owner_prio,
+                count,
+            } = state
+            {
+                *count -= 1;
+                if *count > 0 {
+                    return;
+                }
+                // Reset original priority of owner.
+                SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                    scheduler.set_priority(*owner_id, *owner_prio);
+                });
+                // Pop next thread from waitlist so that it can acquire the mutex.
+                if let Some((tid, _)) = waiters.pop(cs) {
+                    SCHEDULER.with_mut_cs(cs, |scheduler| {
+                        *owner_id = tid;
+                        *owner_prio = scheduler.get_unchecked(tid).prio;
+                    });
+                    *count = 1;
+                } else {
+                    // Unlock if waitlist was empty.
+                    *state = LockState::Unlocked;
+                }
+            }
+        });
+    }
+}
+
+unsafe impl<T: Send> Sync for RecursiveMutex<T> {}
+
+/// Grants shared access to the [`RecursiveMutex`] inner data.
+///
+/// Dropping the last [`RecursiveMutexGuard`] of the owner will unlock the [`RecursiveMutex`].
+#[must_use = "if unused the RecursiveMutex will immediately unlock"]
+pub struct RecursiveMutexGuard<'a, T> {
+    mutex: &'a RecursiveMutex<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> RecursiveMutexGuard<'a, T> {
+    fn new(mutex: &'a RecursiveMutex<T>) -> Self {
+        Self {
+            mutex,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for RecursiveMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.mutex.inner
+    }
+}
+
+impl<T> Drop for RecursiveMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Release the mutex once when the guard is dropped.
+        self.mutex.release();
+    }
+}
@@ -0,0 +1,427 @@
+//! This module provides a reader-writer lock with priority inheritance for writers.
+
+#![deny(missing_docs)]
+#![expect(unsafe_code)]
+#![expect(
+    clippy::undocumented_unsafe_blocks,
+    reason = "should be addressed eventually"
+)]
+
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use ariel_os_runqueue::{RunqueueId, ThreadId};
+use critical_section::CriticalSection;
+use embassy_time::Duration;
+
+use crate::{
+    SCHEDULER,
+    thread::ThreadState,
+    threadlist::ThreadList,
+    timeout::{TimeoutError, block_until, deadline_after},
+};
+
+/// A reader-writer lock with priority inheritance for writers.
+///
+/// Allows either any number of readers or a single writer at a time.
+/// Once a writer is waiting, new readers have to wait too, so that writers are not starved.
+/// When a writer releases the lock, waiting readers are preferred over waiting writers.
+///
+/// While a writer holds the lock, it inherits the priority of the highest priority waiting
+/// thread, like with [`Mutex`](super::Mutex).
+/// Readers holding the lock do not inherit priorities.
+pub struct RwLock<T> {
+    state: UnsafeCell<RwLockState>,
+    inner: UnsafeCell<T>,
+}
+
+/// State of a [`RwLock`].
+struct RwLockState {
+    lock: LockState,
+    /// Threads waiting for read access.
+    readers: ThreadList,
+    /// Threads waiting for write access.
+    writers: ThreadList,
+}
+
+enum LockState {
+    Unlocked,
+    /// Locked by the given number of readers.
+    Read(usize),
+    Write {
+        /// The current owner of the lock.
+        owner_id: ThreadId,
+        /// The original priority of the current owner (without priority inheritance).
+        owner_prio: RunqueueId,
+    },
+}
+
+impl RwLockState {
+    /// Takes read access if the lock is not written and no writer is waiting.
+    fn try_read(&mut self, cs: CriticalSection<'_>) -> bool {
+        match &mut self.lock {
+            LockState::Unlocked => {
+                self.lock = LockState::Read(1);
+                true
+            }
+            LockState::Read(readers) if self.writers.is_empty(cs) => {
+                *readers += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Takes write access if the lock is unlocked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    fn try_write(&mut self, cs: CriticalSection<'_>) -> bool {
+        if !matches!(self.lock, LockState::Unlocked) {
+            return false;
+        }
+        let (owner_id, owner_prio) = SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+            let current = scheduler
+                .current()
+                .expect("Function should be called inside a thread context.");
+            (current.tid, current.prio)
+        });
+        self.lock = LockState::Write {
+            owner_id,
+            owner_prio,
+        };
+        true
+    }
+
+    /// Lets a writing owner inherit the priority of the highest priority waiting thread, or resets
+    /// it to its original priority if there is none.
+    fn inherit_priority(&self, cs: CriticalSection<'_>) {
+        if let LockState::Write {
+            owner_id,
+            owner_prio,
+        } = self.lock
+        {
+            let prio = [self.readers.head_prio(cs), self.writers.head_prio(cs)]
+                .into_iter()
+                .flatten()
+                .fold(owner_prio, RunqueueId::max);
+            SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                scheduler.set_priority(owner_id, prio);
+            });
+        }
+    }
+
+    /// Wakes up all waiting readers and grants them read access.
+    ///
+    /// Returns `false` if no reader was waiting.
+    fn wake_readers(&mut self, cs: CriticalSection<'_>) -> bool {
+        let mut woken = 0;
+        while self.readers.pop(cs).is_some() {
+            woken += 1;
+        }
+        if woken == 0 {
+            return false;
+        }
+        match &mut self.lock {
+            LockState::Read(readers) => *readers += woken,
+            _ => self.lock = LockState::Read(woken),
+        }
+        true
+    }
+
+    /// Wakes up the highest priority waiting writer and grants it write access.
+    ///
+    /// Returns `false` if no writer was waiting.
+    fn wake_writer(&mut self, cs: CriticalSection<'_>) -> bool {
+        let Some((owner_id, _)) = self.writers.pop(cs) else {
+            return false;
+        };
+        let owner_prio = SCHEDULER.with_cs(cs, |scheduler| scheduler.get_unchecked(owner_id).prio);
+        self.lock = LockState::Write {
+            owner_id,
+            owner_prio,
+        };
+        // The new owner inherits the priority of the remaining waiters.
+        self.inherit_priority(cs);
+        true
+    }
+
+    fn release_read(&mut self, cs: CriticalSection<'_>) {
+        if let LockState::Read(readers) = &mut self.lock {
+            *readers -= 1;
+            if *readers == 0 {
+                self.lock = LockState::Unlocked;
+                // Readers only wait while a writer is waiting, so prefer the writer.
+                let _ = self.wake_writer(cs) || self.wake_readers(cs);
+            }
+        }
+    }
+
+    fn release_write(&mut self, cs: CriticalSection<'_>) {
+        if let LockState::Write {
+            owner_id,
+            owner_prio,
+        } = self.lock
+        {
+            // Reset original priority of owner.
+            SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                scheduler.set_priority(owner_id, owner_prio);
+            });
+            self.lock = LockState::Unlocked;
+            let _ = self.wake_readers(cs) || self.wake_writer(cs);
+        }
+    }
+
+    /// Removes the current reader from the waiters after its deadline was reached.
+    fn read_timeout(&mut self, cs: CriticalSection<'_>) {
+        self.readers.remove_current(cs);
+        self.inherit_priority(cs);
+    }
+
+    /// Removes the current writer from the waiters after its deadline was reached.
+    fn write_timeout(&mut self, cs: CriticalSection<'_>) {
+        self.writers.remove_current(cs);
+        if matches!(self.lock, LockState::Read(_)) && self.writers.is_empty(cs) {
+            // Readers that only waited because of this writer can join now.
+            self.wake_readers(cs);
+        } else {
+            self.inherit_priority(cs);
+        }
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Creates a new **unlocked** [`RwLock`].
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: UnsafeCell::new(RwLockState {
+                lock: LockState::Unlocked,
+                readers: ThreadList::new(),
+                writers: ThreadList::new(),
+            }),
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires read access, blocking the current thread until it is able to do so.
+    ///
+    /// If the lock is held by a writer, or a writer is waiting for it, this function blocks the
+    /// current thread until the lock gets released elsewhere.
+    /// A writer holding the lock inherits the priority of the current thread if that is higher.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            if !state.try_read(cs) {
+                state.readers.put_current(cs, ThreadState::LockBlocked);
+                state.inherit_priority(cs);
+                // Context switch happens here as soon as we leave the critical section.
+            }
+        });
+        // Read access was either granted directly, or handed over when the current thread was
+        // woken up.
+
+        RwLockReadGuard::new(self)
+    }
+
+    /// Acquires read access, blocking the current thread until it is able to do so or `timeout`
+    /// has passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if read access was not granted in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn read_timeout(&self, timeout: Duration) -> Result<RwLockReadGuard<'_, T>, TimeoutError> {
+        // SAFETY (for all closures): access to the state only happens in critical sections, so
+        // it's always unique.
+        block_until(
+            deadline_after(timeout),
+            |cs| unsafe { &mut *self.state.get() }.try_read(cs),
+            |cs| {
+                let state = unsafe { &mut *self.state.get() };
+                state.readers.put_current(cs, ThreadState::LockBlocked);
+                state.inherit_priority(cs);
+            },
+            |cs| unsafe { &mut *self.state.get() }.read_timeout(cs),
+        )?;
+
+        Ok(RwLockReadGuard::new(self))
+    }
+
+    /// Attempts to acquire read access, in a non-blocking fashion.
+    ///
+    /// Returns `None` if the lock is held by a writer, or a writer is waiting for it.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            state.try_read(cs).then(|| RwLockReadGuard::new(self))
+        })
+    }
+
+    /// Acquires write access, blocking the current thread until it is able to do so.
+    ///
+    /// If the lock is held, this function blocks the current thread until the lock gets released
+    /// elsewhere.
+    /// A writer holding the lock inherits the priority of the current thread if that is higher.
+    ///
+    /// The priority is reset once the lock is released. This means that a **user can not change a
+    /// thread's priority while it holds the write lock**, because it will be changed back after
+    /// release!
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            if !state.try_write(cs) {
+                state.writers.put_current(cs, ThreadState::LockBlocked);
+                state.inherit_priority(cs);
+                // Context switch happens here as soon as we leave the critical section.
+            }
+        });
+        // Write access was either granted directly, or handed over when the current thread was
+        // woken up.
+
+        RwLockWriteGuard::new(self)
+    }
+
+    /// Acquires write access, blocking the current thread until it is able to do so or `timeout`
+    /// has passed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if write access was not granted in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a thread context.
+    pub fn write_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<RwLockWriteGuard<'_, T>, TimeoutError> {
+        // SAFETY (for all closures): access to the state only happens in critical sections, so
+        // it's always unique.
+        block_until(
+            deadline_after(timeout),
+            |cs| unsafe { &mut *self.state.get() }.try_write(cs),
+            |cs| {
+                let state = unsafe { &mut *self.state.get() };
+                state.writers.put_current(cs, ThreadState::LockBlocked);
+                state.inherit_priority(cs);
+            },
+            |cs| unsafe { &mut *self.state.get() }.write_timeout(cs),
+        )?;
+
+        Ok(RwLockWriteGuard::new(self))
+    }
+
+    /// Attempts to acquire write access, in a non-blocking fashion.
+    ///
+    /// Returns `None` if the lock is held.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            let state = unsafe { &mut *self.state.get() };
+            state.try_write(cs).then(|| RwLockWriteGuard::new(self))
+        })
+    }
+}
+
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+/// Grants shared access to the [`RwLock`] inner data.
+///
+/// Dropping the [`RwLockReadGuard`] releases the read access.
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> RwLockReadGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> Self {
+        Self {
+            lock,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: there are only readers while a RwLockReadGuard exists.
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            unsafe { &mut *self.lock.state.get() }.release_read(cs);
+        });
+    }
+}
+
+unsafe impl<T: Sync> Sync for RwLockReadGuard<'_, T> {}
+
+/// Grants exclusive access to the [`RwLock`] inner data.
+///
+/// Dropping the [`RwLockWriteGuard`] releases the write access.
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T> RwLockWriteGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> Self {
+        Self {
+            lock,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: RwLockWriteGuard always has unique access.
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: RwLockWriteGuard always has unique access.
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        critical_section::with(|cs| {
+            // SAFETY: access to the state only happens in critical sections, so it's always unique.
+            unsafe { &mut *self.lock.state.get() }.release_write(cs);
+        });
+    }
+}
+
+unsafe impl<T: Sync> Sync for RwLockWriteGuard<'_, T> {}
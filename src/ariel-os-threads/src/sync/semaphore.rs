@@ -0,0 +1,126 @@
+//! This module provides a counting semaphore.
+
+#![expect(unsafe_code)]
+#![deny(missing_docs)]
+#![expect(
+    clippy::undocumented_unsafe_blocks,
+    reason = "should be addressed eventually"
+)]
+
+use core::cell::UnsafeCell;
+
+use embassy_time::Duration;
+
+use crate::{
+    ThreadState,
+    threadlist::ThreadList,
+    timeout::{TimeoutError, block_until, deadline_after},
+};
+
+/// A counting semaphore.
+///
+/// A [`Semaphore`] manages a number of permits. [`Self::acquire()`] takes a permit, blocking the
+/// current thread until one is available, and [`Self::release()`] gives it back.
+/// Waiting threads are woken up in the order of their priorities.
+pub struct Semaphore {
+    state: UnsafeCell<SemaphoreState>,
+}
+
+unsafe impl Sync for Semaphore {}
+
+struct SemaphoreState {
+    /// Number of available permits.
+    permits: usize,
+    /// Threads waiting for a permit.
+    waiters: ThreadList,
+}
+
+impl Semaphore {
+    /// Creates a new [`Semaphore`] with `permits` available permits.
+    #[must_use]
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: UnsafeCell::new(SemaphoreState {
+                permits,
+                waiters: ThreadList::new(),
+            }),
+        }
+    }
+
+    /// Returns the number of available permits.
+    pub fn available_permits(&self) -> usize {
+        critical_section::with(|_| unsafe { &*self.state.get() }.permits)
+    }
+
+    /// Takes a permit (blocking).
+    ///
+    /// If no permit is available, this function will block the current thread until a permit
+    /// gets released elsewhere.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn acquire(&self) {
+        critical_section::with(|cs| {
+            let state = unsafe { &mut *self.state.get() };
+            if !state.try_acquire() {
+                // The releasing thread hands its permit over directly.
+                state.waiters.put_current(cs, ThreadState::LockBlocked);
+            }
+        });
+    }
+
+    /// Takes a permit (blocking), giving up after `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`TimeoutError`] if no permit became available in time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is called outside of a thread context.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Result<(), TimeoutError> {
+        block_until(
+            deadline_after(timeout),
+            |_| unsafe { &mut *self.state.get() }.try_acquire(),
+            |cs| {
+                let state = unsafe { &mut *self.state.get() };
+                state.waiters.put_current(cs, ThreadState::LockBlocked);
+            },
+            |cs| {
+                let state = unsafe { &mut *self.state.get() };
+                state.waiters.remove_current(cs);
+            },
+        )
+    }
+
+    /// Takes a permit (non-blocking).
+    ///
+    /// Returns `true` if a permit was available, `false` otherwise.
+    pub fn try_acquire(&self) -> bool {
+        critical_section::with(|_| unsafe { &mut *self.state.get() }.try_acquire())
+    }
+
+    /// Releases a permit.
+    ///
+    /// If there are waiters, the permit is handed over to the highest priority waiter.
+    pub fn release(&self) {
+        critical_section::with(|cs| {
+            let state = unsafe { &mut *self.state.get() };
+            if state.waiters.pop(cs).is_none() {
+                state.permits += 1;
+            }
+        });
+    }
+}
+
+impl SemaphoreState {
+    fn try_acquire(&mut self) -> bool {
+        if self.permits > 0 {
+            self.permits -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -36,34 +36,32 @@ impl WaitQueue {
         critical_section::with(|cs| self.wait_cs(cs));
     }
 
-    fn wait_cs(&self, cs: CriticalSection<'_>) {
+    pub(crate) fn wait_cs(&self, cs: CriticalSection<'_>) {
         let waiters = unsafe { &mut *self.waiters.get() };
         waiters.put_current(cs, ThreadState::WaitQueueBlocked);
     }
 
     /// Waits for this [`WaitQueue`] to be notified, with deadline (blocking).
     ///
+    /// Returns `false` if the deadline was reached before being notified.
+    ///
     /// # Panics
     ///
     /// Panics if this is called outside of a thread context.
     pub fn wait_until(&self, deadline: embassy_time::Instant) -> bool {
         ariel_os_debug::log::trace!("WaitQueue::wait_until()");
-        // Safety:
-        // `on_timeout` takes care of removing the thread from the threadlist.
-        unsafe {
-            crate::timeout::with_deadline(
-                deadline,
-                |cs| {
-                    self.wait_cs(cs);
-                },
-                |cs| {
-                    ariel_os_debug::log::trace!("WaitQueue::wait_until() timeout");
-                    #[expect(unused_unsafe)]
-                    let waiters = unsafe { &mut *self.waiters.get() };
-                    !waiters.remove_current(cs)
-                },
-            )
-        }
+        crate::timeout::block_until(
+            deadline,
+            |_| false,
+            |cs| {
+                self.wait_cs(cs);
+            },
+            |cs| {
+                ariel_os_debug::log::trace!("WaitQueue::wait_until() timeout");
+                self.remove_current_cs(cs);
+            },
+        )
+        .is_ok()
     }
 
     /// Waits for this [`WaitQueue`] to be notified, with deadline and check fn (blocking).
@@ -112,12 +110,20 @@ impl WaitQueue {
     }
     /// Notify one waiter.
     pub fn notify_one(&self) {
-        critical_section::with(|cs| {
-            let waiters = unsafe { &mut *self.waiters.get() };
-            #[allow(unused_variables, reason = "log macro sometimes doesn't use this")]
-            let res = waiters.pop(cs);
-            ariel_os_debug::log::trace!("WaitQueue::notify_one() notifying {:?}", res);
-        });
+        critical_section::with(|cs| self.notify_one_cs(cs));
+    }
+
+    pub(crate) fn notify_one_cs(&self, cs: CriticalSection<'_>) {
+        let waiters = unsafe { &mut *self.waiters.get() };
+        #[allow(unused_variables, reason = "log macro sometimes doesn't use this")]
+        let res = waiters.pop(cs);
+        ariel_os_debug::log::trace!("WaitQueue::notify_one() notifying {:?}", res);
+    }
+
+    /// Removes the current thread from the waiters after its deadline was reached.
+    pub(crate) fn remove_current_cs(&self, cs: CriticalSection<'_>) {
+        let waiters = unsafe { &mut *self.waiters.get() };
+        waiters.remove_current(cs);
     }
 }
 
@@ -34,8 +34,18 @@ pub struct Thread {
     pub stack_lowest: usize,
     /// Highest stack address.
     pub stack_highest: usize,
+
+    /// Human-readable name of the thread, if any.
+    pub name: Option<&'static str>,
+
+    /// Incremented every time the thread slot is reused, to tell apart threads with the same
+    /// [`ThreadId`].
+    pub(crate) generation: u32,
 }
 
+/// Byte that's used to paint stacks.
+pub(crate) const STACK_PAINT_COLOR: u8 = 0xCC;
+
 /// Possible states of a thread.
 #[derive(Copy, Clone, PartialEq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -49,7 +59,7 @@ pub enum ThreadState {
     Running,
     /// Suspended / paused.
     Parked,
-    /// Waiting to acquire a [`super::lock::Lock`].
+    /// Waiting to acquire a [`crate::sync::Lock`] or another lock-like primitive.
     LockBlocked,
     /// Waiting for [`ThreadFlags`] to be set.
     FlagBlocked(crate::thread_flags::WaitMode),
@@ -57,8 +67,10 @@ pub enum ThreadState {
     ChannelRxBlocked(usize),
     /// Waiting to send on a [`crate::sync::Channel`], i.e. waiting for the receiver.
     ChannelTxBlocked(usize),
-    /// Waiting for a [`super::wait_queue::WaitQueue`].
+    /// Waiting for a [`crate::sync::WaitQueue`].
     WaitQueueBlocked,
+    /// Killed while executing on another core, until that core has switched away from it.
+    Zombie,
 }
 
 impl Thread {
@@ -75,6 +87,8 @@ impl Thread {
             deadline: None,
             stack_highest: 0,
             stack_lowest: 0,
+            name: None,
+            generation: 0,
         }
     }
 
@@ -84,9 +98,6 @@ impl Thread {
     /// - must only be called before the stack is active (within `arch::setup_stack()`).
     #[allow(dead_code, reason = "not used in all configurations")]
     pub(crate) unsafe fn stack_paint_init(&mut self, sp: usize) {
-        // Byte that's used to paint stacks.
-        const STACK_PAINT_COLOR: u8 = 0xCC;
-
         for pos in self.stack_lowest..sp {
             // SAFETY: Writing to the slice that was passed to `setup_stack()` is fine
             unsafe {
@@ -105,6 +116,6 @@ mod tests {
         // `ThreadData` is arch-specific, and is replaced with a dummy value in tests; its size is
         // non-zero otherwise.
         assert_eq!(size_of::<ThreadData>(), 0);
-        assert_eq!(size_of::<Thread>(), size_of::<ThreadData>() + 56);
+        assert_eq!(size_of::<Thread>(), size_of::<ThreadData>() + 72);
     }
 }
@@ -1,5 +1,10 @@
 //! Thread flags.
-use crate::{SCHEDULER, Scheduler, ThreadId, ThreadState};
+use embassy_time::Duration;
+
+use crate::{
+    SCHEDULER, Scheduler, ThreadId, ThreadState,
+    timeout::{TimeoutError, block_until, deadline_after},
+};
 
 /// Bitmask that represent the flags that are set for a thread.
 pub type ThreadFlags = u16;
@@ -72,6 +77,112 @@ pub fn wait_one(mask: ThreadFlags) -> ThreadFlags {
     }
 }
 
+/// Waits until all flags in `mask` are set for the current thread, giving up after `timeout`.
+///
+/// Returns the set flags for this mask and clears them for the thread.
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if the flags were not set in time.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub fn wait_all_timeout(mask: ThreadFlags, timeout: Duration) -> Result<ThreadFlags, TimeoutError> {
+    wait_timeout(timeout, WaitMode::All(mask), |flags| {
+        (*flags & mask == mask).then(|| {
+            *flags &= !mask;
+            mask
+        })
+    })
+}
+
+/// Waits until any flag in `mask` is set for the current thread, giving up after `timeout`.
+///
+/// Returns all set flags for this mask and clears them for the thread.
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if none of the flags was set in time.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub fn wait_any_timeout(mask: ThreadFlags, timeout: Duration) -> Result<ThreadFlags, TimeoutError> {
+    wait_timeout(timeout, WaitMode::Any(mask), |flags| {
+        let res = *flags & mask;
+        (res != 0).then(|| {
+            *flags &= !res;
+            res
+        })
+    })
+}
+
+/// Waits until any flag in `mask` is set for the current thread, giving up after `timeout`.
+///
+/// Compared to [`wait_any_timeout`], this returns and clears only one flag
+/// from the mask.
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if none of the flags was set in time.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+pub fn wait_one_timeout(mask: ThreadFlags, timeout: Duration) -> Result<ThreadFlags, TimeoutError> {
+    wait_timeout(timeout, WaitMode::Any(mask), |flags| {
+        let mut res = *flags & mask;
+        (res != 0).then(|| {
+            // clear all but least significant bit
+            res &= !res + 1;
+            *flags &= !res;
+            res
+        })
+    })
+}
+
+/// Waits until `take()` returns `Some` for the current thread's flags, giving up after `timeout`.
+///
+/// The thread is blocked with `mode` while waiting.
+///
+/// # Errors
+///
+/// Returns [`TimeoutError`] if `take()` did not succeed in time.
+///
+/// # Panics
+///
+/// Panics if this is called outside of a thread context.
+fn wait_timeout(
+    timeout: Duration,
+    mode: WaitMode,
+    take: impl Fn(&mut ThreadFlags) -> Option<ThreadFlags>,
+) -> Result<ThreadFlags, TimeoutError> {
+    let deadline = deadline_after(timeout);
+    let mut res = None;
+    loop {
+        block_until(
+            deadline,
+            |cs| {
+                res = SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                    take(&mut scheduler.current().unwrap().flags)
+                });
+                res.is_some()
+            },
+            |cs| {
+                SCHEDULER.with_mut_cs(cs, |mut scheduler| {
+                    let thread_id = scheduler.current_tid().unwrap();
+                    scheduler.set_state(thread_id, ThreadState::FlagBlocked(mode));
+                });
+            },
+            |_| {},
+        )?;
+        if let Some(flags) = res {
+            return Ok(flags);
+        }
+    }
+}
+
 /// Clears flags for the current thread.
 ///
 /// # Panics
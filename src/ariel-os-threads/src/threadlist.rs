@@ -58,22 +58,36 @@ impl ThreadList {
     /// Sets the thread's [`ThreadState`] to [`ThreadState::Running`] and triggers
     /// the scheduler.
     ///
+    /// Threads that have already been woken up because their deadline was reached are skipped.
+    ///
     /// Returns the thread's [`ThreadId`] and its previous [`ThreadState`].
     pub fn pop(&mut self, cs: CriticalSection<'_>) -> Option<(ThreadId, ThreadState)> {
-        let head = self.head?;
         SCHEDULER.with_mut_cs(cs, |mut scheduler| {
-            self.head = scheduler.thread_blocklist[usize::from(head)].take();
-            let old_state = scheduler.set_state(head, ThreadState::Running);
-            Some((head, old_state))
+            loop {
+                let head = self.head?;
+                self.head = scheduler.thread_blocklist[usize::from(head)].take();
+                if scheduler.get_unchecked(head).state == ThreadState::Running {
+                    continue;
+                }
+                let old_state = scheduler.set_state(head, ThreadState::Running);
+                return Some((head, old_state));
+            }
         })
     }
 
+    /// Returns the priority of the head of this [`ThreadList`], i.e., the highest priority among
+    /// the waiting threads.
+    pub fn head_prio(&self, cs: CriticalSection<'_>) -> Option<RunqueueId> {
+        let head = self.head?;
+        SCHEDULER.with_cs(cs, |scheduler| Some(scheduler.get_unchecked(head).prio))
+    }
+
+    /// Unlinks `thread_id` from this [`ThreadList`], without changing its state.
     fn remove_inner(&mut self, scheduler: &mut Scheduler, thread_id: ThreadId) -> bool {
         ariel_os_debug::log::trace!("remove_current() {:?}", thread_id);
         if let Some(head) = self.head {
             if head == thread_id {
                 self.head = scheduler.thread_blocklist[usize::from(head)].take();
-                scheduler.set_state(head, ThreadState::Running);
                 return true;
             }
             let mut cur = head;
@@ -81,7 +95,6 @@ impl ThreadList {
                 if next == thread_id {
                     scheduler.thread_blocklist[usize::from(cur)] =
                         scheduler.thread_blocklist[usize::from(next)].take();
-                    scheduler.set_state(next, ThreadState::Running);
                     return true;
                 }
                 cur = next;
@@ -92,6 +105,9 @@ impl ThreadList {
 
     /// Removes the current thread from this [`ThreadList`].
     ///
+    /// This is used by threads that were woken up because their deadline was reached, so the
+    /// thread is already in [`ThreadState::Running`].
+    ///
     /// ## Panics
     /// Panics if this is called outside of a thread context.
     pub(crate) fn remove_current(&mut self, cs: CriticalSection<'_>) -> bool {
@@ -0,0 +1,303 @@
+//! Time slicing among threads of the same priority.
+//!
+//! A timer periodically checks the thread running on each core. Once a thread has been running
+//! for longer than the time slice of its priority level, it is moved to the tail of its runqueue,
+//! so that the next ready thread with the same priority gets to run.
+//!
+//! The timer only runs while a thread has to share its core with another ready thread of the same
+//! priority. The scheduler restarts it once such a thread becomes ready.
+//!
+//! The timer is armed from the context switch handler: arming it from within its own callback
+//! would re-enter the time driver, and arming it while the scheduler is borrowed could call the
+//! callback right away.
+
+use core::task::{RawWaker, RawWakerVTable, Waker};
+
+use embassy_time::Duration;
+
+use crate::{
+    CORE_COUNT, RunqueueId, SCHED_PRIO_LEVELS, SCHEDULER, Scheduler, ThreadId, ThreadState,
+};
+
+/// The default time slice of every priority level, in microseconds.
+///
+/// Can be configured at build time through the `CONFIG_THREAD_TIME_SLICE_US` environment variable.
+/// A value of `0` disables time slicing unless enabled for a priority level using
+/// [`set_time_slice()`].
+pub const TIME_SLICE_US: usize = ariel_os_utils::usize_from_env_or!(
+    "CONFIG_THREAD_TIME_SLICE_US",
+    10_000,
+    "default time slice of same-priority threads (in microseconds)"
+);
+
+/// Time-slicing state of the scheduler.
+pub(crate) struct TimeSlicing {
+    /// Length of the time slice for each priority level in timer ticks, `0` if disabled.
+    quanta: [u64; SCHED_PRIO_LEVELS],
+    /// Thread that currently owns the time slice on each core, and when that slice expires.
+    slices: [Option<(ThreadId, u64)>; CORE_COUNT],
+    /// Whether the tick timer is running or about to be armed.
+    started: bool,
+    /// When the next tick is due, if the timer still needs to be armed for it, see [`arm()`].
+    pending: Option<u64>,
+    /// Whether the time driver has been initialized, see [`start()`].
+    timer_ready: bool,
+}
+
+impl TimeSlicing {
+    pub const fn new() -> Self {
+        Self {
+            quanta: [to_ticks(Duration::from_micros(TIME_SLICE_US as u64)); SCHED_PRIO_LEVELS],
+            slices: [None; CORE_COUNT],
+            started: false,
+            pending: None,
+            timer_ready: false,
+        }
+    }
+}
+
+/// Converts a time slice to timer ticks, making sure that non-zero slices are at least one tick.
+const fn to_ticks(quantum: Duration) -> u64 {
+    let ticks = quantum.as_ticks();
+    if ticks == 0 && quantum.as_micros() > 0 {
+        1
+    } else {
+        ticks
+    }
+}
+
+impl Scheduler {
+    /// Handles a time-slicing tick.
+    ///
+    /// Rotates the runqueue of every core whose current thread has used up its time slice.
+    /// Returns when the next tick is due, or `None` if no core has a thread that shares it with
+    /// another ready thread of the same priority.
+    fn time_slice_tick(&mut self, now: u64) -> Option<u64> {
+        let mut next: Option<u64> = None;
+
+        for core in 0..CORE_COUNT {
+            let current = self
+                .current_on_core(core)
+                .filter(|&tid| self.get_unchecked(tid).state == ThreadState::Running);
+            let Some(tid) = current else {
+                self.time_slicing.slices[core] = None;
+                continue;
+            };
+            let prio = self.get_unchecked(tid).prio;
+            let quantum = self.time_slicing.quanta[usize::from(prio)];
+            if quantum == 0 || !self.is_contended(prio) {
+                self.time_slicing.slices[core] = None;
+                continue;
+            }
+
+            let expiry = match self.time_slicing.slices[core] {
+                // The thread still has time left.
+                Some((owner, expiry)) if owner == tid && now < expiry => expiry,
+                // The thread has used up its slice.
+                Some((owner, _)) if owner == tid => {
+                    let expiry = now.saturating_add(quantum);
+                    // The slice of the next thread starts right away.
+                    let next_tid = self.rotate_on_core(core, prio).unwrap_or(tid);
+                    self.time_slicing.slices[core] = Some((next_tid, expiry));
+                    expiry
+                }
+                // Another thread has been scheduled since the last tick, start a new slice.
+                _ => {
+                    let expiry = now.saturating_add(quantum);
+                    self.time_slicing.slices[core] = Some((tid, expiry));
+                    expiry
+                }
+            };
+            next = Some(next.map_or(expiry, |next| next.min(expiry)));
+        }
+
+        next
+    }
+
+    /// Restarts the time-slicing timer if it is stopped and might be needed.
+    ///
+    /// This is called whenever a thread becomes ready or changes its priority.
+    pub(crate) fn time_slice_restart(&mut self) {
+        if !self.time_slicing.timer_ready
+            || self.time_slicing.started
+            || !self.may_need_time_slicing()
+        {
+            return;
+        }
+        let next = self.time_slice_tick(embassy_time_driver::now());
+        self.time_slice_request_tick(next);
+    }
+
+    /// Requests the timer to be armed for the next tick, or stops it if `next` is `None`.
+    fn time_slice_request_tick(&mut self, next: Option<u64>) {
+        self.time_slicing.started = next.is_some();
+        self.time_slicing.pending = next;
+        if next.is_some() {
+            // The context switch handler arms the timer.
+            crate::schedule();
+        }
+    }
+
+    /// Returns whether a thread might have to share its core with another ready thread of the
+    /// same priority, for which time slicing is enabled.
+    ///
+    /// This also covers the thread that is scheduled next, e.g., when the current thread is about
+    /// to block.
+    fn may_need_time_slicing(&self) -> bool {
+        let enabled = |prio: RunqueueId| self.time_slicing.quanta[usize::from(prio)] > 0;
+        if self
+            .runqueue
+            .get_next_with_rq()
+            .is_some_and(|(_, prio)| enabled(prio) && self.runqueue.has_multiple(prio))
+        {
+            return true;
+        }
+        (0..CORE_COUNT).any(|core| {
+            self.current_on_core(core)
+                .map(|tid| self.get_unchecked(tid))
+                .filter(|thread| thread.state == ThreadState::Running)
+                .is_some_and(|thread| enabled(thread.prio) && self.is_contended(thread.prio))
+        })
+    }
+
+    /// Returns whether another thread is ready to run at priority `prio`, besides the one
+    /// currently running at it.
+    #[allow(clippy::unused_self, reason = "only unused in clippy path")]
+    fn is_contended(&self, prio: RunqueueId) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "single-core")] {
+                // On single-core, the running thread is part of the runqueue.
+                self.runqueue.has_multiple(prio)
+            }
+            else if #[cfg(feature = "multi-core")] {
+                !self.runqueue.is_empty(prio)
+            }
+            else {
+                let _ = prio;
+                false
+            }
+        }
+    }
+
+    /// Returns the thread that is currently scheduled on core `core`.
+    #[allow(clippy::unused_self, reason = "only unused in clippy path")]
+    fn current_on_core(&self, core: usize) -> Option<ThreadId> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "single-core")] {
+                let _ = core;
+                self.current_thread
+            }
+            else if #[cfg(feature = "multi-core")] {
+                self.current_threads[core]
+            }
+            else {
+                let _ = core;
+                None
+            }
+        }
+    }
+
+    /// Moves the current thread of core `core` to the tail of its runqueue, if another thread
+    /// with the same priority is ready.
+    ///
+    /// Returns the thread that is scheduled next, or `None` if no other thread with the same
+    /// priority is ready.
+    fn rotate_on_core(&mut self, core: usize, prio: RunqueueId) -> Option<ThreadId> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "single-core")] {
+                let _ = core;
+                if !self.runqueue.advance(prio) {
+                    return None;
+                }
+                crate::schedule();
+            }
+            else if #[cfg(feature = "multi-core")] {
+                // On multi-core, running threads are not part of the runqueue.
+                // Invoking the scheduler re-adds the current thread at the tail of its runqueue.
+                if self.runqueue.is_empty(prio) {
+                    return None;
+                }
+                crate::schedule_on_core(crate::CoreId(core as u8));
+            }
+            else {
+                let _ = core;
+            }
+        }
+        self.runqueue.peek_head(prio)
+    }
+}
+
+fn tick(_ptr: *const ()) {
+    let now = embassy_time_driver::now();
+    SCHEDULER.with_mut(|mut scheduler| {
+        let next = scheduler.time_slice_tick(now);
+        scheduler.time_slice_request_tick(next);
+    });
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(
+    // clone
+    |ptr| RawWaker::new(ptr, &VTABLE),
+    tick,
+    tick,
+    |_ptr| {},
+);
+
+fn schedule_tick(at: u64) {
+    let raw_waker = RawWaker::new(core::ptr::null(), &VTABLE);
+    let waker = unsafe { Waker::from_raw(raw_waker) };
+    embassy_time_driver::schedule_wake(at, &waker);
+}
+
+/// Arms the timer for the tick requested by the scheduler, if any.
+///
+/// This must only be called from the context switch handler, outside of any borrow of the
+/// scheduler.
+pub(crate) fn arm() {
+    let pending = SCHEDULER.with_mut(|mut scheduler| scheduler.time_slicing.pending.take());
+    if let Some(at) = pending {
+        schedule_tick(at);
+    }
+}
+
+/// Marks the time driver as initialized, and starts the time-slicing timer if a thread has to
+/// share its core with another one of the same priority.
+pub(crate) fn start() {
+    SCHEDULER.with_mut(|mut scheduler| {
+        scheduler.time_slicing.timer_ready = true;
+        scheduler.time_slice_restart();
+    });
+}
+
+/// Sets the time slice of threads with priority `prio`.
+///
+/// Once a thread has been running for the duration of its time slice, the next ready thread with
+/// the same priority is scheduled, as if the thread had called [`yield_same()`](crate::yield_same).
+/// `None` disables time slicing for this priority level, so that its threads are scheduled
+/// cooperatively.
+///
+/// Time slices are enforced with the resolution of the timer driver, a thread might exceed its
+/// slice by up to the shortest time slice of all priority levels.
+///
+/// # Panics
+///
+/// Panics if `prio` is >= [`SCHED_PRIO_LEVELS`].
+pub fn set_time_slice(prio: RunqueueId, time_slice: Option<Duration>) {
+    SCHEDULER.with_mut(|mut scheduler| {
+        scheduler.time_slicing.quanta[usize::from(prio)] = time_slice.map_or(0, to_ticks);
+    });
+    start();
+}
+
+/// Returns the time slice of threads with priority `prio`, or `None` if time slicing is disabled
+/// for this priority level.
+///
+/// # Panics
+///
+/// Panics if `prio` is >= [`SCHED_PRIO_LEVELS`].
+pub fn time_slice(prio: RunqueueId) -> Option<Duration> {
+    SCHEDULER.with(|scheduler| {
+        let quantum = scheduler.time_slicing.quanta[usize::from(prio)];
+        (quantum > 0).then(|| Duration::from_ticks(quantum))
+    })
+}
@@ -3,9 +3,20 @@ use core::task::{RawWaker, RawWakerVTable, Waker};
 use critical_section::CriticalSection;
 use embassy_time::Duration;
 
-use crate::{SCHEDULER, ThreadId, ThreadState, thread_flags::ThreadFlags};
+use crate::{SCHEDULER, ThreadId, ThreadState};
 
-const THREAD_FLAG_TIMEOUT: ThreadFlags = 2; // TODO: find more appropriate value
+/// Error returned when a blocking operation has reached its timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimeoutError;
+
+impl core::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "operation timed out")
+    }
+}
+
+impl core::error::Error for TimeoutError {}
 
 fn wake(ptr: *const ()) {
     #[expect(clippy::cast_possible_truncation)]
@@ -13,21 +24,24 @@ fn wake(ptr: *const ()) {
     SCHEDULER.with_mut(|mut scheduler| {
         if let Some(deadline) = scheduler.threads[usize::from(thread_id)].deadline {
             let now = embassy_time_driver::now();
-            if now >= deadline {
+            if now >= deadline && scheduler.get_state(thread_id) == Some(ThreadState::Running) {
+                // The thread has been woken up in the meantime, it clears its deadline itself.
+                ariel_os_debug::log::debug!(
+                    "timer for {:?} expired, but thread is already running (deadline={:?}, now={:?})",
+                    thread_id,
+                    deadline,
+                    now
+                );
+            } else if now >= deadline {
                 ariel_os_debug::log::debug!(
                     "timer for {:?} expired, triggering thread (deadline={:?}, now={:?})",
                     thread_id,
                     deadline,
                     now
                 );
+                // The cleared deadline tells the thread that it timed out, see `clear_deadline()`.
                 scheduler.threads[usize::from(thread_id)].deadline = None;
-                scheduler.flag_set(thread_id, THREAD_FLAG_TIMEOUT);
-                match scheduler.get_state(thread_id) {
-                    Some(ThreadState::Running) => {}
-                    _ => {
-                        scheduler.set_state(thread_id, ThreadState::Running);
-                    }
-                }
+                scheduler.set_state(thread_id, ThreadState::Running);
             } else {
                 ariel_os_debug::log::debug!(
                     "timer for {:?} not due yet (deadline={:?}, now={:?})",
@@ -63,6 +77,9 @@ fn schedule_thread_wakeup(thread_id: ThreadId, deadline: u64) {
 /// Sets up the deadline timer.
 /// Returns true if the deadline was in the future.
 ///
+/// The timer only wakes up the thread if it isn't running anymore, i.e., once it has blocked in
+/// the same critical section.
+///
 /// # Panics
 /// - panics when not called from a thread
 fn set_deadline(cs: CriticalSection<'_>, deadline: u64) -> bool {
@@ -71,7 +88,6 @@ fn set_deadline(cs: CriticalSection<'_>, deadline: u64) -> bool {
     let thread_id = SCHEDULER.with_mut_cs(cs, |mut scheduler| {
         let thread = scheduler.current().expect("must be called from a thread");
         thread.deadline = Some(deadline);
-        thread.flags &= !THREAD_FLAG_TIMEOUT;
         thread.tid
     });
 
@@ -80,6 +96,11 @@ fn set_deadline(cs: CriticalSection<'_>, deadline: u64) -> bool {
 
     SCHEDULER.with_mut_cs(cs, |mut scheduler| {
         let thread = scheduler.current().expect("must be called from a thread");
+        // If the deadline has already passed, the timer might have fired without waking up the
+        // (still running) thread.
+        if embassy_time_driver::now() >= deadline {
+            thread.deadline = None;
+        }
         thread.deadline.is_some()
     })
 }
@@ -107,42 +128,54 @@ fn clear_deadline(cs: CriticalSection<'_>) -> bool {
     did_clear
 }
 
-/// Runs the given function with a deadline timer.
+/// Blocks the current thread until it is woken up or `deadline` is reached.
 ///
-/// If the deadline is in the past the function will not be called.
-/// If the deadline is reached before the function returns, the thread will be set to runnable
-/// state, and `on_timeout()` will be called after the function returns.
+/// 1. Calls `try_now()`. If that returns true, early out returning `Ok(())`.
+/// 2. If `deadline` is in the past, return `Err(TimeoutError)`.
+/// 3. If `deadline` is in the future, set the thread's deadline and call `block()`, which is
+///    expected to block the current thread.
+/// 4. On return, if `deadline` has expired, call `on_timeout()` and return `Err(TimeoutError)`.
+///    Otherwise, the thread was woken up in time and `Ok(())` is returned.
 ///
-/// Returns false if the deadline was reached, and `on_timeout()` was called.
+/// A thread that was woken up by the deadline timer is set to [`ThreadState::Running`], while it
+/// might still be linked into a [`ThreadList`](crate::threadlist::ThreadList).
+/// `on_timeout()` is responsible for removing it again.
 ///
-/// # Safety
-/// This will set the calling thread to `Runnable` after the timeout expires. Caller must ensure safety implications of that.
-pub(crate) unsafe fn with_deadline(
+/// # Errors
+///
+/// Returns [`TimeoutError`] if `deadline` was reached.
+pub(crate) fn block_until(
     deadline: embassy_time::Instant,
-    f: impl FnOnce(CriticalSection<'_>),
-    on_timeout: impl FnOnce(CriticalSection<'_>) -> bool,
-) -> bool {
+    try_now: impl FnOnce(CriticalSection<'_>) -> bool,
+    block: impl FnOnce(CriticalSection<'_>),
+    on_timeout: impl FnOnce(CriticalSection<'_>),
+) -> Result<(), TimeoutError> {
     let deadline = deadline.as_ticks();
-    critical_section::with(|cs| {
-        if set_deadline(cs, deadline) {
-            // The deadline was in the future, so we can run the function
-            f(cs);
-            ariel_os_debug::log::debug!("with_deadline: cs at {}", embassy_time_driver::now());
-            true
-        } else {
-            // The deadline was in the past, so we don't run the function
-            ariel_os_debug::log::debug!("with_deadline: deadline {} was in the past", deadline);
-            false
-        }
-    }) && critical_section::with(|cs| {
-        if clear_deadline(cs) {
-            ariel_os_debug::log::debug!("with_deadline: cleared deadline {}", deadline);
-            false
+    let blocked = critical_section::with(|cs| {
+        if try_now(cs) {
+            Ok(false)
+        } else if set_deadline(cs, deadline) {
+            block(cs);
+            Ok(true)
         } else {
-            ariel_os_debug::log::debug!("with_deadline: timeout deadline {}", deadline);
-            on_timeout(cs)
+            ariel_os_debug::log::debug!("block_until: deadline {} was in the past", deadline);
+            Err(TimeoutError)
         }
-    })
+    })?;
+
+    if blocked {
+        critical_section::with(|cs| {
+            if clear_deadline(cs) {
+                ariel_os_debug::log::debug!("block_until: cleared deadline {}", deadline);
+                Ok(())
+            } else {
+                ariel_os_debug::log::debug!("block_until: timeout deadline {}", deadline);
+                on_timeout(cs);
+                Err(TimeoutError)
+            }
+        })?;
+    }
+    Ok(())
 }
 
 /// Runs a custom wait function with check function.
@@ -205,10 +238,14 @@ pub(crate) unsafe fn with_deadline_check(
     }
 }
 
+/// Returns the deadline for a blocking operation with the given timeout.
+pub(crate) fn deadline_after(timeout: Duration) -> embassy_time::Instant {
+    embassy_time::Instant::now().saturating_add(timeout)
+}
+
 /// Put the current thread to sleep for the given duration.
 pub fn sleep(duration: Duration) {
-    let deadline = embassy_time::Instant::now().saturating_add(duration);
-    sleep_until(deadline);
+    sleep_until(deadline_after(duration));
 }
 
 /// Put the current thread to sleep until the given deadline.
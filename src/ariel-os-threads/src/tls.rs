@@ -0,0 +1,111 @@
+//! Thread-local storage.
+//!
+//! Rather than reserving a region in each thread's TCB or stack, every [`ThreadLocal`] holds a
+//! slot for each possible thread. This keeps the size of the TCB independent of the thread-local
+//! values an application uses, and needs neither linker support for TLS sections nor the unstable
+//! `#[thread_local]` attribute, at the cost of reserving memory for threads that never access the
+//! value. A region per thread would instead have to be sized through configuration (like the
+//! stacks), as the thread-local values are only known once the application is linked, and would
+//! need a table of offsets into it; a slot per thread keeps each value a plain `static`.
+
+use core::cell::{Cell, UnsafeCell};
+
+use crate::{SCHEDULER, THREAD_COUNT};
+
+/// Declares a new thread-local value of type [`ThreadLocal`].
+///
+/// Like `std::thread_local!`, the initializer expression is evaluated lazily, on the first access
+/// from each thread.
+/// One or more values can be declared at once, e.g., `thread_local! { static ERRNO: i32 = 0; }`.
+#[macro_export]
+macro_rules! thread_local {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $(#[$attr])* $vis static $name: $crate::ThreadLocal<$t> = $crate::ThreadLocal::new(|| $init);
+        $crate::thread_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])* $vis static $name: $crate::ThreadLocal<$t> = $crate::ThreadLocal::new(|| $init);
+    };
+    () => {};
+}
+
+/// A value of which each thread has its own copy.
+///
+/// Storage for every possible thread is reserved statically, i.e., a [`ThreadLocal`] occupies
+/// [`THREAD_COUNT`] times the size of `T`.
+/// The value of a thread is initialized on its first access, and re-initialized when its
+/// [`ThreadId`](crate::ThreadId) gets reused by a new thread, even if the previous thread was
+/// killed while accessing it.
+/// Values of threads that have ended are not dropped until then.
+///
+/// Usually declared using the [`thread_local!`](crate::thread_local!) macro.
+pub struct ThreadLocal<T> {
+    init: fn() -> T,
+    /// The value of each thread.
+    slots: [Slot<T>; THREAD_COUNT],
+}
+
+/// The value of a thread in a [`ThreadLocal`].
+///
+/// Unlike a `RefCell`, the borrow flag can be reset once the slot is taken over by a new thread.
+struct Slot<T> {
+    /// Generation of the thread the value belongs to.
+    generation: Cell<u32>,
+    /// Whether the value is being accessed.
+    borrowed: Cell<bool>,
+    value: UnsafeCell<Option<T>>,
+}
+
+// SAFETY: each slot is only accessed by the thread it belongs to, or by interrupt handlers while
+// that thread is interrupted on the same core. Interrupt handlers run to completion before the
+// thread resumes, so the fields of a slot are never modified concurrently. A killed thread's
+// `ThreadId` is only reused once no core executes the thread anymore.
+unsafe impl<T: Send> Sync for ThreadLocal<T> {}
+
+impl<T> ThreadLocal<T> {
+    /// Creates a new [`ThreadLocal`], with `init` providing the initial value for each thread.
+    #[must_use]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            init,
+            slots: [const {
+                Slot {
+                    generation: Cell::new(0),
+                    borrowed: Cell::new(false),
+                    value: UnsafeCell::new(None),
+                }
+            }; THREAD_COUNT],
+        }
+    }
+
+    /// Runs `f` with mutable access to the current thread's value.
+    ///
+    /// When called from an interrupt handler, this accesses the value of the interrupted thread.
+    ///
+    /// Returns `None` if there is no current thread, or if the value is already being accessed,
+    /// i.e., from within `f`, or from an interrupt handler while the interrupted thread accesses
+    /// it.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let (tid, generation) = SCHEDULER.with(|scheduler| {
+            let tid = scheduler.current_tid()?;
+            Some((tid, scheduler.get_unchecked(tid).generation))
+        })?;
+        let slot = &self.slots[usize::from(tid)];
+        // A previous thread with the same ID may have been killed while accessing its value, so
+        // the borrow flag only counts for the current generation.
+        if slot.borrowed.replace(true) && slot.generation.get() == generation {
+            return None;
+        }
+        // SAFETY: the value is only accessed while `borrowed` is set, which was just done for the
+        // current thread, and is only done for one thread at a time (see `Sync` impl).
+        let value = unsafe { &mut *slot.value.get() };
+        if slot.generation.replace(generation) != generation {
+            // The slot still holds the value of a previous thread with the same ID. Dropping it
+            // may access this value, which fails as the slot is borrowed.
+            *value = None;
+        }
+        let result = f(value.get_or_insert_with(self.init));
+        slot.borrowed.set(false);
+        Some(result)
+    }
+}
@@ -245,6 +245,8 @@ pub struct CwtClaimsSet<'a> {
     pub aud: Option<&'a str>,
     #[n(4)]
     pub(crate) exp: u64,
+    #[n(5)]
+    pub(crate) nbf: Option<u64>,
     #[n(6)]
     pub(crate) iat: u64,
     #[b(8)]
@@ -412,6 +414,7 @@ impl AceCborAuthzInfoResponse {
 /// * a list of recognized `authorities` (Authorization Servers) to authenticate the token,
 ///   the output of which is also later used to parse the token's scope.
 /// * a random nonce2
+/// * the time provider, which learns from the token's issue time
 /// * a callback that, once the peer's recipient ID is known, chooses an own recipient ID
 ///   (because it's up to the pool of security contexts to pick one, and the peers can not pick
 ///   identical ones)
@@ -436,6 +439,7 @@ pub(crate) fn process_acecbor_authz_info<GC: crate::GeneralClaims>(
     payload: &[u8],
     authorities: &impl crate::seccfg::ServerSecurityConfig<GeneralClaims = GC>,
     nonce2: [u8; OWN_NONCE_LEN],
+    time: &mut impl crate::time::TimeProvider,
     server_recipient_id: impl FnOnce(&[u8]) -> COwn,
 ) -> Result<(AceCborAuthzInfoResponse, liboscore::PrimitiveContext, GC), CredentialError> {
     trace!(
@@ -481,6 +485,9 @@ pub(crate) fn process_acecbor_authz_info<GC: crate::GeneralClaims>(
     // <https://codeberg.org/chrysn/minicbor-adapters/pulls/1>
     // trace!("Decrypted CWT claims: {}", parsed);
 
+    // The token is authenticated by the AS, which vouches for its issue time.
+    time.past_trusted(parsed.iat);
+
     let Cnf {
         osc: Some(osc),
         cose_key: None,
@@ -509,6 +516,8 @@ pub(crate) fn process_acecbor_authz_info<GC: crate::GeneralClaims>(
 /// Verifies an ACE token sent in an EAD3 by the rules of the `authorities`, and produces both the
 /// decrypted claims and the extracted EDHOC specific credential.
 ///
+/// The token's issue time is reported to the `time` provider.
+///
 /// # Errors
 ///
 /// This produces errors if the input (which is typically received from the network) is
@@ -520,6 +529,7 @@ pub(crate) fn process_acecbor_authz_info<GC: crate::GeneralClaims>(
 pub(crate) fn process_edhoc_token<GeneralClaims>(
     ead3: &[u8],
     authorities: &impl crate::seccfg::ServerSecurityConfig<GeneralClaims = GeneralClaims>,
+    time: &mut impl crate::time::TimeProvider,
 ) -> Result<(lakers::Credential, GeneralClaims), CredentialError> {
     let mut buffer = heapless::Vec::<u8, MAX_SUPPORTED_ACCESSTOKEN_LEN>::new();
 
@@ -553,6 +563,9 @@ pub(crate) fn process_edhoc_token<GeneralClaims>(
         return Err(CredentialErrorDetail::UnsupportedExtension.into());
     };
 
+    // The token is authenticated by the AS, which vouches for its issue time.
+    time.past_trusted(parsed.iat);
+
     let Cnf {
         osc: None,
         cose_key: Some(cose_key),
@@ -0,0 +1,749 @@
+//! The client side of OSCORE and EDHOC.
+//!
+//! An [`OscoreEdhocClient`] holds the security context towards a single server, and is configured
+//! with the own EDHOC credential and the credential expected of the server. Requests are sent
+//! through [`OscoreEdhocClient::protecting()`], which wraps any [`coap_request::Stack`] (typically
+//! a client already directed at the server) into one that protects requests with OSCORE.
+//!
+//! When no security context has been established yet, EDHOC is run as the initiator by sending
+//! message 1 to the server's `/.well-known/edhoc` resource. Message 3 is then sent along with the
+//! first OSCORE protected request, as described in [RFC9668](https://www.rfc-editor.org/rfc/rfc9668).
+
+use core::marker::PhantomData;
+
+use coap_message::{
+    Code as _, MessageOption, MinimalWritableMessage, MutableWritableMessage, OptionNumber as _,
+    ReadableMessage,
+};
+use coap_request::{Request, Stack};
+use defmt_or_log::{Debug2Format, debug, error, trace};
+
+use crate::helpers::{COwn, poll_once};
+use crate::seccontext::{EDHOC_COPY_BUFFER_SIZE, oscore_context_from_edhoc};
+
+/// Error type returned when sending a request through a [`ProtectedStack`].
+#[derive(Debug)]
+pub enum ClientError<T> {
+    /// The underlying CoAP stack failed to send a request or to receive a response.
+    Transport(T),
+    /// The EDHOC exchange with the server failed, for example because the server did not present
+    /// the expected credential.
+    Edhoc,
+    /// The request could not be built or protected, for example because it exceeded the size of
+    /// the internal buffers.
+    Request,
+    /// The response could not be verified or decrypted.
+    ///
+    /// If the server sent an unprotected response (typically because it lost the security
+    /// context), the security context is discarded, so that the next request runs EDHOC anew.
+    Response,
+}
+
+/// Security state of an [`OscoreEdhocClient`].
+#[allow(
+    clippy::large_enum_variant,
+    reason = "the message is only kept until the server confirmed the context"
+)]
+enum ClientStage {
+    /// No security context has been established; the next request starts EDHOC.
+    Empty,
+    /// EDHOC completed on our side; message 3 is sent along with every request until a protected
+    /// response shows that the server has processed it.
+    EdhocSend3 {
+        message_3: lakers::EdhocMessageBuffer,
+        context: liboscore::PrimitiveContext,
+    },
+    /// The security context is established on both sides.
+    Oscore(liboscore::PrimitiveContext),
+}
+
+impl ClientStage {
+    /// Returns the OSCORE context and the EDHOC message 3 that still needs to be sent, if any.
+    fn context(
+        &mut self,
+    ) -> Option<(
+        &mut liboscore::PrimitiveContext,
+        Option<&lakers::EdhocMessageBuffer>,
+    )> {
+        match self {
+            ClientStage::Empty => None,
+            ClientStage::EdhocSend3 { message_3, context } => Some((context, Some(message_3))),
+            ClientStage::Oscore(context) => Some((context, None)),
+        }
+    }
+
+    /// Records that the server has successfully responded to a protected request.
+    fn confirm(&mut self) {
+        *self = match core::mem::replace(self, ClientStage::Empty) {
+            ClientStage::EdhocSend3 { context, .. } => ClientStage::Oscore(context),
+            other => other,
+        };
+    }
+}
+
+/// A CoAP client's security context towards a single server, established through EDHOC and used
+/// with OSCORE.
+///
+/// This is the client side counterpart of an [`OscoreEdhocHandler`](crate::OscoreEdhocHandler)
+/// (or any other CoAP server supporting EDHOC in the responder role).
+pub struct OscoreEdhocClient<Crypto: lakers::Crypto, CryptoFactory: Fn() -> Crypto> {
+    own_credential: lakers::Credential,
+    own_key: lakers::BytesP256ElemLen,
+    peer_credential: lakers::Credential,
+    crypto_factory: CryptoFactory,
+    stage: ClientStage,
+    _crypto: PhantomData<Crypto>,
+}
+
+impl<Crypto: lakers::Crypto, CryptoFactory: Fn() -> Crypto>
+    OscoreEdhocClient<Crypto, CryptoFactory>
+{
+    /// Creates a client that authenticates with `own_credential` (whose private key is
+    /// `own_key`), and only accepts a server that presents `peer_credential`.
+    ///
+    /// No communication happens until the first request is sent.
+    ///
+    /// `crypto_factory` is used like in [`OscoreEdhocHandler::new()`](crate::OscoreEdhocHandler::new).
+    pub fn new(
+        own_credential: lakers::Credential,
+        own_key: lakers::BytesP256ElemLen,
+        peer_credential: lakers::Credential,
+        crypto_factory: CryptoFactory,
+    ) -> Self {
+        Self {
+            own_credential,
+            own_key,
+            peer_credential,
+            crypto_factory,
+            stage: ClientStage::Empty,
+            _crypto: PhantomData,
+        }
+    }
+
+    /// Returns whether a security context has been established and confirmed by the server.
+    pub fn is_established(&self) -> bool {
+        matches!(self.stage, ClientStage::Oscore(_))
+    }
+
+    /// Discards the security context, so that the next request runs EDHOC anew.
+    pub fn reset(&mut self) {
+        self.stage = ClientStage::Empty;
+    }
+
+    /// Wraps a CoAP stack, such that requests sent through it are protected with this client's
+    /// security context.
+    ///
+    /// The stack needs to be directed at the server this client is configured for; with
+    /// [`embedded_nal_coap`](https://docs.rs/embedded-nal-coap), that is the result of
+    /// `client.to(address)`.
+    pub fn protecting<S: Stack>(
+        &mut self,
+        stack: S,
+    ) -> ProtectedStack<'_, S, Crypto, CryptoFactory> {
+        ProtectedStack {
+            client: self,
+            stack,
+        }
+    }
+
+    /// Runs EDHOC as the initiator through `stack`, and derives the OSCORE context from it.
+    ///
+    /// # Errors
+    ///
+    /// This produces errors if the transport fails, or if the server's messages are malformed or
+    /// not authenticated by the expected credential.
+    async fn run_edhoc<S: Stack>(
+        &mut self,
+        stack: &mut S,
+    ) -> Result<(), ClientError<S::TransportError>> {
+        // There is only a single security context per client, so any value works.
+        let c_i = COwn::not_in_iter(core::iter::empty());
+
+        trace!("Sending EDHOC message 1");
+        let (initiator, message_1) = lakers::EdhocInitiator::new(
+            (self.crypto_factory)(),
+            lakers::EDHOCMethod::StatStat,
+            lakers::EDHOCSuite::CipherSuite2,
+        )
+        .prepare_message_1(Some(c_i.into()), &None)
+        .map_err(edhoc_error)?;
+
+        let message_2 = stack
+            .request(EdhocMessage1 { message_1 })
+            .await
+            .map_err(ClientError::Transport)?
+            .ok_or(ClientError::Edhoc)?;
+
+        trace!("Processing EDHOC message 2");
+        let (mut initiator, c_r, id_cred_r, ead_2) =
+            initiator.parse_message_2(&message_2).map_err(edhoc_error)?;
+
+        if let Some(ead_2) = ead_2
+            && ead_2.is_critical
+        {
+            error!("Critical EAD2 item received, aborting");
+            return Err(ClientError::Edhoc);
+        }
+
+        let cred_r = lakers::credential_check_or_fetch(Some(self.peer_credential), id_cred_r)
+            .map_err(edhoc_error)?;
+
+        // Our own identity is only revealed after the server has been identified.
+        initiator
+            .set_identity(self.own_key, self.own_credential)
+            .map_err(edhoc_error)?;
+        let initiator = initiator.verify_message_2(cred_r).map_err(edhoc_error)?;
+
+        // We're sending our ID by reference: the server can only accept us if it knows our
+        // credential anyway.
+        let (initiator, message_3, _prk_out) = initiator
+            .prepare_message_3(lakers::CredentialTransfer::ByReference, &None)
+            .map_err(edhoc_error)?;
+
+        let mut initiator = initiator
+            .completed_without_message_4()
+            .map_err(edhoc_error)?;
+
+        let oscore_secret = initiator.edhoc_exporter(0u8, &[], 16); // label is 0
+        let oscore_salt = initiator.edhoc_exporter(1u8, &[], 8); // label is 1
+
+        let context = oscore_context_from_edhoc(
+            &oscore_secret[..16],
+            &oscore_salt[..8],
+            c_r.as_slice(),
+            c_i.as_slice(),
+        );
+
+        debug!(
+            "Established OSCORE context with sender ID {:?} through EDHOC",
+            c_r.as_slice()
+        );
+
+        self.stage = ClientStage::EdhocSend3 { message_3, context };
+
+        Ok(())
+    }
+}
+
+/// A CoAP stack that protects all requests sent through it with the security context of an
+/// [`OscoreEdhocClient`].
+///
+/// This is created through [`OscoreEdhocClient::protecting()`].
+///
+/// Requests are built into, and responses read from, messages that are encrypted and decrypted
+/// by libOSCORE. As that happens in synchronous callbacks, requests need to build and process
+/// their messages without waiting (which is the case for all of
+/// [`coap_request_implementations`](https://docs.rs/coap-request-implementations)); others fail
+/// with [`ClientError::Request`] or [`ClientError::Response`].
+pub struct ProtectedStack<'a, S: Stack, Crypto: lakers::Crypto, CryptoFactory: Fn() -> Crypto> {
+    client: &'a mut OscoreEdhocClient<Crypto, CryptoFactory>,
+    stack: S,
+}
+
+impl<S: Stack, Crypto: lakers::Crypto, CryptoFactory: Fn() -> Crypto> Stack
+    for ProtectedStack<'_, S, Crypto, CryptoFactory>
+{
+    type RequestUnionError = <liboscore::ProtectedMessage as MinimalWritableMessage>::UnionError;
+    type RequestMessage<'a>
+        = liboscore::ProtectedMessage
+    where
+        Self: 'a;
+    type ResponseMessage<'a>
+        = liboscore::ProtectedMessage
+    where
+        Self: 'a;
+    type TransportError = ClientError<S::TransportError>;
+
+    async fn request<Req: Request<Self>>(
+        &mut self,
+        request: Req,
+    ) -> Result<Req::Output, Self::TransportError> {
+        if matches!(self.client.stage, ClientStage::Empty) {
+            self.client.run_edhoc(&mut self.stack).await?;
+        }
+
+        self.stack
+            .request(OscoreRequest {
+                stage: &mut self.client.stage,
+                request,
+                _stack: PhantomData::<fn() -> Self>,
+            })
+            .await
+            .map_err(ClientError::Transport)?
+    }
+}
+
+/// The request carrying EDHOC message 1 to the server's `/.well-known/edhoc` resource.
+struct EdhocMessage1 {
+    message_1: lakers::EdhocMessageBuffer,
+}
+
+impl<S: Stack> Request<S> for EdhocMessage1 {
+    type Carry = ();
+    /// The EDHOC message 2, or `None` if the server did not send a usable one.
+    type Output = Option<lakers::EdhocMessageBuffer>;
+
+    async fn build_request(
+        &mut self,
+        request: &mut S::RequestMessage<'_>,
+    ) -> Result<Self::Carry, S::RequestUnionError> {
+        write_edhoc_message_1(request, self.message_1.as_slice())
+    }
+
+    async fn process_response(
+        &mut self,
+        response: &S::ResponseMessage<'_>,
+        _carry: Self::Carry,
+    ) -> Self::Output {
+        let code: u8 = response.code().into();
+        if code != coap_numbers::code::CHANGED {
+            error!("Server responded to EDHOC message 1 with code {}.", code);
+            return None;
+        }
+        lakers::EdhocMessageBuffer::new_from_slice(response.payload())
+            .map_err(|e| error!("EDHOC message 2 is too long: {:?}", Debug2Format(&e)))
+            .ok()
+    }
+}
+
+/// An application request that is protected with OSCORE while it is being sent through the inner
+/// stack `S` of a [`ProtectedStack`] `P`.
+struct OscoreRequest<'a, P, Req> {
+    stage: &'a mut ClientStage,
+    request: Req,
+    _stack: PhantomData<fn() -> P>,
+}
+
+impl<'s, S: Stack, Crypto: lakers::Crypto, CryptoFactory: Fn() -> Crypto, Req> Request<S>
+    for OscoreRequest<'_, ProtectedStack<'s, S, Crypto, CryptoFactory>, Req>
+where
+    Req: Request<ProtectedStack<'s, S, Crypto, CryptoFactory>>,
+{
+    /// The OSCORE correlation data and the application request's carry.
+    ///
+    /// Errors are carried to the response processing, as they can not be expressed as errors of
+    /// the inner stack.
+    type Carry =
+        Result<(liboscore::raw::oscore_requestid_t, Req::Carry), ClientError<S::TransportError>>;
+    type Output = Result<Req::Output, ClientError<S::TransportError>>;
+
+    async fn build_request(
+        &mut self,
+        request: &mut S::RequestMessage<'_>,
+    ) -> Result<Self::Carry, S::RequestUnionError> {
+        let Some((context, message_3)) = self.stage.context() else {
+            error!("No security context to protect the request with.");
+            return Ok(Err(ClientError::Request));
+        };
+
+        // libOSCORE can only work on a particular message type, which also sidesteps the need to
+        // reorder options when the EDHOC option is added.
+        let mut buffer = [0u8; EDHOC_COPY_BUFFER_SIZE];
+        let mut code = 0;
+        let mut protected =
+            coap_message_implementations::inmemory_write::Message::new(&mut code, &mut buffer[..]);
+
+        let inner = &mut self.request;
+        let (correlation, carry) =
+            match liboscore::protect_request(&mut protected, context, |request| {
+                poll_once(inner.build_request(request))
+            }) {
+                Ok((correlation, Some(Ok(carry)))) => (correlation, carry),
+                Ok((_, Some(Err(e)))) => {
+                    error!("Building the request failed: {:?}", Debug2Format(&e));
+                    return Ok(Err(ClientError::Request));
+                }
+                Ok((_, None)) => {
+                    error!("Building the request did not complete without waiting.");
+                    return Ok(Err(ClientError::Request));
+                }
+                Err(e) => {
+                    error!("Protecting the request failed: {:?}", Debug2Format(&e));
+                    return Ok(Err(ClientError::Request));
+                }
+            };
+
+        let message_3 = message_3.map(lakers::EdhocMessageBuffer::as_slice);
+        copy_protected_request(request, &protected, message_3)?;
+
+        Ok(Ok((correlation, carry)))
+    }
+
+    async fn process_response(
+        &mut self,
+        response: &S::ResponseMessage<'_>,
+        carry: Self::Carry,
+    ) -> Self::Output {
+        let (mut correlation, carry) = carry?;
+
+        // See comment on EDHOC_COPY_BUFFER_SIZE: libOSCORE needs mutable access to a message of a
+        // type it knows.
+        let mut buffer = [0u8; EDHOC_COPY_BUFFER_SIZE];
+        let mut code = 0;
+        let mut copied =
+            coap_message_implementations::inmemory_write::Message::new(&mut code, &mut buffer[..]);
+        copied.set_code(response.code().into());
+        let mut oscore_option: Option<crate::seccontext::OscoreOption> = None;
+        for opt in response.options() {
+            if opt.number() == coap_numbers::option::OSCORE {
+                oscore_option = opt.value().try_into().ok();
+            }
+            copied.add_option(opt.number(), opt.value()).map_err(|_| {
+                error!("Options produced in unexpected sequence.");
+                ClientError::Response
+            })?;
+        }
+        copied.set_payload(response.payload()).map_err(|_| {
+            error!("Unexpectedly large response");
+            ClientError::Response
+        })?;
+
+        let Some(oscore_option) = oscore_option else {
+            error!("Server responded without OSCORE, discarding security context.");
+            *self.stage = ClientStage::Empty;
+            return Err(ClientError::Response);
+        };
+        let oscore_option = liboscore::OscoreOption::parse(&oscore_option).map_err(|_| {
+            error!("OSCORE option could not be parsed");
+            ClientError::Response
+        })?;
+
+        let Some((context, _)) = self.stage.context() else {
+            error!("Security context vanished before the response was processed.");
+            return Err(ClientError::Response);
+        };
+
+        let inner = &mut self.request;
+        let output = liboscore::unprotect_response(
+            &mut copied,
+            context,
+            oscore_option,
+            &mut correlation,
+            |response| poll_once(inner.process_response(response, carry)),
+        );
+
+        match output {
+            Ok(Some(output)) => {
+                self.stage.confirm();
+                Ok(output)
+            }
+            Ok(None) => {
+                // The response was authentic, so the server has the context.
+                self.stage.confirm();
+                error!("Processing the response did not complete without waiting.");
+                Err(ClientError::Response)
+            }
+            Err(e) => {
+                error!("Decryption failure: {:?}", Debug2Format(&e));
+                Err(ClientError::Response)
+            }
+        }
+    }
+}
+
+/// Writes a request carrying EDHOC message 1 to the server's `/.well-known/edhoc` resource.
+///
+/// # Errors
+///
+/// This produces errors if the message can not hold the request.
+fn write_edhoc_message_1<M: MutableWritableMessage>(
+    request: &mut M,
+    message_1: &[u8],
+) -> Result<(), M::UnionError> {
+    request.set_code(M::Code::new(coap_numbers::code::POST)?);
+    let uri_path = M::OptionNumber::new(coap_numbers::option::URI_PATH)?;
+    request.add_option(uri_path, b".well-known")?;
+    request.add_option(uri_path, b"edhoc")?;
+
+    // EDHOC message 1 is prefixed with CBOR `true` when sent in a CoAP request.
+    let payload = request.payload_mut_with_len(1 + message_1.len())?;
+    let (first_byte, rest) = payload.split_at_mut(1);
+    first_byte.copy_from_slice(&[0xf5]);
+    rest.copy_from_slice(message_1);
+
+    Ok(())
+}
+
+/// Copies an OSCORE protected request into the message that is sent, adding an EDHOC option and
+/// prepending EDHOC message 3 to the payload if `message_3` is given.
+///
+/// # Errors
+///
+/// This produces errors if the message can not hold the request.
+fn copy_protected_request<M: MutableWritableMessage>(
+    request: &mut M,
+    protected: &impl ReadableMessage,
+    message_3: Option<&[u8]>,
+) -> Result<(), M::UnionError> {
+    request.set_code(M::Code::new(protected.code().into())?);
+
+    let mut edhoc_pending = message_3.is_some();
+    for opt in protected.options() {
+        if edhoc_pending && opt.number() > coap_numbers::option::EDHOC {
+            request.add_option(M::OptionNumber::new(coap_numbers::option::EDHOC)?, b"")?;
+            edhoc_pending = false;
+        }
+        request.add_option(M::OptionNumber::new(opt.number())?, opt.value())?;
+    }
+    if edhoc_pending {
+        request.add_option(M::OptionNumber::new(coap_numbers::option::EDHOC)?, b"")?;
+    }
+
+    // Message 3 is a CBOR byte string, which precedes the OSCORE ciphertext.
+    let message_3 = message_3.unwrap_or_default();
+    let ciphertext = protected.payload();
+    let payload = request.payload_mut_with_len(message_3.len() + ciphertext.len())?;
+    let (head, tail) = payload.split_at_mut(message_3.len());
+    head.copy_from_slice(message_3);
+    tail.copy_from_slice(ciphertext);
+
+    Ok(())
+}
+
+/// Logs a [`lakers::EDHOCError`] and converts it into a [`ClientError`].
+#[expect(
+    clippy::needless_pass_by_value,
+    reason = "ergonomics at the call sites need this"
+)]
+fn edhoc_error<T>(e: lakers::EDHOCError) -> ClientError<T> {
+    error!("EDHOC with the server failed: {:?}", Debug2Format(&e));
+    ClientError::Edhoc
+}
+
+#[cfg(test)]
+mod tests {
+    use coap_message::{
+        Code as _, MinimalWritableMessage, MutableWritableMessage, OptionNumber as _,
+        ReadableMessage,
+    };
+    use coap_message_implementations::inmemory_write::Message;
+    use coap_request::{Request, Stack};
+
+    use super::{ClientError, OscoreEdhocClient};
+    use crate::helpers::poll_once;
+
+    const CLIENT_CREDENTIAL: &[u8] = &hexlit::hex!(
+        "A2027734322D35302D33312D46462D45462D33372D33322D333908A101A5010202412B2001215820AC75E9ECE3E50BFC8ED60399889522405C47BF16DF96660A41298CB4307F7EB62258206E5DE611388A4B8A8211334AC7D37ECB52A387D257E6DB3C2A93DF21FF3AFFC8"
+    );
+    const CLIENT_KEY: [u8; 32] =
+        hexlit::hex!("fb13adeb6518cee5f88417660841142e830a81fe334380a953406a1305e8706b");
+    const SERVER_CREDENTIAL: &[u8] = &hexlit::hex!(
+        "A2026008A101A5010202410A2001215820BBC34960526EA4D32E940CAD2A234148DDC21791A12AFBCBAC93622046DD44F02258204519E257236B2A0CE2023F0931F1F386CA7AFDA64FCDE0108C224C51EABF6072"
+    );
+    const SERVER_KEY: [u8; 32] =
+        hexlit::hex!("72cc4761dbd4c78f758931aa589d348d1ef874a7e303ede2f140dcf3e6aa4aac");
+
+    type Crypto = lakers_crypto_rustcrypto::Crypto<TestRng>;
+
+    /// Deterministic xorshift generator; good enough for tests, and for nothing else.
+    struct TestRng(u64);
+
+    impl rand_core::RngCore for TestRng {
+        #[expect(
+            clippy::cast_possible_truncation,
+            reason = "the lower half of the output is as good as any other"
+        )]
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest);
+        }
+    }
+
+    impl rand_core::CryptoRng for TestRng {}
+
+    // Lakers' RustCrypto backend still uses the older `rand_core`.
+    impl rand_core_06::RngCore for TestRng {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::RngCore::next_u32(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::RngCore::next_u64(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::RngCore::fill_bytes(self, dest);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core_06::Error> {
+            rand_core::RngCore::fill_bytes(self, dest);
+            Ok(())
+        }
+    }
+
+    impl rand_core_06::CryptoRng for TestRng {}
+
+    /// Application handler that answers every request with "hello".
+    struct Hello;
+
+    impl coap_handler::Handler for Hello {
+        type RequestData = ();
+        type ExtractRequestError = core::convert::Infallible;
+        type BuildResponseError<M: MinimalWritableMessage> = M::UnionError;
+
+        fn extract_request_data<M: ReadableMessage>(
+            &mut self,
+            _request: &M,
+        ) -> Result<(), Self::ExtractRequestError> {
+            Ok(())
+        }
+
+        fn estimate_length(&mut self, _request: &()) -> usize {
+            16
+        }
+
+        fn build_response<M: MutableWritableMessage>(
+            &mut self,
+            response: &mut M,
+            _request: (),
+        ) -> Result<(), M::UnionError> {
+            response.set_code(M::Code::new(coap_numbers::code::CONTENT)?);
+            response.set_payload(b"hello")?;
+            Ok(())
+        }
+    }
+
+    /// A GET request whose output is the payload of a successful response.
+    struct GetHello;
+
+    impl<S: Stack> Request<S> for GetHello {
+        type Carry = ();
+        type Output = Option<heapless::Vec<u8, 16>>;
+
+        async fn build_request(
+            &mut self,
+            request: &mut S::RequestMessage<'_>,
+        ) -> Result<(), S::RequestUnionError> {
+            write_get_hello(request)
+        }
+
+        async fn process_response(
+            &mut self,
+            response: &S::ResponseMessage<'_>,
+            _carry: (),
+        ) -> Self::Output {
+            let code: u8 = response.code().into();
+            if code != coap_numbers::code::CONTENT {
+                return None;
+            }
+            heapless::Vec::from_slice(response.payload()).ok()
+        }
+    }
+
+    fn write_get_hello<M: MutableWritableMessage>(request: &mut M) -> Result<(), M::UnionError> {
+        request.set_code(M::Code::new(coap_numbers::code::GET)?);
+        request.add_option(
+            M::OptionNumber::new(coap_numbers::option::URI_PATH)?,
+            b"hello",
+        )?;
+        Ok(())
+    }
+
+    /// A CoAP stack that hands each request directly to a server handler.
+    struct Loopback<'h, H>(&'h mut H);
+
+    impl<H: coap_handler::Handler> Stack for Loopback<'_, H> {
+        type RequestUnionError = <Message<'static> as MinimalWritableMessage>::UnionError;
+        type RequestMessage<'a>
+            = Message<'a>
+        where
+            Self: 'a;
+        type ResponseMessage<'a>
+            = Message<'a>
+        where
+            Self: 'a;
+        type TransportError = core::convert::Infallible;
+
+        async fn request<Req: Request<Self>>(
+            &mut self,
+            mut request: Req,
+        ) -> Result<Req::Output, Self::TransportError> {
+            let mut request_code = 0;
+            let mut request_buffer = [0; 1024];
+            let mut request_message = Message::new(&mut request_code, &mut request_buffer[..]);
+            let Ok(carry) = request.build_request(&mut request_message).await else {
+                panic!("request does not fit the buffer");
+            };
+
+            let Ok(extracted) = self.0.extract_request_data(&request_message) else {
+                panic!("server failed to process the request");
+            };
+            let mut response_code = 0;
+            let mut response_buffer = [0; 1024];
+            let mut response_message = Message::new(&mut response_code, &mut response_buffer[..]);
+            let Ok(()) = self.0.build_response(&mut response_message, extracted) else {
+                panic!("server failed to build the response");
+            };
+
+            Ok(request.process_response(&response_message, carry).await)
+        }
+    }
+
+    fn credential(encoded: &[u8]) -> lakers::Credential {
+        lakers::Credential::parse_ccs(encoded).expect("test credentials are valid")
+    }
+
+    fn server() -> impl coap_handler::Handler {
+        let config = crate::seccfg::ConfigBuilder::new()
+            .with_own_edhoc_credential(credential(SERVER_CREDENTIAL), SERVER_KEY)
+            .with_known_edhoc_credential(
+                credential(CLIENT_CREDENTIAL),
+                crate::scope::AllowAll.into(),
+            );
+        crate::OscoreEdhocHandler::new(
+            Hello,
+            config,
+            || Crypto::new(TestRng(1)),
+            TestRng(2),
+            crate::time::TimeUnknown,
+        )
+    }
+
+    #[test]
+    fn loopback() {
+        let mut server = server();
+        let mut client = OscoreEdhocClient::new(
+            credential(CLIENT_CREDENTIAL),
+            CLIENT_KEY,
+            credential(SERVER_CREDENTIAL),
+            || Crypto::new(TestRng(3)),
+        );
+        assert!(!client.is_established());
+
+        // The first request runs EDHOC, the second one uses the established context.
+        for _ in 0..2 {
+            let response = poll_once(client.protecting(Loopback(&mut server)).request(GetHello));
+            let Some(Ok(Some(payload))) = response else {
+                panic!("request through the client failed");
+            };
+            assert_eq!(payload.as_slice(), b"hello");
+            assert!(client.is_established());
+        }
+    }
+
+    #[test]
+    fn unexpected_server_credential() {
+        let mut server = server();
+        // The server presents its own credential, which the client does not expect.
+        let mut client = OscoreEdhocClient::new(
+            credential(CLIENT_CREDENTIAL),
+            CLIENT_KEY,
+            credential(CLIENT_CREDENTIAL),
+            || Crypto::new(TestRng(3)),
+        );
+
+        let response = poll_once(client.protecting(Loopback(&mut server)).request(GetHello));
+        assert!(matches!(response, Some(Err(ClientError::Edhoc))));
+        assert!(!client.is_established());
+    }
+}
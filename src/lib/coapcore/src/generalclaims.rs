@@ -31,6 +31,14 @@ pub trait GeneralClaims: core::fmt::Debug {
     /// request when eviction of a security context is being considered.
     fn time_constraint(&self) -> crate::time::TimeConstraint;
 
+    /// Returns whether the claims were revoked after they were created.
+    ///
+    /// Like the [time constraint](Self::time_constraint), this is evaluated on every request; a
+    /// security context whose claims are revoked is discarded.
+    fn is_revoked(&self) -> bool {
+        false
+    }
+
     /// Access whether a security context is important.
     ///
     /// This is intentionally vague (importance of a security context can vary by application), but
@@ -106,3 +106,19 @@ impl From<COwn> for lakers::ConnId {
             .expect("ConnId is always big enough for at least COwn")
     }
 }
+
+/// Runs a future to completion, provided it completes without waiting.
+///
+/// This is used where asynchronous code is called from within synchronous callbacks (as
+/// libOSCORE's protect and unprotect functions have them). Request builders and response
+/// processors that merely write or read a message complete right away; for all others, `None` is
+/// returned.
+pub(crate) fn poll_once<F: Future>(future: F) -> Option<F::Output> {
+    let mut future = core::pin::pin!(future);
+    match future.as_mut().poll(&mut core::task::Context::from_waker(
+        core::task::Waker::noop(),
+    )) {
+        core::task::Poll::Ready(output) => Some(output),
+        core::task::Poll::Pending => None,
+    }
+}
@@ -1,9 +1,9 @@
 //! A CoAP security tool for embedded devices, supporting OSCORE/EDHOC and managing credentials.
 //!
-//! This crate is under active development; breaking changes will be made as necessary. It
-//! currently only handles the server side of CoAP exchanges. At runtime, there is more copying of
-//! messages than is generally preferred; those result from limitations of underlying tools and are
-//! being addressed there.
+//! This crate is under active development; breaking changes will be made as necessary. It mainly
+//! handles the server side of CoAP exchanges; the client side is limited to running EDHOC with a
+//! single known server. At runtime, there is more copying of messages than is generally preferred;
+//! those result from limitations of underlying tools and are being addressed there.
 //!
 //! This crate builds on several components technically and logically:
 //!
@@ -27,6 +27,10 @@
 //!
 //! The arguments passed to the [`OscoreEdhocHandler`] at construction guide its behavior.
 //!
+//! On the client side, an [`OscoreEdhocClient`][client::OscoreEdhocClient] holds the security
+//! context towards a server. It wraps a CoAP client stack (something that implements
+//! [`coap_request::Stack`]) into one that runs EDHOC on demand and protects requests with OSCORE.
+//!
 //! # Logging
 //!
 //! Extensive logging is available in this crate through [`defmt_or_log`], depending on features
@@ -61,6 +65,7 @@
 mod iana;
 
 mod helpers;
+mod observation;
 
 pub mod time;
 
@@ -70,6 +75,10 @@ pub mod scope;
 pub use generalclaims::GeneralClaims;
 pub mod seccfg;
 
+pub mod client;
+
+pub mod persistence;
+
 // Might warrant a standalone crate at some point
 //
 // This is pub only to make the doctests run (but the crate's pub-ness needs a major overhaul
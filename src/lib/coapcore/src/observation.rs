@@ -0,0 +1,128 @@
+//! Retention of OSCORE protected requests that registered an observation.
+//!
+//! Notifications for an OSCORE protected observation are produced by processing the inner request
+//! again, which can not be recovered from the outer request because of replay protection. Thus,
+//! the inner request is kept along with what is needed to protect the notifications, and looked up
+//! by the outer request's OSCORE option (which is unique as it contains the Partial IV).
+
+use coap_message::{MutableWritableMessage as _, ReadableMessage};
+
+use crate::helpers::COwn;
+use crate::seccontext::OscoreOption;
+
+/// Number of retained observations; when exceeded, the oldest observation is discarded.
+const MAX_OBSERVATIONS: usize = 4;
+
+/// Maximum length of an inner request's serialized options and payload that can be retained.
+const MAX_REQUEST_LEN: usize = 64;
+
+#[derive(Clone)]
+pub(crate) struct Observation {
+    pub(crate) kid: COwn,
+    /// Value of the outer request's OSCORE option.
+    pub(crate) oscore_option: OscoreOption,
+    pub(crate) correlation: liboscore::raw::oscore_requestid_t,
+    /// Code of the inner request.
+    pub(crate) code: u8,
+    /// Serialized options and payload of the inner request.
+    pub(crate) request: heapless::Vec<u8, MAX_REQUEST_LEN>,
+}
+
+/// An inner request that may register an observation, before it is known whether decryption
+/// succeeds.
+pub(crate) struct ObservationRequest {
+    code: u8,
+    request: heapless::Vec<u8, MAX_REQUEST_LEN>,
+}
+
+impl ObservationRequest {
+    /// Copies a request if it registers an observation, and is small enough to be retained.
+    pub(crate) fn copy_if_registering(request: &impl ReadableMessage) -> Option<Self> {
+        use coap_message::MessageOption as _;
+
+        // Observe value 0 is usually encoded as an empty option.
+        if !request.options().any(|o| {
+            o.number() == coap_numbers::option::OBSERVE && o.value().iter().all(|b| *b == 0)
+        }) {
+            return None;
+        }
+
+        let mut code = 0;
+        let mut buffer = [0; MAX_REQUEST_LEN];
+        let mut copy =
+            coap_message_implementations::inmemory_write::Message::new(&mut code, &mut buffer);
+        copy.set_from_message(request).ok()?;
+        let len = copy.finish();
+        Some(Self {
+            code,
+            request: buffer.get(..len)?.try_into().ok()?,
+        })
+    }
+
+    pub(crate) fn into_observation(
+        self,
+        kid: COwn,
+        oscore_option: OscoreOption,
+        correlation: liboscore::raw::oscore_requestid_t,
+    ) -> Observation {
+        Observation {
+            kid,
+            oscore_option,
+            correlation,
+            code: self.code,
+            request: self.request,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Observations {
+    entries: [Option<Observation>; MAX_OBSERVATIONS],
+    /// Index of the entry that is replaced next if all are occupied.
+    next: usize,
+}
+
+impl Observations {
+    pub(crate) fn insert(&mut self, observation: Observation) {
+        let index = self
+            .entries
+            .iter()
+            .position(Option::is_none)
+            .unwrap_or_else(|| {
+                let index = self.next;
+                self.next = (self.next + 1) % MAX_OBSERVATIONS;
+                index
+            });
+        if let Some(entry) = self.entries.get_mut(index) {
+            *entry = Some(observation);
+        }
+    }
+
+    pub(crate) fn find(&self, oscore_option: &[u8]) -> Option<&Observation> {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|o| o.oscore_option.as_slice() == oscore_option)
+    }
+
+    pub(crate) fn remove(&mut self, oscore_option: &[u8]) {
+        for entry in &mut self.entries {
+            if entry
+                .as_ref()
+                .is_some_and(|o| o.oscore_option.as_slice() == oscore_option)
+            {
+                *entry = None;
+            }
+        }
+    }
+
+    /// Discards the observations of a security context, whose recipient ID is about to be used
+    /// for a new context.
+    pub(crate) fn remove_context(&mut self, kid: COwn) {
+        for entry in &mut self.entries {
+            if entry.as_ref().is_some_and(|o| o.kid == kid) {
+                *entry = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! Persistence of OSCORE security contexts across reboots of the server.
+//!
+//! Security contexts established through EDHOC can be persisted through an implementation of
+//! [`ContextPersistence`] passed to
+//! [`OscoreEdhocHandler::with_context_persistence()`][crate::OscoreEdhocHandler::with_context_persistence].
+//! Their mutable parts are handled as described in [RFC8613 Appendix
+//! B.1](https://www.rfc-editor.org/rfc/rfc8613#appendix-B.1):
+//!
+//! * Sender sequence numbers are reserved in steps: Only numbers below the persisted bound are
+//!   used, and the bound is advanced before it is reached.
+//! * The replay window is not persisted. Instead, after a restart, the first request on a restored
+//!   context is answered with an Echo option (see [RFC9175]), and only a request carrying that
+//!   value is accepted and initializes the replay window.
+//!
+//! Contexts whose authorization was established through an ACE token are not persisted, as the
+//! authorization could not be recovered without the token.
+//!
+//! [RFC9175]: https://www.rfc-editor.org/rfc/rfc9175
+
+use crate::helpers::COwn;
+
+/// Number of sender sequence numbers reserved with each update of the persisted state (`K` in
+/// RFC8613 Appendix B.1.1).
+pub(crate) const SEQUENCE_NUMBER_STEP: u64 = 32;
+
+/// Length of the Echo values sent on restored contexts.
+pub(crate) const ECHO_LEN: usize = 8;
+
+/// Maximum length of an OSCORE Sender ID that is persisted.
+///
+/// This is the longest ID usable with AES-CCM-16-64-128, which has a 13 byte nonce.
+const MAX_ID_LEN: usize = 7;
+
+/// Maximum length of an `ID_CRED_I` that is persisted.
+///
+/// This accommodates credentials identified by short key IDs; contexts with peers identified by
+/// longer values (in particular by value) are not persisted.
+const MAX_ID_CRED_LEN: usize = 16;
+
+/// Maximum length of a [`PersistedContext`] in its serialized form.
+pub const MAX_PERSISTED_LEN: usize = 64;
+
+// Array head, byte string heads with their content, and a 64-bit integer.
+const _MAX_PERSISTED_LEN_CHECK: () = assert!(
+    1 + (1 + 16)
+        + (1 + 8)
+        + (1 + MAX_ID_LEN)
+        + (1 + COwn::MAX_SLICE_LEN)
+        + (1 + MAX_ID_CRED_LEN)
+        + 9
+        <= MAX_PERSISTED_LEN
+);
+
+/// State of an OSCORE security context from which it can be restored.
+#[derive(Clone, Debug)]
+pub struct PersistedContext {
+    pub(crate) secret: [u8; 16],
+    pub(crate) salt: [u8; 8],
+    pub(crate) sender_id: heapless::Vec<u8, MAX_ID_LEN>,
+    pub(crate) recipient_id: COwn,
+    /// The peer's `ID_CRED_I`, through which the authorization is recovered.
+    pub(crate) id_cred_peer: heapless::Vec<u8, MAX_ID_CRED_LEN>,
+    /// Bound below which sender sequence numbers may have been used.
+    pub(crate) sequence_number_bound: u64,
+}
+
+/// Error type of [`PersistedContext::decode()`].
+#[derive(Debug)]
+pub struct InvalidPersistedContext;
+
+impl PersistedContext {
+    /// Creates the state of a freshly established context, if it is suitable for persisting.
+    pub(crate) fn new(
+        secret: &[u8],
+        salt: &[u8],
+        sender_id: &[u8],
+        recipient_id: COwn,
+        id_cred_peer: &[u8],
+    ) -> Option<Self> {
+        Some(Self {
+            secret: secret.try_into().ok()?,
+            salt: salt.try_into().ok()?,
+            sender_id: sender_id.try_into().ok()?,
+            recipient_id,
+            id_cred_peer: id_cred_peer.try_into().ok()?,
+            sequence_number_bound: SEQUENCE_NUMBER_STEP,
+        })
+    }
+
+    /// The OSCORE Recipient ID of the context.
+    ///
+    /// This is unique among the contexts that are persisted at any time.
+    #[must_use]
+    pub fn recipient_id(&self) -> &[u8] {
+        self.recipient_id.as_slice()
+    }
+
+    /// Bound below which sender sequence numbers may have been used on the context.
+    #[must_use]
+    pub fn sequence_number_bound(&self) -> u64 {
+        self.sequence_number_bound
+    }
+
+    /// Serializes the state into `buffer`, returning the used part.
+    #[expect(
+        clippy::missing_panics_doc,
+        reason = "buffer size is checked statically"
+    )]
+    pub fn encode<'b>(&self, buffer: &'b mut [u8; MAX_PERSISTED_LEN]) -> &'b [u8] {
+        let mut encoder =
+            minicbor::Encoder::new(minicbor::encode::write::Cursor::new(&mut buffer[..]));
+        encoder
+            .array(6)
+            .and_then(|e| e.bytes(&self.secret))
+            .and_then(|e| e.bytes(&self.salt))
+            .and_then(|e| e.bytes(&self.sender_id))
+            .and_then(|e| e.bytes(self.recipient_id.as_slice()))
+            .and_then(|e| e.bytes(&self.id_cred_peer))
+            .and_then(|e| e.u64(self.sequence_number_bound))
+            .expect("buffer is sized to fit any state");
+        let len = encoder.into_writer().position();
+        #[allow(
+            clippy::indexing_slicing,
+            reason = "the cursor stays within the buffer"
+        )]
+        &buffer[..len]
+    }
+
+    /// Deserializes a state previously produced by [`.encode()`](Self::encode).
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if the data is corrupted or was produced by an incompatible version.
+    pub fn decode(data: &[u8]) -> Result<Self, InvalidPersistedContext> {
+        fn invalid<E>(_: E) -> minicbor::decode::Error {
+            minicbor::decode::Error::message("unexpected item size")
+        }
+
+        let mut decoder = minicbor::Decoder::new(data);
+        let mut decode = || -> Result<Self, minicbor::decode::Error> {
+            if decoder.array()? != Some(6) {
+                return Err(minicbor::decode::Error::message("unexpected length"));
+            }
+            Ok(Self {
+                secret: decoder.bytes()?.try_into().map_err(invalid)?,
+                salt: decoder.bytes()?.try_into().map_err(invalid)?,
+                sender_id: decoder.bytes()?.try_into().map_err(invalid)?,
+                recipient_id: COwn::from_kid(decoder.bytes()?)
+                    .ok_or_else(|| minicbor::decode::Error::message("unexpected recipient ID"))?,
+                id_cred_peer: decoder.bytes()?.try_into().map_err(invalid)?,
+                sequence_number_bound: decoder.u64()?,
+            })
+        };
+        decode().map_err(|_| InvalidPersistedContext)
+    }
+}
+
+/// Storage for the security contexts of an [`OscoreEdhocHandler`][crate::OscoreEdhocHandler].
+///
+/// The methods are called during request processing, and thus can not wait for the storage
+/// operation to complete; implementations typically queue the operation. Until a stored state is
+/// durable, responses that would use sequence numbers beyond the previously durable bound are
+/// refused with 5.03 Service Unavailable (such responses are rare, as responses usually reuse the
+/// request's nonce).
+pub trait ContextPersistence {
+    /// Whether any contexts are persisted.
+    ///
+    /// Implementations other than [`NoPersistence`] should leave this at its default.
+    const PERSISTS: bool = true;
+
+    /// Stores the state of a context, replacing any state that has the same recipient ID.
+    fn store(&mut self, context: &PersistedContext);
+
+    /// Removes the state with the given recipient ID, if any is stored.
+    fn remove(&mut self, recipient_id: &[u8]);
+
+    /// Returns the sequence number bound of the state with the given recipient ID that was last
+    /// written successfully.
+    ///
+    /// This is `None` if no state of the context has been written yet, in which case the context
+    /// can not be restored, and its sequence numbers are not limited.
+    fn durable_sequence_number_bound(&self, recipient_id: &[u8]) -> Option<u64>;
+}
+
+/// A [`ContextPersistence`] that does not persist any contexts.
+///
+/// This is the default of an [`OscoreEdhocHandler`][crate::OscoreEdhocHandler].
+pub struct NoPersistence;
+
+impl ContextPersistence for NoPersistence {
+    const PERSISTS: bool = false;
+
+    fn store(&mut self, _context: &PersistedContext) {}
+
+    fn remove(&mut self, _recipient_id: &[u8]) {}
+
+    fn durable_sequence_number_bound(&self, _recipient_id: &[u8]) -> Option<u64> {
+        None
+    }
+}
+
+/// Accesses the sender sequence number of a libOSCORE context.
+pub(crate) fn sender_sequence_number(context: &mut liboscore::PrimitiveContext) -> &mut u64 {
+    &mut context.as_mut().sender_sequence_number
+}
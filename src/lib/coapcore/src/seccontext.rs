@@ -15,6 +15,10 @@ use defmt_or_log::{Debug2Format, debug, error, trace};
 
 use crate::generalclaims::{self, GeneralClaims as _};
 use crate::helpers::COwn;
+use crate::observation::{ObservationRequest, Observations};
+use crate::persistence::{
+    self, ContextPersistence, ECHO_LEN, NoPersistence, PersistedContext, SEQUENCE_NUMBER_STEP,
+};
 use crate::scope::Scope as _;
 use crate::seccfg::ServerSecurityConfig;
 
@@ -43,14 +47,14 @@ const fn has_oscore<SSC: ServerSecurityConfig>() -> bool {
 /// knows, but that's not why we do it, that's what downcasting would be for.)
 ///
 /// Furthermore, we need mutable access (something we can't easily gain by just downcasting).
-const EDHOC_COPY_BUFFER_SIZE: usize = 1152;
+pub(crate) const EDHOC_COPY_BUFFER_SIZE: usize = 1152;
 
 /// A pool of security contexts shareable by several users inside a thread.
 type SecContextPool<Crypto, Claims> =
     crate::oluru::OrderedPool<SecContextState<Crypto, Claims>, MAX_CONTEXTS, LEVEL_COUNT>;
 
 /// A copy of the OSCORE option.
-type OscoreOption = heapless::Vec<u8, 16>;
+pub(crate) type OscoreOption = heapless::Vec<u8, 16>;
 
 struct SecContextState<Crypto: lakers::Crypto, GeneralClaims: generalclaims::GeneralClaims> {
     // FIXME: Updating this should also check the timeout.
@@ -58,6 +62,11 @@ struct SecContextState<Crypto: lakers::Crypto, GeneralClaims: generalclaims::Gen
     // This is Some(...) unless the stage is unusable.
     authorization: Option<GeneralClaims>,
     protocol_stage: SecContextStage<Crypto>,
+    /// State from which the OSCORE context is restored after a reboot, if it is persisted.
+    persisted: Option<PersistedContext>,
+    /// Echo value that a request needs to carry before the replay window of a restored OSCORE
+    /// context is initialized.
+    echo: Option<[u8; ECHO_LEN]>,
 }
 
 impl<Crypto: lakers::Crypto, GeneralClaims: generalclaims::GeneralClaims> Default
@@ -67,6 +76,8 @@ impl<Crypto: lakers::Crypto, GeneralClaims: generalclaims::GeneralClaims> Defaul
         Self {
             authorization: None,
             protocol_stage: SecContextStage::Empty,
+            persisted: None,
+            echo: None,
         }
     }
 }
@@ -155,6 +166,25 @@ impl<Crypto: lakers::Crypto, GeneralClaims: generalclaims::GeneralClaims>
     }
 }
 
+/// Produces a [`COwn`] (as a recipient identifier) that is both available in the `pool` and not
+/// equal to the peer's recipient identifier.
+///
+/// This is a free function rather than a method of [`OscoreEdhocHandler`] so that it can be used
+/// while other parts of the handler are borrowed exclusively.
+fn cown_but_not<Crypto: lakers::Crypto, GeneralClaims: generalclaims::GeneralClaims>(
+    pool: &SecContextPool<Crypto, GeneralClaims>,
+    c_peer: &[u8],
+) -> COwn {
+    COwn::not_in_iter(
+        pool.iter()
+            .filter_map(|entry| entry.corresponding_cown())
+            // C_R does not only need to be unique, it also must not be identical
+            // to C_I. If it is not expressible as a COwn (as_slice gives []),
+            // that's fine and we don't have to consider it.
+            .chain(COwn::from_kid(c_peer).as_slice().iter().copied()),
+    )
+}
+
 /// A CoAP handler wrapping inner resources, and adding EDHOC, OSCORE and ACE support.
 ///
 /// While the ACE (authz-info) and EDHOC parts could be implemented as a handler that is to be
@@ -167,6 +197,7 @@ pub struct OscoreEdhocHandler<
     SSC: ServerSecurityConfig,
     RNG: rand_core::RngCore + rand_core::CryptoRng,
     TP: TimeProvider,
+    P: ContextPersistence = NoPersistence,
 > {
     // It'd be tempted to have sharing among multiple handlers for multiple CoAP stacks, but
     // locks for such sharing could still be acquired in a factory (at which point it may make
@@ -190,6 +221,10 @@ pub struct OscoreEdhocHandler<
 
     crypto_factory: CryptoFactory,
     rng: RNG,
+
+    persistence: P,
+
+    observations: Observations,
 }
 
 impl<
@@ -230,6 +265,99 @@ impl<
             authorities,
             rng,
             time,
+            persistence: NoPersistence,
+            observations: Observations::default(),
+        }
+    }
+
+    /// Enables persistence of security contexts established through EDHOC, see the
+    /// [`persistence`][crate::persistence] module.
+    ///
+    /// The `restored` contexts are states that were stored earlier through the same
+    /// `persistence`. Requests on them are only processed after their freshness was verified
+    /// through an Echo option. As they are stored again with advanced sequence number bounds right
+    /// away, those states should be durable before the handler starts processing requests.
+    pub fn with_context_persistence<P: ContextPersistence>(
+        self,
+        persistence: P,
+        restored: impl IntoIterator<Item = PersistedContext>,
+    ) -> OscoreEdhocHandler<H, Crypto, CryptoFactory, SSC, RNG, TP, P> {
+        let mut handler = OscoreEdhocHandler {
+            pool: self.pool,
+            authorities: self.authorities,
+            inner: self.inner,
+            time: self.time,
+            crypto_factory: self.crypto_factory,
+            rng: self.rng,
+            persistence,
+            observations: self.observations,
+        };
+        for persisted in restored {
+            handler.restore(persisted);
+        }
+        handler
+    }
+}
+
+impl<
+    H: coap_handler::Handler,
+    Crypto: lakers::Crypto,
+    CryptoFactory: Fn() -> Crypto,
+    SSC: ServerSecurityConfig,
+    RNG: rand_core::RngCore + rand_core::CryptoRng,
+    TP: TimeProvider,
+    P: ContextPersistence,
+> OscoreEdhocHandler<H, Crypto, CryptoFactory, SSC, RNG, TP, P>
+{
+    /// Restores a persisted security context into the pool.
+    ///
+    /// The context's peer is looked up again, so that a peer that is not recognized any more does
+    /// not regain access.
+    fn restore(&mut self, mut persisted: PersistedContext) {
+        let authorization = lakers::IdCred::from_full_value(&persisted.id_cred_peer)
+            .ok()
+            .and_then(|id_cred| self.authorities.expand_id_cred_x(id_cred));
+        let Some((_, authorization)) = authorization else {
+            debug!("Peer of persisted context is not recognized any more, discarding.");
+            self.persistence.remove(persisted.recipient_id());
+            return;
+        };
+
+        let mut context = oscore_context_from_edhoc(
+            &persisted.secret,
+            &persisted.salt,
+            &persisted.sender_id,
+            persisted.recipient_id.as_slice(),
+        );
+        // Any lower sequence number may have been used before the reboot.
+        *persistence::sender_sequence_number(&mut context) = persisted.sequence_number_bound;
+        persisted.sequence_number_bound += SEQUENCE_NUMBER_STEP;
+        self.persistence.store(&persisted);
+
+        let mut echo = [0; ECHO_LEN];
+        self.rng.fill_bytes(&mut echo);
+
+        debug!(
+            "Restored OSCORE context with recipient ID {:?}",
+            persisted.recipient_id()
+        );
+        let evicted = self.pool.force_insert(SecContextState {
+            protocol_stage: SecContextStage::Oscore(context),
+            authorization: Some(authorization),
+            persisted: Some(persisted),
+            echo: Some(echo),
+        });
+        self.forget(evicted);
+    }
+
+    /// Removes the persisted state of a security context that left the pool.
+    fn forget(&mut self, evicted: Option<SecContextState<Crypto, SSC::GeneralClaims>>) {
+        if let Some(SecContextState {
+            persisted: Some(persisted),
+            ..
+        }) = evicted
+        {
+            self.persistence.remove(persisted.recipient_id());
         }
     }
 
@@ -238,15 +366,106 @@ impl<
     fn cown_but_not(&self, c_peer: &[u8]) -> COwn {
         // Let's pick one now already: this allows us to use the identifier in our
         // request data.
-        COwn::not_in_iter(
-            self.pool
-                .iter()
-                .filter_map(|entry| entry.corresponding_cown())
-                // C_R does not only need to be unique, it also must not be identical
-                // to C_I. If it is not expressible as a COwn (as_slice gives []),
-                // that's fine and we don't have to consider it.
-                .chain(COwn::from_kid(c_peer).as_slice().iter().copied()),
+        cown_but_not(&self.pool, c_peer)
+    }
+
+    /// Builds a notification for an observation that was registered by `request`.
+    ///
+    /// The request is the original (outer) request message as it was received. If it was
+    /// protected by OSCORE, the notification is protected in the same security context, provided
+    /// that the request was retained when it was processed; its authorization is checked again.
+    /// Otherwise, the request is processed again with the authorization of unprotected requests.
+    ///
+    /// This is a building block for implementing the CoAP stack side of observations (RFC7641):
+    /// The stack keeps the registrations and sends the notifications, while the inner handler
+    /// renders them, including their Observe option.
+    ///
+    /// # Errors
+    ///
+    /// This produces an error if no notification can be sent any more, in which case the stack
+    /// should drop the registration.
+    ///
+    /// # Panics
+    ///
+    /// Panics (like the [`Handler`][coap_handler::Handler] implementation) if the writable message
+    /// is not a [`coap_message_implementations::inmemory_write::Message`] when sending a protected
+    /// notification.
+    pub fn build_notification<M: MutableWritableMessage>(
+        &mut self,
+        request: &impl ReadableMessage,
+        response: &mut M,
+    ) -> Result<(), NotificationError> {
+        let oscore_option = request
+            .options()
+            .find(|o| o.number() == coap_numbers::option::OSCORE);
+
+        let Some(oscore_option) = oscore_option else {
+            if !self.authorities.nosec_authorization().is_some_and(|s| {
+                s.scope().request_is_allowed(request)
+                    && s.time_constraint().is_valid_with(&mut self.time)
+            }) {
+                return Err(NotificationError);
+            }
+            let extracted = self
+                .inner
+                .extract_request_data(request)
+                .map_err(|_| NotificationError)?;
+            return self
+                .inner
+                .build_response(response, extracted)
+                .map_err(|_| NotificationError);
+        };
+
+        if !has_oscore::<SSC>() {
+            return Err(NotificationError);
+        }
+
+        let Some(observation) = self.observations.find(oscore_option.value()).cloned() else {
+            debug!("Protected request for notification was not retained.");
+            return Err(NotificationError);
+        };
+        let inner_request = coap_message_implementations::inmemory::Message::new(
+            observation.code,
+            &observation.request,
+        );
+
+        let time = &mut self.time;
+        let allowed = self
+            .pool
+            .lookup(
+                |c| c.corresponding_cown() == Some(observation.kid),
+                |c| match c {
+                    SecContextState {
+                        protocol_stage: SecContextStage::Oscore(_),
+                        authorization: Some(authorization),
+                        ..
+                    } => {
+                        authorization.scope().request_is_allowed(&inner_request)
+                            && authorization.time_constraint().is_valid_with(time)
+                            && !authorization.is_revoked()
+                    }
+                    _ => false,
+                },
+            )
+            .unwrap_or(false);
+        if !allowed {
+            debug!("Observation is not authorized any more.");
+            self.observations.remove(oscore_option.value());
+            return Err(NotificationError);
+        }
+
+        let extracted = self.inner.extract_request_data(&inner_request);
+        let mut correlation = observation.correlation;
+        // Notifications carry their own Partial IV (RFC8613 Section 4.1.3.5.2).
+        correlation.is_first_use = false;
+        self.build_oscore_response(
+            response,
+            observation.kid,
+            correlation,
+            Some(AuthorizationChecked::Allowed(extracted)),
+            true,
         )
+        .map_err(|_| NotificationError)
     }
 
     /// Processes a CoAP request containing a message sent to /.well-known/edhoc.
@@ -306,8 +525,9 @@ impl<
             }
 
             let c_r = self.cown_but_not(c_i.as_slice());
+            self.observations.remove_context(c_r);
 
-            let _evicted = self.pool.force_insert(SecContextState {
+            let evicted = self.pool.force_insert(SecContextState {
                 protocol_stage: SecContextStage::EdhocResponderProcessedM1 {
                     c_r,
                     c_i,
@@ -315,7 +535,9 @@ impl<
                     requested_cred_by_value,
                 },
                 authorization: self.authorities.nosec_authorization(),
+                ..Default::default()
             });
+            self.forget(evicted);
 
             Ok(OwnRequestData::EdhocOkSend2(c_r))
         } else {
@@ -354,6 +576,7 @@ impl<
                             requested_cred_by_value,
                         },
                     authorization,
+                    ..
                 } = taken
                 else {
                     todo!();
@@ -383,6 +606,7 @@ impl<
                         c_r,
                     },
                     authorization,
+                    ..Default::default()
                 };
                 Ok(message_2)
             },
@@ -432,6 +656,7 @@ impl<
         with_edhoc: bool,
     ) -> Result<OwnRequestData<Result<H::RequestData, H::ExtractRequestError>>, CoAPError> {
         let payload = request.payload();
+        let raw_oscore_option = oscore_option;
 
         // We know this to not fail b/c we only got here due to its presence
         let oscore_option = liboscore::OscoreOption::parse(oscore_option).map_err(|_| {
@@ -473,6 +698,8 @@ impl<
         let SecContextState {
             protocol_stage: SecContextStage::Oscore(mut oscore_context),
             authorization: Some(authorization),
+            persisted,
+            echo,
         } = taken
         else {
             // FIXME: How'd we even get there? Should this be unreachable?
@@ -483,8 +710,9 @@ impl<
         if !authorization
             .time_constraint()
             .is_valid_with(&mut self.time)
+            || authorization.is_revoked()
         {
-            // Token expired.
+            // Token expired, or the peer was revoked.
             //
             // By returning early after having taken the context, we discard it completely.
             //
@@ -492,7 +720,10 @@ impl<
             // authorization at all -- it may be that for the purpose of time series it is useful
             // to retain the authorization (if there is some kind of renewal tokens / token
             // series).
-            debug!("Discarding expired context");
+            debug!("Discarding expired or revoked context");
+            if let Some(persisted) = persisted {
+                self.persistence.remove(persisted.recipient_id());
+            }
             return Err(CoAPError::bad_request());
         }
 
@@ -531,19 +762,37 @@ impl<
                 CoAPError::internal_server_error()
             })?;
 
+        let mut observation_request = None;
         let decrypted = liboscore::unprotect_request(
             &mut copied_message,
             oscore_option,
             &mut oscore_context,
             |request| {
-                if authorization.scope().request_is_allowed(request) {
+                // On a restored context, only a request that carries the expected Echo value is
+                // known to be fresh (RFC8613 Appendix B.1.2); any other is not processed.
+                if let Some(echo) = &echo
+                    && !request
+                        .options()
+                        .any(|o| o.number() == coap_numbers::option::ECHO && o.value() == &echo[..])
+                {
+                    return None;
+                }
+                Some(if authorization.scope().request_is_allowed(request) {
+                    observation_request = ObservationRequest::copy_if_registering(request);
                     AuthorizationChecked::Allowed(self.inner.extract_request_data(request))
                 } else {
                     AuthorizationChecked::NotAllowed
-                }
+                })
             },
         );
 
+        // Once a request was verified, the replay window is initialized.
+        let echo = if matches!(decrypted, Ok((_, Some(_)))) {
+            None
+        } else {
+            echo
+        };
+
         // With any luck, this never moves out.
         //
         // Storing it even on decryption failure to avoid DoS from the first message (but
@@ -552,6 +801,8 @@ impl<
         let _evicted = self.pool.force_insert(SecContextState {
             protocol_stage: SecContextStage::Oscore(oscore_context),
             authorization: Some(authorization),
+            persisted,
+            echo,
         });
         debug_assert!(
             matches!(
@@ -570,10 +821,28 @@ impl<
             return Err(CoAPError::unauthorized());
         };
 
+        let Some(extracted) = extracted else {
+            debug!("Request on restored context needs to be verified through Echo.");
+            return Ok(OwnRequestData::EchoRequired { kid, correlation });
+        };
+
+        let observing = observation_request.is_some();
+        if let Some(observation_request) = observation_request {
+            // Retained even if the inner handler does not accept the registration; the entry is
+            // then never looked up, and eventually replaced.
+            self.observations
+                .insert(observation_request.into_observation(
+                    kid,
+                    raw_oscore_option.clone(),
+                    correlation,
+                ));
+        }
+
         Ok(OwnRequestData::EdhocOscoreRequest {
             kid,
             correlation,
             extracted,
+            observing,
         })
     }
 
@@ -590,7 +859,7 @@ impl<
     /// This panics if cipher suite negotiation passed for a suite whose algorithms are unsupported
     /// in libOSCORE.
     fn process_edhoc_in_payload(
-        &self,
+        &mut self,
         payload: &[u8],
         sec_context_state: SecContextState<Crypto, SSC::GeneralClaims>,
     ) -> Result<(SecContextState<Crypto, SSC::GeneralClaims>, usize), CoAPError> {
@@ -627,7 +896,7 @@ impl<
             let mut cred_i_and_authorization = None;
 
             if let Some(lakers::EADItem { label: crate::iana::edhoc_ead::ACETOKEN, value: Some(value), .. }) = ead_3.take() {
-                match crate::ace::process_edhoc_token(value.as_slice(), &self.authorities) {
+                match crate::ace::process_edhoc_token(value.as_slice(), &self.authorities, &mut self.time) {
                     Ok(ci_and_a) => cred_i_and_authorization = Some(ci_and_a),
                     Err(e) => {
                         error!("Received unprocessable token {}, error: {:?}", defmt_or_log::wrappers::Cbor(value.as_slice()), Debug2Format(&e));
@@ -635,6 +904,9 @@ impl<
                 }
             }
 
+            // Authorization from a token could not be recovered after a reboot.
+            let persistable = P::PERSISTS && cred_i_and_authorization.is_none();
+
             if cred_i_and_authorization.is_none() {
                 cred_i_and_authorization = self
                     .authorities
@@ -664,33 +936,36 @@ impl<
 
             let oscore_secret = responder.edhoc_exporter(0u8, &[], 16); // label is 0
             let oscore_salt = responder.edhoc_exporter(1u8, &[], 8); // label is 1
-            let oscore_secret = &oscore_secret[..16];
-            let oscore_salt = &oscore_salt[..8];
-
-            let sender_id = c_i.as_slice();
-            let recipient_id = c_r.as_slice();
-
-            // FIXME probe cipher suite
-            let hkdf = liboscore::HkdfAlg::from_number(crate::iana::cose_alg::HKDF_HMAC256256).unwrap();
-            let aead = liboscore::AeadAlg::from_number(crate::iana::cose_alg::AES_CCM_16_64_128).unwrap();
-
-            let immutables = liboscore::PrimitiveImmutables::derive(
-                hkdf,
-                oscore_secret,
-                oscore_salt,
-                None,
-                aead,
-                sender_id,
-                recipient_id,
-            )
-            // FIXME convert error
-            .unwrap();
 
-            let context = liboscore::PrimitiveContext::new_from_fresh_material(immutables);
+            let context = oscore_context_from_edhoc(
+                &oscore_secret[..16],
+                &oscore_salt[..8],
+                c_i.as_slice(),
+                c_r.as_slice(),
+            );
+
+            let persisted = if persistable {
+                PersistedContext::new(
+                    &oscore_secret[..16],
+                    &oscore_salt[..8],
+                    c_i.as_slice(),
+                    c_r,
+                    id_cred_i.as_full_value(),
+                )
+            } else {
+                None
+            };
+            if let Some(persisted) = &persisted {
+                self.persistence.store(persisted);
+            } else if persistable {
+                debug!("Security context is not suitable for persisting.");
+            }
 
             SecContextState {
                 protocol_stage: SecContextStage::Oscore(context),
                 authorization: Some(authorization),
+                persisted,
+                echo: None,
             }
         } else {
             // Return the state. Best bet is that it was already advanced to an OSCORE
@@ -711,6 +986,11 @@ impl<
     /// Builds an OSCORE response message after successful processing of a request in
     /// [`Self::extract_oscore_edhoc()`].
     ///
+    /// If `observing` is set (for a request that registered an observation, or for a
+    /// notification), an Observe option of the inner response is also added as an outer option,
+    /// and the outer code is 2.05 Content ([RFC8613 Section 4.1.3.5.2 and
+    /// 4.2](https://www.rfc-editor.org/rfc/rfc8613#section-4.1.3.5.2)).
+    ///
     /// # Errors
     ///
     /// This produces errors if requests are processed in unexpected out-of-order ways.
@@ -725,7 +1005,8 @@ impl<
         response: &mut M,
         kid: COwn,
         mut correlation: liboscore::raw::oscore_requestid_t,
-        extracted: AuthorizationChecked<Result<H::RequestData, H::ExtractRequestError>>,
+        extracted: Option<AuthorizationChecked<Result<H::RequestData, H::ExtractRequestError>>>,
+        observing: bool,
     ) -> Result<(), Result<CoAPError, M::UnionError>> {
         response.set_code(M::Code::new(coap_numbers::code::CHANGED).map_err(|x| Err(x.into()))?);
 
@@ -747,7 +1028,7 @@ impl<
                     .lookup(|c| c.corresponding_cown() == Some(kid), |matched| {
                         // Not checking authorization any more: we don't even have access to the
                         // request any more, that check was done.
-                        let SecContextState { protocol_stage: SecContextStage::Oscore(oscore_context), .. } = matched else {
+                        let SecContextState { protocol_stage: SecContextStage::Oscore(oscore_context), persisted, echo, .. } = matched else {
                             // State vanished before response was built.
                             //
                             // As it is, depending on the CoAP stack, there may be DoS if a peer
@@ -761,6 +1042,47 @@ impl<
 
                         response.set_code(coap_numbers::code::CHANGED);
 
+                        // The inner response to an observation is rendered ahead of protection:
+                        // Its Observe option is carried out as an outer option, which precedes
+                        // the OSCORE option.
+                        let mut plaintext_code = 0;
+                        let mut plaintext_buffer = [0u8; EDHOC_COPY_BUFFER_SIZE];
+                        let mut plaintext = coap_message_implementations::inmemory_write::Message::new(&mut plaintext_code, &mut plaintext_buffer[..]);
+                        let (extracted, prerendered) = match extracted {
+                            Some(AuthorizationChecked::Allowed(Ok(extracted))) if observing => {
+                                (None, Some(self.inner.build_response(&mut plaintext, extracted)))
+                            }
+                            extracted => (extracted, None),
+                        };
+                        if let Some(Ok(())) = prerendered
+                            && let Some(observe) = plaintext
+                                .options()
+                                .find(|o| o.number() == coap_numbers::option::OBSERVE)
+                        {
+                            response.set_code(coap_numbers::code::CONTENT);
+                            if response.add_option(coap_numbers::option::OBSERVE, observe.value()).is_err() {
+                                error!("Outer Observe option could not be added.");
+                            }
+                        }
+
+                        if extracted.is_none() && prerendered.is_none() {
+                            // The request may be a replay, so the response must not reuse its
+                            // nonce.
+                            correlation.is_first_use = false;
+                        }
+
+                        if let Some(persisted) = persisted.as_ref()
+                            && self
+                                .persistence
+                                .durable_sequence_number_bound(persisted.recipient_id())
+                                .is_some_and(|bound| *persistence::sender_sequence_number(oscore_context) >= bound)
+                        {
+                            // Only happens if the storage does not keep up; numbers beyond the
+                            // durable bound could be reused after a reboot.
+                            error!("Sequence numbers exhausted up to the durable bound.");
+                            return Err(CoAPError::service_unavailable());
+                        }
+
                         if liboscore::protect_response(
                             response,
                             // SECURITY BIG FIXME: How do we make sure that our correlation is really for
@@ -769,28 +1091,22 @@ impl<
                             // should be a tie; carry the OSCORE context in an owned way?).
                             oscore_context,
                             &mut correlation,
-                            |response| match extracted {
-                                AuthorizationChecked::Allowed(Ok(extracted)) => match self.inner.build_response(response, extracted) {
+                            |response| match (prerendered, extracted) {
+                                (Some(Ok(())), _) => {
+                                    if response.set_from_message(&plaintext).is_err() {
+                                        error!("Rendered response does not fit the protected message.");
+                                        // FIXME rewind message
+                                        response.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
+                                    }
+                                }
+                                (Some(Err(e)), _) => render_build_error(e, response),
+                                (None, Some(AuthorizationChecked::Allowed(Ok(extracted)))) => match self.inner.build_response(response, extracted) {
                                     Ok(()) => {
                                         // All fine, response was built
                                     },
-                                    // One attempt to render rendering errors
-                                    // FIXME rewind message
-                                    Err(e) => {
-                                        error!("Rendering successful extraction failed with {:?}", Debug2Format(&e));
-                                        match e.render(response) {
-                                            Ok(()) => {
-                                                error!("Error rendered.");
-                                            },
-                                            Err(e2) => {
-                                                error!("Error could not be rendered: {:?}.", Debug2Format(&e2));
-                                                // FIXME rewind message
-                                                response.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
-                                            }
-                                        }
-                                    },
+                                    Err(e) => render_build_error(e, response),
                                 },
-                                AuthorizationChecked::Allowed(Err(inner_request_error)) => {
+                                (None, Some(AuthorizationChecked::Allowed(Err(inner_request_error)))) => {
                                     error!("Extraction failed with {:?}.", Debug2Format(&inner_request_error));
                                     match inner_request_error.render(response) {
                                         Ok(()) => {
@@ -815,12 +1131,20 @@ impl<
                                         }
                                     }
                                 }
-                                AuthorizationChecked::NotAllowed => {
+                                (None, Some(AuthorizationChecked::NotAllowed)) => {
                                     if self.authorities.render_not_allowed(response).is_err() {
                                         // FIXME rewind message
                                         response.set_code(coap_numbers::code::UNAUTHORIZED);
                                     }
                                 }
+                                (None, None) => {
+                                    response.set_code(coap_numbers::code::UNAUTHORIZED);
+                                    if let Some(echo) = echo.as_ref()
+                                        && response.add_option(coap_numbers::option::ECHO, &echo[..]).is_err()
+                                    {
+                                        error!("Echo option could not be added.");
+                                    }
+                                }
                             },
                         )
                         .is_err()
@@ -828,6 +1152,15 @@ impl<
                             error!("Oups, responding with weird state");
                             // todo!("Thanks to the protect API we've lost access to our response");
                         }
+
+                        // Advancing the bound well before it is reached gives the storage time to
+                        // catch up.
+                        if let Some(persisted) = persisted.as_mut()
+                            && *persistence::sender_sequence_number(oscore_context) + SEQUENCE_NUMBER_STEP / 2 >= persisted.sequence_number_bound
+                        {
+                            persisted.sequence_number_bound += SEQUENCE_NUMBER_STEP;
+                            self.persistence.store(persisted);
+                        }
                         Ok(())
                     })
                 .transpose().map_err(Ok)?;
@@ -851,36 +1184,81 @@ impl<
         let mut nonce2 = [0; crate::ace::OWN_NONCE_LEN];
         self.rng.fill_bytes(&mut nonce2);
 
-        let (response, oscore, generalclaims) =
-            crate::ace::process_acecbor_authz_info(payload, &self.authorities, nonce2, |nonce1| {
+        let pool = &self.pool;
+        let (response, oscore, generalclaims) = crate::ace::process_acecbor_authz_info(
+            payload,
+            &self.authorities,
+            nonce2,
+            &mut self.time,
+            |nonce1| {
                 // This preferably (even exclusively) produces EDHOC-ideal recipient IDs, but as long
                 // as we're having more of those than slots, no point in not reusing the code.
-                self.cown_but_not(nonce1)
-            })
-            .map_err(|e| {
-                error!("Sending out error:");
-                error!("{:?}", Debug2Format(&e));
-                e.position
-                    // FIXME: Could also come from processing inner
-                    .map_or(CoAPError::bad_request(), CoAPError::bad_request_with_rbep)
-            })?;
+                cown_but_not(pool, nonce1)
+            },
+        )
+        .map_err(|e| {
+            error!("Sending out error:");
+            error!("{:?}", Debug2Format(&e));
+            e.position
+                // FIXME: Could also come from processing inner
+                .map_or(CoAPError::bad_request(), CoAPError::bad_request_with_rbep)
+        })?;
 
         debug!(
             "Established OSCORE context with recipient ID {:?} and authorization {:?} through ACE-OSCORE",
             oscore.recipient_id(),
             Debug2Format(&generalclaims)
         );
+        if let Some(kid) = COwn::from_kid(oscore.recipient_id()) {
+            self.observations.remove_context(kid);
+        }
         // FIXME: This should be flagged as "unconfirmed" for rapid eviction, as it could be part
         // of a replay.
-        let _evicted = self.pool.force_insert(SecContextState {
+        let evicted = self.pool.force_insert(SecContextState {
             protocol_stage: SecContextStage::Oscore(oscore),
             authorization: Some(generalclaims),
+            ..Default::default()
         });
+        self.forget(evicted);
 
         Ok(response)
     }
 }
 
+/// Derives an OSCORE context from the key material exported from a completed EDHOC session.
+///
+/// This is used both in the responder role (where the sender ID is `C_I`) and in the initiator
+/// role (where the sender ID is `C_R`).
+///
+/// # Panics
+///
+/// This panics if cipher suite negotiation passed for a suite whose algorithms are unsupported
+/// in libOSCORE.
+pub(crate) fn oscore_context_from_edhoc(
+    oscore_secret: &[u8],
+    oscore_salt: &[u8],
+    sender_id: &[u8],
+    recipient_id: &[u8],
+) -> liboscore::PrimitiveContext {
+    // FIXME probe cipher suite
+    let hkdf = liboscore::HkdfAlg::from_number(crate::iana::cose_alg::HKDF_HMAC256256).unwrap();
+    let aead = liboscore::AeadAlg::from_number(crate::iana::cose_alg::AES_CCM_16_64_128).unwrap();
+
+    let immutables = liboscore::PrimitiveImmutables::derive(
+        hkdf,
+        oscore_secret,
+        oscore_salt,
+        None,
+        aead,
+        sender_id,
+        recipient_id,
+    )
+    // FIXME convert error
+    .unwrap();
+
+    liboscore::PrimitiveContext::new_from_fresh_material(immutables)
+}
+
 /// A wrapper around for a handler's inner RequestData used by [`OscoreEdhocHandler`] both for
 /// OSCORE and plain text requests.
 ///
@@ -910,6 +1288,15 @@ pub enum OwnRequestData<I> {
         kid: COwn,
         correlation: liboscore::raw::oscore_requestid_t,
         extracted: AuthorizationChecked<I>,
+        // Whether the request registered an observation
+        observing: bool,
+    },
+    // A request on a restored security context that was not processed because it did not carry
+    // the expected Echo value
+    EchoRequired {
+        #[expect(private_interfaces, reason = "should be addressed eventually")]
+        kid: COwn,
+        correlation: liboscore::raw::oscore_requestid_t,
     },
     ProcessedToken(crate::ace::AceCborAuthzInfoResponse),
 }
@@ -919,6 +1306,30 @@ pub enum OwnRequestData<I> {
 // not supported in match or let destructuring. (But our is_gc_eligible should be good enough
 // anyway).
 
+/// Renders an error of the inner handler's `build_response()` into a protected response.
+///
+/// If that fails too, the response is left with a 5.00 Internal Server Error code.
+fn render_build_error(
+    e: impl core::fmt::Debug + RenderableOnMinimal,
+    response: &mut liboscore::ProtectedMessage,
+) {
+    error!(
+        "Rendering successful extraction failed with {:?}",
+        Debug2Format(&e)
+    );
+    // FIXME rewind message
+    match e.render(response) {
+        Ok(()) => {
+            error!("Error rendered.");
+        }
+        Err(e2) => {
+            error!("Error could not be rendered: {:?}.", Debug2Format(&e2));
+            // FIXME rewind message
+            response.set_code(coap_numbers::code::INTERNAL_SERVER_ERROR);
+        }
+    }
+}
+
 /// Renders a [`lakers::MessageBufferError`] into the common Error type.
 ///
 /// It is yet to be determined whether anything more informative should be returned (likely it
@@ -988,6 +1399,12 @@ fn render_error(e: lakers::EDHOCError) -> CoAPError {
     CoAPError::bad_request()
 }
 
+/// Error type of [`OscoreEdhocHandler::build_notification()`].
+///
+/// No details are given: Whatever the reason, the observation can not be served any more.
+#[derive(Debug)]
+pub struct NotificationError;
+
 /// An Either-style type used internally by [`OscoreEdhocHandler`].
 ///
 /// Other crates should not rely on this (but making it an enum wrapped in a struct for privacy is
@@ -1029,7 +1446,8 @@ impl<
     SSC: ServerSecurityConfig,
     RNG: rand_core::RngCore + rand_core::CryptoRng,
     TP: TimeProvider,
-> coap_handler::Handler for OscoreEdhocHandler<H, Crypto, CryptoFactory, SSC, RNG, TP>
+    P: ContextPersistence,
+> coap_handler::Handler for OscoreEdhocHandler<H, Crypto, CryptoFactory, SSC, RNG, TP, P>
 {
     type RequestData = OrInner<
         OwnRequestData<Result<H::RequestData, H::ExtractRequestError>>,
@@ -1241,11 +1659,19 @@ impl<
                 kid,
                 correlation,
                 extracted,
+                observing,
             }) => {
                 if !has_oscore::<SSC>() {
                     unreachable!("State is not constructed");
                 }
-                self.build_oscore_response(response, kid, correlation, extracted)
+                self.build_oscore_response(response, kid, correlation, Some(extracted), observing)
+                    .map_err(Own)?;
+            }
+            Own(OwnRequestData::EchoRequired { kid, correlation }) => {
+                if !has_oscore::<SSC>() {
+                    unreachable!("State is not constructed");
+                }
+                self.build_oscore_response(response, kid, correlation, None, false)
                     .map_err(Own)?;
             }
             Inner(AuthorizationChecked::Allowed(i)) => {
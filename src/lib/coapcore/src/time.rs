@@ -54,16 +54,19 @@ impl TimeProvider for TimeUnknown {
 /// A processed set of token claims that limit it in time.
 #[derive(Copy, Clone, Debug)]
 pub struct TimeConstraint {
-    // iat would not go in here (that's only to feed a `TimeProvider::past_trusted`; nbf would go
-    // in here but we don't read that yet)
+    // iat would not go in here (that's only to feed a `TimeProvider::past_trusted`)
     exp: Option<u64>,
+    nbf: Option<u64>,
 }
 
 impl TimeConstraint {
     /// Creates a [`TimeConstraint`] with no bounds; it is valid at any time.
     #[must_use]
     pub fn unbounded() -> Self {
-        Self { exp: None }
+        Self {
+            exp: None,
+            nbf: None,
+        }
     }
 
     /// Extract time constraint from a claim.
@@ -74,18 +77,32 @@ impl TimeConstraint {
     pub fn from_claims_set(claims: &crate::ace::CwtClaimsSet<'_>) -> Self {
         TimeConstraint {
             exp: Some(claims.exp),
+            nbf: claims.nbf,
         }
     }
 
     /// Evaluates the constraint against time provided by the time provider.
     ///
-    /// Any uncertainty of the time provider is counted for the benefit of the client.
+    /// Any uncertainty of the time provider is counted for the benefit of the client: A token is
+    /// only considered expired if it expired before the earliest possible current time, and only
+    /// considered not yet valid if it becomes valid after the latest possible current time.
     pub(crate) fn is_valid_with(&self, time_provider: &mut impl TimeProvider) -> bool {
-        let Some(exp) = self.exp else {
+        if self.exp.is_none() && self.nbf.is_none() {
             return true;
-        };
-        let (now_early, _now_late) = time_provider.now();
+        }
+        let (now_early, now_late) = time_provider.now();
 
-        exp > now_early
+        if let Some(exp) = self.exp
+            && exp <= now_early
+        {
+            return false;
+        }
+        if let Some(nbf) = self.nbf
+            && let Some(now_late) = now_late
+            && nbf > now_late
+        {
+            return false;
+        }
+        true
     }
 }
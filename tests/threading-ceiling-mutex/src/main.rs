@@ -0,0 +1,50 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, RunqueueId, ThreadId, sync::CeilingMutex, thread_flags},
+};
+
+const CEILING: RunqueueId = RunqueueId::new(3);
+
+static MUTEX: CeilingMutex<usize> = CeilingMutex::new(CEILING, 0);
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    let tid = thread::current_tid().unwrap();
+
+    let mut counter = MUTEX.lock();
+    // The owner runs with the ceiling priority.
+    assert_eq!(thread::get_priority(tid), Some(CEILING));
+
+    // `thread1` has a higher priority than this thread, but not higher than the ceiling, so it
+    // does not get to run.
+    thread_flags::set(ThreadId::new(1), 0b1);
+    assert_eq!(*counter, 0);
+    *counter += 1;
+
+    drop(counter);
+    // Return to old prio, which lets `thread1` run and finish.
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(1)));
+    assert_eq!(*MUTEX.try_lock().unwrap(), 2);
+
+    ariel_os::debug::log::info!("Test passed!");
+    exit(ExitCode::Success);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread1() {
+    let tid = thread::current_tid().unwrap();
+
+    thread_flags::wait_one(0b1);
+
+    let mut counter = MUTEX.lock();
+    assert_eq!(thread::get_priority(tid), Some(CEILING));
+    // `thread0` released the lock before this thread could run.
+    assert_eq!(*counter, 1);
+    *counter += 1;
+    drop(counter);
+
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(2)));
+}
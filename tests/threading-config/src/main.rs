@@ -0,0 +1,36 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, JoinError, RunqueueId, SCHED_PRIO_LEVELS, SPAWN_STACK_COUNT, THREAD_COUNT},
+};
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    assert_eq!(THREAD_COUNT, 32);
+    assert_eq!(SCHED_PRIO_LEVELS, 16);
+
+    // Spread more threads than the default `THREAD_COUNT` over all higher priority levels; each
+    // runs right away and parks.
+    let highest = u8::try_from(SCHED_PRIO_LEVELS - 1).unwrap();
+    let mut prios = (2..=highest).cycle();
+    let handles: [_; SPAWN_STACK_COUNT] =
+        core::array::from_fn(|_| thread::spawn(thread::park, prios.next().unwrap(), None).unwrap());
+    assert!(
+        handles
+            .iter()
+            .any(|handle| usize::from(handle.thread_id()) >= 16)
+    );
+    assert!(handles.iter().any(|handle| {
+        thread::get_priority(handle.thread_id()) == Some(RunqueueId::new(highest))
+    }));
+
+    for handle in handles {
+        thread::kill(handle.thread_id()).unwrap();
+        assert_eq!(handle.join(), Err(JoinError::Killed));
+    }
+
+    ariel_os::debug::log::info!("Test passed!");
+    exit(ExitCode::Success);
+}
@@ -0,0 +1,54 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit, log::info},
+    thread::{self, thread_flags},
+    time::{Duration, Instant},
+};
+
+const BUSY: Duration = Duration::from_millis(50);
+const SLEEP: Duration = Duration::from_millis(50);
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn busy() {
+    let start = Instant::now();
+    while start.elapsed() < BUSY {}
+
+    // Lets the lower priority thread run.
+    thread_flags::wait_one(0b1);
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn reporter() {
+    let tid = thread::current_tid().unwrap();
+    let core = thread::core_id();
+
+    // The higher priority thread ran first.
+    let busy = thread::threads()
+        .find(|info| info.name == Some("busy"))
+        .unwrap();
+    assert!(busy.cpu_time >= BUSY);
+    assert_eq!(thread::cpu_time(busy.tid), Some(busy.cpu_time));
+
+    // The current thread only just started.
+    let own = thread::cpu_time(tid).unwrap();
+    assert!(own < BUSY);
+
+    let idle_before = thread::idle_time(core);
+    thread::sleep(SLEEP);
+    let idle = thread::idle_time(core) - idle_before;
+    info!("idle for {} ms while sleeping", idle.as_millis());
+    // Allow for the timer resolution and the work done in interrupt handlers.
+    assert!(idle >= SLEEP - Duration::from_millis(5));
+    assert!(idle <= SLEEP + Duration::from_millis(10));
+
+    // Sleeping did not count as running time.
+    assert!(thread::cpu_time(tid).unwrap() - own < SLEEP);
+
+    thread_flags::set(busy.tid, 0b1);
+    assert_eq!(thread::cpu_time(busy.tid), None);
+
+    info!("Test passed!");
+    exit(ExitCode::Success);
+}
@@ -0,0 +1,57 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit, log::info},
+    thread::{self, RunqueueId, ThreadState, thread_flags},
+};
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn waiter() {
+    // Use some stack, so that it shows up in the peak usage.
+    let buf = core::hint::black_box([0xAAu8; 256]);
+    core::hint::black_box(&buf);
+
+    thread_flags::wait_one(0b1);
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn reporter() {
+    let tid = thread::current_tid().unwrap();
+
+    for info in thread::threads() {
+        info!(
+            "{:?} {:?}: {:?}, prio {:?}, stack {}/{}",
+            info.tid, info.name, info.state, info.prio, info.stack_peak_usage, info.stack_size
+        );
+        assert!(info.stack_peak_usage <= info.stack_size);
+    }
+
+    let current = thread::thread_info(tid).unwrap();
+    assert_eq!(current.name, Some("reporter"));
+    assert_eq!(current.state, ThreadState::Running);
+    assert_eq!(current.prio, RunqueueId::new(1));
+    assert!(current.stack_peak_usage > 0);
+
+    // The higher priority thread ran first and is now waiting for flags.
+    let waiter = thread::threads()
+        .find(|info| info.name == Some("waiter"))
+        .unwrap();
+    assert!(matches!(waiter.state, ThreadState::FlagBlocked(_)));
+    assert_eq!(waiter.prio, RunqueueId::new(2));
+    assert!(waiter.stack_peak_usage >= 256);
+
+    assert!(thread::set_name(waiter.tid, "renamed"));
+    assert_eq!(
+        thread::thread_info(waiter.tid).unwrap().name,
+        Some("renamed")
+    );
+
+    // Once the waiter has ended, it is no longer listed.
+    thread_flags::set(waiter.tid, 0b1);
+    assert!(thread::thread_info(waiter.tid).is_none());
+    assert!(thread::threads().all(|info| info.tid != waiter.tid));
+
+    info!("Test passed!");
+    exit(ExitCode::Success);
+}
@@ -0,0 +1,90 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, TimeoutError, sync::Queue},
+    time::Duration,
+};
+
+/// A payload that is neither `Copy` nor `Clone`.
+#[derive(Debug, PartialEq)]
+struct Sample {
+    seq: usize,
+}
+
+const CAPACITY: usize = 4;
+const SAMPLES: usize = 4 * CAPACITY;
+
+static QUEUE: Queue<Sample, CAPACITY> = Queue::new();
+static DONE: Queue<usize, 2> = Queue::new();
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn producer() {
+    assert_eq!(QUEUE.capacity(), CAPACITY);
+    assert!(QUEUE.is_empty());
+
+    // The consumer has a higher priority and is waiting, so each sample is taken right away.
+    for seq in 0..CAPACITY {
+        QUEUE.try_send(Sample { seq }).unwrap();
+        assert!(QUEUE.is_empty());
+    }
+
+    // The consumer is now sleeping, so the queue fills up.
+    for seq in CAPACITY..2 * CAPACITY {
+        QUEUE.try_send(Sample { seq }).unwrap();
+    }
+    assert!(QUEUE.is_full());
+    assert_eq!(QUEUE.len(), CAPACITY);
+    assert_eq!(
+        QUEUE.try_send(Sample { seq: 2 * CAPACITY }),
+        Err(Sample { seq: 2 * CAPACITY })
+    );
+
+    // This blocks until the consumer has woken up and made room.
+    for seq in 2 * CAPACITY..3 * CAPACITY {
+        QUEUE.send(Sample { seq });
+    }
+    assert!(QUEUE.is_full());
+
+    // The consumer is sleeping again, so this blocks until it has made room before the timeout.
+    for seq in 3 * CAPACITY..SAMPLES {
+        assert_eq!(
+            QUEUE.send_timeout(Sample { seq }, Duration::from_secs(1)),
+            Ok(())
+        );
+    }
+    assert_eq!(DONE.recv(), SAMPLES);
+
+    assert_eq!(
+        QUEUE.recv_timeout(Duration::from_millis(10)),
+        Err(TimeoutError)
+    );
+
+    // Nothing is received anymore, so sending times out once the queue is full.
+    for seq in 0..CAPACITY {
+        assert_eq!(
+            QUEUE.send_timeout(Sample { seq }, Duration::from_millis(10)),
+            Ok(())
+        );
+    }
+    assert_eq!(
+        QUEUE.send_timeout(Sample { seq: CAPACITY }, Duration::from_millis(10)),
+        Err(Sample { seq: CAPACITY })
+    );
+    assert_eq!(QUEUE.len(), CAPACITY);
+
+    ariel_os::debug::log::info!("Test passed!");
+    exit(ExitCode::Success);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn consumer() {
+    for expected in 0..SAMPLES {
+        if expected == CAPACITY || expected == 2 * CAPACITY {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(QUEUE.recv(), Sample { seq: expected });
+    }
+    DONE.send(SAMPLES);
+}
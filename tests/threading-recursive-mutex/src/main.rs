@@ -0,0 +1,60 @@
+#![no_main]
+#![no_std]
+
+use core::cell::Cell;
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, RunqueueId, ThreadId, sync::RecursiveMutex, thread_flags},
+};
+
+static MUTEX: RecursiveMutex<Cell<usize>> = RecursiveMutex::new(Cell::new(0));
+
+/// Increments the counter, locking the mutex once per level of recursion.
+fn increment(depth: usize) {
+    let counter = MUTEX.lock();
+    counter.set(counter.get() + 1);
+    if depth > 1 {
+        increment(depth - 1);
+    }
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    let tid = thread::current_tid().unwrap();
+
+    let outer = MUTEX.lock();
+    increment(3);
+    let inner = MUTEX.try_lock().unwrap();
+    assert_eq!(inner.get(), 3);
+
+    // `thread1` has a higher priority and blocks on the mutex.
+    thread_flags::set(ThreadId::new(1), 0b1);
+    // Inherit prio of the waiting thread.
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(2)));
+
+    // Still locked by the outer guard.
+    drop(inner);
+    assert!(MUTEX.is_locked());
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(2)));
+
+    // Releasing the last guard hands the mutex over, which lets `thread1` run.
+    drop(outer);
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(1)));
+
+    thread_flags::wait_one(0b1);
+    assert!(!MUTEX.is_locked());
+    assert_eq!(MUTEX.lock().get(), 5);
+
+    ariel_os::debug::log::info!("Test passed!");
+    exit(ExitCode::Success);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread1() {
+    thread_flags::wait_one(0b1);
+
+    increment(2);
+
+    thread_flags::set(ThreadId::new(0), 0b1);
+}
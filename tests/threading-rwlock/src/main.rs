@@ -0,0 +1,94 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, RunqueueId, ThreadId, TimeoutError, sync::RwLock, thread_flags},
+    time::Duration,
+};
+
+static RWLOCK: RwLock<usize> = RwLock::new(0);
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    let tid = thread::current_tid().unwrap();
+
+    let mut value = RWLOCK.write();
+
+    // Both other threads have higher priorities, so setting a flag causes a context switch and
+    // lets them block on the lock.
+    thread_flags::set(ThreadId::new(1), 0b1);
+    // Inherit prio of waiting reader.
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(2)));
+    thread_flags::set(ThreadId::new(2), 0b1);
+    // Inherit prio of waiting writer.
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(3)));
+
+    *value += 1;
+    // Waiting readers are preferred over the waiting writer.
+    drop(value);
+
+    // Return to old prio.
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(1)));
+
+    // Wait for other threads to complete.
+    thread_flags::wait_all(0b11);
+    assert_eq!(*RWLOCK.read(), 2);
+
+    // Readers can share the lock, but exclude writers.
+    let first = RWLOCK.read();
+    let second = RWLOCK.try_read().unwrap();
+    assert_eq!(*first, *second);
+    assert!(RWLOCK.try_write().is_none());
+    assert!(matches!(
+        RWLOCK.write_timeout(Duration::from_millis(10)),
+        Err(TimeoutError)
+    ));
+    drop(first);
+    drop(second);
+
+    // A writer excludes readers.
+    let value = RWLOCK.try_write().unwrap();
+    assert!(RWLOCK.try_read().is_none());
+    assert!(matches!(
+        RWLOCK.read_timeout(Duration::from_millis(10)),
+        Err(TimeoutError)
+    ));
+    drop(value);
+
+    assert!(RWLOCK.read_timeout(Duration::from_millis(10)).is_ok());
+
+    ariel_os::debug::log::info!("Test passed!");
+    exit(ExitCode::Success);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread1() {
+    thread_flags::wait_one(0b1);
+
+    let value = RWLOCK.read();
+    // Got the lock before the higher priority writer.
+    assert_eq!(*value, 1);
+    // New readers have to wait while a writer is waiting.
+    assert!(RWLOCK.try_read().is_none());
+    drop(value);
+
+    thread_flags::set(ThreadId::new(0), 0b1);
+}
+
+#[ariel_os::thread(autostart, priority = 3)]
+fn thread2() {
+    thread_flags::wait_one(0b1);
+
+    let mut value = RWLOCK.write();
+    assert_eq!(*value, 1);
+    // Priority didn't change because this thread has higher prio than all waiting threads.
+    assert_eq!(
+        thread::get_priority(thread::current_tid().unwrap()),
+        Some(RunqueueId::new(3))
+    );
+    *value += 1;
+    drop(value);
+
+    thread_flags::set(ThreadId::new(0), 0b10);
+}
@@ -0,0 +1,59 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{ThreadId, TimeoutError, sync::Semaphore, thread_flags},
+    time::Duration,
+};
+use portable_atomic::{AtomicUsize, Ordering};
+
+static SEMAPHORE: Semaphore = Semaphore::new(2);
+static RUN_ORDER: AtomicUsize = AtomicUsize::new(0);
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    assert_eq!(SEMAPHORE.available_permits(), 2);
+    SEMAPHORE.acquire();
+    assert!(SEMAPHORE.try_acquire());
+    assert_eq!(SEMAPHORE.available_permits(), 0);
+
+    assert!(!SEMAPHORE.try_acquire());
+    assert_eq!(
+        SEMAPHORE.acquire_timeout(Duration::from_millis(10)),
+        Err(TimeoutError)
+    );
+
+    // Both other threads have higher priorities, so setting a flag causes a context switch and
+    // lets them block on the semaphore.
+    thread_flags::set(ThreadId::new(1), 0b1);
+    thread_flags::set(ThreadId::new(2), 0b1);
+
+    // The permit is handed over to the highest priority waiter, which passes it on to the other
+    // waiter when releasing it.
+    SEMAPHORE.release();
+    assert_eq!(RUN_ORDER.load(Ordering::Acquire), 2);
+    assert_eq!(SEMAPHORE.available_permits(), 1);
+
+    SEMAPHORE.release();
+    assert_eq!(SEMAPHORE.available_permits(), 2);
+
+    ariel_os::debug::log::info!("Test passed!");
+    exit(ExitCode::Success);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread1() {
+    thread_flags::wait_one(0b1);
+    SEMAPHORE.acquire();
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 1);
+    SEMAPHORE.release();
+}
+
+#[ariel_os::thread(autostart, priority = 3)]
+fn thread2() {
+    thread_flags::wait_one(0b1);
+    SEMAPHORE.acquire();
+    assert_eq!(RUN_ORDER.fetch_add(1, Ordering::AcqRel), 0);
+    SEMAPHORE.release();
+}
@@ -0,0 +1,46 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, JoinError, SPAWN_STACK_COUNT, SpawnError},
+};
+use portable_atomic::{AtomicUsize, Ordering};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    // Higher priority than this thread, so it runs to completion right away.
+    let offset = 40;
+    let handle = thread::spawn(move || offset + 2, 2, None).unwrap();
+    assert!(handle.is_finished());
+    assert_eq!(handle.join(), Ok(42));
+
+    // Lower priority, so it only runs once this thread blocks in `join()`.
+    let handle = thread::spawn(|| COUNTER.fetch_add(1, Ordering::AcqRel), 0, None).unwrap();
+    assert!(!handle.is_finished());
+    assert_eq!(handle.join(), Ok(0));
+
+    // Detached threads return their stack once they end.
+    for _ in 0..2 * SPAWN_STACK_COUNT {
+        drop(thread::spawn(|| COUNTER.fetch_add(1, Ordering::AcqRel), 2, None).unwrap());
+    }
+    assert_eq!(COUNTER.load(Ordering::Acquire), 1 + 2 * SPAWN_STACK_COUNT);
+
+    // Parked threads keep their stack until killed.
+    let handles: [_; SPAWN_STACK_COUNT] =
+        core::array::from_fn(|_| thread::spawn(thread::park, 2, None).unwrap());
+    assert_eq!(
+        thread::spawn(|| {}, 2, None).err(),
+        Some(SpawnError::NoFreeStack)
+    );
+    for handle in handles {
+        thread::kill(handle.thread_id()).unwrap();
+        assert_eq!(handle.join(), Err(JoinError::Killed));
+    }
+    assert!(thread::spawn(|| {}, 2, None).unwrap().join().is_ok());
+
+    ariel_os::debug::log::info!("Test passed!");
+    exit(ExitCode::Success);
+}
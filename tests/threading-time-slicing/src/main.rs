@@ -0,0 +1,45 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{self, RunqueueId},
+    time::Duration,
+};
+use portable_atomic::{AtomicUsize, Ordering};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+const ROUNDS: usize = 3;
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    assert_eq!(
+        thread::time_slice(RunqueueId::new(1)),
+        Some(Duration::from_micros(thread::TIME_SLICE_US as u64))
+    );
+    thread::set_time_slice(RunqueueId::new(1), Some(Duration::from_millis(1)));
+
+    // Busy-loop without ever yielding, until `thread1` got to run a few times.
+    let mut seen = COUNTER.load(Ordering::Acquire);
+    let mut rounds = 0;
+    while rounds < ROUNDS {
+        let current = COUNTER.load(Ordering::Acquire);
+        if current != seen {
+            seen = current;
+            rounds += 1;
+        }
+    }
+
+    ariel_os::debug::log::info!("Test passed!");
+    exit(ExitCode::Success);
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread1() {
+    // Never yields either, so this only makes progress when preempted by `thread0`'s time slices
+    // running out.
+    loop {
+        COUNTER.fetch_add(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,89 @@
+#![no_main]
+#![no_std]
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use ariel_os::{
+    debug::{ExitCode, exit},
+    thread::{
+        self, RunqueueId, ThreadId, TimeoutError,
+        sync::{Channel, Event, Lock, Mutex},
+        thread_flags,
+    },
+    time::Duration,
+};
+
+static CHANNEL: Channel<u32> = Channel::new();
+static EVENT: Event = Event::new();
+static LOCK: Lock = Lock::new();
+static MUTEX: Mutex<()> = Mutex::new(());
+
+static THREAD0: AtomicU8 = AtomicU8::new(0);
+static THREAD1: AtomicU8 = AtomicU8::new(0);
+
+const TIMEOUT: Duration = Duration::from_millis(10);
+
+fn store_tid(slot: &AtomicU8) -> ThreadId {
+    let tid = thread::current_tid().unwrap();
+    slot.store(u8::try_from(usize::from(tid)).unwrap(), Ordering::Relaxed);
+    tid
+}
+
+fn load_tid(slot: &AtomicU8) -> ThreadId {
+    ThreadId::new(slot.load(Ordering::Relaxed))
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread0() {
+    let tid = store_tid(&THREAD0);
+    let thread1 = load_tid(&THREAD1);
+
+    // Nobody else is there, so all of these time out.
+    assert_eq!(
+        thread_flags::wait_any_timeout(0b1, TIMEOUT),
+        Err(TimeoutError)
+    );
+    assert_eq!(
+        thread_flags::wait_any_timeout(0b10, TIMEOUT),
+        Err(TimeoutError)
+    );
+    assert_eq!(EVENT.wait_timeout(TIMEOUT), Err(TimeoutError));
+    assert_eq!(CHANNEL.recv_timeout(TIMEOUT), Err(TimeoutError));
+    assert_eq!(CHANNEL.send_timeout(&1, TIMEOUT), Err(TimeoutError));
+    LOCK.acquire();
+    assert_eq!(LOCK.acquire_timeout(TIMEOUT), Err(TimeoutError));
+    LOCK.release();
+    assert_eq!(LOCK.acquire_timeout(TIMEOUT), Ok(()));
+    LOCK.release();
+    thread::sleep(TIMEOUT);
+    // Timing out does not leave any flags behind.
+    assert_eq!(thread_flags::get(), 0);
+
+    let guard = MUTEX.lock();
+    // thread1 tries to get the mutex, and this thread inherits its priority.
+    thread_flags::set(thread1, 0b1);
+    assert_eq!(thread::get_priority(tid), thread::get_priority(thread1));
+    // Once thread1 has given up, the inherited priority is reset.
+    thread::sleep(TIMEOUT * 2);
+    assert_eq!(thread::get_priority(tid), Some(RunqueueId::new(1)));
+    drop(guard);
+
+    // thread1 is waiting for the event, then receives on the channel.
+    EVENT.set();
+    CHANNEL.send(&42);
+    assert_eq!(thread_flags::wait_any_timeout(0b10, TIMEOUT), Ok(0b10));
+
+    ariel_os::debug::log::info!("Test passed!");
+    exit(ExitCode::Success);
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread1() {
+    store_tid(&THREAD1);
+    thread_flags::wait_any(0b1);
+    assert!(MUTEX.lock_timeout(TIMEOUT).is_err());
+
+    assert_eq!(EVENT.wait_timeout(TIMEOUT * 10), Ok(()));
+    assert_eq!(CHANNEL.recv_timeout(TIMEOUT * 10), Ok(42));
+    thread_flags::set(load_tid(&THREAD0), 0b10);
+}
@@ -0,0 +1,54 @@
+#![no_main]
+#![no_std]
+
+use ariel_os::{
+    debug::{ExitCode, exit, log::info},
+    thread::{self, ThreadId, thread_flags},
+};
+use portable_atomic::{AtomicUsize, Ordering};
+
+static INITIALIZED: AtomicUsize = AtomicUsize::new(0);
+static THREAD0: AtomicUsize = AtomicUsize::new(0);
+
+thread::thread_local! {
+    static COUNTER: usize = {
+        INITIALIZED.fetch_add(1, Ordering::AcqRel);
+        0
+    };
+    static NAME: &'static str = "";
+}
+
+fn count(n: usize) -> usize {
+    for _ in 0..n {
+        COUNTER.with(|counter| *counter += 1).unwrap();
+    }
+    COUNTER.with(|counter| *counter).unwrap()
+}
+
+#[ariel_os::thread(autostart, priority = 2)]
+fn thread0() {
+    let tid = thread::current_tid().unwrap();
+    THREAD0.store(usize::from(tid), Ordering::Release);
+    NAME.with(|name| *name = "thread0");
+    assert_eq!(count(3), 3);
+
+    // Let the other thread run.
+    thread_flags::wait_one(0b1);
+
+    // The other thread's accesses did not change this thread's values.
+    assert_eq!(count(1), 4);
+    assert_eq!(NAME.with(|name| *name), Some("thread0"));
+    assert_eq!(INITIALIZED.load(Ordering::Acquire), 2);
+
+    info!("Test passed!");
+    exit(ExitCode::Success);
+}
+
+#[ariel_os::thread(autostart, priority = 1)]
+fn thread1() {
+    assert_eq!(NAME.with(|name| *name), Some(""));
+    assert_eq!(count(5), 5);
+
+    let thread0 = u8::try_from(THREAD0.load(Ordering::Acquire)).unwrap();
+    thread_flags::set(ThreadId::new(thread0), 0b1);
+}